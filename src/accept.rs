@@ -0,0 +1,141 @@
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::warn;
+
+use crate::expiry::ExpiryMap;
+
+const GOOD_IP_TTL: Duration = Duration::from_secs(3600);
+const GOOD_IP_CAPACITY: usize = 100_000;
+
+pub enum AcceptDecision {
+    Admit,
+    RateCeiling,
+    Emergency,
+}
+
+impl AcceptDecision {
+    // Label de oblivion_refused_total: teto global de accept ou greylist do modo
+    // emergência (só IPs conhecidos entram)
+    pub fn refusal(&self) -> Option<&'static str> {
+        match self {
+            AcceptDecision::Admit => None,
+            AcceptDecision::RateCeiling => Some("accept_ceiling"),
+            AcceptDecision::Emergency => Some("greylist"),
+        }
+    }
+}
+
+struct Ceiling {
+    tokens: f64,
+    last_update: Instant,
+}
+
+pub struct AcceptGuard {
+    rate: f64,
+    ceiling: Mutex<Ceiling>,
+    active: AtomicUsize,
+    max_active: usize,
+    emergency_threshold: usize,
+    emergency: AtomicBool,
+    allowlist: Vec<IpAddr>,
+    // Greylist do modo emergência: IP bom até GOOD_IP_TTL depois do último request limpo
+    known_good: ExpiryMap<IpAddr>,
+}
+
+pub struct ConnectionSlot {
+    guard: Arc<AcceptGuard>,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.guard.active.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl AcceptGuard {
+    pub fn new(
+        rate: f64,
+        max_active: usize,
+        emergency_threshold: usize,
+        allowlist: Vec<IpAddr>,
+    ) -> Arc<Self> {
+        Arc::new(AcceptGuard {
+            rate,
+            ceiling: Mutex::new(Ceiling {
+                tokens: rate,
+                last_update: Instant::now(),
+            }),
+            active: AtomicUsize::new(0),
+            max_active,
+            emergency_threshold,
+            emergency: AtomicBool::new(false),
+            allowlist,
+            known_good: ExpiryMap::new(GOOD_IP_CAPACITY),
+        })
+    }
+
+    // Sob pressão de file descriptors o loop de accept deve parar de aceitar.
+    pub fn saturated(&self) -> bool {
+        self.active.load(Ordering::Acquire) >= self.max_active
+    }
+
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Acquire)
+    }
+
+    // ip para a allowlist, key (prefixo IPv6) para o estado de reputação
+    pub fn check(&self, ip: IpAddr, key: IpAddr) -> AcceptDecision {
+        let active = self.active.load(Ordering::Acquire);
+        let emergency = active >= self.emergency_threshold;
+        if emergency != self.emergency.swap(emergency, Ordering::AcqRel) {
+            if emergency {
+                warn!(
+                    active,
+                    "Accept emergency mode engaged: admitting known-good IPs only"
+                );
+            } else {
+                warn!(active, "Accept emergency mode lifted");
+            }
+        }
+
+        if self.allowlist.contains(&ip) {
+            return AcceptDecision::Admit;
+        }
+
+        if emergency && !self.is_known_good(key) {
+            return AcceptDecision::Emergency;
+        }
+
+        let mut ceiling = self.ceiling.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.duration_since(ceiling.last_update).as_secs_f64();
+        ceiling.tokens = (ceiling.tokens + elapsed * self.rate).min(self.rate);
+        ceiling.last_update = now;
+
+        if ceiling.tokens >= 1.0 {
+            ceiling.tokens -= 1.0;
+            AcceptDecision::Admit
+        } else {
+            AcceptDecision::RateCeiling
+        }
+    }
+
+    pub fn open(self: &Arc<Self>) -> ConnectionSlot {
+        self.active.fetch_add(1, Ordering::AcqRel);
+        ConnectionSlot {
+            guard: self.clone(),
+        }
+    }
+
+    // Shard cheio expira o que venceu nele; só vivas demais descartam a marca
+    pub fn mark_good(&self, ip: IpAddr) {
+        self.known_good.insert(ip, Instant::now() + GOOD_IP_TTL);
+    }
+
+    fn is_known_good(&self, ip: IpAddr) -> bool {
+        self.known_good.get(&ip).is_some()
+    }
+}
@@ -0,0 +1,414 @@
+use std::fmt::Write as _;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use arc_swap::ArcSwap;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::mpsc;
+
+use crate::appender::Appender;
+use crate::challenge::to_hex;
+use crate::config::Config;
+use crate::geo::GeoDb;
+use crate::http::Request;
+use crate::metrics::Metrics;
+use crate::reject::Abortable;
+
+const QUEUE_CAPACITY: usize = 10_000;
+
+// combined do nginx (bytes_sent no lugar de body_bytes_sent) + o que só o WAF sabe
+pub const DEFAULT_FORMAT: &str = "$remote_addr - $remote_user [$time_local] \"$request\" $status $bytes_sent \"$http_referer\" \"$http_user_agent\" $request_time $verdict $rule $request_id";
+
+// Além destes, $http_<header> (traço vira _), como no nginx
+const VARIABLES: &[&str] = &[
+    "remote_addr",
+    "remote_user",
+    "host",
+    "request",
+    "request_method",
+    "request_uri",
+    "uri",
+    "args",
+    "server_protocol",
+    "status",
+    "bytes_sent",
+    "request_time",
+    "time_local",
+    "time_iso8601",
+    "msec",
+    "verdict",
+    "rule",
+    "country",
+    "request_id",
+];
+
+// X-Request-Id do cliente só é aproveitado se for um ID, não texto arbitrário
+const MAX_REQUEST_ID: usize = 64;
+
+enum Piece<'a> {
+    Text(&'a str),
+    Var(&'a str),
+}
+
+// $nome ou ${nome}; $ sem nome depois fica literal
+fn walk<'a>(format: &'a str, mut f: impl FnMut(Piece<'a>)) -> Result<(), String> {
+    let mut rest = format;
+    while let Some(at) = rest.find('$') {
+        f(Piece::Text(&rest[..at]));
+        let after = &rest[at + 1..];
+        let (name, tail) = if let Some(braced) = after.strip_prefix('{') {
+            let end = braced
+                .find('}')
+                .ok_or_else(|| "unterminated ${".to_string())?;
+            (&braced[..end], &braced[end + 1..])
+        } else {
+            let end = after
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(after.len());
+            (&after[..end], &after[end..])
+        };
+        if name.is_empty() {
+            f(Piece::Text("$"));
+        } else {
+            f(Piece::Var(name));
+        }
+        rest = tail;
+    }
+    f(Piece::Text(rest));
+    Ok(())
+}
+
+fn known(name: &str) -> bool {
+    VARIABLES.contains(&name) || name.strip_prefix("http_").is_some_and(|h| !h.is_empty())
+}
+
+pub fn check_format(format: &str) -> Result<(), String> {
+    let mut unknown = None;
+    walk(format, |piece| {
+        if let Piece::Var(name) = piece
+            && !known(name)
+        {
+            unknown.get_or_insert(name);
+        }
+    })?;
+    match unknown {
+        Some(name) => Err(format!("unknown variable ${}", name)),
+        None => Ok(()),
+    }
+}
+
+// O que o request deixa para a linha; preenchido ao longo do pipeline. Sem
+// begin() (log desligado, head inválido) não sai linha
+#[derive(Default)]
+pub struct Access {
+    started: Option<Instant>,
+    method: String,
+    uri: String,
+    protocol: String,
+    host: String,
+    // Só os headers que o formato atual pede ($http_*)
+    headers: Vec<(String, String)>,
+    request_id: String,
+    pub verdict: &'static str,
+    pub rule: Option<u32>,
+}
+
+// Escrito no stream do cliente desde o último finish()
+pub struct Sent {
+    pub status: Option<u16>,
+    pub bytes: u64,
+}
+
+// Stream do cliente contando o que o WAF escreveu: a status line e os bytes
+// de cada request saem daqui, seja resposta gerada, do upstream ou túnel.
+// Leva junto o Access do request em andamento
+pub struct Metered<S> {
+    inner: S,
+    bytes: u64,
+    head: Vec<u8>,
+    pub access: Access,
+}
+
+impl<S> Metered<S> {
+    pub fn new(inner: S) -> Self {
+        Metered {
+            inner,
+            bytes: 0,
+            head: Vec::with_capacity(12),
+            access: Access::default(),
+        }
+    }
+
+    // Fecha o request atual: o próximo começa do zero
+    pub fn finish(&mut self) -> (Access, Sent) {
+        // "HTTP/1.1 200"
+        let status = self
+            .head
+            .get(9..12)
+            .and_then(|code| std::str::from_utf8(code).ok())
+            .and_then(|code| code.parse().ok());
+        self.head.clear();
+        let sent = Sent {
+            status,
+            bytes: std::mem::take(&mut self.bytes),
+        };
+        (std::mem::take(&mut self.access), sent)
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Metered<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Metered<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = &mut *self;
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = result {
+            this.bytes += n as u64;
+            let missing = 12usize.saturating_sub(this.head.len()).min(n);
+            this.head.extend_from_slice(&buf[..missing]);
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl<S: Abortable> Abortable for Metered<S> {
+    fn reset_on_close(&self) {
+        self.inner.reset_on_close();
+    }
+}
+
+struct Entry {
+    line: String,
+    path: String,
+}
+
+// Access log ([access_log]): uma linha por request no formato configurado.
+// Como os eventos, sai por fila: disco lento vira linha descartada e contada
+pub struct AccessLog {
+    config: Arc<ArcSwap<Config>>,
+    tx: mpsc::Sender<Entry>,
+    metrics: Arc<Metrics>,
+    geo: Option<Arc<GeoDb>>,
+}
+
+impl AccessLog {
+    pub fn new(
+        config: Arc<ArcSwap<Config>>,
+        metrics: Arc<Metrics>,
+        geo: Option<Arc<GeoDb>>,
+    ) -> Arc<Self> {
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(write_loop(rx));
+        Arc::new(AccessLog {
+            config,
+            tx,
+            metrics,
+            geo,
+        })
+    }
+
+    // Head lido: guarda o que a linha vai precisar (o request some antes do fim)
+    pub fn begin(&self, access: &mut Access, req: &Request) {
+        let config = self.config.load();
+        if config.access_log.path.is_none() {
+            return;
+        }
+        let mut headers = Vec::new();
+        let _ = walk(&config.access_log.format, |piece| {
+            if let Piece::Var(name) = piece
+                && let Some(header) = name.strip_prefix("http_")
+            {
+                let header = header.replace('_', "-");
+                if let Some(value) = req.header(&header) {
+                    headers.push((header, value.to_string()));
+                }
+            }
+        });
+        let request_id = req
+            .header("X-Request-Id")
+            .filter(|id| {
+                !id.is_empty()
+                    && id.len() <= MAX_REQUEST_ID
+                    && id
+                        .bytes()
+                        .all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b))
+            })
+            .map(String::from)
+            .unwrap_or_else(|| {
+                let mut raw = [0u8; 16];
+                let _ = getrandom::getrandom(&mut raw);
+                to_hex(&raw)
+            });
+        *access = Access {
+            started: Some(Instant::now()),
+            method: req.method.clone(),
+            uri: req.path.clone(),
+            protocol: req.version.clone(),
+            host: req.header("Host").unwrap_or_default().to_string(),
+            headers,
+            request_id,
+            verdict: "",
+            rule: None,
+        };
+    }
+
+    pub fn record(&self, access: &Access, ip: IpAddr, sent: Sent) {
+        let Some(started) = access.started else {
+            return;
+        };
+        let config = self.config.load();
+        let Some(path) = &config.access_log.path else {
+            return;
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut line = String::new();
+        let _ = walk(&config.access_log.format, |piece| match piece {
+            Piece::Text(text) => line.push_str(text),
+            Piece::Var(name) => {
+                let value = match name {
+                    "remote_addr" => ip.to_string(),
+                    "host" => access.host.clone(),
+                    "request" => format!("{} {} {}", access.method, access.uri, access.protocol),
+                    "request_method" => access.method.clone(),
+                    "request_uri" => access.uri.clone(),
+                    "uri" => access.uri.split('?').next().unwrap_or_default().to_string(),
+                    "args" => access
+                        .uri
+                        .split_once('?')
+                        .map(|(_, q)| q.to_string())
+                        .unwrap_or_default(),
+                    "server_protocol" => access.protocol.clone(),
+                    "status" => sent.status.map(|s| s.to_string()).unwrap_or_default(),
+                    "bytes_sent" => sent.bytes.to_string(),
+                    "request_time" => format!("{:.3}", started.elapsed().as_secs_f64()),
+                    "time_local" => time_local(now.as_secs()),
+                    "time_iso8601" => time_iso8601(now.as_secs()),
+                    "msec" => format!("{}.{:03}", now.as_secs(), now.subsec_millis()),
+                    "verdict" => access.verdict.to_string(),
+                    "rule" => access.rule.map(|r| r.to_string()).unwrap_or_default(),
+                    "country" => self
+                        .geo
+                        .as_ref()
+                        .and_then(|geo| geo.lookup(ip))
+                        .map(|origin| origin.country().to_string())
+                        .unwrap_or_default(),
+                    "request_id" => access.request_id.clone(),
+                    other => other
+                        .strip_prefix("http_")
+                        .and_then(|h| {
+                            let h = h.replace('_', "-");
+                            access.headers.iter().find(|(k, _)| *k == h)
+                        })
+                        .map(|(_, v)| v.clone())
+                        .unwrap_or_default(),
+                };
+                escape(&mut line, &value);
+            }
+        });
+        line.push('\n');
+        let entry = Entry {
+            line,
+            path: path.clone(),
+        };
+        if self.tx.try_send(entry).is_err() {
+            self.metrics.inc("oblivion_access_log_dropped_total", &[]);
+        }
+    }
+}
+
+// Como o nginx: vazio vira "-", aspas, barra e bytes de controle/não-ASCII
+// viram \xHH. Nada do cliente quebra a linha ou fecha o campo entre aspas
+fn escape(out: &mut String, value: &str) {
+    if value.is_empty() {
+        out.push('-');
+        return;
+    }
+    for b in value.bytes() {
+        if b == b'"' || b == b'\\' || !(0x20..0x7f).contains(&b) {
+            let _ = write!(out, "\\x{:02X}", b);
+        } else {
+            out.push(b as char);
+        }
+    }
+}
+
+// (ano, mês, dia) de dias desde 1970-01-01 (algoritmo civil_from_days)
+fn civil(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn split(secs: u64) -> ((i64, u32, u32), u64, u64, u64) {
+    let secs_of_day = secs % 86_400;
+    (
+        civil((secs / 86_400) as i64),
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+    )
+}
+
+// 16/Oct/2026:10:00:00 +0000 (sempre UTC)
+fn time_local(secs: u64) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let ((year, month, day), h, m, s) = split(secs);
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        h,
+        m,
+        s
+    )
+}
+
+fn time_iso8601(secs: u64) -> String {
+    let ((year, month, day), h, m, s) = split(secs);
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}+00:00",
+        year, month, day, h, m, s
+    )
+}
+
+async fn write_loop(mut rx: mpsc::Receiver<Entry>) {
+    let mut file = Appender::new("access log");
+    while let Some(entry) = rx.recv().await {
+        file.write(&entry.path, &entry.line).await;
+    }
+}
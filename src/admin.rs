@@ -0,0 +1,571 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info, warn};
+
+use crate::bans::BanList;
+use crate::campaigns::Campaigns;
+use crate::capture::{Capture, CaptureFilter};
+use crate::challenge::unix_now;
+use crate::config::AdminConfig;
+use crate::ddos::DdosDetector;
+use arc_swap::ArcSwap;
+
+use crate::engine::WafEngine;
+use crate::error::Error;
+use crate::geo::{AccessList, GeoDb};
+use crate::http::{parse_urlencoded, Request};
+use crate::logging::LogControl;
+use crate::metrics::Metrics;
+use crate::reload::Reloader;
+use crate::shield::Shield;
+use crate::temp_rules::{NewRule, TempRules, MAX_TTL};
+use crate::tls::certificate_names;
+
+// Só localhost: limites fixos bastam
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REQUEST_SIZE: usize = 8192;
+const MIN_TOKEN: usize = 16;
+const TOP_RULES: usize = 10;
+
+pub struct Admin {
+    pub shield: Arc<Shield>,
+    pub engine: Arc<ArcSwap<WafEngine>>,
+    pub reloader: Reloader,
+    pub metrics: Arc<Metrics>,
+    pub capture: Arc<Capture>,
+    pub logging: Arc<LogControl>,
+    pub bans: Arc<BanList>,
+    pub ddos: Arc<DdosDetector>,
+    pub campaigns: Arc<Campaigns>,
+    pub temp_rules: Arc<TempRules>,
+    pub token: Option<Vec<u8>>,
+    pub allow: AccessList,
+    pub geo: Option<Arc<GeoDb>>,
+}
+
+pub fn load_token(config: &AdminConfig) -> Result<Option<Vec<u8>>, Error> {
+    let Some(path) = &config.token_file else {
+        return Ok(None);
+    };
+    let raw = std::fs::read(path)
+        .map_err(|e| Error::Config(format!("admin.token_file '{}' ilegível: {}", path, e)))?;
+    let token = raw.trim_ascii().to_vec();
+    if token.len() < MIN_TOKEN || !token.iter().all(u8::is_ascii_graphic) {
+        return Err(Error::Config(format!(
+            "admin.token_file '{}': token precisa de pelo menos {} caracteres visíveis",
+            path, MIN_TOKEN
+        )));
+    }
+    Ok(Some(token))
+}
+
+// Comparação em tempo constante: a latência não entrega quantos bytes bateram
+fn authorized(req: &Request, token: Option<&[u8]>) -> bool {
+    let Some(token) = token else {
+        return true;
+    };
+    let Some(given) = req
+        .header("Authorization")
+        .and_then(|h| h.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+    let given = given.trim().as_bytes();
+    given.len() == token.len() && given.iter().zip(token).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+pub async fn serve(
+    addr: &str,
+    admin: Arc<Admin>,
+    tls: Option<Arc<ServerConfig>>,
+) -> Result<(), Error> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|e| Error::Bind(addr.to_string(), e))?;
+    info!(tls = tls.is_some(), "Admin API listening on {}", addr);
+    let acceptor = tls.map(TlsAcceptor::from);
+
+    loop {
+        let (stream, peer_addr) = match listener.accept().await {
+            Ok(s) => s,
+            Err(e) => {
+                debug!("Admin accept error: {}", e);
+                continue;
+            }
+        };
+        debug!("Admin connection from {}", peer_addr);
+
+        let admin = admin.clone();
+        let acceptor = acceptor.clone();
+        tokio::spawn(async move {
+            let Some(acceptor) = acceptor else {
+                handle(stream, peer_addr, &[], admin).await;
+                return;
+            };
+            match timeout(REQUEST_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => {
+                    let names = stream
+                        .get_ref()
+                        .1
+                        .peer_certificates()
+                        .and_then(|c| c.first())
+                        .map(|cert| certificate_names(&cert.0))
+                        .unwrap_or_default();
+                    handle(stream, peer_addr, &names, admin).await;
+                }
+                Ok(Err(e)) => debug!("Admin TLS handshake failed from {}: {}", peer_addr, e),
+                Err(_) => debug!("Admin TLS handshake timed out from {}", peer_addr),
+            }
+        });
+    }
+}
+
+async fn handle<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    peer_addr: SocketAddr,
+    identities: &[String],
+    admin: Arc<Admin>,
+) {
+    let mut accumulator: Vec<u8> = Vec::new();
+    let mut buffer = [0u8; 1024];
+
+    loop {
+        let n = match timeout(REQUEST_TIMEOUT, stream.read(&mut buffer)).await {
+            Ok(Ok(0)) | Err(_) | Ok(Err(_)) => return,
+            Ok(Ok(n)) => n,
+        };
+        if accumulator.len() + n > MAX_REQUEST_SIZE {
+            return;
+        }
+        accumulator.extend_from_slice(&buffer[..n]);
+        if accumulator.windows(4).any(|w| w == b"\r\n\r\n") {
+            break;
+        }
+    }
+
+    let raw = String::from_utf8_lossy(&accumulator).to_string();
+    let (status, body, json) = match Request::parse(&raw) {
+        Ok(req) => {
+            // Accept: application/json: listagens viram objetos; o resto, {ok, message}
+            let json = req
+                .header("Accept")
+                .is_some_and(|a| a.contains("application/json"));
+            // Origem primeiro (rede, país/ASN, certificado), token depois
+            let allowed = admin.allow.is_empty()
+                || admin
+                    .allow
+                    .matches(peer_addr.ip(), identities, admin.geo.as_deref());
+            let authorized = allowed && authorized(&req, admin.token.as_deref());
+            if !allowed {
+                warn!(peer = %peer_addr, identities = ?identities, method = %req.method, path = req.path_only(), "Admin request from client outside admin.allow");
+                admin.metrics.inc("oblivion_admin_forbidden_total", &[]);
+            } else if !authorized {
+                warn!(peer = %peer_addr, method = %req.method, path = req.path_only(), "Admin request without valid token");
+                admin.metrics.inc("oblivion_admin_unauthorized_total", &[]);
+            }
+            let native = (authorized && json)
+                .then(|| route_json(&req, &admin))
+                .flatten();
+            let (status, body) = match native {
+                Some((status, value)) => (status, value.to_string() + "\n"),
+                None => {
+                    let (status, text) = if !allowed {
+                        ("403 Forbidden", "client not allowed\n".to_string())
+                    } else if authorized {
+                        route(&req, peer_addr, &admin)
+                    } else {
+                        ("401 Unauthorized", "missing or invalid token\n".to_string())
+                    };
+                    if json {
+                        let ok = status.starts_with('2');
+                        let value = json!({"ok": ok, "message": text.trim_end()});
+                        (status, value.to_string() + "\n")
+                    } else {
+                        (status, text)
+                    }
+                }
+            };
+            (status, body, json)
+        }
+        Err(e) => ("400 Bad Request", format!("{}\n", e), false),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        if json {
+            "application/json"
+        } else {
+            "text/plain"
+        },
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    // Com TLS manda o close_notify: o cliente distingue fim de resposta de corte
+    let _ = stream.shutdown().await;
+}
+
+// (id, matches, categoria, descrição) das regras que mais casaram desde o start
+fn top_rules(req: &Request, admin: &Admin) -> Vec<(String, u64, &'static str, String)> {
+    let query = parse_urlencoded(req.query().unwrap_or(""));
+    let limit = query
+        .iter()
+        .find(|(k, _)| k == "limit")
+        .and_then(|(_, v)| v.parse::<usize>().ok())
+        .unwrap_or(TOP_RULES);
+    let engine = admin.engine.load();
+    admin
+        .metrics
+        .totals_by("oblivion_rule_matches_total", "rule")
+        .into_iter()
+        .take(limit)
+        .map(|(id, matches)| {
+            let rule = engine.rules().rules.iter().find(|r| r.id.to_string() == id);
+            (
+                id,
+                matches,
+                rule.map_or("-", |r| r.category.label()),
+                rule.map_or_else(|| "-".to_string(), |r| r.description()),
+            )
+        })
+        .collect()
+}
+
+// Versão JSON das leituras; None = a resposta em texto vale, embrulhada
+fn route_json(req: &Request, admin: &Admin) -> Option<(&'static str, Value)> {
+    let body = match (req.method.as_str(), req.path_only()) {
+        ("GET", "/status") => json!({"under_attack": admin.shield.under_attack()}),
+        ("GET", "/bans") => Value::from_iter(admin.bans.list().into_iter().map(|(key, remaining)| {
+            json!({"key": key.to_string(), "remaining_secs": remaining.as_secs()})
+        })),
+        ("GET", "/rules") => {
+            let query = parse_urlencoded(req.query().unwrap_or(""));
+            let tag = query.iter().find(|(k, _)| k == "tag").map(|(_, v)| v);
+            let engine = admin.engine.load();
+            let rules = engine.rules();
+            Value::from_iter(
+                rules
+                    .rules
+                    .iter()
+                    .filter(|rule| tag.is_none_or(|t| rule.tags.contains(t)))
+                    .map(|rule| {
+                        json!({
+                            "id": rule.id,
+                            "category": rule.category.label(),
+                            "action": rules.action_for(rule).label(),
+                            "tags": rule.tags,
+                            "severity": rule.severity.map(|s| s.label()),
+                            "score": rule.anomaly_score(),
+                            "description": rule.description(),
+                        })
+                    }),
+            )
+        }
+        ("GET", "/stats/top-rules") => Value::from_iter(top_rules(req, admin).into_iter().map(
+            |(id, matches, category, description)| {
+                json!({"id": id, "matches": matches, "category": category, "description": description})
+            },
+        )),
+        _ => return None,
+    };
+    Some(("200 OK", body))
+}
+
+fn route(req: &Request, peer_addr: SocketAddr, admin: &Admin) -> (&'static str, String) {
+    match (req.method.as_str(), req.path_only()) {
+        ("GET", "/status") => (
+            "200 OK",
+            format!("under_attack={}\n", admin.shield.under_attack()),
+        ),
+        ("POST", "/under-attack/on") => {
+            admin.shield.set_under_attack(true);
+            ("200 OK", "under_attack=true\n".to_string())
+        }
+        ("POST", "/under-attack/off") => {
+            admin.shield.set_under_attack(false);
+            ("200 OK", "under_attack=false\n".to_string())
+        }
+        ("GET", "/capture") => match admin.capture.status() {
+            Some(filter) => ("200 OK", format!("{:?}\n", filter)),
+            None => ("200 OK", "inactive\n".to_string()),
+        },
+        ("POST", "/capture") => {
+            let query = parse_urlencoded(req.query().unwrap_or(""));
+            let get = |name: &str| query.iter().find(|(k, _)| k == name).map(|(_, v)| v);
+            let remaining = match get("count").map(|c| c.parse::<usize>()) {
+                None => 10,
+                Some(Ok(n)) if n > 0 => n,
+                Some(_) => return ("400 Bad Request", "invalid count\n".to_string()),
+            };
+            let ip = match get("ip").map(|ip| ip.parse()) {
+                None => None,
+                Some(Ok(ip)) => Some(ip),
+                Some(Err(_)) => return ("400 Bad Request", "invalid ip\n".to_string()),
+            };
+            let filter = CaptureFilter {
+                remaining,
+                ip,
+                path_prefix: get("path").cloned(),
+            };
+            let body = format!("{:?}\n", filter);
+            admin.capture.start(filter);
+            ("200 OK", body)
+        }
+        ("DELETE", "/capture") => {
+            admin.capture.stop();
+            ("200 OK", "inactive\n".to_string())
+        }
+        ("GET", "/log-level") => ("200 OK", format!("{}\n", admin.logging.current())),
+        ("POST", "/log-level") => {
+            let query = parse_urlencoded(req.query().unwrap_or(""));
+            let get = |name: &str| query.iter().find(|(k, _)| k == name).map(|(_, v)| v);
+            let Some(filter) = get("filter") else {
+                return ("400 Bad Request", "missing filter\n".to_string());
+            };
+            let ttl = match get("ttl").map(|t| t.parse::<u64>()) {
+                None | Some(Ok(0)) => None,
+                Some(Ok(secs)) => Some(Duration::from_secs(secs)),
+                Some(Err(_)) => return ("400 Bad Request", "invalid ttl\n".to_string()),
+            };
+            match admin.logging.set(filter, ttl) {
+                Ok(current) => ("200 OK", format!("{}\n", current)),
+                Err(e) => ("400 Bad Request", format!("invalid filter: {}\n", e)),
+            }
+        }
+        ("DELETE", "/log-level") => ("200 OK", format!("{}\n", admin.logging.reset())),
+        ("GET", "/bans") => {
+            let mut out = String::new();
+            for (key, remaining) in admin.bans.list() {
+                out.push_str(&format!("{}\t{}s\n", key, remaining.as_secs()));
+            }
+            ("200 OK", out)
+        }
+        ("POST", "/bans") | ("DELETE", "/bans") => {
+            let query = parse_urlencoded(req.query().unwrap_or(""));
+            let get = |name: &str| query.iter().find(|(k, _)| k == name).map(|(_, v)| v);
+            let ip = match get("ip").map(|ip| ip.parse()) {
+                Some(Ok(ip)) => ip,
+                None => return ("400 Bad Request", "missing ip\n".to_string()),
+                Some(Err(_)) => return ("400 Bad Request", "invalid ip\n".to_string()),
+            };
+            if req.method == "DELETE" {
+                return match admin.bans.unban(ip) {
+                    true => ("200 OK", "unbanned\n".to_string()),
+                    false => ("404 Not Found", "not banned\n".to_string()),
+                };
+            }
+            let ttl = match get("ttl").map(|t| t.parse::<u64>()) {
+                None => Duration::from_secs(3600),
+                Some(Ok(secs)) if secs > 0 => Duration::from_secs(secs),
+                Some(_) => return ("400 Bad Request", "invalid ttl\n".to_string()),
+            };
+            match admin.bans.ban(ip, ttl) {
+                true => ("200 OK", format!("banned for {}s\n", ttl.as_secs())),
+                false => ("503 Service Unavailable", "ban list full\n".to_string()),
+            }
+        }
+        ("GET", "/ddos") => {
+            let mut out = String::new();
+            for m in admin.ddos.list() {
+                out.push_str(&format!(
+                    "{:016x}\t{}\t{}s\t{}\t{}\n",
+                    m.id,
+                    m.action.label(),
+                    m.remaining.as_secs(),
+                    m.hits,
+                    m.label
+                ));
+            }
+            ("200 OK", out)
+        }
+        ("DELETE", "/ddos") => {
+            let query = parse_urlencoded(req.query().unwrap_or(""));
+            let id = query.iter().find(|(k, _)| k == "id").map(|(_, v)| v);
+            let id = match id.map(|id| u64::from_str_radix(id, 16)) {
+                Some(Ok(id)) => id,
+                None => return ("400 Bad Request", "missing id\n".to_string()),
+                Some(Err(_)) => return ("400 Bad Request", "invalid id\n".to_string()),
+            };
+            match admin.ddos.lift(id) {
+                true => ("200 OK", "lifted\n".to_string()),
+                false => ("404 Not Found", "no such mitigation\n".to_string()),
+            }
+        }
+        ("GET", "/campaigns") => {
+            let query = parse_urlencoded(req.query().unwrap_or(""));
+            let limit = query
+                .iter()
+                .find(|(k, _)| k == "limit")
+                .and_then(|(_, v)| v.parse::<usize>().ok())
+                .unwrap_or(usize::MAX);
+            let mut out = String::new();
+            for c in admin.campaigns.list().into_iter().take(limit) {
+                out.push_str(&format!(
+                    "{:016x}\t{}\t{}\t{}{}\t{}\t{}\t{}\t{}\t{:?}\n",
+                    c.id,
+                    c.rule,
+                    c.events,
+                    c.ips,
+                    if c.ips_capped { "+" } else { "" },
+                    c.first_seen,
+                    c.last_seen,
+                    c.field,
+                    c.paths.join(","),
+                    c.sample
+                ));
+            }
+            ("200 OK", out)
+        }
+        ("DELETE", "/campaigns") => (
+            "200 OK",
+            format!("{} campaign(s) cleared\n", admin.campaigns.clear()),
+        ),
+        ("GET", "/temp-rules") => {
+            let now = unix_now();
+            let mut out = String::new();
+            for r in admin.temp_rules.list() {
+                out.push_str(&format!(
+                    "{}\t{}s\t{}\t{}\t{}\t{}\t{:?}\n",
+                    r.id,
+                    r.expires_at.saturating_sub(now),
+                    r.path,
+                    r.param.as_deref().unwrap_or("-"),
+                    r.regex.as_deref().unwrap_or("-"),
+                    r.author,
+                    r.reason
+                ));
+            }
+            ("200 OK", out)
+        }
+        ("POST", "/temp-rules") | ("DELETE", "/temp-rules") => {
+            let query = parse_urlencoded(req.query().unwrap_or(""));
+            let get = |name: &str| {
+                query
+                    .iter()
+                    .find(|(k, _)| k == name)
+                    .map(|(_, v)| v.clone())
+                    .filter(|v| !v.is_empty())
+            };
+            // Trilha de auditoria: quem mexeu é obrigatório
+            let Some(author) = get("author") else {
+                return ("400 Bad Request", "missing author\n".to_string());
+            };
+            if req.method == "DELETE" {
+                let id = match get("id").map(|id| id.parse::<u64>()) {
+                    Some(Ok(id)) => id,
+                    None => return ("400 Bad Request", "missing id\n".to_string()),
+                    Some(Err(_)) => return ("400 Bad Request", "invalid id\n".to_string()),
+                };
+                return match admin.temp_rules.remove(id, &author, peer_addr) {
+                    Ok(true) => ("200 OK", "removed\n".to_string()),
+                    Ok(false) => ("404 Not Found", "no such temporary rule\n".to_string()),
+                    Err(e) => ("500 Internal Server Error", format!("{}\n", e)),
+                };
+            }
+            let Some(path) = get("path") else {
+                return ("400 Bad Request", "missing path\n".to_string());
+            };
+            let ttl = match get("ttl").map(|t| t.parse::<u64>()) {
+                None => Duration::from_secs(3600),
+                Some(Ok(secs)) if secs > 0 && secs <= MAX_TTL.as_secs() => {
+                    Duration::from_secs(secs)
+                }
+                Some(_) => {
+                    return (
+                        "400 Bad Request",
+                        format!("ttl must be 1..={}\n", MAX_TTL.as_secs()),
+                    )
+                }
+            };
+            let new = NewRule {
+                path,
+                param: get("param"),
+                regex: get("regex"),
+                ttl,
+                author,
+                reason: get("reason").unwrap_or_else(|| "incident".to_string()),
+            };
+            match admin.temp_rules.add(new, peer_addr) {
+                Ok(rule) => (
+                    "200 OK",
+                    format!("added {} for {}s\n", rule.id, ttl.as_secs()),
+                ),
+                Err(e) => ("400 Bad Request", format!("{}\n", e)),
+            }
+        }
+        ("POST", "/reload") => {
+            let query = parse_urlencoded(req.query().unwrap_or(""));
+            let dry_run = query
+                .iter()
+                .any(|(k, v)| k == "dry_run" && matches!(v.as_str(), "" | "1" | "true"));
+            match admin.reloader.reload(dry_run) {
+                Ok(changes) if changes.is_empty() => ("200 OK", "no changes\n".to_string()),
+                Ok(changes) => {
+                    let mut out = if dry_run {
+                        "dry run, not applied:\n"
+                    } else {
+                        "applied:\n"
+                    }
+                    .to_string();
+                    for change in changes {
+                        out.push_str(&format!("  {}\n", change));
+                    }
+                    ("200 OK", out)
+                }
+                Err(e) => (
+                    "422 Unprocessable Entity",
+                    format!("reload rejected: {}\n", e),
+                ),
+            }
+        }
+        ("GET", "/metrics") => {
+            let mut out = admin.metrics.render();
+            out.push_str(&format!(
+                "oblivion_log_dropped_total {}\n",
+                admin.logging.dropped()
+            ));
+            ("200 OK", out)
+        }
+        ("GET", "/rules") => {
+            let query = parse_urlencoded(req.query().unwrap_or(""));
+            let tag = query.iter().find(|(k, _)| k == "tag").map(|(_, v)| v);
+            let engine = admin.engine.load();
+            let rules = engine.rules();
+            let mut out = String::new();
+            for rule in &rules.rules {
+                if tag.is_some_and(|t| !rule.tags.contains(t)) {
+                    continue;
+                }
+                out.push_str(&format!(
+                    "{}\t{}\t{}\t{}\t{}\n",
+                    rule.id,
+                    rule.category.label(),
+                    rules.action_for(rule).label(),
+                    rule.tags.join(","),
+                    rule.description()
+                ));
+            }
+            ("200 OK", out)
+        }
+        ("GET", "/stats/top-rules") => {
+            let mut out = String::new();
+            for (id, matches, category, description) in top_rules(req, admin) {
+                out.push_str(&format!(
+                    "{}\t{}\t{}\t{}\n",
+                    id, matches, category, description
+                ));
+            }
+            ("200 OK", out)
+        }
+        _ => ("404 Not Found", "unknown admin endpoint\n".to_string()),
+    }
+}
@@ -0,0 +1,40 @@
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tracing::warn;
+
+// Arquivo de log só de append, dono de uma task de escrita (access log, eventos).
+// Caminho novo (reload) reabre; append sobrevive a logrotate copytruncate
+pub struct Appender {
+    what: &'static str,
+    file: Option<(String, File)>,
+}
+
+impl Appender {
+    pub fn new(what: &'static str) -> Self {
+        Appender { what, file: None }
+    }
+
+    // Falha vira aviso e o arquivo é reaberto na próxima linha
+    pub async fn write(&mut self, path: &str, line: &str) {
+        if self.file.as_ref().is_none_or(|(open, _)| open != path) {
+            self.file = match OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .await
+            {
+                Ok(f) => Some((path.to_string(), f)),
+                Err(e) => {
+                    warn!(file = %path, error = %e, "Failed to open {}", self.what);
+                    None
+                }
+            };
+        }
+        if let Some((_, f)) = self.file.as_mut()
+            && let Err(e) = f.write_all(line.as_bytes()).await
+        {
+            warn!(file = %path, error = %e, "Failed to write {}", self.what);
+            self.file = None;
+        }
+    }
+}
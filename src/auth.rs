@@ -0,0 +1,420 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::challenge::{from_hex, unix_now};
+use crate::config::AuthConfig;
+use crate::error::Error;
+use crate::http::Request;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const MIN_SECRET: usize = 32;
+
+// O que a rota aceita como credencial (`auth: [jwt, api_key]`); basta uma
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mechanism {
+    // Rota aberta, mesmo com [auth] default
+    None,
+    // Certificado de cliente validado por listener.client_ca (CN/SAN DNS)
+    Mtls,
+    Jwt,
+    ApiKey,
+    // Só a presença do cookie de [auth] session_cookie; quem valida é o backend.
+    // Não tem principal, então nunca satisfaz uma rota com auth_principals
+    SessionPresent,
+}
+
+impl Mechanism {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Mechanism::None => "none",
+            Mechanism::Mtls => "mtls",
+            Mechanism::Jwt => "jwt",
+            Mechanism::ApiKey => "api_key",
+            Mechanism::SessionPresent => "session_present",
+        }
+    }
+}
+
+// 401 = nenhuma credencial aceita pela rota chegou válida; 403 = chegou, mas
+// de um principal fora de auth_principals
+#[derive(Debug)]
+pub enum AuthError {
+    Missing,
+    InvalidToken,
+    TokenExpired,
+    InvalidApiKey,
+    Forbidden,
+}
+
+impl AuthError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AuthError::Missing => "missing_credentials",
+            AuthError::InvalidToken => "invalid_token",
+            AuthError::TokenExpired => "token_expired",
+            AuthError::InvalidApiKey => "invalid_api_key",
+            AuthError::Forbidden => "principal_not_allowed",
+        }
+    }
+
+    // bearer: a rota aceita JWT, então o 401 diz como se autenticar
+    pub fn response(&self, bearer: bool) -> Vec<u8> {
+        let (status, challenge) = match self {
+            AuthError::Forbidden => ("403 Forbidden", None),
+            AuthError::Missing => ("401 Unauthorized", Some("Bearer realm=\"oblivion\"")),
+            AuthError::InvalidToken | AuthError::TokenExpired => (
+                "401 Unauthorized",
+                Some("Bearer realm=\"oblivion\", error=\"invalid_token\""),
+            ),
+            AuthError::InvalidApiKey => ("401 Unauthorized", None),
+        };
+        let challenge = challenge
+            .filter(|_| bearer)
+            .map(|c| format!("WWW-Authenticate: {}\r\n", c))
+            .unwrap_or_default();
+        let body = format!("{{\"error\":\"{}\"}}", self.code());
+        format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\n{}X-Oblivion-Error: {}\r\nCache-Control: no-store\r\nContent-Length: {}\r\n\r\n{}",
+            status,
+            challenge,
+            self.code(),
+            body.len(),
+            body
+        )
+        .into_bytes()
+    }
+}
+
+// Quem passou: mecanismo e principal (nome do certificado, sub do JWT, nome da chave)
+pub struct Granted {
+    pub mechanism: Mechanism,
+    pub principal: Option<String>,
+}
+
+enum Credential {
+    Absent,
+    Invalid(AuthError),
+    // Nomes do principal; sessão não tem nenhum
+    Valid(Vec<String>),
+}
+
+pub struct Authenticator {
+    jwt_secret: Option<Vec<u8>>,
+    // sha256 da chave -> nome
+    api_keys: HashMap<Vec<u8>, String>,
+}
+
+impl Authenticator {
+    pub fn new(config: &AuthConfig) -> Result<Arc<Self>, Error> {
+        let jwt_secret = match &config.jwt_secret_file {
+            Some(path) => {
+                let raw = std::fs::read(path).map_err(|e| {
+                    Error::Config(format!("auth.jwt_secret_file '{}' ilegível: {}", path, e))
+                })?;
+                let secret = raw.trim_ascii().to_vec();
+                if secret.len() < MIN_SECRET {
+                    return Err(Error::Config(format!(
+                        "auth.jwt_secret_file '{}': segredo curto demais (mínimo {} bytes)",
+                        path, MIN_SECRET
+                    )));
+                }
+                Some(secret)
+            }
+            None => None,
+        };
+        let mut api_keys = HashMap::new();
+        if let Some(path) = &config.api_keys_file {
+            let raw = std::fs::read_to_string(path).map_err(|e| {
+                Error::Config(format!("auth.api_keys_file '{}' ilegível: {}", path, e))
+            })?;
+            for (n, line) in raw.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let digest = line
+                    .split_once(char::is_whitespace)
+                    .and_then(|(name, hash)| {
+                        from_hex(&hash.trim().to_ascii_lowercase())
+                            .filter(|d| d.len() == 32)
+                            .map(|d| (name, d))
+                    });
+                let Some((name, digest)) = digest else {
+                    return Err(Error::Config(format!(
+                        "auth.api_keys_file '{}' linha {}: esperado \"<nome> <sha256 hex>\"",
+                        path,
+                        n + 1
+                    )));
+                };
+                api_keys.insert(digest, name.to_string());
+            }
+        }
+        Ok(Arc::new(Authenticator {
+            jwt_secret,
+            api_keys,
+        }))
+    }
+
+    pub fn api_keys(&self) -> usize {
+        self.api_keys.len()
+    }
+
+    // Ok(None) = rota sem exigência. Mecanismo sem configuração em [auth] nunca é satisfeito
+    pub fn check(
+        &self,
+        req: &Request,
+        client_names: &[String],
+        accept: &[Mechanism],
+        principals: &[String],
+        config: &AuthConfig,
+    ) -> Result<Option<Granted>, AuthError> {
+        if accept.is_empty() || accept.contains(&Mechanism::None) {
+            return Ok(None);
+        }
+        let mut invalid = None;
+        let mut forbidden = false;
+        for &mechanism in accept {
+            let credential = match mechanism {
+                Mechanism::None => continue,
+                Mechanism::Mtls if client_names.is_empty() => Credential::Absent,
+                Mechanism::Mtls => Credential::Valid(client_names.to_vec()),
+                Mechanism::Jwt => self.jwt(req, config),
+                Mechanism::ApiKey => self.api_key(req, config),
+                Mechanism::SessionPresent if !principals.is_empty() => continue,
+                Mechanism::SessionPresent => match &config.session_cookie {
+                    Some(name) if req.cookie(name).is_some_and(|v| !v.is_empty()) => {
+                        Credential::Valid(Vec::new())
+                    }
+                    _ => Credential::Absent,
+                },
+            };
+            match credential {
+                Credential::Absent => {}
+                Credential::Invalid(e) => {
+                    invalid.get_or_insert(e);
+                }
+                Credential::Valid(names)
+                    if principals.is_empty()
+                        || names
+                            .iter()
+                            .any(|n| principals.iter().any(|p| p.eq_ignore_ascii_case(n))) =>
+                {
+                    return Ok(Some(Granted {
+                        mechanism,
+                        principal: names.into_iter().next(),
+                    }));
+                }
+                Credential::Valid(_) => forbidden = true,
+            }
+        }
+        if forbidden {
+            return Err(AuthError::Forbidden);
+        }
+        Err(invalid.unwrap_or(AuthError::Missing))
+    }
+
+    fn api_key(&self, req: &Request, config: &AuthConfig) -> Credential {
+        if self.api_keys.is_empty() {
+            return Credential::Absent;
+        }
+        let Some(key) = req.header(&config.api_key_header).filter(|k| !k.is_empty()) else {
+            return Credential::Absent;
+        };
+        // Só o hash fica na memória e no arquivo; lookup pelo digest
+        let digest = Sha256::digest(key.trim().as_bytes());
+        match self.api_keys.get(digest.as_slice()) {
+            Some(name) => Credential::Valid(vec![name.clone()]),
+            None => Credential::Invalid(AuthError::InvalidApiKey),
+        }
+    }
+
+    // Só HS256: "alg" diferente (inclusive "none") é token inválido, não rebaixamento
+    fn jwt(&self, req: &Request, config: &AuthConfig) -> Credential {
+        let Some(secret) = &self.jwt_secret else {
+            return Credential::Absent;
+        };
+        let Some(token) = req
+            .header("Authorization")
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .map(str::trim)
+        else {
+            return Credential::Absent;
+        };
+        match verify_jwt(token, secret, config) {
+            Ok(names) => Credential::Valid(names),
+            Err(e) => Credential::Invalid(e),
+        }
+    }
+}
+
+fn verify_jwt(token: &str, secret: &[u8], config: &AuthConfig) -> Result<Vec<String>, AuthError> {
+    let decode = |part: &str| -> Result<Value, AuthError> {
+        let raw = URL_SAFE_NO_PAD
+            .decode(part)
+            .map_err(|_| AuthError::InvalidToken)?;
+        serde_json::from_slice(&raw).map_err(|_| AuthError::InvalidToken)
+    };
+    let mut parts = token.split('.');
+    let (Some(header), Some(payload), Some(signature), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(AuthError::InvalidToken);
+    };
+    if decode(header)?.get("alg").and_then(Value::as_str) != Some("HS256") {
+        return Err(AuthError::InvalidToken);
+    }
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| AuthError::InvalidToken)?;
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC aceita qualquer chave");
+    mac.update(header.as_bytes());
+    mac.update(b".");
+    mac.update(payload.as_bytes());
+    mac.verify_slice(&signature)
+        .map_err(|_| AuthError::InvalidToken)?;
+
+    let claims = decode(payload)?;
+    // NumericDate pode ter fração (RFC 7519 2); tipo errado é token inválido, não ausente
+    let date = |claim: &str| match claims.get(claim) {
+        None => Ok(None),
+        Some(v) => v.as_f64().map(Some).ok_or(AuthError::InvalidToken),
+    };
+    let now = unix_now() as f64;
+    let leeway = config.jwt_leeway as f64;
+    match date("exp")? {
+        Some(exp) if exp + leeway < now => return Err(AuthError::TokenExpired),
+        None if config.jwt_require_exp => return Err(AuthError::InvalidToken),
+        _ => {}
+    }
+    if date("nbf")?.is_some_and(|nbf| nbf > now + leeway) {
+        return Err(AuthError::InvalidToken);
+    }
+    if let Some(issuer) = &config.jwt_issuer
+        && claims.get("iss").and_then(Value::as_str) != Some(issuer.as_str())
+    {
+        return Err(AuthError::InvalidToken);
+    }
+    // aud pode ser string ou lista (RFC 7519 4.1.3)
+    if let Some(audience) = &config.jwt_audience {
+        let matches = match claims.get("aud") {
+            Some(Value::String(aud)) => aud == audience,
+            Some(Value::Array(list)) => list.iter().any(|a| a.as_str() == Some(audience)),
+            _ => false,
+        };
+        if !matches {
+            return Err(AuthError::InvalidToken);
+        }
+    }
+    Ok(claims
+        .get("sub")
+        .and_then(Value::as_str)
+        .map(String::from)
+        .into_iter()
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const SECRET: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+    fn token(alg: &str, claims: Value) -> String {
+        let encode = |v: Value| URL_SAFE_NO_PAD.encode(v.to_string());
+        let signing = format!(
+            "{}.{}",
+            encode(json!({"alg": alg, "typ": "JWT"})),
+            encode(claims)
+        );
+        let mut mac = HmacSha256::new_from_slice(SECRET).unwrap();
+        mac.update(signing.as_bytes());
+        let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+        format!("{}.{}", signing, signature)
+    }
+
+    fn verify(token: &str, config: &AuthConfig) -> Result<Vec<String>, &'static str> {
+        verify_jwt(token, SECRET, config).map_err(|e| e.code())
+    }
+
+    fn now() -> f64 {
+        unix_now() as f64
+    }
+
+    #[test]
+    fn only_hs256_is_accepted() {
+        let config = AuthConfig::default();
+        let claims = json!({"sub": "ana", "exp": now() + 60.0});
+        assert_eq!(
+            verify(&token("HS256", claims.clone()), &config),
+            Ok(vec!["ana".into()])
+        );
+        for alg in ["none", "HS512", "RS256"] {
+            assert_eq!(
+                verify(&token(alg, claims.clone()), &config),
+                Err("invalid_token")
+            );
+        }
+        let unsigned = token("HS256", claims);
+        let unsigned = &unsigned[..unsigned.rfind('.').unwrap() + 1];
+        assert_eq!(verify(unsigned, &config), Err("invalid_token"));
+    }
+
+    #[test]
+    fn audience_as_string_or_list() {
+        let config = AuthConfig {
+            jwt_audience: Some("api".into()),
+            ..AuthConfig::default()
+        };
+        let exp = now() + 60.0;
+        for (aud, ok) in [
+            (json!("api"), true),
+            (json!(["web", "api"]), true),
+            (json!("web"), false),
+            (json!(["web"]), false),
+            (Value::Null, false),
+        ] {
+            let result = verify(&token("HS256", json!({"aud": aud, "exp": exp})), &config);
+            assert_eq!(result.is_ok(), ok, "aud {}", aud);
+        }
+    }
+
+    #[test]
+    fn exp_and_nbf_with_leeway_and_fractions() {
+        let config = AuthConfig {
+            jwt_leeway: 30,
+            ..AuthConfig::default()
+        };
+        let check = |claims: Value| verify(&token("HS256", claims), &config).map(|_| ());
+        assert_eq!(check(json!({"exp": now() - 10.0})), Ok(()));
+        assert_eq!(check(json!({"exp": now() - 60.5})), Err("token_expired"));
+        assert_eq!(check(json!({"exp": now() + 0.5})), Ok(()));
+        assert_eq!(
+            check(json!({"exp": now() + 60.0, "nbf": now() + 10.0})),
+            Ok(())
+        );
+        assert_eq!(
+            check(json!({"exp": now() + 60.0, "nbf": now() + 60.5})),
+            Err("invalid_token")
+        );
+        assert_eq!(check(json!({"exp": "tomorrow"})), Err("invalid_token"));
+
+        // Sem exp: recusado por padrão, aceito com jwt_require_exp = false
+        assert_eq!(check(json!({"sub": "ana"})), Err("invalid_token"));
+        let lenient = AuthConfig {
+            jwt_require_exp: false,
+            ..AuthConfig::default()
+        };
+        assert!(verify(&token("HS256", json!({"sub": "ana"})), &lenient).is_ok());
+    }
+}
@@ -0,0 +1,168 @@
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder};
+use regex::RegexSet;
+
+use crate::engine::WafEngine;
+use crate::http::Request;
+use crate::rules::{Operator, Rule, Target, Transform};
+
+// Candidato à reescrita do engine: em vez de varrer regra por regra, cada par
+// alvo/cadeia vira um autômato Aho-Corasick (literais), um RegexSet e uma lista
+// de operadores restantes. Só existe para comparação contra o engine atual.
+struct Group {
+    target: Target,
+    chain: Vec<Transform>,
+    literals: Option<(AhoCorasick, Vec<usize>)>,
+    phrases: Option<(AhoCorasick, Vec<usize>)>,
+    regexes: Option<(RegexSet, Vec<usize>)>,
+    direct: Vec<usize>,
+}
+
+pub struct AutomatonEngine {
+    groups: Vec<Group>,
+    // Regras com exclusões (!ARGS:foo) rodam uma a uma pelo engine
+    filtered: Vec<usize>,
+    rule_count: usize,
+}
+
+fn automaton(patterns: &[String], case_insensitive: bool) -> Result<AhoCorasick, String> {
+    AhoCorasickBuilder::new()
+        .ascii_case_insensitive(case_insensitive)
+        .build(patterns)
+        .map_err(|e| e.to_string())
+}
+
+impl Group {
+    fn build(
+        target: Target,
+        chain: Vec<Transform>,
+        rules: &[(usize, &Rule)],
+    ) -> Result<Self, String> {
+        let mut literals = (Vec::new(), Vec::new());
+        let mut phrases = (Vec::new(), Vec::new());
+        let mut regexes = (Vec::new(), Vec::new());
+        let mut direct = Vec::new();
+
+        for (idx, rule) in rules {
+            match &rule.operator {
+                Operator::Contains(p) => {
+                    literals.0.push(p.clone());
+                    literals.1.push(*idx);
+                }
+                Operator::PhraseMatch(list) => {
+                    for p in list {
+                        phrases.0.push(p.clone());
+                        phrases.1.push(*idx);
+                    }
+                }
+                Operator::Regex(re) => {
+                    regexes.0.push(re.as_str().to_string());
+                    regexes.1.push(*idx);
+                }
+                _ => direct.push(*idx),
+            }
+        }
+
+        let regexes = if regexes.0.is_empty() {
+            None
+        } else {
+            match RegexSet::new(&regexes.0) {
+                Ok(set) => Some((set, regexes.1)),
+                // Conjunto grande demais para um autômato só: cai para regra por regra
+                Err(_) => {
+                    direct.extend(regexes.1);
+                    None
+                }
+            }
+        };
+        let literals = if literals.0.is_empty() {
+            None
+        } else {
+            Some((automaton(&literals.0, false)?, literals.1))
+        };
+        let phrases = if phrases.0.is_empty() {
+            None
+        } else {
+            Some((automaton(&phrases.0, true)?, phrases.1))
+        };
+
+        Ok(Group {
+            target,
+            chain,
+            literals,
+            phrases,
+            regexes,
+            direct,
+        })
+    }
+}
+
+impl AutomatonEngine {
+    pub fn new(rules: &[Rule]) -> Result<Self, String> {
+        let mut keys: Vec<(Target, Vec<Transform>)> = Vec::new();
+        let mut members: Vec<Vec<(usize, &Rule)>> = Vec::new();
+        let mut filtered = Vec::new();
+        for (idx, rule) in rules.iter().enumerate() {
+            if !rule.exclusions.is_empty() {
+                filtered.push(idx);
+                continue;
+            }
+            for target in &rule.targets {
+                let key = (target.clone(), rule.transforms.clone());
+                let slot = match keys.iter().position(|k| *k == key) {
+                    Some(slot) => slot,
+                    None => {
+                        keys.push(key);
+                        members.push(Vec::new());
+                        keys.len() - 1
+                    }
+                };
+                members[slot].push((idx, rule));
+            }
+        }
+
+        let groups = keys
+            .into_iter()
+            .zip(members)
+            .map(|((target, chain), rules)| Group::build(target, chain, &rules))
+            .collect::<Result<_, _>>()?;
+        Ok(AutomatonEngine {
+            groups,
+            filtered,
+            rule_count: rules.len(),
+        })
+    }
+
+    // matched[i] = a regra i (na ordem do RuleSet) casou
+    pub fn matches(&self, engine: &WafEngine, req: &Request) -> Vec<bool> {
+        let rules = &engine.rules().rules;
+        let mut matched = vec![false; self.rule_count];
+        for group in &self.groups {
+            for value in engine.target_values(req, &group.target, &group.chain) {
+                if let Some((ac, owners)) = &group.literals {
+                    for m in ac.find_overlapping_iter(&value) {
+                        matched[owners[m.pattern().as_usize()]] = true;
+                    }
+                }
+                if let Some((ac, owners)) = &group.phrases {
+                    for m in ac.find_overlapping_iter(&value) {
+                        matched[owners[m.pattern().as_usize()]] = true;
+                    }
+                }
+                if let Some((set, owners)) = &group.regexes {
+                    for i in set.matches(&value) {
+                        matched[owners[i]] = true;
+                    }
+                }
+                for idx in &group.direct {
+                    if !matched[*idx] && rules[*idx].operator.matches(&value) {
+                        matched[*idx] = true;
+                    }
+                }
+            }
+        }
+        for idx in &self.filtered {
+            matched[*idx] = engine.match_rule(req, &rules[*idx]);
+        }
+        matched
+    }
+}
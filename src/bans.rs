@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::{debug, info, warn};
+
+use crate::config::BansConfig;
+use crate::error::Error;
+use crate::events::{Event, Events};
+use crate::expiry::ExpiryMap;
+use crate::keying::client_key;
+use crate::metrics::Metrics;
+
+const BAN_CAPACITY: usize = 100_000;
+const BAN_GC_INTERVAL: Duration = Duration::from_secs(5);
+const NFT_TABLE: &str = "oblivion";
+
+// Bans longos vão também para um set do nftables: o kernel descarta os pacotes
+// antes do accept e expira o elemento sozinho (timeout do set)
+pub struct KernelFilter {
+    pub ports: Vec<u16>,
+    pub min_ttl: Duration,
+}
+
+pub struct BanList {
+    entries: ExpiryMap<IpAddr>,
+    // Bloqueios recentes por cliente: (contagem, início da janela)
+    strikes: Mutex<HashMap<IpAddr, (u32, Instant)>>,
+    v6_prefix: u8,
+    kernel: Option<KernelFilter>,
+    events: Arc<Events>,
+    metrics: Arc<Metrics>,
+}
+
+async fn nft(script: String) -> Result<(), String> {
+    let mut child = Command::new("nft")
+        .args(["-f", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("nft: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(script.as_bytes())
+            .await
+            .map_err(|e| format!("nft: {}", e))?;
+    }
+    let output = child
+        .wait_with_output()
+        .await
+        .map_err(|e| format!("nft: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "nft: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+impl KernelFilter {
+    // Recria a tabela do zero: nenhum ban sobrevive ao restart do processo
+    async fn install(&self) -> Result<(), Error> {
+        let script = format!(
+            "table inet {table} {{}}\n\
+             delete table inet {table}\n\
+             table inet {table} {{\n\
+             \tset banned4 {{ type ipv4_addr; flags interval, timeout; }}\n\
+             \tset banned6 {{ type ipv6_addr; flags interval, timeout; }}\n\
+             \tchain prerouting {{\n\
+             \t\ttype filter hook prerouting priority raw; policy accept;\n\
+             \t\tip saddr @banned4 tcp dport {{ {ports} }} drop\n\
+             \t\tip6 saddr @banned6 tcp dport {{ {ports} }} drop\n\
+             \t}}\n\
+             }}\n",
+            table = NFT_TABLE,
+            ports = self
+                .ports
+                .iter()
+                .map(u16::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        nft(script)
+            .await
+            .map_err(|e| Error::Config(format!("falha ao instalar o filtro nftables: {}", e)))
+    }
+
+    fn element(key: IpAddr, v6_prefix: u8) -> (&'static str, String) {
+        match key {
+            IpAddr::V4(v4) => ("banned4", v4.to_string()),
+            IpAddr::V6(v6) => ("banned6", format!("{}/{}", v6, v6_prefix.min(128))),
+        }
+    }
+}
+
+impl BanList {
+    pub async fn new(
+        v6_prefix: u8,
+        kernel: Option<KernelFilter>,
+        events: Arc<Events>,
+        metrics: Arc<Metrics>,
+    ) -> Result<Arc<Self>, Error> {
+        if let Some(filter) = &kernel {
+            filter.install().await?;
+            info!(ports = ?filter.ports, min_ttl = ?filter.min_ttl, "Kernel ban filter installed");
+        }
+
+        let bans = Arc::new(BanList {
+            entries: ExpiryMap::new(BAN_CAPACITY),
+            strikes: Mutex::new(HashMap::new()),
+            v6_prefix,
+            kernel,
+            events,
+            metrics,
+        });
+
+        // Acorda no vencimento mais próximo (ou a cada BAN_GC_INTERVAL, que
+        // pega bans novos mais curtos): cada passada só toca o que venceu
+        let gc = bans.clone();
+        tokio::spawn(async move {
+            loop {
+                let wake = gc.entries.next_expiry().map_or(BAN_GC_INTERVAL, |at| {
+                    at.saturating_duration_since(Instant::now())
+                        .min(BAN_GC_INTERVAL)
+                });
+                tokio::time::sleep(wake).await;
+                gc.expire();
+            }
+        });
+
+        Ok(bans)
+    }
+
+    fn expire(&self) {
+        let expired = self.entries.expire();
+        if expired > 0 {
+            debug!(expired, "Expired bans");
+            self.metrics
+                .add("oblivion_bans_expired_total", &[], expired as u64);
+        }
+    }
+
+    // Veredito Block conta contra o cliente; auto_ban_blocks na janela vira ban
+    pub fn strike(&self, ip: IpAddr, config: &BansConfig) -> bool {
+        if config.auto_ban_blocks == 0 {
+            return false;
+        }
+        let key = client_key(ip, self.v6_prefix);
+        let now = Instant::now();
+        {
+            let mut strikes = self.strikes.lock().unwrap();
+            if strikes.len() >= BAN_CAPACITY && !strikes.contains_key(&key) {
+                strikes.retain(|_, (_, start)| now.duration_since(*start) < config.auto_ban_window);
+                if strikes.len() >= BAN_CAPACITY {
+                    return false;
+                }
+            }
+            let entry = strikes.entry(key).or_insert((0, now));
+            if now.duration_since(entry.1) >= config.auto_ban_window {
+                *entry = (0, now);
+            }
+            entry.0 += 1;
+            if entry.0 < config.auto_ban_blocks {
+                return false;
+            }
+            strikes.remove(&key);
+        }
+        warn!(%key, blocks = config.auto_ban_blocks, window = ?config.auto_ban_window, "Repeated blocks: banning client");
+        self.metrics.inc("oblivion_auto_bans_total", &[]);
+        self.ban(ip, config.auto_ban_ttl)
+    }
+
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        let key = client_key(ip, self.v6_prefix);
+        self.entries.get(&key).is_some()
+    }
+
+    pub fn ban(&self, ip: IpAddr, ttl: Duration) -> bool {
+        let key = client_key(ip, self.v6_prefix);
+        if !self.entries.insert(key, Instant::now() + ttl) {
+            warn!(%key, "Ban list full, ban not recorded");
+            return false;
+        }
+        info!(%key, ?ttl, "Client banned");
+        self.metrics.inc("oblivion_bans_total", &[]);
+        self.events.emit(Event::Ban { ip: key, ttl });
+
+        if let Some(filter) = &self.kernel
+            && ttl >= filter.min_ttl
+        {
+            let (set, element) = KernelFilter::element(key, self.v6_prefix);
+            let script = format!(
+                "add element inet {} {} {{ {} timeout {}s }}\n",
+                NFT_TABLE,
+                set,
+                element,
+                ttl.as_secs()
+            );
+            tokio::spawn(async move {
+                if let Err(e) = nft(script).await {
+                    warn!(error = %e, "Failed to push ban to kernel filter");
+                }
+            });
+        }
+        true
+    }
+
+    pub fn unban(&self, ip: IpAddr) -> bool {
+        let key = client_key(ip, self.v6_prefix);
+        let removed = self.entries.remove(&key);
+        if removed {
+            info!(%key, "Client unbanned");
+            self.events.emit(Event::Unban { ip: key });
+            if self.kernel.is_some() {
+                let (set, element) = KernelFilter::element(key, self.v6_prefix);
+                let script = format!(
+                    "delete element inet {} {} {{ {} }}\n",
+                    NFT_TABLE, set, element
+                );
+                tokio::spawn(async move {
+                    // Ban curto nunca foi pro kernel: erro aqui é esperado
+                    if let Err(e) = nft(script).await {
+                        debug!(error = %e, "Kernel filter element not removed");
+                    }
+                });
+            }
+        }
+        removed
+    }
+
+    pub fn list(&self) -> Vec<(IpAddr, Duration)> {
+        let now = Instant::now();
+        let mut bans: Vec<(IpAddr, Duration)> = self
+            .entries
+            .live()
+            .into_iter()
+            .map(|(key, until)| (key, until.saturating_duration_since(now)))
+            .collect();
+        bans.sort_by_key(|(_, remaining)| std::cmp::Reverse(*remaining));
+        bans
+    }
+}
@@ -0,0 +1,343 @@
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use rustls::{Certificate, ClientConfig, OwnedTrustAnchor, PrivateKey, RootCertStore, ServerName};
+use rustls_pemfile::Item;
+
+// Mesmos códigos do oblivion (sysexits), mais 1 = a API recusou o pedido
+const EXIT_REJECTED: u8 = 1;
+const EXIT_USAGE: u8 = 64;
+const EXIT_UNAVAILABLE: u8 = 69;
+const EXIT_NOPERM: u8 = 77;
+
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Parser)]
+#[command(
+    name = "oblivionctl",
+    version,
+    about = "Client for the oblivion admin API"
+)]
+struct Cli {
+    /// Admin API address ([admin] addr); https://host:port when [admin] cert is set
+    #[arg(
+        long,
+        env = "OBLIVION_ADMIN",
+        value_name = "ADDR",
+        default_value = "127.0.0.1:9901"
+    )]
+    admin: String,
+    /// File with the admin token ([admin] token_file); OBLIVION_ADMIN_TOKEN also works
+    #[arg(long, env = "OBLIVION_ADMIN_TOKEN_FILE", value_name = "FILE")]
+    token_file: Option<String>,
+    /// PEM bundle with the CA of the admin certificate (https only; default = public roots)
+    #[arg(long, env = "OBLIVION_ADMIN_CA", value_name = "FILE")]
+    ca: Option<String>,
+    /// Client certificate (PEM) for [admin] client_ca / allow.identities
+    #[arg(
+        long,
+        env = "OBLIVION_ADMIN_CERT",
+        value_name = "FILE",
+        requires = "key"
+    )]
+    cert: Option<String>,
+    /// Private key (PEM, unencrypted) of --cert
+    #[arg(
+        long,
+        env = "OBLIVION_ADMIN_KEY",
+        value_name = "FILE",
+        requires = "cert"
+    )]
+    key: Option<String>,
+    /// Print the API response as JSON
+    #[arg(long)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Show whether under-attack mode is on
+    Status,
+    /// Turn under-attack mode on or off
+    UnderAttack {
+        #[arg(value_parser = ["on", "off"])]
+        state: String,
+    },
+    /// Ban a client IP (or its IPv6 prefix) for a while: 90s, 30m, 1h, 7d
+    Ban {
+        ip: String,
+        #[arg(default_value = "1h", value_parser = parse_duration)]
+        duration: u64,
+    },
+    /// Lift a ban
+    Unban { ip: String },
+    /// List active bans
+    Bans,
+    /// Rule set operations
+    #[command(subcommand)]
+    Rules(RulesCommand),
+    /// Counters from the running process
+    #[command(subcommand)]
+    Stats(StatsCommand),
+}
+
+#[derive(Subcommand)]
+enum RulesCommand {
+    /// Reload the rule files
+    Reload {
+        /// Only show what would change
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// List loaded rules
+    List {
+        #[arg(long)]
+        tag: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum StatsCommand {
+    /// Rules with the most matches since start
+    TopRules {
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+    },
+}
+
+// "90", "90s", "30m", "1h", "7d" -> segundos
+fn parse_duration(text: &str) -> Result<u64, String> {
+    let (number, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => text.split_at(i),
+        None => (text, "s"),
+    };
+    let scale = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return Err(format!("unknown unit '{}' (s, m, h, d)", unit)),
+    };
+    match number.parse::<u64>() {
+        Ok(n) if n > 0 => Ok(n * scale),
+        _ => Err(format!("invalid duration '{}'", text)),
+    }
+}
+
+fn encode(value: &str) -> String {
+    utf8_percent_encode(value, NON_ALPHANUMERIC).to_string()
+}
+
+// (método, path com query) de cada comando
+fn endpoint(command: &Command) -> (&'static str, String) {
+    match command {
+        Command::Status => ("GET", "/status".to_string()),
+        Command::UnderAttack { state } => ("POST", format!("/under-attack/{}", state)),
+        Command::Ban { ip, duration } => {
+            ("POST", format!("/bans?ip={}&ttl={}", encode(ip), duration))
+        }
+        Command::Unban { ip } => ("DELETE", format!("/bans?ip={}", encode(ip))),
+        Command::Bans => ("GET", "/bans".to_string()),
+        Command::Rules(RulesCommand::Reload { dry_run: true }) => {
+            ("POST", "/reload?dry_run=1".to_string())
+        }
+        Command::Rules(RulesCommand::Reload { dry_run: false }) => ("POST", "/reload".to_string()),
+        Command::Rules(RulesCommand::List { tag: Some(tag) }) => {
+            ("GET", format!("/rules?tag={}", encode(tag)))
+        }
+        Command::Rules(RulesCommand::List { tag: None }) => ("GET", "/rules".to_string()),
+        Command::Stats(StatsCommand::TopRules { limit }) => {
+            ("GET", format!("/stats/top-rules?limit={}", limit))
+        }
+    }
+}
+
+fn token(cli: &Cli) -> Result<Option<String>, String> {
+    if let Some(path) = &cli.token_file {
+        let raw = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        return Ok(Some(raw.trim().to_string()));
+    }
+    Ok(std::env::var("OBLIVION_ADMIN_TOKEN").ok())
+}
+
+// "https://host:porta" -> (true, "host:porta")
+fn split_scheme(admin: &str) -> (bool, &str) {
+    match admin.strip_prefix("https://") {
+        Some(addr) => (true, addr),
+        None => (false, admin.strip_prefix("http://").unwrap_or(admin)),
+    }
+}
+
+fn pem_certs(path: &str) -> Result<Vec<Vec<u8>>, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    let certs =
+        rustls_pemfile::certs(&mut BufReader::new(file)).map_err(|e| format!("{}: {}", path, e))?;
+    if certs.is_empty() {
+        return Err(format!("{}: no certificate", path));
+    }
+    Ok(certs)
+}
+
+fn pem_key(path: &str) -> Result<PrivateKey, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    rustls_pemfile::read_all(&mut BufReader::new(file))
+        .map_err(|e| format!("{}: {}", path, e))?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(der) | Item::RSAKey(der) | Item::ECKey(der) => Some(PrivateKey(der)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("{}: no unencrypted private key", path))
+}
+
+// None = admin em texto puro
+fn tls_config(cli: &Cli) -> Result<Option<Arc<ClientConfig>>, String> {
+    if !split_scheme(&cli.admin).0 {
+        return Ok(None);
+    }
+    let mut roots = RootCertStore::empty();
+    match &cli.ca {
+        Some(path) => {
+            for der in pem_certs(path)? {
+                roots
+                    .add(&Certificate(der))
+                    .map_err(|e| format!("{}: {}", path, e))?;
+            }
+        }
+        None => roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                ta.subject,
+                ta.spki,
+                ta.name_constraints,
+            )
+        })),
+    }
+    let builder = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots);
+    let config = match (&cli.cert, &cli.key) {
+        (Some(cert), Some(key)) => {
+            let chain = pem_certs(cert)?.into_iter().map(Certificate).collect();
+            builder
+                .with_client_auth_cert(chain, pem_key(key)?)
+                .map_err(|e| format!("{}: {}", cert, e))?
+        }
+        _ => builder.with_no_client_auth(),
+    };
+    Ok(Some(Arc::new(config)))
+}
+
+trait Stream: Read + Write {}
+
+impl<T: Read + Write> Stream for T {}
+
+// Um request por conexão, como o admin atende: (status, corpo)
+fn call(
+    cli: &Cli,
+    tls: Option<Arc<ClientConfig>>,
+    method: &str,
+    path: &str,
+    token: Option<&str>,
+) -> std::io::Result<(u16, String)> {
+    let (_, addr) = split_scheme(&cli.admin);
+    let tcp = TcpStream::connect(addr)?;
+    tcp.set_read_timeout(Some(IO_TIMEOUT))?;
+    tcp.set_write_timeout(Some(IO_TIMEOUT))?;
+    let mut stream: Box<dyn Stream> = match tls {
+        Some(config) => {
+            let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+            let name = ServerName::try_from(host.trim_start_matches('[').trim_end_matches(']'))
+                .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))?;
+            let connection =
+                rustls::ClientConnection::new(config, name).map_err(std::io::Error::other)?;
+            Box::new(rustls::StreamOwned::new(connection, tcp))
+        }
+        None => Box::new(tcp),
+    };
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: 0\r\nConnection: close\r\n",
+        method, path, addr
+    );
+    if let Some(token) = token {
+        request.push_str(&format!("Authorization: Bearer {}\r\n", token));
+    }
+    if cli.json {
+        request.push_str("Accept: application/json\r\n");
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes())?;
+
+    let mut response = Vec::new();
+    match stream.read_to_end(&mut response) {
+        // Servidor que fecha sem close_notify: o que chegou vale se veio inteiro
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof && !response.is_empty() => {}
+        result => {
+            result?;
+        }
+    }
+    let response = String::from_utf8_lossy(&response);
+    let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed response");
+    let (head, body) = response.split_once("\r\n\r\n").ok_or_else(invalid)?;
+    let status = head
+        .split(' ')
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(invalid)?;
+    Ok((status, body.to_string()))
+}
+
+fn main() -> ExitCode {
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) => {
+            let _ = e.print();
+            return ExitCode::from(if e.use_stderr() { EXIT_USAGE } else { 0 });
+        }
+    };
+    let token = match token(&cli) {
+        Ok(token) => token,
+        Err(e) => {
+            eprintln!("oblivionctl: token: {}", e);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+    let tls = match tls_config(&cli) {
+        Ok(tls) => tls,
+        Err(e) => {
+            eprintln!("oblivionctl: tls: {}", e);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+    let (method, path) = endpoint(&cli.command);
+    match call(&cli, tls, method, &path, token.as_deref()) {
+        Ok((status, body)) if (200..300).contains(&status) => {
+            print!("{}", body);
+            ExitCode::SUCCESS
+        }
+        Ok((status, body)) => {
+            // --json: o corpo de erro também é JSON, e vai para o stdout do script
+            if cli.json {
+                print!("{}", body);
+            } else {
+                eprint!("oblivionctl: {} {}: {}", method, path, body);
+            }
+            ExitCode::from(if status == 401 || status == 403 {
+                EXIT_NOPERM
+            } else {
+                EXIT_REJECTED
+            })
+        }
+        Err(e) => {
+            eprintln!("oblivionctl: {}: {}", cli.admin, e);
+            ExitCode::from(EXIT_UNAVAILABLE)
+        }
+    }
+}
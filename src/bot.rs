@@ -0,0 +1,360 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::http::Request;
+use crate::tls::HelloInfo;
+
+const HISTORY_LEN: usize = 16;
+const CLIENT_TTL: Duration = Duration::from_secs(600);
+const CLIENT_CAPACITY: usize = 100_000;
+
+// Humanos não clicam com desvio padrão de 5% do intervalo médio
+const MIN_INTERVALS: usize = 8;
+const REGULAR_CV: f64 = 0.05;
+const REGULAR_MAX_MEAN: f64 = 30.0;
+const SCAN_MIN_PATHS: usize = 12;
+const SCAN_ENTROPY: f64 = 0.99;
+const PAGES_WITHOUT_ASSETS: u32 = 10;
+
+// Headers que um navegador não troca entre requests; uma troca ainda pode ser
+// update ou ajuste do usuário, a partir da segunda é ferramenta rotacionando
+const STABLE_HEADERS: [(&str, &str, u32); 3] = [
+    ("User-Agent", "ua_churn", 35),
+    ("Accept-Language", "accept_language_churn", 20),
+    ("Sec-CH-UA-Platform", "platform_churn", 20),
+];
+const MIN_CHURN: u32 = 2;
+
+const ASSET_EXTENSIONS: &[&str] = &[
+    "css", "js", "mjs", "png", "jpg", "jpeg", "gif", "svg", "ico", "webp", "woff", "woff2",
+];
+
+#[derive(Debug, Default)]
+pub struct BotScore {
+    pub score: u32,
+    pub signals: Vec<&'static str>,
+}
+
+impl BotScore {
+    pub fn flag(&mut self, signal: &'static str, weight: u32) {
+        self.score = (self.score + weight).min(100);
+        self.signals.push(signal);
+    }
+}
+
+#[derive(PartialEq)]
+enum Browser {
+    Chromium,
+    Firefox,
+    Safari,
+}
+
+fn claimed_browser(user_agent: &str) -> Option<Browser> {
+    if user_agent.contains("Chrome/") || user_agent.contains("Chromium/") {
+        Some(Browser::Chromium)
+    } else if user_agent.contains("Firefox/") {
+        Some(Browser::Firefox)
+    } else if user_agent.contains("Safari/") && user_agent.contains("Version/") {
+        Some(Browser::Safari)
+    } else {
+        None
+    }
+}
+
+// O UA é só texto; TLS, ALPN e headers denunciam o cliente real
+fn check_consistency(req: &Request, hello: &HelloInfo, score: &mut BotScore) {
+    let Some(browser) = req.header("User-Agent").and_then(claimed_browser) else {
+        return;
+    };
+
+    // Sem TLS não há ClientHello para contradizer o UA
+    if !hello.plaintext {
+        if browser != Browser::Firefox && !hello.grease {
+            score.flag("ua_tls_mismatch", 30);
+        }
+        if !hello.alpn.iter().any(|p| p == "h2") {
+            score.flag("ua_alpn_mismatch", 20);
+        }
+    }
+    if req
+        .header_order
+        .first()
+        .is_some_and(|h| !h.eq_ignore_ascii_case("Host"))
+    {
+        score.flag("ua_header_order", 15);
+    }
+    if !req.has_header("Accept") || !req.has_header("Accept-Language") {
+        score.flag("ua_accept_mismatch", 15);
+    }
+    if browser == Browser::Chromium && !req.has_header("Sec-CH-UA") {
+        score.flag("ua_client_hints_missing", 15);
+    }
+}
+
+struct ClientHistory {
+    times: VecDeque<Instant>,
+    paths: VecDeque<String>,
+    pages: u32,
+    assets: u32,
+    asset_first: bool,
+}
+
+// Valor (hash) de cada STABLE_HEADERS no último request e quantas vezes mudou
+struct HeaderHistory {
+    last_seen: Instant,
+    values: [Option<u64>; STABLE_HEADERS.len()],
+    changes: [u32; STABLE_HEADERS.len()],
+}
+
+pub struct BotDetector {
+    clients: Mutex<HashMap<IpAddr, ClientHistory>>,
+    // Por cookie de sessão ou, sem ele, por IP + fingerprint do ClientHello:
+    // o mesmo cliente TLS, não todo mundo atrás do mesmo NAT
+    identities: Mutex<HashMap<u64, HeaderHistory>>,
+}
+
+fn is_asset(path: &str) -> bool {
+    path.rsplit_once('.')
+        .is_some_and(|(_, ext)| ASSET_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+// Entropia de Shannon normalizada (0 = sempre o mesmo path, 1 = todos distintos)
+fn path_entropy(paths: &VecDeque<String>) -> f64 {
+    if paths.len() < 2 {
+        return 0.0;
+    }
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for path in paths {
+        *counts.entry(path).or_default() += 1;
+    }
+    let n = paths.len() as f64;
+    let entropy: f64 = counts
+        .values()
+        .map(|c| {
+            let p = *c as f64 / n;
+            -p * p.log2()
+        })
+        .sum();
+    entropy / n.log2()
+}
+
+impl ClientHistory {
+    fn new() -> Self {
+        ClientHistory {
+            times: VecDeque::with_capacity(HISTORY_LEN),
+            paths: VecDeque::with_capacity(HISTORY_LEN),
+            pages: 0,
+            assets: 0,
+            asset_first: false,
+        }
+    }
+
+    fn last_seen(&self) -> Option<Instant> {
+        self.times.back().copied()
+    }
+
+    fn regular_intervals(&self) -> bool {
+        if self.times.len() <= MIN_INTERVALS {
+            return false;
+        }
+        let intervals: Vec<f64> = self
+            .times
+            .iter()
+            .zip(self.times.iter().skip(1))
+            .map(|(a, b)| b.duration_since(*a).as_secs_f64())
+            .collect();
+        let n = intervals.len() as f64;
+        let mean = intervals.iter().sum::<f64>() / n;
+        if mean <= 0.0 || mean > REGULAR_MAX_MEAN {
+            return false;
+        }
+        let variance = intervals.iter().map(|i| (i - mean).powi(2)).sum::<f64>() / n;
+        variance.sqrt() / mean < REGULAR_CV
+    }
+}
+
+impl BotDetector {
+    pub fn new() -> Arc<Self> {
+        Arc::new(BotDetector {
+            clients: Mutex::new(HashMap::new()),
+            identities: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn check_churn(
+        &self,
+        ip: IpAddr,
+        session: Option<&str>,
+        req: &Request,
+        hello: &HelloInfo,
+        score: &mut BotScore,
+    ) {
+        let mut hasher = DefaultHasher::new();
+        match session {
+            Some(id) => id.hash(&mut hasher),
+            None => (ip, hello.fingerprint).hash(&mut hasher),
+        }
+        let key = hasher.finish();
+        let now = Instant::now();
+        let mut identities = self.identities.lock().unwrap();
+        if identities.len() >= CLIENT_CAPACITY && !identities.contains_key(&key) {
+            identities.retain(|_, h| now.duration_since(h.last_seen) < CLIENT_TTL);
+            if identities.len() >= CLIENT_CAPACITY {
+                return;
+            }
+        }
+
+        let history = identities.entry(key).or_insert(HeaderHistory {
+            last_seen: now,
+            values: [None; STABLE_HEADERS.len()],
+            changes: [0; STABLE_HEADERS.len()],
+        });
+        if now.duration_since(history.last_seen) >= CLIENT_TTL {
+            history.values = [None; STABLE_HEADERS.len()];
+            history.changes = [0; STABLE_HEADERS.len()];
+        }
+        history.last_seen = now;
+        for (i, (header, signal, weight)) in STABLE_HEADERS.iter().enumerate() {
+            let value = req.header(header).map(|v| {
+                let mut hasher = DefaultHasher::new();
+                v.hash(&mut hasher);
+                hasher.finish()
+            });
+            if history.values[i].is_some() && value != history.values[i] {
+                history.changes[i] += 1;
+            }
+            history.values[i] = value;
+            if history.changes[i] >= MIN_CHURN {
+                score.flag(signal, *weight);
+            }
+        }
+    }
+
+    pub fn observe(
+        &self,
+        ip: IpAddr,
+        session: Option<&str>,
+        req: &Request,
+        hello: &HelloInfo,
+    ) -> BotScore {
+        let mut score = BotScore::default();
+        check_consistency(req, hello, &mut score);
+        self.check_churn(ip, session, req, hello, &mut score);
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+
+        if clients.len() >= CLIENT_CAPACITY && !clients.contains_key(&ip) {
+            clients.retain(|_, h| {
+                h.last_seen()
+                    .is_some_and(|t| now.duration_since(t) < CLIENT_TTL)
+            });
+            if clients.len() >= CLIENT_CAPACITY {
+                return score;
+            }
+        }
+
+        let history = clients.entry(ip).or_insert_with(ClientHistory::new);
+        if history
+            .last_seen()
+            .is_some_and(|t| now.duration_since(t) >= CLIENT_TTL)
+        {
+            *history = ClientHistory::new();
+        }
+
+        let path = req.path_only();
+        if is_asset(path) {
+            // Asset sem página antes e sem Referer: navegador não chega assim
+            if history.pages == 0 && history.assets == 0 && !req.has_header("Referer") {
+                history.asset_first = true;
+            }
+            history.assets += 1;
+        } else {
+            history.pages += 1;
+        }
+
+        if history.times.len() == HISTORY_LEN {
+            history.times.pop_front();
+            history.paths.pop_front();
+        }
+        history.times.push_back(now);
+        history.paths.push_back(path.to_string());
+
+        if history.regular_intervals() {
+            score.flag("regular_interval", 40);
+        }
+        if history.asset_first {
+            score.flag("asset_before_page", 15);
+        }
+        if history.assets == 0 && history.pages >= PAGES_WITHOUT_ASSETS {
+            score.flag("pages_without_assets", 20);
+        }
+        if history.paths.len() >= SCAN_MIN_PATHS
+            && history.assets == 0
+            && path_entropy(&history.paths) >= SCAN_ENTROPY
+        {
+            score.flag("path_scan", 25);
+        }
+        score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::h2server::http1_head;
+
+    const CHROME: &str = "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 \
+                          (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36";
+
+    // ClientHello de OpenSSL: sem GREASE, com h2
+    fn openssl_hello() -> HelloInfo {
+        HelloInfo {
+            alpn: vec!["h2".to_string()],
+            ..HelloInfo::default()
+        }
+    }
+
+    fn signals(req: &Request) -> Vec<&'static str> {
+        let mut score = BotScore::default();
+        check_consistency(req, &openssl_hello(), &mut score);
+        score.signals
+    }
+
+    #[test]
+    fn lowercase_user_agent_is_checked() {
+        let raw = format!(
+            "GET / HTTP/1.1\r\nHost: a\r\nuser-agent: {}\r\n\r\n",
+            CHROME
+        );
+        let signals = signals(&Request::parse(&raw).unwrap());
+        assert!(signals.contains(&"ua_tls_mismatch"), "{:?}", signals);
+        assert!(
+            signals.contains(&"ua_client_hints_missing"),
+            "{:?}",
+            signals
+        );
+    }
+
+    #[test]
+    fn h2_bridged_user_agent_is_checked() {
+        let (parts, ()) = http::Request::builder()
+            .method("GET")
+            .uri("https://a/page")
+            .header("user-agent", CHROME)
+            .header("accept", "text/html")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let mut head = http1_head(&parts);
+        head.extend_from_slice(b"\r\n");
+        let req = Request::parse(std::str::from_utf8(&head).unwrap()).unwrap();
+        let signals = signals(&req);
+        assert!(signals.contains(&"ua_tls_mismatch"), "{:?}", signals);
+        assert!(signals.contains(&"ua_accept_mismatch"), "{:?}", signals);
+        assert!(!signals.contains(&"ua_header_order"), "{:?}", signals);
+    }
+}
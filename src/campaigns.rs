@@ -0,0 +1,126 @@
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use tracing::info;
+
+use crate::challenge::unix_now;
+use crate::engine::MatchedData;
+use crate::metrics::Metrics;
+
+// Scan com payload aleatório não pode crescer a tabela sem limite: cheia, sai a
+// campanha parada há mais tempo
+const MAX_CAMPAIGNS: usize = 10_000;
+// Acima disso a campanha só conta eventos; IPs distintos ficam em "1000+"
+const MAX_IPS: usize = 1_000;
+const MAX_PATHS: usize = 8;
+
+struct Campaign {
+    rule: u32,
+    field: String,
+    sample: String,
+    first_seen: u64,
+    last_seen: u64,
+    events: u64,
+    ips: HashSet<IpAddr>,
+    paths: Vec<String>,
+}
+
+pub struct CampaignInfo {
+    pub id: u64,
+    pub rule: u32,
+    pub field: String,
+    pub sample: String,
+    pub first_seen: u64,
+    pub last_seen: u64,
+    pub events: u64,
+    pub ips: usize,
+    pub ips_capped: bool,
+    pub paths: Vec<String>,
+}
+
+// Bloqueios agrupados pela família do payload (MatchedData.family): o mesmo
+// ataque vindo de milhares de IPs vira uma linha
+pub struct Campaigns {
+    table: Mutex<HashMap<u64, Campaign>>,
+    metrics: Arc<Metrics>,
+}
+
+impl Campaigns {
+    pub fn new(metrics: Arc<Metrics>) -> Arc<Self> {
+        Arc::new(Campaigns {
+            table: Mutex::new(HashMap::new()),
+            metrics,
+        })
+    }
+
+    pub fn record(&self, ip: IpAddr, path: &str, matched: &MatchedData) {
+        let now = unix_now();
+        let path = path.split('?').next().unwrap_or(path);
+        let mut table = self.table.lock().unwrap();
+        if !table.contains_key(&matched.family) {
+            if table.len() >= MAX_CAMPAIGNS
+                && let Some(oldest) = table
+                    .iter()
+                    .min_by_key(|(_, c)| c.last_seen)
+                    .map(|(id, _)| *id)
+            {
+                table.remove(&oldest);
+            }
+            info!(
+                campaign = format!("{:016x}", matched.family),
+                rule = matched.rule,
+                field = %matched.field,
+                "New attack campaign"
+            );
+            self.metrics.inc("oblivion_campaigns_total", &[]);
+        }
+        let campaign = table.entry(matched.family).or_insert_with(|| Campaign {
+            rule: matched.rule,
+            field: matched.field.clone(),
+            sample: matched.context.clone(),
+            first_seen: now,
+            last_seen: now,
+            events: 0,
+            ips: HashSet::new(),
+            paths: Vec::new(),
+        });
+        campaign.events += 1;
+        campaign.last_seen = now;
+        if campaign.ips.len() < MAX_IPS {
+            campaign.ips.insert(ip);
+        }
+        if campaign.paths.len() < MAX_PATHS && !campaign.paths.iter().any(|p| p == path) {
+            campaign.paths.push(path.to_string());
+        }
+    }
+
+    // Mais eventos primeiro
+    pub fn list(&self) -> Vec<CampaignInfo> {
+        let table = self.table.lock().unwrap();
+        let mut out: Vec<CampaignInfo> = table
+            .iter()
+            .map(|(id, c)| CampaignInfo {
+                id: *id,
+                rule: c.rule,
+                field: c.field.clone(),
+                sample: c.sample.clone(),
+                first_seen: c.first_seen,
+                last_seen: c.last_seen,
+                events: c.events,
+                ips: c.ips.len(),
+                ips_capped: c.ips.len() >= MAX_IPS,
+                paths: c.paths.clone(),
+            })
+            .collect();
+        out.sort_by_key(|c| std::cmp::Reverse(c.events));
+        out
+    }
+
+    pub fn clear(&self) -> usize {
+        let mut table = self.table.lock().unwrap();
+        let count = table.len();
+        table.clear();
+        count
+    }
+}
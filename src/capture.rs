@@ -0,0 +1,98 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+#[derive(Debug, Clone)]
+pub struct CaptureFilter {
+    pub remaining: usize,
+    pub ip: Option<IpAddr>,
+    pub path_prefix: Option<String>,
+}
+
+pub struct Capture {
+    active: AtomicBool,
+    filter: Mutex<Option<CaptureFilter>>,
+    path: String,
+}
+
+impl Capture {
+    pub fn new(path: &str) -> Arc<Self> {
+        Arc::new(Capture {
+            active: AtomicBool::new(false),
+            filter: Mutex::new(None),
+            path: path.to_string(),
+        })
+    }
+
+    pub fn start(&self, filter: CaptureFilter) {
+        info!(?filter, file = %self.path, "Debug capture started");
+        *self.filter.lock().unwrap() = Some(filter);
+        self.active.store(true, Ordering::Release);
+    }
+
+    pub fn stop(&self) {
+        self.active.store(false, Ordering::Release);
+        *self.filter.lock().unwrap() = None;
+    }
+
+    pub fn status(&self) -> Option<CaptureFilter> {
+        self.filter.lock().unwrap().clone()
+    }
+
+    // Fora de captura é só um load atômico no caminho quente
+    pub fn claim(&self, ip: IpAddr, path: &str) -> bool {
+        if !self.active.load(Ordering::Acquire) {
+            return false;
+        }
+        let mut guard = self.filter.lock().unwrap();
+        let Some(filter) = guard.as_mut() else {
+            return false;
+        };
+        if filter.ip.is_some_and(|f| f != ip)
+            || filter
+                .path_prefix
+                .as_ref()
+                .is_some_and(|p| !path.starts_with(p.as_str()))
+        {
+            return false;
+        }
+
+        filter.remaining -= 1;
+        if filter.remaining == 0 {
+            *guard = None;
+            self.active.store(false, Ordering::Release);
+            info!(file = %self.path, "Debug capture finished");
+        }
+        true
+    }
+
+    pub async fn record(&self, peer_addr: SocketAddr, lines: &[String]) {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or(0.0);
+        let mut entry = format!("=== {:.3} {}\n", ts, peer_addr);
+        for line in lines {
+            entry.push_str(line);
+            entry.push('\n');
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await;
+        let result = match file {
+            Ok(mut f) => f.write_all(entry.as_bytes()).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            warn!(file = %self.path, error = %e, "Failed to write debug capture");
+        }
+    }
+}
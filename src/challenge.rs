@@ -0,0 +1,212 @@
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::session::Session;
+
+pub const CLEARANCE_COOKIE: &str = "oblivion_clearance";
+
+// sha256 em JS puro: crypto.subtle só existe em contexto seguro, e o desafio
+// também sai pelo listener HTTP. Procura o contador cujo hash de
+// "<desafio>.<contador>" começa com `difficulty` bits zero e grava o clearance
+const SOLVER: &str = r#"<script>
+(function(){var K=[0x428a2f98,0x71374491,0xb5c0fbcf,0xe9b5dba5,0x3956c25b,0x59f111f1,0x923f82a4,0xab1c5ed5,0xd807aa98,0x12835b01,0x243185be,0x550c7dc3,0x72be5d74,0x80deb1fe,0x9bdc06a7,0xc19bf174,0xe49b69c1,0xefbe4786,0x0fc19dc6,0x240ca1cc,0x2de92c6f,0x4a7484aa,0x5cb0a9dc,0x76f988da,0x983e5152,0xa831c66d,0xb00327c8,0xbf597fc7,0xc6e00bf3,0xd5a79147,0x06ca6351,0x14292967,0x27b70a85,0x2e1b2138,0x4d2c6dfc,0x53380d13,0x650a7354,0x766a0abb,0x81c2c92e,0x92722c85,0xa2bfe8a1,0xa81a664b,0xc24b8b70,0xc76c51a3,0xd192e819,0xd6990624,0xf40e3585,0x106aa070,0x19a4c116,0x1e376c08,0x2748774c,0x34b0bcb5,0x391c0cb3,0x4ed8aa4a,0x5b9cca4f,0x682e6ff3,0x748f82ee,0x78a5636f,0x84c87814,0x8cc70208,0x90befffa,0xa4506ceb,0xbef9a3f7,0xc67178f2];
+function sha256(s){var H=[0x6a09e667,0xbb67ae85,0x3c6ef372,0xa54ff53a,0x510e527f,0x9b05688c,0x1f83d9ab,0x5be0cd19],m=[],l=s.length*8,i,j;
+for(i=0;i<s.length;i++)m[i>>2]|=s.charCodeAt(i)<<(24-i%4*8);
+m[l>>5]|=0x80<<(24-l%32);m[(l+64>>9<<4)+15]=l;
+for(i=0;i<m.length;i+=16){var w=[],a=H[0],b=H[1],c=H[2],d=H[3],e=H[4],f=H[5],g=H[6],h=H[7];
+for(j=0;j<64;j++){if(j<16)w[j]=m[i+j]|0;else{var x=w[j-15],y=w[j-2];w[j]=((x>>>7|x<<25)^(x>>>18|x<<14)^x>>>3)+((y>>>17|y<<15)^(y>>>19|y<<13)^y>>>10)+w[j-7]+w[j-16]|0;}
+var t=h+((e>>>6|e<<26)^(e>>>11|e<<21)^(e>>>25|e<<7))+(e&f^~e&g)+K[j]+w[j]|0,u=((a>>>2|a<<30)^(a>>>13|a<<19)^(a>>>22|a<<10))+(a&b^a&c^b&c)|0;
+h=g;g=f;f=e;e=d+t|0;d=c;c=b;b=a;a=t+u|0;}
+H[0]=H[0]+a|0;H[1]=H[1]+b|0;H[2]=H[2]+c|0;H[3]=H[3]+d|0;H[4]=H[4]+e|0;H[5]=H[5]+f|0;H[6]=H[6]+g|0;H[7]=H[7]+h|0;}
+return H;}
+function zeros(H){for(var n=0,i=0;i<8;i++){if(H[i])return n+Math.clz32(H[i]);n+=32;}return n;}
+var ch="{challenge}";
+for(var n=0;zeros(sha256(ch+"."+n))<{bits};n++);
+document.cookie="{cookie}="+ch+"."+n+"; Path=/; Max-Age={max_age}; SameSite=Lax{secure}";
+location.reload();})();
+</script>"#;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Clone)]
+pub struct Challenge {
+    secret: [u8; 32],
+    ttl: Duration,
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl Challenge {
+    pub fn new(ttl: Duration) -> Self {
+        let mut secret = [0u8; 32];
+        getrandom::getrandom(&mut secret).expect("❌ Erro: sem fonte de entropia para o challenge");
+        Challenge { secret, ttl }
+    }
+
+    fn sign(&self, subject: &str, expires: u64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.secret).expect("HMAC aceita qualquer chave");
+        mac.update(subject.as_bytes());
+        mac.update(b"|");
+        mac.update(expires.to_string().as_bytes());
+        mac
+    }
+
+    pub fn issue(&self, subject: &str) -> String {
+        let expires = unix_now() + self.ttl.as_secs();
+        let tag = self.sign(subject, expires).finalize().into_bytes();
+        format!("{}.{}", expires, to_hex(&tag))
+    }
+
+    // Clearance preso ao IP, ou à sessão quando há uma (acompanha o navegador entre IPs).
+    // Token = "<desafio assinado>.<contador>", com o trabalho de `difficulty` bits feito
+    pub fn verify(
+        &self,
+        ip: IpAddr,
+        session: Option<&Session>,
+        token: Option<&str>,
+        difficulty: u32,
+    ) -> bool {
+        let Some(token) = token.filter(|t| solved(t, difficulty)) else {
+            return false;
+        };
+        let Some((challenge, _)) = token.rsplit_once('.') else {
+            return false;
+        };
+        self.check(&ip.to_string(), Some(challenge))
+            || session.is_some_and(|s| self.check(&session_subject(s), Some(challenge)))
+    }
+
+    fn check(&self, subject: &str, token: Option<&str>) -> bool {
+        let Some((expires, tag)) = token.and_then(|t| t.split_once('.')) else {
+            return false;
+        };
+        let Ok(expires) = expires.parse::<u64>() else {
+            return false;
+        };
+        if expires < unix_now() || tag.len() % 2 != 0 {
+            return false;
+        }
+        let Some(tag) = from_hex(tag) else {
+            return false;
+        };
+        self.sign(subject, expires).verify_slice(&tag).is_ok()
+    }
+
+    // Nada de clearance pronto: a página só devolve o desafio, o cookie sai do trabalho
+    pub fn response(
+        &self,
+        ip: IpAddr,
+        session: Option<&Session>,
+        difficulty: u32,
+        secure: bool,
+    ) -> Vec<u8> {
+        let subject = session.map_or_else(|| ip.to_string(), session_subject);
+        let session_cookie = session
+            .and_then(|s| s.set_cookie.as_deref())
+            .map(|c| format!("Set-Cookie: {}\r\n", c))
+            .unwrap_or_default();
+        let solver = SOLVER
+            .replace("{challenge}", &self.issue(&subject))
+            .replace("{bits}", &difficulty.to_string())
+            .replace("{cookie}", CLEARANCE_COOKIE)
+            .replace("{max_age}", &self.ttl.as_secs().to_string())
+            .replace("{secure}", if secure { "; Secure" } else { "" });
+        let body = format!(
+            "<html><head><title>Checking your browser</title></head>\
+             <body>Checking your browser...<noscript> JavaScript is required.</noscript>\
+             {}</body></html>",
+            solver
+        );
+        format!(
+            "HTTP/1.1 503 Service Unavailable\r\n\
+             {}\
+             Cache-Control: no-store\r\n\
+             Retry-After: 1\r\n\
+             Content-Type: text/html\r\n\
+             Content-Length: {}\r\n\r\n{}",
+            session_cookie,
+            body.len(),
+            body
+        )
+        .into_bytes()
+    }
+}
+
+// sha256 do token começa com `difficulty` bits zero; contador só em dígitos
+fn solved(token: &str, difficulty: u32) -> bool {
+    let Some((_, counter)) = token.rsplit_once('.') else {
+        return false;
+    };
+    if counter.is_empty() || counter.len() > 20 || !counter.bytes().all(|b| b.is_ascii_digit()) {
+        return false;
+    }
+    let mut zeros = 0;
+    for byte in Sha256::digest(token.as_bytes()) {
+        zeros += byte.leading_zeros();
+        if byte != 0 {
+            break;
+        }
+    }
+    zeros >= difficulty
+}
+
+fn session_subject(session: &Session) -> String {
+    format!("session:{}", session.id)
+}
+
+pub fn from_hex(s: &str) -> Option<Vec<u8>> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|h| u8::from_str_radix(h, 16).ok()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solve(challenge: &str, difficulty: u32) -> String {
+        (0u64..)
+            .map(|n| format!("{}.{}", challenge, n))
+            .find(|token| solved(token, difficulty))
+            .unwrap()
+    }
+
+    #[test]
+    fn clearance_needs_the_work_done() {
+        let challenge = Challenge::new(Duration::from_secs(60));
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let issued = challenge.issue(&ip.to_string());
+
+        let token = solve(&issued, 12);
+        assert!(challenge.verify(ip, None, Some(&token), 12));
+        // Trabalho menor que o exigido, desafio sem contador, outro IP
+        assert!(!challenge.verify(ip, None, Some(&token), 30));
+        assert!(!challenge.verify(ip, None, Some(&issued), 12));
+        assert!(!challenge.verify("203.0.113.8".parse().unwrap(), None, Some(&token), 12));
+        let forged = solve(&format!("{}0", issued), 12);
+        assert!(!challenge.verify(ip, None, Some(&forged), 12));
+    }
+
+    #[test]
+    fn challenge_page_sets_no_clearance() {
+        let challenge = Challenge::new(Duration::from_secs(60));
+        let page = challenge.response("203.0.113.7".parse().unwrap(), None, 12, true);
+        let page = String::from_utf8(page).unwrap();
+        assert!(!page.contains("Set-Cookie"));
+        assert!(page.contains("<12;"));
+        assert!(!page.contains("{challenge}"));
+    }
+}
@@ -0,0 +1,621 @@
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::admin::load_token;
+use crate::auth::Authenticator;
+use crate::automaton::AutomatonEngine;
+use clap::{Parser, Subcommand};
+
+use crate::config::{Config, ListenerConfig};
+use crate::engine::{Verdict, WafEngine};
+use crate::error::Error;
+use crate::forward_head;
+use crate::geo::GeoDb;
+use crate::http::{normalize_path, Request};
+use crate::metrics::Metrics;
+use crate::rules::Action;
+use crate::rules::{url_decode, RuleSet};
+use crate::session::Sessions;
+use crate::tls::{admin_tls_config, load_tls_config};
+use crate::watermark::load_secret;
+
+const SLOW_RULE_FACTOR: f64 = 10.0;
+const SLOW_RULE_FLOOR: Duration = Duration::from_micros(50);
+
+#[derive(Parser)]
+#[command(name = "oblivion", version, about = "WAF reverse proxy (HTTPS)")]
+pub struct Cli {
+    /// Config file [default: oblivion.toml, optional]
+    #[arg(long, env = "OBLIVION_CONFIG", value_name = "FILE")]
+    pub config: Option<String>,
+    /// Listener address; repeat or comma-separate for several (replaces [[listener]])
+    #[arg(
+        long,
+        env = "OBLIVION_LISTEN",
+        value_name = "ADDR",
+        value_delimiter = ','
+    )]
+    pub listen: Vec<String>,
+    /// Upstream host:port
+    #[arg(long, env = "OBLIVION_UPSTREAM", value_name = "ADDR")]
+    pub upstream: Option<String>,
+    /// Certificate (PEM) for every listener
+    #[arg(long, env = "OBLIVION_CERT", value_name = "FILE")]
+    pub cert: Option<String>,
+    /// Private key (PEM) for every listener
+    #[arg(long, env = "OBLIVION_KEY", value_name = "FILE")]
+    pub key: Option<String>,
+    /// Base log level; RUST_LOG directives still apply on top
+    #[arg(long, env = "OBLIVION_LOG_LEVEL", value_name = "LEVEL", value_parser = ["error", "warn", "info", "debug", "trace"])]
+    pub log_level: Option<String>,
+    /// Validate config, certificates and rules, then exit without binding
+    #[arg(long)]
+    pub check_config: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Rule file tooling
+    #[command(subcommand)]
+    Rules(RulesCommand),
+    /// Show how a raw request is parsed, normalized, judged and forwarded
+    Transform {
+        #[arg(long, value_name = "REQUEST_FILE")]
+        show: String,
+        rules: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum RulesCommand {
+    /// Time every rule against a payload corpus
+    Bench {
+        rules: String,
+        corpus: String,
+        #[arg(default_value_t = 100)]
+        iterations: usize,
+    },
+    /// Diff verdicts of the current engine against the Aho-Corasick candidate
+    Compare { rules: String, corpus: String },
+    /// Run the match/pass cases declared in each rule
+    Test { rules: Option<String> },
+}
+
+impl Cli {
+    // Flags valem por cima do arquivo
+    pub fn apply(&self, config: &mut Config) {
+        if !self.listen.is_empty() {
+            let template = config.listeners.first().cloned().unwrap_or_default();
+            config.listeners = self
+                .listen
+                .iter()
+                .map(|addr| ListenerConfig {
+                    addr: addr.clone(),
+                    v6_only: None,
+                    ..template.clone()
+                })
+                .collect();
+        }
+        for listener in &mut config.listeners {
+            if let Some(cert) = &self.cert {
+                listener.cert = cert.clone();
+            }
+            if let Some(key) = &self.key {
+                listener.key = key.clone();
+            }
+        }
+        if let Some(upstream) = &self.upstream {
+            config.upstream.addr = upstream.clone();
+        }
+    }
+}
+
+// Subcomandos rodam e saem
+pub fn execute(command: Command, config: &Config) -> Result<(), Error> {
+    match command {
+        Command::Rules(RulesCommand::Bench {
+            rules,
+            corpus,
+            iterations,
+        }) => rules_bench(&rules, &corpus, iterations),
+        Command::Rules(RulesCommand::Compare { rules, corpus }) => rules_compare(&rules, &corpus),
+        Command::Rules(RulesCommand::Test { rules }) => rules_test(rules.as_deref(), config),
+        Command::Transform { show, rules } => transform_show(&show, rules.as_deref(), config),
+    }
+}
+
+// Tudo que o startup carrega antes do bind; aponta todos os problemas, não só o primeiro
+pub fn check_config(config: &Config, source: &str) -> Result<(), Error> {
+    println!(
+        "ok   config {}: {} listener(s), upstream {}",
+        source,
+        config.listeners.len(),
+        config.upstream.addr
+    );
+    let mut failures = Vec::new();
+    for listener in &config.listeners {
+        match load_tls_config(listener) {
+            Ok(_) => println!(
+                "ok   tls {}: {} + {}, {} SNI name(s)",
+                listener.addr,
+                listener.cert,
+                listener.key,
+                listener.sni.iter().map(|s| s.names.len()).sum::<usize>()
+            ),
+            Err(e) => {
+                println!("FAIL tls {}: {}", listener.addr, e);
+                failures.push(e);
+            }
+        }
+    }
+    if let Some(path) = &config.session.secret_file {
+        match Sessions::new(&config.session) {
+            Ok(_) => println!("ok   session secret {}", path),
+            Err(e) => {
+                println!("FAIL session secret {}: {}", path, e);
+                failures.push(e);
+            }
+        }
+    }
+    if config.auth.jwt_secret_file.is_some() || config.auth.api_keys_file.is_some() {
+        match Authenticator::new(&config.auth) {
+            Ok(auth) => println!("ok   auth: {} api key(s)", auth.api_keys()),
+            Err(e) => {
+                println!("FAIL auth: {}", e);
+                failures.push(e);
+            }
+        }
+    }
+    if let Some(path) = &config.watermark.secret_file {
+        match load_secret(&config.watermark) {
+            Ok(_) => println!("ok   watermark secret {}", path),
+            Err(e) => {
+                println!("FAIL watermark secret {}: {}", path, e);
+                failures.push(e);
+            }
+        }
+    }
+    if let Some(path) = &config.admin.token_file {
+        match load_token(&config.admin) {
+            Ok(_) => println!("ok   admin token {}", path),
+            Err(e) => {
+                println!("FAIL admin token {}: {}", path, e);
+                failures.push(e);
+            }
+        }
+    }
+    if let Some(cert) = &config.admin.cert {
+        match admin_tls_config(&config.admin) {
+            Ok(_) => println!("ok   admin tls {}", cert),
+            Err(e) => {
+                println!("FAIL admin tls {}: {}", cert, e);
+                failures.push(e);
+            }
+        }
+    }
+    if let Some(path) = &config.geo.database {
+        match GeoDb::load(path) {
+            Ok(geo) => println!("ok   geo {}: {} range(s)", path, geo.ranges()),
+            Err(e) => {
+                println!("FAIL geo {}: {}", path, e);
+                failures.push(e);
+            }
+        }
+    }
+    let rules_source = if Path::new(&config.files.rules).exists() {
+        config.files.rules.as_str()
+    } else {
+        "(built-in)"
+    };
+    match RuleSet::load_or_default(&config.files.rules) {
+        Ok(rules) => println!(
+            "ok   rules {}: {} rule(s), {} route(s)",
+            rules_source,
+            rules.rules.len(),
+            rules.routes.len()
+        ),
+        Err(e) => {
+            println!("FAIL rules {}: {}", rules_source, e);
+            failures.push(Error::Config(format!("regras inválidas: {}", e)));
+        }
+    }
+    for (i, (site, resolved)) in config.resolved_sites().enumerate() {
+        let upstream = &resolved.upstream.addr;
+        match &site.rules {
+            Some(path) if *path != config.files.rules => match RuleSet::load(path) {
+                Ok(rules) => println!(
+                    "ok   site {} {}: upstream {}, rules {} ({} rule(s), {} route(s))",
+                    i,
+                    site.hosts.join(","),
+                    upstream,
+                    path,
+                    rules.rules.len(),
+                    rules.routes.len()
+                ),
+                Err(e) => {
+                    println!("FAIL site {} {}: {}", i, site.hosts.join(","), e);
+                    failures.push(Error::Config(format!("regras do site {}: {}", i, e)));
+                }
+            },
+            _ => println!(
+                "ok   site {} {}: upstream {}",
+                i,
+                site.hosts.join(","),
+                upstream
+            ),
+        }
+    }
+
+    let count = failures.len();
+    match failures.into_iter().next() {
+        None => Ok(()),
+        Some(first) if count == 1 => Err(first),
+        Some(first) => {
+            println!("{} problem(s) found", count);
+            Err(first)
+        }
+    }
+}
+
+// Uma linha por payload: "<uri>" ou "<METHOD> <uri> [body]"
+pub(crate) fn payload_request(line: &str) -> Result<Request, Error> {
+    let mut parts = line.splitn(3, ' ');
+    let first = parts.next().unwrap_or("");
+    let (method, uri, body) = if first.starts_with('/') {
+        ("GET", first, "")
+    } else {
+        (
+            first,
+            parts.next().unwrap_or("/"),
+            parts.next().unwrap_or(""),
+        )
+    };
+    let content_type = if body.is_empty() {
+        ""
+    } else {
+        "Content-Type: application/x-www-form-urlencoded\r\n"
+    };
+    let raw = format!(
+        "{} {} HTTP/1.1\r\nHost: bench.local\r\n{}Content-Length: {}\r\n\r\n{}",
+        method,
+        uri,
+        content_type,
+        body.len(),
+        body
+    );
+    Request::parse(&raw)
+}
+
+fn load_corpus(path: &str) -> Result<Vec<Request>, Error> {
+    let source = fs::read_to_string(path).map_err(|e| Error::Parse(format!("{}: {}", path, e)))?;
+    let mut requests = Vec::new();
+
+    for (n, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        requests.push(
+            payload_request(line)
+                .map_err(|e| Error::Parse(format!("{}:{}: {}", path, n + 1, e)))?,
+        );
+    }
+
+    if requests.is_empty() {
+        return Err(Error::Parse(format!("{}: corpus is empty", path)));
+    }
+    Ok(requests)
+}
+
+fn rules_bench(rules_path: &str, corpus_path: &str, iterations: usize) -> Result<(), Error> {
+    let rules = RuleSet::load(rules_path).map_err(Error::Config)?;
+    let corpus = load_corpus(corpus_path)?;
+    let engine = WafEngine::new(rules, Metrics::new());
+    let iterations = iterations.max(1);
+
+    let mut latencies = Vec::with_capacity(corpus.len() * iterations);
+    let mut blocked = 0;
+    for _ in 0..iterations {
+        for req in &corpus {
+            let start = Instant::now();
+            let verdict = engine.inspect(req);
+            latencies.push(start.elapsed());
+            if matches!(verdict, Verdict::Block(..)) {
+                blocked += 1;
+            }
+        }
+    }
+    latencies.sort();
+    let total: Duration = latencies.iter().sum();
+    let pct = |p: f64| latencies[((latencies.len() - 1) as f64 * p) as usize];
+
+    println!(
+        "corpus: {} payloads x {} iterations, {} blocked",
+        corpus.len(),
+        iterations,
+        blocked / iterations
+    );
+    println!(
+        "inspect latency: avg {:?}  p50 {:?}  p99 {:?}  max {:?}",
+        total / latencies.len() as u32,
+        pct(0.5),
+        pct(0.99),
+        latencies[latencies.len() - 1]
+    );
+
+    let mut costs = Vec::new();
+    for rule in &engine.rules().rules {
+        let mut elapsed = Duration::ZERO;
+        let mut hits = 0;
+        for _ in 0..iterations {
+            for req in &corpus {
+                let start = Instant::now();
+                if engine.match_rule(req, rule) {
+                    hits += 1;
+                }
+                elapsed += start.elapsed();
+            }
+        }
+        let avg = elapsed / (corpus.len() * iterations) as u32;
+        costs.push((rule, avg, hits / iterations));
+    }
+
+    let mut sorted: Vec<Duration> = costs.iter().map(|(_, avg, _)| *avg).collect();
+    sorted.sort();
+    let median = sorted[sorted.len() / 2];
+    let slow_threshold = median.mul_f64(SLOW_RULE_FACTOR).max(SLOW_RULE_FLOOR);
+
+    costs.sort_by_key(|(_, avg, _)| std::cmp::Reverse(*avg));
+    println!();
+    println!("{:>8}  {:>12}  {:>6}  rule", "id", "avg/payload", "hits");
+    let mut slow = 0;
+    for (rule, avg, hits) in &costs {
+        let flag = if *avg > slow_threshold {
+            slow += 1;
+            "  ⚠️ SLOW"
+        } else {
+            ""
+        };
+        println!(
+            "{:>8}  {:>12?}  {:>6}  {}{}",
+            rule.id,
+            avg,
+            hits,
+            rule.description(),
+            flag
+        );
+    }
+
+    if slow > 0 {
+        println!();
+        println!(
+            "{} rule(s) above {:?} (10x median or {:?})",
+            slow, slow_threshold, SLOW_RULE_FLOOR
+        );
+    }
+    Ok(())
+}
+
+// Casos declarados em cada regra; qualquer falha sai com código != 0 (CI)
+fn rules_test(rules_path: Option<&str>, config: &Config) -> Result<(), Error> {
+    let rules = match rules_path {
+        Some(path) => RuleSet::load(path),
+        None => RuleSet::load_or_default(&config.files.rules),
+    }
+    .map_err(Error::Config)?;
+    let engine = WafEngine::new(rules, Metrics::new());
+
+    let mut cases = 0;
+    let mut failed = 0;
+    let mut untested = Vec::new();
+    for rule in &engine.rules().rules {
+        if rule.tests.is_empty() {
+            untested.push(rule.id.to_string());
+            continue;
+        }
+        let expectations = rule
+            .tests
+            .matches
+            .iter()
+            .map(|p| (p, true))
+            .chain(rule.tests.pass.iter().map(|p| (p, false)));
+        for (payload, expected) in expectations {
+            cases += 1;
+            let req = payload_request(payload)
+                .map_err(|e| Error::Parse(format!("rule {}: {:?}: {}", rule.id, payload, e)))?;
+            if engine.match_rule(&req, rule) == expected {
+                continue;
+            }
+            failed += 1;
+            println!(
+                "FAIL rule {}: expected {} for {:?}",
+                rule.id,
+                if expected { "match" } else { "no match" },
+                payload
+            );
+        }
+    }
+
+    println!(
+        "{} cases, {} failed, {} rule(s) without tests",
+        cases,
+        failed,
+        untested.len()
+    );
+    if !untested.is_empty() {
+        println!("untested: {}", untested.join(", "));
+    }
+    if failed > 0 {
+        return Err(Error::TestFailure(format!(
+            "{} rule test(s) failed",
+            failed
+        )));
+    }
+    Ok(())
+}
+
+// Engine atual x candidato (Aho-Corasick + RegexSet), só o estágio de regras:
+// checagens de protocolo, rotas e profiles ficam de fora dos dois lados
+fn rules_compare(rules_path: &str, corpus_path: &str) -> Result<(), Error> {
+    let rules = RuleSet::load(rules_path).map_err(Error::Config)?;
+    let corpus = load_corpus(corpus_path)?;
+    let engine = WafEngine::new(rules, Metrics::new());
+    let candidate = AutomatonEngine::new(&engine.rules().rules)
+        .map_err(|e| Error::Config(format!("automaton: {}", e)))?;
+    let rules = &engine.rules().rules;
+
+    // Primeira regra (na ordem do arquivo) com ação Block decide o veredito
+    let verdict = |matched: &[bool]| {
+        rules
+            .iter()
+            .zip(matched)
+            .find(|(rule, m)| **m && engine.rules().action_for(rule) == Action::Block)
+            .map(|(rule, _)| rule.id)
+    };
+    let ids = |matched: &[bool], other: &[bool]| {
+        rules
+            .iter()
+            .zip(matched.iter().zip(other))
+            .filter(|(_, (a, b))| **a && !**b)
+            .map(|(rule, _)| rule.id.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+
+    let mut legacy_time = Duration::ZERO;
+    let mut candidate_time = Duration::ZERO;
+    let mut blocked = (0, 0);
+    let mut verdict_diffs = 0;
+    let mut rule_diffs = 0;
+    for req in &corpus {
+        let start = Instant::now();
+        let legacy: Vec<bool> = rules.iter().map(|r| engine.match_rule(req, r)).collect();
+        legacy_time += start.elapsed();
+        let start = Instant::now();
+        let automaton = candidate.matches(&engine, req);
+        candidate_time += start.elapsed();
+
+        let (a, b) = (verdict(&legacy), verdict(&automaton));
+        blocked.0 += a.is_some() as usize;
+        blocked.1 += b.is_some() as usize;
+        if legacy == automaton {
+            continue;
+        }
+        rule_diffs += 1;
+        let label = |v: Option<u32>| v.map_or("allow".to_string(), |id| format!("block({})", id));
+        let marker = if a != b {
+            verdict_diffs += 1;
+            "VERDICT"
+        } else {
+            "rules"
+        };
+        println!(
+            "{:<8} {} {}  legacy={} automaton={}  only-legacy=[{}] only-automaton=[{}]",
+            marker,
+            req.method,
+            req.path,
+            label(a),
+            label(b),
+            ids(&legacy, &automaton),
+            ids(&automaton, &legacy)
+        );
+    }
+
+    println!("\ncorpus: {} payloads, {} rules", corpus.len(), rules.len());
+    println!("legacy:    {} blocked, {:?}", blocked.0, legacy_time);
+    println!("automaton: {} blocked, {:?}", blocked.1, candidate_time);
+    println!(
+        "diffs: {} verdict, {} rule-level",
+        verdict_diffs, rule_diffs
+    );
+    Ok(())
+}
+
+fn print_escaped(bytes: &[u8]) {
+    for line in bytes.split_inclusive(|b| *b == b'\n') {
+        println!("  {:?}", String::from_utf8_lossy(line));
+    }
+}
+
+// raw -> parsed -> normalizado por campo -> veredito -> bytes encaminhados
+fn transform_show(
+    request_path: &str,
+    rules_path: Option<&str>,
+    config: &Config,
+) -> Result<(), Error> {
+    let raw =
+        fs::read(request_path).map_err(|e| Error::Parse(format!("{}: {}", request_path, e)))?;
+    let rules = match rules_path {
+        Some(path) => RuleSet::load(path),
+        None => RuleSet::load_or_default(&config.files.rules),
+    }
+    .map_err(Error::Config)?;
+    let engine = WafEngine::new(rules, Metrics::new());
+
+    // Arquivos editados à mão costumam ter só LF
+    let text = String::from_utf8_lossy(&raw).to_string();
+    let (head, body) = match (text.find("\r\n\r\n"), text.find("\n\n")) {
+        (Some(crlf), Some(lf)) if lf < crlf => (&text[..lf + 2], &text[lf + 2..]),
+        (Some(crlf), _) => (&text[..crlf + 4], &text[crlf + 4..]),
+        (None, Some(lf)) => (&text[..lf + 2], &text[lf + 2..]),
+        (None, None) => (text.as_str(), ""),
+    };
+    let mut req =
+        Request::parse(head).map_err(|e| Error::Parse(format!("{}: {}", request_path, e)))?;
+    req.body = body.to_string();
+
+    println!("== raw ({} bytes)", raw.len());
+    print_escaped(&raw);
+
+    println!("\n== parsed");
+    println!("  method: {}", req.method);
+    println!("  target: {}", req.path);
+    println!("  version: {}", req.version);
+    for (i, name) in req.header_order.iter().enumerate() {
+        // Repetidos já vêm juntos no primeiro
+        if req.header_order[..i]
+            .iter()
+            .any(|h| h.eq_ignore_ascii_case(name))
+        {
+            continue;
+        }
+        println!("  header {}: {:?}", name, req.header(name).unwrap_or(""));
+    }
+    println!("  body: {:?}", req.body);
+    println!("  params: {:?}", req.params());
+    println!("  cookies: {:?}", req.cookies().collect::<Vec<_>>());
+
+    println!("\n== normalized");
+    println!("  route path: {:?}", url_decode(req.path_only()));
+    println!("  forward path: {:?}", normalize_path(req.path_only()));
+    for line in engine.normalized(&req) {
+        println!("{}", line);
+    }
+
+    println!("\n== evaluation");
+    let (verdict, trace) = engine.trace(&req);
+    for line in trace
+        .iter()
+        .filter(|l| l.starts_with("rule ") || l.starts_with("host=") || l.starts_with("profile="))
+    {
+        println!("  {}", line);
+    }
+    println!("  verdict: {:?}", verdict);
+
+    if let Verdict::Block(..) = verdict {
+        println!("\n== forwarded: nothing (blocked)");
+        return Ok(());
+    }
+    let route = engine.route(&req);
+    let (mut forwarded, stripped) = forward_head(&req, route, &config.upstream, false);
+    forwarded.extend_from_slice(req.body.as_bytes());
+    println!("\n== forwarded ({} bytes)", forwarded.len());
+    if !stripped.is_empty() {
+        println!("  stripped: {}", stripped.join(", "));
+    }
+    print_escaped(&forwarded);
+    Ok(())
+}
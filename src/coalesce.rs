@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::sync::watch;
+use tokio::time::timeout;
+use tracing::{debug, error, warn};
+
+use crate::config::{Config, UpstreamProtocol};
+use crate::error::Error;
+use crate::h2c::{self, H2Pool};
+use crate::http::{insert_header, strip_headers, Request};
+use crate::metrics::Metrics;
+use crate::upstream::{connect_retrying, Admission, Failure, RetryBudget};
+use crate::{connect_upstream, failure_response, BAD_GATEWAY, DEADLINE_HEADER, GATEWAY_TIMEOUT};
+
+const UPSTREAM_BUSY: &[u8] =
+    b"HTTP/1.1 503 Service Unavailable\r\nRetry-After: 1\r\nContent-Length: 13\r\n\r\nUpstream Busy";
+
+// Headers que mudam a representação: entram na chave junto com host + alvo
+const KEY_HEADERS: [&str; 3] = ["Accept", "Accept-Encoding", "Accept-Language"];
+
+// Resposta crua completa do upstream, ou a resposta de erro que todos recebem
+pub type Outcome = Result<Arc<Vec<u8>>, &'static [u8]>;
+
+#[derive(Clone, Copy)]
+pub enum Role {
+    Leader,
+    Follower,
+}
+
+impl Role {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Role::Leader => "leader",
+            Role::Follower => "follower",
+        }
+    }
+}
+
+// GET sem corpo e sem credencial; com Cookie/Authorization a resposta pode ser pessoal
+pub fn key(req: &Request) -> Option<String> {
+    if !req.method.eq_ignore_ascii_case("GET")
+        || req.content_length().is_some_and(|cl| cl > 0)
+        || ["Transfer-Encoding", "Authorization", "Cookie"]
+            .iter()
+            .any(|h| req.has_header(h))
+    {
+        return None;
+    }
+    let mut key = format!("{} {}", req.header("Host").unwrap_or(""), req.path);
+    for name in KEY_HEADERS {
+        key.push('\n');
+        key.push_str(req.header(name).unwrap_or(""));
+    }
+    Some(key)
+}
+
+// Um fetch por chave em andamento; quem chega depois espera o mesmo resultado
+#[derive(Default)]
+pub struct Coalescer {
+    inflight: Mutex<HashMap<String, watch::Receiver<Option<Outcome>>>>,
+}
+
+impl Coalescer {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Coalescer::default())
+    }
+
+    // O fetch roda em task própria: o líder desconectar não derruba os outros
+    pub async fn join(self: &Arc<Self>, key: String, fetch: Fetch) -> (Outcome, Role) {
+        let (mut rx, role) = {
+            let mut inflight = self.inflight.lock().unwrap();
+            match inflight.get(&key) {
+                Some(rx) => (rx.clone(), Role::Follower),
+                None => {
+                    let (tx, rx) = watch::channel(None);
+                    inflight.insert(key.clone(), rx.clone());
+                    let coalescer = self.clone();
+                    tokio::spawn(async move {
+                        let outcome = fetch.run().await;
+                        coalescer.inflight.lock().unwrap().remove(&key);
+                        let _ = tx.send(Some(outcome));
+                    });
+                    (rx, Role::Leader)
+                }
+            }
+        };
+        let outcome = match rx.wait_for(Option::is_some).await {
+            Ok(outcome) => outcome.clone().unwrap_or(Err(BAD_GATEWAY)),
+            Err(_) => Err(BAD_GATEWAY),
+        };
+        (outcome, role)
+    }
+}
+
+// Tudo que o fetch do líder precisa, já resolvido contra rota e config
+pub struct Fetch {
+    pub config: Arc<Config>,
+    pub head: Vec<u8>,
+    pub connect_timeout: Duration,
+    pub first_byte_timeout: Duration,
+    pub total_timeout: Option<Duration>,
+    pub deadline: Option<Instant>,
+    pub max_size: u64,
+    pub admission: Arc<Admission>,
+    pub retries: Arc<RetryBudget>,
+    pub metrics: Arc<Metrics>,
+    pub h2c: Arc<H2Pool>,
+}
+
+impl Fetch {
+    async fn run(self) -> Outcome {
+        // Só o líder ocupa slot de admissão; os seguidores nunca chegam ao upstream
+        let _permit = match self.admission.acquire().await {
+            Ok(permit) => permit,
+            Err(e) => {
+                warn!(error = %e, "Upstream admission rejected coalesced fetch");
+                return Err(UPSTREAM_BUSY);
+            }
+        };
+        match self.total_timeout {
+            Some(total) => match timeout(total, self.exchange()).await {
+                Ok(outcome) => outcome,
+                Err(_) => {
+                    warn!(category = "upstream", timeout = ?total, "Upstream total timeout exceeded");
+                    Failure::TotalTimeout.record(&self.metrics);
+                    Err(GATEWAY_TIMEOUT)
+                }
+            },
+            None => self.exchange().await,
+        }
+    }
+
+    async fn exchange(&self) -> Outcome {
+        let remaining = || {
+            self.deadline
+                .map(|d| d.saturating_duration_since(Instant::now()))
+        };
+        let mut connect_timeout = self.connect_timeout;
+        let mut first_byte_timeout = self.first_byte_timeout;
+        if let Some(left) = remaining() {
+            if left.is_zero() {
+                warn!(
+                    category = "upstream",
+                    "Request deadline exceeded before upstream"
+                );
+                return Err(GATEWAY_TIMEOUT);
+            }
+            connect_timeout = connect_timeout.min(left);
+        }
+        let mut head = self.head.clone();
+        // Resposta inteira até o EOF: nada de keep-alive com o upstream
+        let (stripped, _) = strip_headers(&head, &["Connection"]);
+        head = stripped;
+        insert_header(&mut head, "Connection", "close");
+
+        let upstream_failed = |e: Error| {
+            error!(category = e.category(), error = %e, "Upstream connection failed");
+            failure_response(&e)
+        };
+        let (upstream, tls) = (&self.config.upstream, self.config.upstream_tls());
+        let tls = tls.map(Arc::as_ref);
+        let (retries, metrics) = (&self.retries, &self.metrics);
+        match upstream.protocol {
+            UpstreamProtocol::Http1 => {
+                let connect = || connect_upstream(upstream, tls, connect_timeout);
+                let mut upstream = connect_retrying(upstream, retries, metrics, connect)
+                    .await
+                    .map_err(upstream_failed)?;
+                if let Some(left) = remaining() {
+                    head = strip_headers(&head, &[DEADLINE_HEADER]).0;
+                    insert_header(&mut head, DEADLINE_HEADER, &left.as_millis().to_string());
+                    first_byte_timeout = first_byte_timeout.min(left);
+                }
+                if let Err(e) = upstream.write_all(&head).await {
+                    error!(category = "upstream", error = %e, "Failed to send headers to upstream");
+                    Failure::Reset.record(metrics);
+                    return Err(BAD_GATEWAY);
+                }
+                self.read_response(&mut upstream, first_byte_timeout).await
+            }
+            UpstreamProtocol::H2c => {
+                let connect = || self.h2c.sender(upstream, tls, connect_timeout);
+                let sender = connect_retrying(upstream, retries, metrics, connect)
+                    .await
+                    .map_err(upstream_failed)?;
+                if let Some(left) = remaining() {
+                    head = strip_headers(&head, &[DEADLINE_HEADER]).0;
+                    insert_header(&mut head, DEADLINE_HEADER, &left.as_millis().to_string());
+                    first_byte_timeout = first_byte_timeout.min(left);
+                }
+                let pending = h2c::send(
+                    sender,
+                    &head,
+                    Vec::new(),
+                    0,
+                    &mut tokio::io::empty(),
+                    Duration::ZERO,
+                )
+                .await
+                .map_err(|e| {
+                    debug!("h2c exchange ended: {}", e);
+                    BAD_GATEWAY
+                })?;
+                let response = match timeout(first_byte_timeout, pending).await {
+                    Ok(Ok(response)) => response,
+                    Ok(Err(e)) => {
+                        debug!("h2c exchange ended: {}", e);
+                        Failure::Reset.record(metrics);
+                        return Err(BAD_GATEWAY);
+                    }
+                    Err(_) => {
+                        warn!(category = "upstream", timeout = ?first_byte_timeout, "Upstream first byte timeout");
+                        Failure::FirstByteTimeout.record(metrics);
+                        return Err(GATEWAY_TIMEOUT);
+                    }
+                };
+                self.read_response(&mut h2c::into_reader(response), first_byte_timeout)
+                    .await
+            }
+        }
+    }
+
+    async fn read_response<R>(&self, upstream: &mut R, first_byte_timeout: Duration) -> Outcome
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut response = Vec::new();
+        let mut buffer = vec![0u8; 16 * 1024];
+        loop {
+            let read = if response.is_empty() {
+                match timeout(first_byte_timeout, upstream.read(&mut buffer)).await {
+                    Ok(read) => read,
+                    Err(_) => {
+                        warn!(category = "upstream", timeout = ?first_byte_timeout, "Upstream first byte timeout");
+                        Failure::FirstByteTimeout.record(&self.metrics);
+                        return Err(GATEWAY_TIMEOUT);
+                    }
+                }
+            } else {
+                upstream.read(&mut buffer).await
+            };
+            match read {
+                Ok(0) if response.is_empty() => {
+                    Failure::Reset.record(&self.metrics);
+                    return Err(BAD_GATEWAY);
+                }
+                Ok(0) => return Ok(Arc::new(response)),
+                Ok(n) => response.extend_from_slice(&buffer[..n]),
+                Err(e) => {
+                    debug!("Coalesced fetch ended: {}", e);
+                    Failure::Reset.record(&self.metrics);
+                    return Err(BAD_GATEWAY);
+                }
+            }
+            if response.len() as u64 > self.max_size {
+                warn!(
+                    category = "upstream",
+                    limit = self.max_size,
+                    "Upstream response size limit exceeded"
+                );
+                return Err(BAD_GATEWAY);
+            }
+        }
+    }
+}
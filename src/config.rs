@@ -0,0 +1,1771 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use tracing::warn;
+
+use crate::access_log;
+use crate::auth::Mechanism;
+use crate::error::Error;
+use crate::geo::AccessList;
+use crate::grpc;
+use crate::keying::Cidr;
+use crate::limiter::GcConfig;
+use crate::reject::RejectPolicy;
+use crate::routes::PathPattern;
+use crate::sniff::ContentMismatch;
+use crate::upstream_tls::UpstreamTls;
+
+// Segundos (aceita fração), como os timeouts de rota
+fn secs<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+    let secs = f64::deserialize(d)?;
+    if !(secs.is_finite() && secs > 0.0) {
+        return Err(D::Error::custom("must be a positive number of seconds"));
+    }
+    Ok(Duration::from_secs_f64(secs))
+}
+
+fn opt_secs<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Duration>, D::Error> {
+    secs(d).map(Some)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum TlsVersion {
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+// Certificado escolhido pelo SNI do ClientHello
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SniCert {
+    pub names: Vec<String>,
+    pub cert: String,
+    pub key: String,
+    // Versão mínima própria do domínio; ausente = a do listener
+    #[serde(default)]
+    pub min_tls: Option<TlsVersion>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenerConfig {
+    pub addr: String,
+    // Padrão para cliente sem SNI ou com nome fora de [[listener.sni]]
+    pub cert: String,
+    pub key: String,
+    pub sni: Vec<SniCert>,
+    pub min_tls: TlsVersion,
+    // Decidido no ClientHello, antes de qualquer trabalho criptográfico:
+    // require_sni recusa cliente sem SNI; strict_sni recusa nome que não esteja
+    // em server_names, [[listener.sni]] ou nos hosts dos [[site]]
+    pub require_sni: bool,
+    pub strict_sni: bool,
+    pub server_names: Vec<String>,
+    // Só vale para endereços IPv6; ausente = dual-stack, a menos que outro
+    // listener IPv4 use a mesma porta
+    pub v6_only: Option<bool>,
+    // Anuncia "h2" no ALPN; cada stream vira um request HTTP/1.1 inspecionado
+    pub http2: bool,
+    // CA dos clientes mTLS: certificado é pedido mas opcional; quem apresenta um
+    // válido tem CN/SAN comparados com [internal] identities
+    pub client_ca: Option<String>,
+    // Chave cifrada (ENCRYPTED PRIVATE KEY), inclusive as do sni: senha na variável
+    // de ambiente ou no arquivo; sem nenhum dos dois, pergunta no terminal
+    pub key_passphrase_env: Option<String>,
+    pub key_passphrase_file: Option<String>,
+    // HTTP em texto puro nesta porta (cliente mal configurado)
+    pub cleartext: CleartextPolicy,
+    // Header PROXY (v1 ou v2) do load balancer antes do TLS: limites, bans e
+    // logs usam o cliente que ele anuncia. proxy_from = quem pode mandar
+    pub proxy_protocol: bool,
+    pub proxy_from: Vec<Cidr>,
+}
+
+// Resposta a quem fala HTTP sem TLS na porta TLS, no lugar do alerta
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CleartextPolicy {
+    // GET/HEAD com Host: 301 para https:// na mesma porta; o resto, como reject
+    #[default]
+    Redirect,
+    // 400 explicando que a porta é HTTPS
+    Reject,
+    // Fecha sem resposta (só log e métrica)
+    Close,
+}
+
+impl CleartextPolicy {
+    pub fn label(&self) -> &'static str {
+        match self {
+            CleartextPolicy::Redirect => "redirect",
+            CleartextPolicy::Reject => "reject",
+            CleartextPolicy::Close => "close",
+        }
+    }
+}
+
+impl Default for ListenerConfig {
+    fn default() -> Self {
+        ListenerConfig {
+            addr: "0.0.0.0:4433".to_string(),
+            cert: "cert.pem".to_string(),
+            key: "key.pem".to_string(),
+            sni: Vec::new(),
+            min_tls: TlsVersion::Tls12,
+            require_sni: false,
+            strict_sni: false,
+            server_names: Vec::new(),
+            v6_only: None,
+            http2: true,
+            client_ca: None,
+            key_passphrase_env: None,
+            key_passphrase_file: None,
+            cleartext: CleartextPolicy::Redirect,
+            proxy_protocol: false,
+            proxy_from: Vec::new(),
+        }
+    }
+}
+
+// http1: uma conexão por request; h2c: uma conexão HTTP/2 multiplexada
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpstreamProtocol {
+    Http1,
+    H2c,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpstreamConfig {
+    // "host:porta" (ou "http://host:porta"); "https://host:porta" cifra até o backend
+    pub addr: String,
+    pub protocol: UpstreamProtocol,
+    #[serde(deserialize_with = "secs")]
+    pub connect_timeout: Duration,
+    #[serde(deserialize_with = "secs")]
+    pub first_byte_timeout: Duration,
+    // Prazo total do request (parse + inspeção + upstream até o primeiro byte)
+    #[serde(deserialize_with = "opt_secs")]
+    pub deadline: Option<Duration>,
+    pub max_connections: usize,
+    pub queue_depth: usize,
+    #[serde(deserialize_with = "secs")]
+    pub queue_timeout: Duration,
+    // Backend quebrado ou amplificação refletida: acima disso 502 (ou corta a conexão)
+    pub max_response_size: u64,
+    // true = encaminha o request canonicalizado em vez dos bytes originais
+    pub normalize: bool,
+    // true = Content-Type em vez de content-type ao reescrever o head
+    pub canonical_header_case: bool,
+    // SNI e nome validado no certificado do backend https; padrão = host do addr
+    pub tls_server_name: Option<String>,
+    // Bundle PEM de CAs para o backend; padrão = raízes públicas
+    pub tls_ca: Option<String>,
+    // sha256 do certificado do backend (hex, ":" opcional); qualquer um serve
+    pub tls_pins: Vec<String>,
+    // Resumo da decisão do WAF em DECISION_HEADER para os logs da aplicação
+    pub decision_header: bool,
+    // Novas tentativas de connect recusado/falho (nada foi mandado ainda)
+    pub retries: u32,
+    // Orçamento global: retries até retry_budget × requests dos últimos 10s,
+    // nunca menos que retry_min
+    pub retry_budget: f64,
+    pub retry_min: u32,
+}
+
+// "https://host:porta" -> (true, "host:porta")
+fn split_scheme(addr: &str) -> (bool, &str) {
+    let addr = addr.trim_end_matches('/');
+    if let Some(rest) = addr.strip_prefix("https://") {
+        (true, rest)
+    } else {
+        (false, addr.strip_prefix("http://").unwrap_or(addr))
+    }
+}
+
+impl UpstreamConfig {
+    pub fn tls(&self) -> bool {
+        split_scheme(&self.addr).0
+    }
+
+    // host:porta para o connect
+    pub fn endpoint(&self) -> &str {
+        split_scheme(&self.addr).1
+    }
+
+    pub fn host(&self) -> &str {
+        let endpoint = self.endpoint();
+        let host = endpoint.rsplit_once(':').map_or(endpoint, |(host, _)| host);
+        host.trim_start_matches('[').trim_end_matches(']')
+    }
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        UpstreamConfig {
+            addr: "127.0.0.1:8000".to_string(),
+            protocol: UpstreamProtocol::Http1,
+            connect_timeout: Duration::from_secs(3),
+            first_byte_timeout: Duration::from_secs(60),
+            deadline: None,
+            max_connections: 256,
+            queue_depth: 1024,
+            queue_timeout: Duration::from_secs(2),
+            max_response_size: 256 * 1024 * 1024,
+            normalize: false,
+            canonical_header_case: false,
+            tls_server_name: None,
+            tls_ca: None,
+            tls_pins: Vec::new(),
+            decision_header: false,
+            retries: 1,
+            retry_budget: 0.2,
+            retry_min: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    pub addr: String,
+    // Com token, todo request precisa de "Authorization: Bearer <token>"
+    pub token_file: Option<String>,
+    // Vazio = qualquer um que alcance addr; com critérios, só quem casar um deles
+    // (antes do token, que continua valendo)
+    pub allow: AccessList,
+    // Com cert/key a API fala TLS; client_ca pede certificado de cliente para
+    // allow.identities
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    pub client_ca: Option<String>,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        AdminConfig {
+            addr: "127.0.0.1:9901".to_string(),
+            token_file: None,
+            allow: AccessList::default(),
+            cert: None,
+            key: None,
+            client_ca: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilesConfig {
+    pub rules: String,
+    pub capture: String,
+    // Intervalo de checagem dos certificados/chaves dos listeners no disco
+    #[serde(deserialize_with = "secs")]
+    pub cert_poll_interval: Duration,
+    // Trilha das regras temporárias do admin (JSON por linha); relida no start
+    pub temp_rules_audit: String,
+}
+
+impl Default for FilesConfig {
+    fn default() -> Self {
+        FilesConfig {
+            rules: "rules.yaml".to_string(),
+            capture: "oblivion-capture.log".to_string(),
+            cert_poll_interval: Duration::from_secs(30),
+            temp_rules_audit: "oblivion-temp-rules.log".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+    pub max_header_size: usize,
+    pub max_body_size: u64,
+    // Teto do corpo lido para inspeção; ausente = max_body_size
+    pub max_inspect_body: Option<u64>,
+    // TCP aberto até o handshake TLS terminar (ClientHello incluso)
+    #[serde(deserialize_with = "secs")]
+    pub handshake_timeout: Duration,
+    #[serde(deserialize_with = "secs")]
+    pub header_timeout: Duration,
+    #[serde(deserialize_with = "secs")]
+    pub body_timeout: Duration,
+    // Keep-alive: espera pelo próximo request e teto de requests na mesma
+    // conexão (1 = uma resposta por conexão)
+    #[serde(deserialize_with = "secs")]
+    pub keepalive_timeout: Duration,
+    pub keepalive_requests: u64,
+    // Slow-read: bytes/s mínimos depois da carência, sem progresso por write_stall cai
+    pub min_read_rate: f64,
+    #[serde(deserialize_with = "secs")]
+    pub slow_read_grace: Duration,
+    #[serde(deserialize_with = "secs")]
+    pub write_stall: Duration,
+    pub slow_read_buffer: usize,
+    #[serde(deserialize_with = "secs")]
+    pub delivery_deadline: Duration,
+    // Rotas com streaming (SSE, long-poll): no lugar do delivery_deadline, fecha
+    // depois disso sem um byte do upstream; também é o first_byte padrão delas
+    #[serde(deserialize_with = "secs")]
+    pub stream_idle_timeout: Duration,
+}
+
+impl ClientConfig {
+    pub fn inspect_limit(&self) -> u64 {
+        self.max_inspect_body.unwrap_or(self.max_body_size)
+    }
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            max_header_size: 8192,
+            max_body_size: 10 * 1024 * 1024,
+            max_inspect_body: None,
+            handshake_timeout: Duration::from_secs(10),
+            header_timeout: Duration::from_secs(5),
+            body_timeout: Duration::from_secs(15),
+            keepalive_timeout: Duration::from_secs(15),
+            keepalive_requests: 1000,
+            min_read_rate: 1024.0,
+            slow_read_grace: Duration::from_secs(10),
+            write_stall: Duration::from_secs(30),
+            slow_read_buffer: 1024 * 1024,
+            delivery_deadline: Duration::from_secs(600),
+            stream_idle_timeout: Duration::from_secs(300),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub request_rate: f64,
+    pub request_burst: f64,
+    pub connection_rate: f64,
+    pub connection_burst: f64,
+    // Limiter, reputação e clearance agrupam IPv6 por prefixo (IPv4 fica por endereço)
+    pub ipv6_prefix: u8,
+    #[serde(deserialize_with = "secs")]
+    pub gc_interval: Duration,
+    #[serde(deserialize_with = "secs")]
+    pub idle_ttl: Duration,
+    pub under_attack_scale: f64,
+    // JA3 (md5) ou JA4 de ferramentas conhecidas (sqlmap, masscan, scrapers
+    // headless): esses clientes passam também por um limite próprio, por IP
+    pub fingerprints: Vec<String>,
+    pub fingerprint_rate: f64,
+    pub fingerprint_burst: f64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            request_rate: 5.0,
+            request_burst: 10.0,
+            connection_rate: 10.0,
+            connection_burst: 20.0,
+            ipv6_prefix: 64,
+            gc_interval: Duration::from_secs(60),
+            idle_ttl: Duration::from_secs(600),
+            under_attack_scale: 0.2,
+            fingerprints: Vec::new(),
+            fingerprint_rate: 0.2,
+            fingerprint_burst: 3.0,
+        }
+    }
+}
+
+impl RateLimitConfig {
+    pub fn gc(&self) -> GcConfig {
+        GcConfig {
+            interval: self.gc_interval,
+            idle_ttl: self.idle_ttl,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AcceptConfig {
+    pub rate_ceiling: f64,
+    pub max_active: usize,
+    pub emergency_threshold: usize,
+    #[serde(deserialize_with = "secs")]
+    pub pause: Duration,
+    pub allowlist: Vec<IpAddr>,
+    // Antes do TLS não existe resposta HTTP: respond vira close
+    pub policy: RejectPolicy,
+}
+
+impl Default for AcceptConfig {
+    fn default() -> Self {
+        AcceptConfig {
+            rate_ceiling: 2000.0,
+            max_active: 10_000,
+            emergency_threshold: 8_000,
+            pause: Duration::from_millis(50),
+            allowlist: vec![[127, 0, 0, 1].into(), std::net::Ipv6Addr::LOCALHOST.into()],
+            policy: RejectPolicy::Close,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PolicyConfig {
+    // respond | close | reset | drop para clientes bloqueados/limitados
+    pub block: RejectPolicy,
+    pub rate_limit: RejectPolicy,
+    #[serde(deserialize_with = "secs")]
+    pub silent_drop_hold: Duration,
+    // Reaproveita vereditos Block de requests idênticos (rotas podem desligar)
+    pub block_cache: bool,
+    // Content-Type declarado x corpo real: off | log | inspect | block (rotas podem trocar)
+    pub content_mismatch: ContentMismatch,
+    // Modo sombra: bloqueio por regra/conteúdo só é registrado e o request segue.
+    // Rate limit, desafio e auth continuam valendo
+    pub detect_only: bool,
+}
+
+impl Default for PolicyConfig {
+    fn default() -> Self {
+        PolicyConfig {
+            block: RejectPolicy::Respond,
+            rate_limit: RejectPolicy::Respond,
+            silent_drop_hold: Duration::from_secs(30),
+            block_cache: true,
+            content_mismatch: ContentMismatch::Inspect,
+            detect_only: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BansConfig {
+    // Linux: bans a partir de kernel_min_ttl viram drop no nftables (precisa de CAP_NET_ADMIN)
+    pub kernel_filter: bool,
+    #[serde(deserialize_with = "secs")]
+    pub kernel_min_ttl: Duration,
+    // Vereditos Block na janela até o ban automático (0 = desligado)
+    pub auto_ban_blocks: u32,
+    #[serde(deserialize_with = "secs")]
+    pub auto_ban_window: Duration,
+    #[serde(deserialize_with = "secs")]
+    pub auto_ban_ttl: Duration,
+    // Uma linha por block/rate_limit/ban/unban (fail2ban: datepattern {EPOCH})
+    pub event_log: Option<String>,
+    // Mesma linha como datagrama num socket unix
+    pub event_socket: Option<String>,
+}
+
+impl Default for BansConfig {
+    fn default() -> Self {
+        BansConfig {
+            kernel_filter: false,
+            kernel_min_ttl: Duration::from_secs(600),
+            auto_ban_blocks: 0,
+            auto_ban_window: Duration::from_secs(60),
+            auto_ban_ttl: Duration::from_secs(3600),
+            event_log: None,
+            event_socket: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChallengeConfig {
+    #[serde(deserialize_with = "secs")]
+    pub ttl: Duration,
+    // Histórico sem timing soma no máximo 60; precisa de timing ou UA inconsistente
+    pub bot_score: u32,
+    // Bits zero exigidos no sha256 da prova de trabalho; cada bit dobra o custo
+    pub difficulty: u32,
+    pub nonce_capacity: usize,
+    // Clientes que nunca recebem desafio (under attack, DDoS, bot score)
+    pub exempt: AccessList,
+}
+
+impl Default for ChallengeConfig {
+    fn default() -> Self {
+        ChallengeConfig {
+            ttl: Duration::from_secs(3600),
+            bot_score: 70,
+            difficulty: 18,
+            nonce_capacity: 100_000,
+            exempt: AccessList::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MitigationAction {
+    Challenge,
+    RateLimit,
+}
+
+impl MitigationAction {
+    pub fn label(&self) -> &'static str {
+        match self {
+            MitigationAction::Challenge => "challenge",
+            MitigationAction::RateLimit => "rate_limit",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DdosConfig {
+    pub enabled: bool,
+    #[serde(deserialize_with = "secs")]
+    pub window: Duration,
+    // Pico = média de requests/s na janela acima disso
+    pub spike_rate: f64,
+    // Fração do tráfego da janela que torna um cluster dominante
+    pub dominant_share: f64,
+    pub min_requests: u64,
+    pub action: MitigationAction,
+    #[serde(deserialize_with = "secs")]
+    pub mitigation_ttl: Duration,
+    // rate_limit: um bucket para o cluster inteiro, não por IP
+    pub cluster_rate: f64,
+    pub cluster_burst: f64,
+    pub max_mitigations: usize,
+}
+
+impl Default for DdosConfig {
+    fn default() -> Self {
+        DdosConfig {
+            enabled: false,
+            window: Duration::from_secs(10),
+            spike_rate: 200.0,
+            dominant_share: 0.3,
+            min_requests: 500,
+            action: MitigationAction::Challenge,
+            mitigation_ttl: Duration::from_secs(300),
+            cluster_rate: 20.0,
+            cluster_burst: 50.0,
+            max_mitigations: 32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AcmeConfig {
+    // Nomes do certificado (um SAN só); vazio = ACME desligado
+    pub domains: Vec<String>,
+    pub directory: String,
+    // ["mailto:ops@exemplo.com"]
+    pub contact: Vec<String>,
+    // Aceite dos termos da CA; sem ele nenhuma conta é criada
+    pub terms_agreed: bool,
+    // Chave da conta, certificado e chave emitidos
+    pub cache_dir: String,
+    #[serde(deserialize_with = "secs")]
+    pub renew_before: Duration,
+    // Raízes extras para falar com o diretório (Pebble, CA interna)
+    pub ca_file: Option<String>,
+}
+
+impl Default for AcmeConfig {
+    fn default() -> Self {
+        AcmeConfig {
+            domains: Vec::new(),
+            directory: "https://acme-v02.api.letsencrypt.org/directory".to_string(),
+            contact: Vec::new(),
+            terms_agreed: false,
+            cache_dir: "acme".to_string(),
+            renew_before: Duration::from_secs(30 * 86400),
+            ca_file: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MirrorConfig {
+    // Servidor NATS (host:port) que recebe um JSON por request; ausente = desligado
+    pub nats: Option<String>,
+    pub subject: String,
+    pub batch_size: usize,
+    #[serde(deserialize_with = "secs")]
+    pub flush_interval: Duration,
+    // Eventos esperando o sink; cheio = descartado (oblivion_mirror_dropped_total)
+    pub queue_capacity: usize,
+}
+
+impl Default for MirrorConfig {
+    fn default() -> Self {
+        MirrorConfig {
+            nats: None,
+            subject: "oblivion.requests".to_string(),
+            batch_size: 256,
+            flush_interval: Duration::from_secs(1),
+            queue_capacity: 10_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+    pub fn label(&self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HostPrefix {
+    // __Host-/__Secure- passam como vieram
+    #[default]
+    Off,
+    // Completa o que o prefixo exige (Secure, Path=/, sem Domain)
+    Fix,
+    // Cookie que não cumpre o prefixo sai da resposta
+    Drop,
+}
+
+// Atributos impostos aos Set-Cookie do upstream (apps legadas sem Secure/HttpOnly)
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CookieConfig {
+    pub secure: bool,
+    pub http_only: bool,
+    // Substitui o SameSite do upstream; None também força Secure
+    pub same_site: Option<SameSite>,
+    // Cookies que o JS precisa ler (token CSRF): ficam sem HttpOnly
+    pub http_only_exempt: Vec<String>,
+    pub host_prefix: HostPrefix,
+}
+
+impl CookieConfig {
+    pub fn enabled(&self) -> bool {
+        self.secure
+            || self.http_only
+            || self.same_site.is_some()
+            || self.host_prefix != HostPrefix::Off
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlainMode {
+    // 301 para https:// no mesmo Host
+    #[default]
+    Redirect,
+    // Mesmo pipeline do HTTPS, sem TLS (atrás de outro terminador ou rede interna)
+    Proxy,
+}
+
+// Listener HTTP sem TLS (porta 80), desligado sem addr
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlainHttpConfig {
+    pub addr: Option<String>,
+    pub mode: PlainMode,
+    // Porta do Location; ausente = a do primeiro [[listener]] (443 some da URL)
+    pub https_port: Option<u16>,
+    // Como no [[listener]]: header PROXY antes do request
+    pub proxy_protocol: bool,
+    pub proxy_from: Vec<Cidr>,
+}
+
+// Tráfego serviço-a-serviço: sem normalização, regras nem rate limit (só log)
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InternalConfig {
+    pub cidrs: Vec<Cidr>,
+    // CN ou SAN DNS do certificado de cliente (exige listener.client_ca)
+    pub identities: Vec<String>,
+}
+
+impl InternalConfig {
+    pub fn matches(&self, ip: IpAddr, identities: &[String]) -> bool {
+        self.cidrs.iter().any(|c| c.contains(ip))
+            || identities
+                .iter()
+                .any(|id| self.identities.iter().any(|i| i.eq_ignore_ascii_case(id)))
+    }
+}
+
+// Conexões WebSocket (Upgrade: websocket) depois do 101: frames validados nos
+// dois sentidos
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebSocketConfig {
+    // Mensagens de texto do cliente passam pelas regras como REQUEST_BODY;
+    // permessage-deflate sai do handshake para elas chegarem legíveis
+    pub inspect: bool,
+    // Soma dos fragmentos de uma mensagem; acima disso close 1009
+    pub max_message_size: usize,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        WebSocketConfig {
+            inspect: false,
+            max_message_size: 1024 * 1024,
+        }
+    }
+}
+
+// Requests gRPC (Content-Type application/grpc): chegam por HTTP/2 e vão ao
+// upstream h2c com os trailers (grpc-status) de volta
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GrpcConfig {
+    // Cada mensagem do corpo; acima disso RESOURCE_EXHAUSTED sem chegar ao upstream
+    pub max_message_size: usize,
+    // :path aceitos, "/pacote.Serviço/Método" ou "/pacote.Serviço/*"; vazio = todos
+    pub methods: Vec<String>,
+}
+
+impl Default for GrpcConfig {
+    fn default() -> Self {
+        GrpcConfig {
+            max_message_size: 4 * 1024 * 1024,
+            methods: Vec::new(),
+        }
+    }
+}
+
+// Respostas do upstream vistas pelas regras com alvo RESPONSE_BODY; rota com
+// inspect_response / decompress_response decide por cima destes
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResponseConfig {
+    pub inspect: bool,
+    // Corpo como veio do upstream; acima disso a resposta passa sem inspeção
+    pub max_body_size: usize,
+    // Prefixos do Content-Type inspecionados (imagem, vídeo etc. passam direto)
+    pub content_types: Vec<String>,
+    // gzip, deflate e br abertos para as regras e recomprimidos se o corpo mudar
+    pub decompress: bool,
+    // Limite do corpo descomprimido (bomba de compressão) e do tempo gasto nele
+    pub max_decompressed_size: usize,
+    #[serde(deserialize_with = "secs")]
+    pub decompress_timeout: Duration,
+}
+
+impl Default for ResponseConfig {
+    fn default() -> Self {
+        ResponseConfig {
+            inspect: false,
+            max_body_size: 1024 * 1024,
+            content_types: [
+                "text/",
+                "application/json",
+                "application/xml",
+                "application/javascript",
+            ]
+            .map(String::from)
+            .to_vec(),
+            decompress: true,
+            max_decompressed_size: 8 * 1024 * 1024,
+            decompress_timeout: Duration::from_millis(200),
+        }
+    }
+}
+
+// Credenciais que as rotas podem exigir com `auth:`; cada mecanismo só vale
+// se configurado aqui. Segredo e chaves só mudam com restart
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    // Exigência de quem não declara `auth:`; vazio = rota aberta
+    pub default: Vec<Mechanism>,
+    // JWT HS256 em "Authorization: Bearer"; iss/aud checados se configurados
+    pub jwt_secret_file: Option<String>,
+    pub jwt_issuer: Option<String>,
+    pub jwt_audience: Option<String>,
+    // Tolerância de relógio em exp/nbf
+    pub jwt_leeway: u64,
+    // Token sem exp não vale para sempre: recusado, a não ser que desligado aqui
+    pub jwt_require_exp: bool,
+    // Uma chave por linha: "<nome> <sha256 hex da chave>"; o nome é o principal
+    pub api_keys_file: Option<String>,
+    pub api_key_header: String,
+    // Cookie de sessão da aplicação: o WAF só confere a presença, quem valida é o backend
+    pub session_cookie: Option<String>,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            default: Vec::new(),
+            jwt_secret_file: None,
+            jwt_issuer: None,
+            jwt_audience: None,
+            jwt_leeway: 30,
+            jwt_require_exp: true,
+            api_keys_file: None,
+            api_key_header: "X-Api-Key".to_string(),
+            session_cookie: None,
+        }
+    }
+}
+
+// OPTIONS * (asterisk-form, RFC 9112 3.2.4): pergunta sobre o servidor, não sobre um recurso
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OptionsPolicy {
+    // 200 com o Allow de server.allow, sem chegar ao upstream
+    #[default]
+    Respond,
+    // Segue para o upstream como qualquer request
+    Forward,
+    // 501 sem corpo
+    Reject,
+}
+
+impl OptionsPolicy {
+    pub fn label(&self) -> &'static str {
+        match self {
+            OptionsPolicy::Respond => "respond",
+            OptionsPolicy::Forward => "forward",
+            OptionsPolicy::Reject => "reject",
+        }
+    }
+}
+
+// O que o WAF revela de si nas respostas que ele mesmo gera (bloqueio, 429,
+// 502/504, desafio, 401); respostas do upstream passam como vieram
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    // Valor do header Server:; vazio = nenhum
+    pub header: String,
+    // X-Oblivion-Error com o código do erro (auth, assinatura, replay)
+    pub error_header: bool,
+    pub options: OptionsPolicy,
+    // Allow: da resposta a OPTIONS *
+    pub allow: Vec<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            header: String::new(),
+            error_header: true,
+            options: OptionsPolicy::Respond,
+            allow: ["GET", "HEAD", "POST", "PUT", "DELETE", "PATCH", "OPTIONS"]
+                .map(String::from)
+                .to_vec(),
+        }
+    }
+}
+
+// Uma linha por request atendido, no formato de log_format do nginx ($var ou
+// ${var}); pipelines que já leem combined/JSON do nginx seguem iguais
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessLogConfig {
+    // None = desligado
+    pub path: Option<String>,
+    pub format: String,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        AccessLogConfig {
+            path: None,
+            format: access_log::DEFAULT_FORMAT.to_string(),
+        }
+    }
+}
+
+// Base IP -> país/ASN (iptoasn.com, TSV) para as listas de acesso; só muda com restart
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GeoConfig {
+    pub database: Option<String>,
+}
+
+// Snippet HTML (JS de bot detection, aviso, analytics) inserido nas páginas
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InjectConfig {
+    // Vazio = desligado; {nonce} vira um nonce CSP novo por resposta
+    pub snippet: String,
+    // Mesma sintaxe de rota (prefixo com *); vazio = todos os paths
+    pub paths: Vec<PathPattern>,
+    // Bytes do corpo segurados procurando </head> antes de desistir
+    pub scan_limit: usize,
+}
+
+impl Default for InjectConfig {
+    fn default() -> Self {
+        InjectConfig {
+            snippet: String::new(),
+            paths: Vec::new(),
+            scan_limit: 64 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuntimeMode {
+    // Um runtime multi-thread, estado único compartilhado
+    Shared,
+    // Uma thread por core com listener (SO_REUSEPORT), engine e limiters
+    // próprios; consumo dos limiters trocado pelo barramento
+    PerCore,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RuntimeConfig {
+    pub mode: RuntimeMode,
+    // 0 = um por CPU
+    pub workers: usize,
+    // A cada quanto cada core publica o consumo dos seus limiters
+    #[serde(deserialize_with = "secs")]
+    pub sync_interval: Duration,
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        RuntimeConfig {
+            mode: RuntimeMode::Shared,
+            workers: 0,
+            sync_interval: Duration::from_millis(50),
+        }
+    }
+}
+
+impl RuntimeConfig {
+    pub fn workers(&self) -> usize {
+        match self.mode {
+            RuntimeMode::Shared => 1,
+            RuntimeMode::PerCore if self.workers > 0 => self.workers,
+            RuntimeMode::PerCore => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+}
+
+// Marca por cliente nas respostas das rotas com watermark: (header/html)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WatermarkConfig {
+    // Sem arquivo o segredo é aleatório por processo (tokens só rastreáveis pela trilha)
+    pub secret_file: Option<String>,
+    pub header: String,
+    // token -> ip/sessão/path, JSON por linha
+    pub audit_log: String,
+}
+
+impl Default for WatermarkConfig {
+    fn default() -> Self {
+        WatermarkConfig {
+            secret_file: None,
+            header: "X-Request-Ref".to_string(),
+            audit_log: "oblivion-watermarks.log".to_string(),
+        }
+    }
+}
+
+// Cookie first-party: ID aleatório assinado, nada derivado de IP, UA ou conta
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    pub enabled: bool,
+    pub cookie: String,
+    #[serde(deserialize_with = "secs")]
+    pub lifetime: Duration,
+    pub same_site: SameSite,
+    // Paths sem cookie e sem correlação (mesma sintaxe de rota: prefixo com *)
+    pub opt_out: Vec<PathPattern>,
+    // DNT: 1 / Sec-GPC: 1 desligam o rastreio para o request
+    pub respect_dnt: bool,
+    // Chave HMAC compartilhada entre instâncias; sem ela cada processo sorteia a sua
+    pub secret_file: Option<String>,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            enabled: false,
+            cookie: "oblivion_sid".to_string(),
+            lifetime: Duration::from_secs(30 * 24 * 3600),
+            same_site: SameSite::Lax,
+            opt_out: Vec::new(),
+            respect_dnt: true,
+            secret_file: None,
+        }
+    }
+}
+
+// [[site]]: aplicação atrás de um ou mais Hosts. Ausente = herda o global
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SiteConfig {
+    // "shop.example.com" ou "*.shop.example.com" (só subdomínios)
+    pub hosts: Vec<String>,
+    pub upstream: Option<String>,
+    pub protocol: Option<UpstreamProtocol>,
+    pub tls_server_name: Option<String>,
+    pub tls_ca: Option<String>,
+    pub tls_pins: Option<Vec<String>>,
+    #[serde(deserialize_with = "opt_secs")]
+    pub connect_timeout: Option<Duration>,
+    #[serde(deserialize_with = "opt_secs")]
+    pub first_byte_timeout: Option<Duration>,
+    #[serde(deserialize_with = "opt_secs")]
+    pub deadline: Option<Duration>,
+    pub max_response_size: Option<u64>,
+    pub normalize: Option<bool>,
+    pub max_body_size: Option<u64>,
+    pub max_inspect_body: Option<u64>,
+    pub block: Option<RejectPolicy>,
+    pub block_cache: Option<bool>,
+    pub content_mismatch: Option<ContentMismatch>,
+    pub detect_only: Option<bool>,
+    // Arquivo de regras próprio (rotas, perfis, regras); ausente = o global
+    pub rules: Option<String>,
+}
+
+impl SiteConfig {
+    // host já vem em minúsculas e sem porta
+    pub fn matches(&self, host: &str) -> bool {
+        SiteConfig::pattern_matches(&self.hosts, host)
+    }
+
+    pub fn pattern_matches(patterns: &[String], host: &str) -> bool {
+        patterns.iter().any(|pattern| {
+            let pattern = pattern.to_ascii_lowercase();
+            match pattern.strip_prefix("*.") {
+                Some(suffix) => host
+                    .strip_suffix(suffix)
+                    .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+                None => pattern == host,
+            }
+        })
+    }
+
+    fn apply(&self, config: &mut Config) {
+        let (upstream, client) = (&mut config.upstream, &mut config.client);
+        if let Some(addr) = &self.upstream {
+            upstream.addr = addr.clone();
+        }
+        upstream.protocol = self.protocol.unwrap_or(upstream.protocol);
+        if self.upstream.is_some() || self.tls_server_name.is_some() {
+            upstream.tls_server_name = self.tls_server_name.clone();
+        }
+        upstream.tls_ca = self.tls_ca.clone().or(upstream.tls_ca.take());
+        if let Some(pins) = &self.tls_pins {
+            upstream.tls_pins = pins.clone();
+        }
+        upstream.connect_timeout = self.connect_timeout.unwrap_or(upstream.connect_timeout);
+        upstream.first_byte_timeout = self
+            .first_byte_timeout
+            .unwrap_or(upstream.first_byte_timeout);
+        upstream.deadline = self.deadline.or(upstream.deadline);
+        upstream.max_response_size = self.max_response_size.unwrap_or(upstream.max_response_size);
+        upstream.normalize = self.normalize.unwrap_or(upstream.normalize);
+        client.max_body_size = self.max_body_size.unwrap_or(client.max_body_size);
+        client.max_inspect_body = self.max_inspect_body.or(client.max_inspect_body);
+        config.policy.block = self.block.unwrap_or(config.policy.block);
+        config.policy.block_cache = self.block_cache.unwrap_or(config.policy.block_cache);
+        config.policy.content_mismatch = self
+            .content_mismatch
+            .unwrap_or(config.policy.content_mismatch);
+        config.policy.detect_only = self.detect_only.unwrap_or(config.policy.detect_only);
+        if let Some(rules) = &self.rules {
+            config.files.rules = rules.clone();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    #[serde(rename = "listener")]
+    pub listeners: Vec<ListenerConfig>,
+    pub upstream: UpstreamConfig,
+    pub admin: AdminConfig,
+    pub files: FilesConfig,
+    pub client: ClientConfig,
+    pub rate_limit: RateLimitConfig,
+    pub accept: AcceptConfig,
+    pub policy: PolicyConfig,
+    pub bans: BansConfig,
+    pub challenge: ChallengeConfig,
+    pub ddos: DdosConfig,
+    pub session: SessionConfig,
+    pub cookies: CookieConfig,
+    pub inject: InjectConfig,
+    pub watermark: WatermarkConfig,
+    pub internal: InternalConfig,
+    pub plain_http: PlainHttpConfig,
+    pub mirror: MirrorConfig,
+    pub acme: AcmeConfig,
+    pub runtime: RuntimeConfig,
+    pub geo: GeoConfig,
+    pub websocket: WebSocketConfig,
+    pub grpc: GrpcConfig,
+    pub response: ResponseConfig,
+    pub auth: AuthConfig,
+    pub server: ServerConfig,
+    pub access_log: AccessLogConfig,
+    #[serde(rename = "site")]
+    pub sites: Vec<SiteConfig>,
+    // Config efetiva de cada site (global + overrides), montada em validated()
+    #[serde(skip)]
+    resolved: Vec<Arc<Config>>,
+    // Cliente TLS do upstream https, montado junto com os sites
+    #[serde(skip)]
+    upstream_tls: Option<Arc<UpstreamTls>>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listeners: vec![ListenerConfig::default()],
+            upstream: UpstreamConfig::default(),
+            admin: AdminConfig::default(),
+            files: FilesConfig::default(),
+            client: ClientConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            accept: AcceptConfig::default(),
+            policy: PolicyConfig::default(),
+            bans: BansConfig::default(),
+            challenge: ChallengeConfig::default(),
+            ddos: DdosConfig::default(),
+            session: SessionConfig::default(),
+            cookies: CookieConfig::default(),
+            inject: InjectConfig::default(),
+            watermark: WatermarkConfig::default(),
+            internal: InternalConfig::default(),
+            plain_http: PlainHttpConfig::default(),
+            mirror: MirrorConfig::default(),
+            acme: AcmeConfig::default(),
+            runtime: RuntimeConfig::default(),
+            geo: GeoConfig::default(),
+            websocket: WebSocketConfig::default(),
+            grpc: GrpcConfig::default(),
+            response: ResponseConfig::default(),
+            auth: AuthConfig::default(),
+            server: ServerConfig::default(),
+            access_log: AccessLogConfig::default(),
+            sites: Vec::new(),
+            resolved: Vec::new(),
+            upstream_tls: None,
+        }
+    }
+}
+
+const MAX_WORKERS: usize = 256;
+
+const ENV_PREFIX: &str = "OBLIVION_";
+// Tratadas pelo clap (mesmas das flags)
+const ENV_FLAGS: &[&str] = &["CONFIG", "LISTEN", "UPSTREAM", "CERT", "KEY", "LOG_LEVEL"];
+const ENV_ALIASES: &[(&str, &str, &str)] = &[
+    ("MAX_BODY", "client", "max_body_size"),
+    ("ADMIN", "admin", "addr"),
+    ("RULES", "files", "rules"),
+];
+const ENV_SECTIONS: &[&str] = &[
+    "upstream",
+    "admin",
+    "files",
+    "client",
+    "rate_limit",
+    "accept",
+    "policy",
+    "bans",
+    "challenge",
+    "ddos",
+    "mirror",
+    "acme",
+    "session",
+    "cookies",
+    "inject",
+    "watermark",
+    "internal",
+    "plain_http",
+    "runtime",
+    "geo",
+    "websocket",
+    "grpc",
+    "response",
+    "auth",
+    "server",
+    "access_log",
+];
+
+// Valor em sintaxe TOML (10, 0.5, true, ["::1"]); o resto vira string sem precisar de aspas
+fn env_value(raw: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("v = {}", raw))
+        .ok()
+        .and_then(|mut t| t.remove("v"))
+        .unwrap_or_else(|| toml::Value::String(raw.to_string()))
+}
+
+// OBLIVION_CLIENT_MAX_BODY_SIZE=1048576 -> ("client", "max_body_size", 1048576)
+fn env_overrides(
+    vars: impl Iterator<Item = (String, String)>,
+) -> Vec<(String, String, String, toml::Value)> {
+    let mut out = Vec::new();
+    for (name, raw) in vars {
+        let Some(rest) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        if ENV_FLAGS.contains(&rest) {
+            continue;
+        }
+        if let Some((_, section, key)) = ENV_ALIASES.iter().find(|(alias, ..)| *alias == rest) {
+            out.push((
+                name.clone(),
+                section.to_string(),
+                key.to_string(),
+                env_value(&raw),
+            ));
+            continue;
+        }
+        let lower = rest.to_ascii_lowercase();
+        let found = ENV_SECTIONS.iter().find_map(|section| {
+            lower
+                .strip_prefix(section)
+                .and_then(|k| k.strip_prefix('_'))
+                .filter(|k| !k.is_empty())
+                .map(|key| (section.to_string(), key.to_string()))
+        });
+        match found {
+            Some((section, key)) => out.push((name.clone(), section, key, env_value(&raw))),
+            None => warn!(variable = %name, "Unknown OBLIVION_ environment variable ignored"),
+        }
+    }
+    out
+}
+
+fn host_port(addr: &str) -> bool {
+    addr.rsplit_once(':')
+        .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok())
+}
+
+fn upstream_addr(addr: &str) -> bool {
+    let endpoint = split_scheme(addr).1;
+    !endpoint.contains('/') && host_port(endpoint)
+}
+
+impl Config {
+    // Arquivo ausente = padrões embutidos, a menos que tenha sido pedido explicitamente.
+    // OBLIVION_<SEÇÃO>_<CHAVE> vale por cima do arquivo
+    pub fn read(path: &str, required: bool) -> Result<Config, Error> {
+        let raw = match std::fs::read_to_string(path) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => String::new(),
+            Err(e) => return Err(Error::Config(format!("'{}' ilegível: {}", path, e))),
+        };
+        let overrides = env_overrides(std::env::vars());
+        // Sem variáveis o erro aponta linha e coluna do arquivo
+        if overrides.is_empty() {
+            return toml::from_str(&raw)
+                .map_err(|e| Error::Config(format!("'{}' inválido: {}", path, e)));
+        }
+
+        let mut table: toml::Table = toml::from_str(&raw)
+            .map_err(|e| Error::Config(format!("'{}' inválido: {}", path, e)))?;
+        let mut names = Vec::new();
+        for (name, section, key, value) in overrides {
+            let section = table
+                .entry(section)
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+            let Some(section) = section.as_table_mut() else {
+                return Err(Error::Config(format!("{}: seção inválida", name)));
+            };
+            section.insert(key, value);
+            names.push(name);
+        }
+        toml::Value::Table(table).try_into().map_err(|e| {
+            Error::Config(format!("'{}' + {} inválido: {}", path, names.join(", "), e))
+        })
+    }
+
+    // Depois dos overrides (flags, ambiente): o que sobe é o que foi validado
+    pub fn validated(mut self, source: &str) -> Result<Config, Error> {
+        self.validate()
+            .map_err(|e| Error::Config(format!("'{}' inválido: {}", source, e)))?;
+        self.resolve_sites()
+            .map_err(|e| Error::Config(format!("'{}' inválido: {}", source, e)))?;
+        Ok(self)
+    }
+
+    pub fn resolve_sites(&mut self) -> Result<(), String> {
+        self.upstream_tls = UpstreamTls::new(&self.upstream)?;
+        self.resolved = self
+            .sites
+            .iter()
+            .enumerate()
+            .map(|(i, site)| {
+                let mut config = self.clone();
+                config.sites = Vec::new();
+                config.resolved = Vec::new();
+                site.apply(&mut config);
+                config.upstream_tls =
+                    UpstreamTls::new(&config.upstream).map_err(|e| format!("site {}: {}", i, e))?;
+                Ok(Arc::new(config))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(())
+    }
+
+    pub fn upstream_tls(&self) -> Option<&Arc<UpstreamTls>> {
+        self.upstream_tls.as_ref()
+    }
+
+    // Nome do SNI (minúsculas) atendido por este listener ou por algum [[site]]
+    pub fn sni_allowed(&self, listener: &ListenerConfig, name: &str) -> bool {
+        let exact = |n: &String| n.eq_ignore_ascii_case(name);
+        listener.sni.iter().any(|s| s.names.iter().any(exact))
+            || SiteConfig::pattern_matches(&listener.server_names, name)
+            || self.sites.iter().any(|site| site.matches(name))
+            || self.acme.domains.iter().any(exact)
+    }
+
+    pub fn resolved_sites(&self) -> impl Iterator<Item = (&SiteConfig, &Arc<Config>)> {
+        self.sites.iter().zip(&self.resolved)
+    }
+
+    // Socket com PROXY protocol: Some(origens aceitas; vazio = qualquer uma)
+    pub fn proxy_protocol(&self, addr: SocketAddr) -> Option<&[Cidr]> {
+        let same = |a: &str| a.parse::<SocketAddr>().ok() == Some(addr);
+        if let Some(l) = self.listeners.iter().find(|l| same(&l.addr)) {
+            return l.proxy_protocol.then_some(&l.proxy_from[..]);
+        }
+        let plain = &self.plain_http;
+        (plain.proxy_protocol && plain.addr.as_deref().is_some_and(same))
+            .then_some(&plain.proxy_from[..])
+    }
+
+    // Host sem porta; o primeiro [[site]] que casar vale
+    pub fn site(&self, host: Option<&str>) -> Option<&Arc<Config>> {
+        let host = host?;
+        let host = match host.rsplit_once(':') {
+            Some((name, port)) if port.parse::<u16>().is_ok() => name,
+            _ => host,
+        };
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        let index = self.sites.iter().position(|s| s.matches(&host))?;
+        self.resolved.get(index)
+    }
+
+    // Sem v6_only explícito, [::] divide a porta com um 0.0.0.0 já configurado
+    pub fn v6_only(&self, listener: &ListenerConfig) -> bool {
+        if let Some(v6_only) = listener.v6_only {
+            return v6_only;
+        }
+        let Ok(addr) = listener.addr.parse::<SocketAddr>() else {
+            return false;
+        };
+        self.listeners.iter().any(|other| {
+            other
+                .addr
+                .parse::<SocketAddr>()
+                .is_ok_and(|o| o.is_ipv4() && o.port() == addr.port())
+        })
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.listeners.is_empty() {
+            return Err("listener: at least one listener is required".to_string());
+        }
+        let mut bound: Vec<SocketAddr> = Vec::new();
+        for listener in &self.listeners {
+            let addr: SocketAddr = listener.addr.parse().map_err(|_| {
+                format!(
+                    "listener.addr: '{}' is not an ip:port address",
+                    listener.addr
+                )
+            })?;
+            if bound.contains(&addr) {
+                return Err(format!("listener.addr: '{}' is listed twice", addr));
+            }
+            if listener.v6_only.is_some() && addr.is_ipv4() {
+                return Err(format!(
+                    "listener.v6_only: '{}' is not an IPv6 address",
+                    addr
+                ));
+            }
+            bound.push(addr);
+            let mut names: Vec<String> = Vec::new();
+            for entry in &listener.sni {
+                if entry.names.is_empty() {
+                    return Err(format!(
+                        "listener.sni: '{}' needs at least one name",
+                        entry.cert
+                    ));
+                }
+                for name in &entry.names {
+                    let name = name.to_ascii_lowercase();
+                    if names.contains(&name) {
+                        return Err(format!("listener.sni: '{}' is listed twice", name));
+                    }
+                    names.push(name);
+                }
+            }
+            if listener.strict_sni
+                && names.is_empty()
+                && listener.server_names.is_empty()
+                && self.sites.is_empty()
+            {
+                return Err(format!(
+                    "listener.strict_sni: '{}' has no server names to accept",
+                    addr
+                ));
+            }
+        }
+        if self.server.header.chars().any(|c| c.is_control()) {
+            return Err("server.header: control characters are not allowed".to_string());
+        }
+        if let Some(method) = self
+            .server
+            .allow
+            .iter()
+            .find(|m| m.is_empty() || !m.bytes().all(|b| b.is_ascii_uppercase() || b == b'-'))
+        {
+            return Err(format!("server.allow: '{}' is not a method", method));
+        }
+        if let Err(e) = access_log::check_format(&self.access_log.format) {
+            return Err(format!("access_log.format: {}", e));
+        }
+        if !self.internal.identities.is_empty()
+            && self.listeners.iter().all(|l| l.client_ca.is_none())
+        {
+            return Err(
+                "internal.identities: needs listener.client_ca to verify client certificates"
+                    .to_string(),
+            );
+        }
+        for (section, list) in [
+            ("admin.allow", &self.admin.allow),
+            ("challenge.exempt", &self.challenge.exempt),
+        ] {
+            list.validate(section)?;
+            if list.uses_geo() && self.geo.database.is_none() {
+                return Err(format!("{}: countries and asns need geo.database", section));
+            }
+        }
+        if self.admin.cert.is_some() != self.admin.key.is_some() {
+            return Err("admin.cert and admin.key go together".to_string());
+        }
+        if self.admin.client_ca.is_some() && self.admin.cert.is_none() {
+            return Err("admin.client_ca: needs admin.cert and admin.key".to_string());
+        }
+        if !self.admin.allow.identities.is_empty() && self.admin.client_ca.is_none() {
+            return Err(
+                "admin.allow.identities: needs admin.client_ca to verify client certificates"
+                    .to_string(),
+            );
+        }
+        if !self.challenge.exempt.identities.is_empty()
+            && self.listeners.iter().all(|l| l.client_ca.is_none())
+        {
+            return Err(
+                "challenge.exempt.identities: needs listener.client_ca to verify client certificates"
+                    .to_string(),
+            );
+        }
+        let proxy_from = self
+            .listeners
+            .iter()
+            .map(|l| ("listener.proxy_from", l.proxy_protocol, &l.proxy_from))
+            .chain([(
+                "plain_http.proxy_from",
+                self.plain_http.proxy_protocol,
+                &self.plain_http.proxy_from,
+            )]);
+        for (name, enabled, from) in proxy_from {
+            if !enabled && !from.is_empty() {
+                return Err(format!("{}: needs proxy_protocol = true", name));
+            }
+            // Sem lista, qualquer cliente anuncia o endereço que quiser e escapa de
+            // bans, limites e do vínculo do clearance
+            if enabled && from.is_empty() {
+                return Err(format!(
+                    "{}: required with proxy_protocol = true (the load balancer addresses)",
+                    name
+                ));
+            }
+        }
+        if let Some(method) = self.grpc.methods.iter().find(|m| !grpc::valid_method(m)) {
+            return Err(format!(
+                "grpc.methods: '{}' is not /package.Service/Method or /package.Service/*",
+                method
+            ));
+        }
+        if let Some(addr) = &self.plain_http.addr {
+            let addr: SocketAddr = addr
+                .parse()
+                .map_err(|_| format!("plain_http.addr: '{}' is not an ip:port address", addr))?;
+            if bound.contains(&addr) {
+                return Err(format!(
+                    "plain_http.addr: '{}' is already a TLS listener",
+                    addr
+                ));
+            }
+        }
+        let admin_addr = self.admin.addr.parse::<SocketAddr>().map_err(|_| {
+            format!(
+                "admin.addr: '{}' is not an ip:port address",
+                self.admin.addr
+            )
+        })?;
+        // Fora do loopback a API (ban, regras, under attack) fica na rede: sem
+        // token nem allow, qualquer um que alcance a porta administra
+        if !admin_addr.ip().is_loopback()
+            && self.admin.token_file.is_none()
+            && self.admin.allow.is_empty()
+        {
+            return Err(format!(
+                "admin.addr: '{}' is not loopback; set admin.token_file or admin.allow",
+                self.admin.addr
+            ));
+        }
+        if !upstream_addr(&self.upstream.addr) {
+            return Err(format!(
+                "upstream.addr: '{}' is not a host:port address",
+                self.upstream.addr
+            ));
+        }
+
+        for (name, value) in [
+            ("client.max_header_size", self.client.max_header_size as u64),
+            ("client.max_body_size", self.client.max_body_size),
+            ("client.max_inspect_body", self.client.inspect_limit()),
+            (
+                "client.slow_read_buffer",
+                self.client.slow_read_buffer as u64,
+            ),
+            (
+                "upstream.max_response_size",
+                self.upstream.max_response_size,
+            ),
+            (
+                "upstream.max_connections",
+                self.upstream.max_connections as u64,
+            ),
+            ("accept.max_active", self.accept.max_active as u64),
+            (
+                "challenge.nonce_capacity",
+                self.challenge.nonce_capacity as u64,
+            ),
+            ("ddos.min_requests", self.ddos.min_requests),
+            ("ddos.max_mitigations", self.ddos.max_mitigations as u64),
+            ("mirror.batch_size", self.mirror.batch_size as u64),
+            ("mirror.queue_capacity", self.mirror.queue_capacity as u64),
+            ("inject.scan_limit", self.inject.scan_limit as u64),
+            (
+                "websocket.max_message_size",
+                self.websocket.max_message_size as u64,
+            ),
+            ("grpc.max_message_size", self.grpc.max_message_size as u64),
+            ("response.max_body_size", self.response.max_body_size as u64),
+            (
+                "response.max_decompressed_size",
+                self.response.max_decompressed_size as u64,
+            ),
+        ] {
+            if value == 0 {
+                return Err(format!("{}: must be greater than zero", name));
+            }
+        }
+
+        let rl = &self.rate_limit;
+        for (name, value) in [
+            ("rate_limit.request_rate", rl.request_rate),
+            ("rate_limit.fingerprint_rate", rl.fingerprint_rate),
+            ("rate_limit.connection_rate", rl.connection_rate),
+            ("accept.rate_ceiling", self.accept.rate_ceiling),
+            ("client.min_read_rate", self.client.min_read_rate),
+            ("ddos.spike_rate", self.ddos.spike_rate),
+            ("ddos.cluster_rate", self.ddos.cluster_rate),
+        ] {
+            if !(value.is_finite() && value > 0.0) {
+                return Err(format!("{}: must be a positive number", name));
+            }
+        }
+        // Burst abaixo de 1 nunca libera um request
+        for (name, value) in [
+            ("rate_limit.request_burst", rl.request_burst),
+            ("rate_limit.fingerprint_burst", rl.fingerprint_burst),
+            ("rate_limit.connection_burst", rl.connection_burst),
+            ("ddos.cluster_burst", self.ddos.cluster_burst),
+        ] {
+            if !(value.is_finite() && value >= 1.0) {
+                return Err(format!("{}: must be at least 1", name));
+            }
+        }
+        if !(rl.under_attack_scale > 0.0 && rl.under_attack_scale <= 1.0) {
+            return Err("rate_limit.under_attack_scale: must be in (0, 1]".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.upstream.retry_budget) {
+            return Err("upstream.retry_budget: must be in [0, 1]".to_string());
+        }
+        let mut hosts: Vec<String> = Vec::new();
+        for (i, site) in self.sites.iter().enumerate() {
+            if site.hosts.is_empty() {
+                return Err(format!("site {}: at least one host is required", i));
+            }
+            for host in &site.hosts {
+                let name = host.strip_prefix("*.").unwrap_or(host);
+                if name.is_empty()
+                    || !name
+                        .bytes()
+                        .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.')
+                {
+                    return Err(format!("site {}: '{}' is not a valid host name", i, host));
+                }
+                let host = host.to_ascii_lowercase();
+                if hosts.contains(&host) {
+                    return Err(format!("site {}: host '{}' is listed twice", i, host));
+                }
+                hosts.push(host);
+            }
+            if let Some(addr) = &site.upstream
+                && !upstream_addr(addr)
+            {
+                return Err(format!(
+                    "site {}: upstream '{}' is not a host:port address",
+                    i, addr
+                ));
+            }
+            if site.max_body_size == Some(0) || site.max_response_size == Some(0) {
+                return Err(format!("site {}: size limits must be greater than zero", i));
+            }
+        }
+
+        if let Some(addr) = &self.mirror.nats
+            && !host_port(addr)
+        {
+            return Err(format!(
+                "mirror.nats: '{}' is not a host:port address",
+                addr
+            ));
+        }
+        // Subject NATS: tokens separados por ponto, sem espaço nem curinga
+        let subject = &self.mirror.subject;
+        if subject.split('.').any(|token| {
+            token.is_empty()
+                || token
+                    .bytes()
+                    .any(|b| b.is_ascii_whitespace() || b == b'*' || b == b'>')
+        }) {
+            return Err(format!(
+                "mirror.subject: '{}' is not a valid NATS subject",
+                subject
+            ));
+        }
+
+        let acme = &self.acme;
+        if !acme.domains.is_empty() {
+            if !acme.terms_agreed {
+                return Err("acme.terms_agreed: must be true to request certificates".to_string());
+            }
+            if !acme.directory.starts_with("https://") {
+                return Err(format!(
+                    "acme.directory: '{}' is not an https URL",
+                    acme.directory
+                ));
+            }
+            // tls-alpn-01 não valida wildcard
+            for domain in &acme.domains {
+                if domain.is_empty()
+                    || !domain
+                        .bytes()
+                        .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.')
+                {
+                    return Err(format!(
+                        "acme.domains: '{}' is not a valid host name",
+                        domain
+                    ));
+                }
+            }
+        }
+
+        // Nome vai direto no header Set-Cookie
+        let cookie = &self.session.cookie;
+        if cookie.is_empty()
+            || !cookie
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+        {
+            return Err(format!(
+                "session.cookie: '{}' is not a valid cookie name",
+                cookie
+            ));
+        }
+        if self.client.handshake_timeout.is_zero() {
+            return Err("client.handshake_timeout: must be greater than 0".to_string());
+        }
+        if self.runtime.workers > MAX_WORKERS {
+            return Err(format!("runtime.workers: at most {}", MAX_WORKERS));
+        }
+        if self.client.keepalive_requests == 0 {
+            return Err("client.keepalive_requests: must be greater than 0".to_string());
+        }
+        let header = &self.watermark.header;
+        if header.is_empty()
+            || !header
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+        {
+            return Err(format!(
+                "watermark.header: '{}' is not a valid header name",
+                header
+            ));
+        }
+        if !(self.ddos.dominant_share > 0.0 && self.ddos.dominant_share <= 1.0) {
+            return Err("ddos.dominant_share: must be in (0, 1]".to_string());
+        }
+        if rl.ipv6_prefix == 0 || rl.ipv6_prefix > 128 {
+            return Err(format!(
+                "rate_limit.ipv6_prefix: {} is not a valid prefix length (1-128)",
+                rl.ipv6_prefix
+            ));
+        }
+
+        if self.accept.emergency_threshold > self.accept.max_active {
+            return Err(format!(
+                "accept.emergency_threshold: {} is above accept.max_active ({})",
+                self.accept.emergency_threshold, self.accept.max_active
+            ));
+        }
+        if self.challenge.bot_score > 100 {
+            return Err(format!(
+                "challenge.bot_score: {} is above the maximum score (100)",
+                self.challenge.bot_score
+            ));
+        }
+        if !(1..=28).contains(&self.challenge.difficulty) {
+            return Err(format!(
+                "challenge.difficulty: {} must be between 1 and 28 bits",
+                self.challenge.difficulty
+            ));
+        }
+        Ok(())
+    }
+}
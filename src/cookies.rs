@@ -0,0 +1,123 @@
+use tracing::debug;
+
+use crate::config::{CookieConfig, HostPrefix, SameSite};
+
+// Reescreve os Set-Cookie do head da resposta conforme [cookies]; o resto dos
+// bytes passa intacto. Retorna quantos foram alterados e quantos removidos
+pub fn harden(head: &[u8], config: &CookieConfig) -> (Vec<u8>, usize, usize) {
+    let mut out = Vec::with_capacity(head.len() + 64);
+    let (mut rewritten, mut dropped) = (0, 0);
+    for (i, line) in head.split_inclusive(|b| *b == b'\n').enumerate() {
+        let value = (i > 0)
+            .then(|| line.iter().position(|b| *b == b':'))
+            .flatten()
+            .filter(|colon| line[..*colon].eq_ignore_ascii_case(b"Set-Cookie"))
+            .and_then(|colon| std::str::from_utf8(&line[colon + 1..]).ok());
+        let Some(value) = value else {
+            out.extend_from_slice(line);
+            continue;
+        };
+        let colon = line.iter().position(|b| *b == b':').unwrap_or(0);
+        match rewrite(value.trim(), config) {
+            Rewrite::Keep => out.extend_from_slice(line),
+            Rewrite::Changed(cookie) => {
+                rewritten += 1;
+                out.extend_from_slice(&line[..colon]);
+                out.extend_from_slice(format!(": {}\r\n", cookie).as_bytes());
+            }
+            Rewrite::Drop(name) => {
+                debug!(cookie = %name, "Dropped Set-Cookie violating its name prefix");
+                dropped += 1;
+            }
+        }
+    }
+    (out, rewritten, dropped)
+}
+
+enum Rewrite {
+    Keep,
+    Changed(String),
+    Drop(String),
+}
+
+fn rewrite(cookie: &str, config: &CookieConfig) -> Rewrite {
+    let mut parts = cookie.split(';').map(str::trim);
+    let pair = parts.next().unwrap_or_default();
+    let name = pair.split('=').next().unwrap_or_default().trim();
+    let mut attrs: Vec<String> = parts
+        .filter(|a| !a.is_empty())
+        .map(str::to_string)
+        .collect();
+    let has =
+        |attrs: &[String], key: &str| attrs.iter().any(|a| attr_name(a).eq_ignore_ascii_case(key));
+    let before = attrs.clone();
+
+    if let Some(same_site) = config.same_site
+        && !attrs.iter().any(|a| {
+            attr_name(a).eq_ignore_ascii_case("SameSite")
+                && attr_value(a).eq_ignore_ascii_case(same_site.label())
+        })
+    {
+        attrs.retain(|a| !attr_name(a).eq_ignore_ascii_case("SameSite"));
+        attrs.push(format!("SameSite={}", same_site.label()));
+    }
+    // Navegador descarta SameSite=None sem Secure
+    if (config.secure || config.same_site == Some(SameSite::None)) && !has(&attrs, "Secure") {
+        attrs.push("Secure".to_string());
+    }
+    if config.http_only
+        && !config.http_only_exempt.iter().any(|e| e == name)
+        && !has(&attrs, "HttpOnly")
+    {
+        attrs.push("HttpOnly".to_string());
+    }
+
+    // RFC 6265bis 4.1.3: __Secure- exige Secure; __Host- também Path=/ e nenhum Domain
+    let host = starts_with_ignore_case(name, "__Host-");
+    if config.host_prefix != HostPrefix::Off && (host || starts_with_ignore_case(name, "__Secure-"))
+    {
+        let path_ok = attrs
+            .iter()
+            .filter(|a| attr_name(a).eq_ignore_ascii_case("Path"))
+            .all(|a| attr_value(a) == "/")
+            && has(&attrs, "Path");
+        let valid = has(&attrs, "Secure") && (!host || (path_ok && !has(&attrs, "Domain")));
+        if !valid {
+            if config.host_prefix == HostPrefix::Drop {
+                return Rewrite::Drop(name.to_string());
+            }
+            if !has(&attrs, "Secure") {
+                attrs.push("Secure".to_string());
+            }
+            if host {
+                attrs.retain(|a| {
+                    !attr_name(a).eq_ignore_ascii_case("Domain")
+                        && !attr_name(a).eq_ignore_ascii_case("Path")
+                });
+                attrs.push("Path=/".to_string());
+            }
+        }
+    }
+
+    if attrs == before {
+        return Rewrite::Keep;
+    }
+    let mut cookie = pair.to_string();
+    for attr in &attrs {
+        cookie.push_str("; ");
+        cookie.push_str(attr);
+    }
+    Rewrite::Changed(cookie)
+}
+
+fn attr_name(attr: &str) -> &str {
+    attr.split('=').next().unwrap_or_default().trim()
+}
+
+fn attr_value(attr: &str) -> &str {
+    attr.split_once('=').map_or("", |(_, v)| v.trim())
+}
+
+fn starts_with_ignore_case(s: &str, prefix: &str) -> bool {
+    s.len() >= prefix.len() && s.as_bytes()[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes())
+}
@@ -0,0 +1,214 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, oneshot};
+use tokio::task::JoinSet;
+use tracing::{debug, error, info};
+
+use crate::coalesce::Coalescer;
+use crate::engine::WafEngine;
+use crate::error::Error;
+use crate::h2c::H2Pool;
+use crate::limiter::RateLimiter;
+use crate::reload::SiteEngines;
+use crate::tls::ListenerTls;
+use crate::{accept_loop, listener, Context};
+
+// Core que atrasa mais que isso perde mensagens (e conta): o limite fica um
+// pouco mais frouxo, nunca mais apertado
+const BUS_CAPACITY: usize = 4096;
+
+pub type Sockets = Vec<(std::net::TcpListener, Option<Arc<ArcSwap<ListenerTls>>>)>;
+
+#[derive(Debug)]
+pub enum Message {
+    // Fichas que um core concedeu por cliente desde a última publicação
+    Consumed {
+        core: usize,
+        limiter: &'static str,
+        grants: Vec<(IpAddr, u32)>,
+    },
+    // Reload trocou as regras: cada core refaz as réplicas
+    Rules,
+}
+
+// Único ponto de contato entre os cores; o resto do estado de cada um é só dele
+#[derive(Clone)]
+pub struct Bus {
+    tx: broadcast::Sender<Arc<Message>>,
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        Bus {
+            tx: broadcast::channel(BUS_CAPACITY).0,
+        }
+    }
+
+    pub fn publish(&self, message: Message) {
+        let _ = self.tx.send(Arc::new(message));
+    }
+}
+
+fn replicas(base: &Context) -> (WafEngine, SiteEngines) {
+    let sites = base
+        .sites
+        .load()
+        .iter()
+        .map(|(path, engine)| (path.clone(), Arc::new(engine.replica())))
+        .collect();
+    (base.engine.load().replica(), sites)
+}
+
+// Contexto do core: limiters, engines e pools próprios; o resto (config,
+// bans, sessões, admission...) continua compartilhado
+pub fn context(base: &Context, limiters: [Arc<RateLimiter>; 3]) -> Context {
+    let [limiter, conn_limiter, fingerprint_limiter] = limiters;
+    let (engine, sites) = replicas(base);
+    Context {
+        engine: Arc::new(ArcSwap::from_pointee(engine)),
+        sites: Arc::new(ArcSwap::from_pointee(sites)),
+        limiter,
+        conn_limiter,
+        fingerprint_limiter,
+        h2c: H2Pool::new(),
+        coalescer: Coalescer::new(),
+        ..base.clone()
+    }
+}
+
+// Thread com runtime próprio (current_thread) rodando os accept loops dos seus
+// sockets; o receiver fecha quando ela termina
+pub fn spawn(
+    core: usize,
+    ctx: Context,
+    base: Arc<Context>,
+    sockets: Sockets,
+    bus: &Bus,
+    sync_interval: Duration,
+) -> Result<oneshot::Receiver<()>, Error> {
+    let (done, finished) = oneshot::channel::<()>();
+    // Inscrito antes de a thread subir: nenhum reload se perde no caminho
+    let rx = bus.tx.subscribe();
+    let bus = bus.clone();
+    std::thread::Builder::new()
+        .name(format!("oblivion-core-{}", core))
+        .spawn(move || {
+            let _done = done;
+            let runtime = match tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(runtime) => runtime,
+                Err(e) => {
+                    error!(core, error = %e, "Core runtime failed to start");
+                    return;
+                }
+            };
+            runtime.block_on(serve(
+                core,
+                Arc::new(ctx),
+                base,
+                sockets,
+                bus,
+                rx,
+                sync_interval,
+            ));
+        })
+        .map_err(|e| Error::Config(format!("core {}: thread não iniciou: {}", core, e)))?;
+    Ok(finished)
+}
+
+async fn serve(
+    core: usize,
+    ctx: Arc<Context>,
+    base: Arc<Context>,
+    sockets: Sockets,
+    bus: Bus,
+    rx: broadcast::Receiver<Arc<Message>>,
+    sync_interval: Duration,
+) {
+    tokio::spawn(publish(core, ctx.clone(), bus, sync_interval));
+    tokio::spawn(listen(core, ctx.clone(), base, rx));
+
+    let mut accept_loops = JoinSet::new();
+    for (socket, tls) in sockets {
+        match listener::register(socket) {
+            Ok(socket) => {
+                accept_loops.spawn(accept_loop(socket, tls, ctx.clone()));
+            }
+            Err(e) => error!(core, category = e.category(), error = %e, "Core listener failed"),
+        }
+    }
+    debug!(core, listeners = accept_loops.len(), "Core started");
+    while let Some(result) = accept_loops.join_next().await {
+        if let Err(e) = result {
+            error!(core, error = %e, "Accept loop stopped");
+        }
+    }
+    info!(core, "Core stopped");
+}
+
+fn limiters(ctx: &Context) -> [&Arc<RateLimiter>; 3] {
+    [&ctx.limiter, &ctx.conn_limiter, &ctx.fingerprint_limiter]
+}
+
+async fn publish(core: usize, ctx: Arc<Context>, bus: Bus, sync_interval: Duration) {
+    let mut interval = tokio::time::interval(sync_interval);
+    loop {
+        interval.tick().await;
+        for limiter in limiters(&ctx) {
+            let grants = limiter.drain_outbox();
+            if !grants.is_empty() {
+                bus.publish(Message::Consumed {
+                    core,
+                    limiter: limiter.name(),
+                    grants,
+                });
+            }
+        }
+    }
+}
+
+async fn listen(
+    core: usize,
+    ctx: Arc<Context>,
+    base: Arc<Context>,
+    mut rx: broadcast::Receiver<Arc<Message>>,
+) {
+    loop {
+        let message = match rx.recv().await {
+            Ok(message) => message,
+            // Entre as perdidas pode ter ido um Rules: refaz as réplicas por garantia
+            Err(RecvError::Lagged(missed)) => {
+                debug!(core, missed, "Core bus lagging, limiter updates lost");
+                ctx.metrics
+                    .add("oblivion_core_bus_lagged_total", &[], missed);
+                Arc::new(Message::Rules)
+            }
+            Err(RecvError::Closed) => return,
+        };
+        match message.as_ref() {
+            Message::Consumed { core: from, .. } if *from == core => {}
+            Message::Consumed {
+                limiter, grants, ..
+            } => {
+                let Some(target) = limiters(&ctx).into_iter().find(|l| l.name() == *limiter) else {
+                    continue;
+                };
+                for (ip, tokens) in grants {
+                    target.debit(*ip, *tokens);
+                }
+            }
+            Message::Rules => {
+                let (engine, sites) = replicas(&base);
+                ctx.engine.store(Arc::new(engine));
+                ctx.sites.store(Arc::new(sites));
+                debug!(core, "Core rules replaced");
+            }
+        }
+    }
+}
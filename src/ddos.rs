@@ -0,0 +1,243 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+use crate::config::{DdosConfig, MitigationAction};
+use crate::http::Request;
+use crate::metrics::{path_template, Metrics};
+use crate::tls::HelloInfo;
+
+// A janela anda em fatias: contagem aproximada sem guardar timestamp por request
+const SLOTS: usize = 10;
+// Flood com fingerprint aleatória não pode crescer o mapa sem limite
+const MAX_FINGERPRINTS: usize = 10_000;
+const MAX_LABEL_UA: usize = 80;
+
+fn hash_of(value: &impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Fuzzy: valores que mudam a cada request (query, IDs no path, cookies) ficam de fora
+pub struct Fingerprint {
+    hash: u64,
+    label: String,
+}
+
+pub fn fingerprint(req: &Request, hello: &HelloInfo) -> Fingerprint {
+    let method = req.method.to_ascii_uppercase();
+    let template = path_template(&req.path);
+    let mut names: Vec<String> = req
+        .header_order
+        .iter()
+        .map(|h| h.to_ascii_lowercase())
+        .collect();
+    names.sort();
+    names.dedup();
+    let headers = hash_of(&names);
+    let user_agent = req.header("User-Agent").unwrap_or("");
+    let hash = hash_of(&(&method, &template, headers, user_agent, hello.fingerprint));
+    let user_agent: String = user_agent.chars().take(MAX_LABEL_UA).collect();
+    Fingerprint {
+        hash,
+        label: format!(
+            "{} {} ua={:?} headers={:016x} tls={:016x}",
+            method, template, user_agent, headers, hello.fingerprint
+        ),
+    }
+}
+
+pub enum Decision {
+    Pass,
+    Challenge,
+    RateLimited,
+}
+
+#[derive(Default)]
+struct Slot {
+    counts: HashMap<u64, (u64, String)>,
+    total: u64,
+}
+
+// Regra temporária gerada para um cluster dominante
+struct Mitigation {
+    action: MitigationAction,
+    label: String,
+    expires: Instant,
+    tokens: f64,
+    last_update: Instant,
+    hits: u64,
+}
+
+struct State {
+    slots: VecDeque<Slot>,
+    slot_started: Instant,
+    mitigations: HashMap<u64, Mitigation>,
+}
+
+pub struct MitigationInfo {
+    pub id: u64,
+    pub action: MitigationAction,
+    pub label: String,
+    pub remaining: Duration,
+    pub hits: u64,
+}
+
+pub struct DdosDetector {
+    state: Mutex<State>,
+    metrics: Arc<Metrics>,
+}
+
+impl DdosDetector {
+    pub fn new(metrics: Arc<Metrics>) -> Arc<Self> {
+        Arc::new(DdosDetector {
+            state: Mutex::new(State {
+                slots: (0..SLOTS).map(|_| Slot::default()).collect(),
+                slot_started: Instant::now(),
+                mitigations: HashMap::new(),
+            }),
+            metrics,
+        })
+    }
+
+    pub fn observe(&self, fingerprint: &Fingerprint, config: &DdosConfig) -> Decision {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+
+        let slot_len = config.window / SLOTS as u32;
+        let elapsed = now.duration_since(state.slot_started);
+        if elapsed >= slot_len {
+            let steps = ((elapsed.as_secs_f64() / slot_len.as_secs_f64()) as usize).min(SLOTS);
+            for _ in 0..steps {
+                state.slots.pop_front();
+                state.slots.push_back(Slot::default());
+            }
+            state.slot_started = now;
+            state.mitigations.retain(|id, m| {
+                let alive = m.expires > now;
+                if !alive {
+                    info!(
+                        cluster = format!("{:016x}", id),
+                        hits = m.hits,
+                        "DDoS cluster mitigation expired"
+                    );
+                }
+                alive
+            });
+            self.evaluate(&mut state, config, now);
+        }
+
+        let slot = state.slots.back_mut().expect("window always has slots");
+        slot.total += 1;
+        if let Some((count, _)) = slot.counts.get_mut(&fingerprint.hash) {
+            *count += 1;
+        } else if slot.counts.len() < MAX_FINGERPRINTS {
+            slot.counts
+                .insert(fingerprint.hash, (1, fingerprint.label.clone()));
+        }
+
+        let Some(mitigation) = state.mitigations.get_mut(&fingerprint.hash) else {
+            return Decision::Pass;
+        };
+        mitigation.hits += 1;
+        match mitigation.action {
+            MitigationAction::Challenge => Decision::Challenge,
+            MitigationAction::RateLimit => {
+                let refill = now.duration_since(mitigation.last_update).as_secs_f64();
+                mitigation.tokens =
+                    (mitigation.tokens + refill * config.cluster_rate).min(config.cluster_burst);
+                mitigation.last_update = now;
+                if mitigation.tokens >= 1.0 {
+                    mitigation.tokens -= 1.0;
+                    Decision::Pass
+                } else {
+                    Decision::RateLimited
+                }
+            }
+        }
+    }
+
+    // Só em pico: tráfego normal concentrado numa rota popular não vira regra
+    fn evaluate(&self, state: &mut State, config: &DdosConfig, now: Instant) {
+        let total: u64 = state.slots.iter().map(|s| s.total).sum();
+        if (total as f64) < config.spike_rate * config.window.as_secs_f64() {
+            return;
+        }
+        let mut clusters: HashMap<u64, (u64, &str)> = HashMap::new();
+        for slot in &state.slots {
+            for (hash, (count, label)) in &slot.counts {
+                clusters.entry(*hash).or_insert((0, label)).0 += count;
+            }
+        }
+
+        let mut created = Vec::new();
+        for (hash, (count, label)) in clusters {
+            let share = count as f64 / total as f64;
+            if count < config.min_requests
+                || share < config.dominant_share
+                || state.mitigations.contains_key(&hash)
+            {
+                continue;
+            }
+            if state.mitigations.len() + created.len() >= config.max_mitigations {
+                warn!(cluster = %label, "DDoS mitigation table full, cluster not mitigated");
+                continue;
+            }
+            warn!(
+                cluster = format!("{:016x}", hash),
+                fingerprint = %label,
+                requests = count,
+                share = format!("{:.2}", share),
+                action = config.action.label(),
+                ttl = ?config.mitigation_ttl,
+                "🚨 DDoS cluster detected: mitigation rule created"
+            );
+            created.push((hash, label.to_string()));
+        }
+        for (hash, label) in created {
+            self.metrics.inc(
+                "oblivion_ddos_mitigations_total",
+                &[("action", config.action.label())],
+            );
+            state.mitigations.insert(
+                hash,
+                Mitigation {
+                    action: config.action,
+                    label,
+                    expires: now + config.mitigation_ttl,
+                    tokens: config.cluster_burst,
+                    last_update: now,
+                    hits: 0,
+                },
+            );
+        }
+    }
+
+    pub fn list(&self) -> Vec<MitigationInfo> {
+        let now = Instant::now();
+        let state = self.state.lock().unwrap();
+        let mut out: Vec<MitigationInfo> = state
+            .mitigations
+            .iter()
+            .filter(|(_, m)| m.expires > now)
+            .map(|(id, m)| MitigationInfo {
+                id: *id,
+                action: m.action,
+                label: m.label.clone(),
+                remaining: m.expires - now,
+                hits: m.hits,
+            })
+            .collect();
+        out.sort_by_key(|m| std::cmp::Reverse(m.hits));
+        out
+    }
+
+    pub fn lift(&self, id: u64) -> bool {
+        self.state.lock().unwrap().mitigations.remove(&id).is_some()
+    }
+}
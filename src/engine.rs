@@ -1,126 +1,843 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, Hash, Hasher, RandomState};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::{debug, info, warn};
+
 use crate::http::Request;
-use percent_encoding::percent_decode_str;
+use crate::metrics::Metrics;
+use crate::profiles::{normalize_host, Profile};
+use crate::routes::Route;
+use crate::rules::{
+    apply_chain, url_decode, value_hash, Action, MatchedDataConfig, Rule, RuleSet, Severity,
+    Target, Transform,
+};
+
+// Onde a regra casou, para o evento dispensar re-rodar o payload.
+// offset/length são bytes no valor já transformado, que é de onde sai o context.
+#[derive(Debug, Clone)]
+pub struct MatchedData {
+    pub rule: u32,
+    pub field: String,
+    pub offset: usize,
+    pub length: usize,
+    pub context: String,
+    // Família do payload (regra + forma normalizada), agrupa campanhas
+    pub family: u64,
+    // sha256 do valor transformado inteiro: é o que vai em suppress
+    pub value_hash: String,
+    pub severity: Option<Severity>,
+}
 
 #[derive(Debug)]
 pub enum Verdict {
     Allow,
-    Block(String),
+    Block(String, Option<MatchedData>),
+}
+
+fn floor_boundary(value: &str, mut i: usize) -> usize {
+    while !value.is_char_boundary(i) {
+        i -= 1;
+    }
+    i
+}
+
+fn ceil_boundary(value: &str, mut i: usize) -> usize {
+    while !value.is_char_boundary(i) {
+        i += 1;
+    }
+    i
+}
+
+// Valores de pares "campo=valor" sensíveis dentro de URI/body/cookie inteiros
+fn sensitive_ranges(value: &str, config: &MatchedDataConfig) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let mut from = 0;
+    for (i, c) in value
+        .char_indices()
+        .chain(std::iter::once((value.len(), '&')))
+    {
+        if !matches!(c, '&' | '?' | ';') {
+            continue;
+        }
+        let segment = &value[from..i];
+        if let Some((key, _)) = segment.split_once('=')
+            && config
+                .mask_fields
+                .iter()
+                .any(|m| m.eq_ignore_ascii_case(key.trim()))
+        {
+            ranges.push((from + key.len() + 1, i));
+        }
+        from = i + c.len_utf8();
+    }
+    ranges
+}
+
+// Forma do payload: números e tokens com dígito (IDs, hashes, nonces) viram '0',
+// espaço vira um só. O path antes da query fica de fora (mesmo payload em rotas
+// diferentes é a mesma campanha); campo sensível entra só pelo trecho casado
+const MAX_SHAPE: usize = 512;
+
+fn payload_family(rule: u32, value: &str, span: (usize, usize), masked: bool) -> u64 {
+    let source = match value[..span.0].find('?') {
+        _ if masked => &value[span.0..span.1],
+        Some(query) => &value[query..],
+        None => value,
+    };
+    let mut shape = String::with_capacity(source.len().min(MAX_SHAPE));
+    let mut token = String::new();
+    let flush = |token: &mut String, shape: &mut String| {
+        if token.bytes().any(|b| b.is_ascii_digit()) {
+            shape.push('0');
+        } else {
+            shape.push_str(token);
+        }
+        token.clear();
+    };
+    for c in source.chars() {
+        if shape.len() >= MAX_SHAPE {
+            break;
+        }
+        if c.is_alphanumeric() {
+            token.push(c);
+            continue;
+        }
+        flush(&mut token, &mut shape);
+        if c.is_whitespace() {
+            if !shape.ends_with(' ') {
+                shape.push(' ');
+            }
+        } else {
+            shape.push(c);
+        }
+    }
+    flush(&mut token, &mut shape);
+    let mut hasher = DefaultHasher::new();
+    (rule, shape.trim()).hash(&mut hasher);
+    hasher.finish()
+}
+
+// O trecho casado fica sempre visível; em volta dele, o que for sensível vira '*'
+fn match_context(
+    value: &str,
+    (start, end): (usize, usize),
+    config: &MatchedDataConfig,
+    masked: &[(usize, usize)],
+) -> String {
+    let from = floor_boundary(value, start.saturating_sub(config.context));
+    let to = ceil_boundary(value, (end + config.context).min(value.len()));
+    value[from..to]
+        .char_indices()
+        .map(|(i, c)| {
+            let i = from + i;
+            let hidden = (i < start || i >= end) && masked.iter().any(|(a, b)| i >= *a && i < *b);
+            if hidden {
+                '*'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+// Flood de payload idêntico vindo de muitos IPs: o Block é reaproveitado sem
+// re-normalizar. Só Block entra no cache; Allow sempre passa pelo pipeline.
+const BLOCK_CACHE_CAPACITY: usize = 10_000;
+const BLOCK_CACHE_TTL: Duration = Duration::from_secs(60);
+
+type Blocked = (String, Option<MatchedData>);
+
+struct BlockCache {
+    hasher: RandomState,
+    entries: Mutex<HashMap<u64, (Blocked, Instant)>>,
+}
+
+impl BlockCache {
+    // Tudo que as regras podem olhar entra na chave
+    fn key(&self, req: &Request) -> u64 {
+        let mut hasher = self.hasher.build_hasher();
+        req.method.hash(&mut hasher);
+        req.path.hash(&mut hasher);
+        req.version.hash(&mut hasher);
+        for name in &req.header_order {
+            name.hash(&mut hasher);
+            req.headers.get(name).hash(&mut hasher);
+        }
+        req.body.hash(&mut hasher);
+        // Regra de fingerprint: mesmo request de outro cliente TLS não herda o bloqueio
+        (&req.ja3, &req.ja4).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn get(&self, key: u64) -> Option<Blocked> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(&key)
+            .filter(|(_, stored)| stored.elapsed() < BLOCK_CACHE_TTL)
+            .map(|(blocked, _)| blocked.clone())
+    }
+
+    fn store(&self, key: u64, blocked: Blocked) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= BLOCK_CACHE_CAPACITY && !entries.contains_key(&key) {
+            entries.retain(|_, (_, stored)| stored.elapsed() < BLOCK_CACHE_TTL);
+            if entries.len() >= BLOCK_CACHE_CAPACITY {
+                return false;
+            }
+        }
+        entries.insert(key, (blocked, Instant::now()));
+        true
+    }
 }
 
 pub struct WafEngine {
-    sqli_signatures: Vec<&'static str>,
-    xss_signatures: Vec<&'static str>,
-    traversal_signatures: Vec<&'static str>,
+    // Compartilhado entre as réplicas por core; o cache de bloqueio é de cada uma
+    rules: Arc<RuleSet>,
     allowed_methods: Vec<&'static str>,
+    metrics: Arc<Metrics>,
+    block_cache: BlockCache,
+}
+
+type CacheKey = (Target, Vec<Transform>);
+
+// Cache dos valores já transformados nesta request: várias regras compartilham
+// o mesmo alvo e a mesma cadeia
+struct Transformed<'a> {
+    req: &'a Request,
+    // Corpo da resposta (já descomprimido) na inspeção da resposta
+    response: Option<&'a str>,
+    params: Vec<(String, String)>,
+    cache: Vec<(CacheKey, Vec<String>)>,
+    trace: Option<Vec<String>>,
+}
+
+impl Transformed<'_> {
+    fn note(&mut self, line: impl FnOnce() -> String) {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.push(line());
+        }
+    }
+}
+
+impl<'a> Transformed<'a> {
+    fn new(req: &'a Request, tracing: bool) -> Self {
+        Transformed {
+            req,
+            response: None,
+            params: req.params(),
+            cache: Vec::new(),
+            trace: tracing.then(Vec::new),
+        }
+    }
+
+    // (nome do campo dentro da coleção, valor); o nome só existe para ARGS/HEADERS/COOKIES
+    fn raw(&self, target: &Target) -> Vec<(Option<&str>, &str)> {
+        let req = self.req;
+        match target {
+            Target::Uri => vec![(None, req.path.as_str())],
+            Target::Path => vec![(None, req.path_only())],
+            Target::QueryString => req.query().map(|q| (None, q)).into_iter().collect(),
+            Target::Args(selector) => self
+                .params
+                .iter()
+                .filter(|(k, _)| selector.as_ref().is_none_or(|name| k == name))
+                .map(|(k, v)| (Some(k.as_str()), v.as_str()))
+                .collect(),
+            Target::ArgsNames => self
+                .params
+                .iter()
+                .map(|(k, _)| (None, k.as_str()))
+                .collect(),
+            Target::Headers(selector) => req
+                .headers
+                .iter()
+                .filter(|(k, _)| {
+                    selector
+                        .as_ref()
+                        .is_none_or(|name| k.eq_ignore_ascii_case(name))
+                })
+                .map(|(k, v)| (Some(k.as_str()), v.as_str()))
+                .collect(),
+            Target::HeaderNames => req.headers.keys().map(|k| (None, k.as_str())).collect(),
+            Target::Cookies(selector) => req
+                .cookies()
+                .filter(|(k, _)| selector.as_ref().is_none_or(|name| k == name))
+                .map(|(k, v)| (Some(k), v))
+                .collect(),
+            Target::Body => vec![(None, req.body.as_str())],
+            Target::ResponseBody => self.response.map(|body| (None, body)).into_iter().collect(),
+            Target::Method => vec![(None, req.method.as_str())],
+            Target::Ja3 => req.ja3.iter().map(|v| (None, v.as_str())).collect(),
+            Target::Ja4 => req.ja4.iter().map(|v| (None, v.as_str())).collect(),
+        }
+    }
+
+    fn values(&mut self, target: &Target, chain: &[Transform]) -> &[String] {
+        let idx = match self
+            .cache
+            .iter()
+            .position(|((t, c), _)| t == target && c == chain)
+        {
+            Some(idx) => idx,
+            None => {
+                let values: Vec<String> = self
+                    .raw(target)
+                    .into_iter()
+                    .map(|(_, v)| apply_chain(chain, v))
+                    .collect();
+                self.note(|| {
+                    format!(
+                        "  {} | t:{} => {:?}",
+                        target,
+                        Transform::chain_name(chain),
+                        values
+                    )
+                });
+                self.cache.push(((target.clone(), chain.to_vec()), values));
+                self.cache.len() - 1
+            }
+        };
+        &self.cache[idx].1
+    }
+
+    fn locate(
+        &mut self,
+        rule: &Rule,
+        config: &MatchedDataConfig,
+        suppressed: Option<&HashSet<String>>,
+    ) -> Option<MatchedData> {
+        for target in &rule.targets {
+            let names: Vec<Option<String>> = self
+                .raw(target)
+                .into_iter()
+                .map(|(name, _)| name.map(str::to_string))
+                .collect();
+            let values = self.values(target, &rule.transforms);
+            for (name, value) in names.iter().zip(values) {
+                if target.excluded(name.as_deref(), &rule.exclusions) {
+                    continue;
+                }
+                let Some(span) = rule.operator.find(value) else {
+                    continue;
+                };
+                let hash = value_hash(value);
+                if suppressed.is_some_and(|s| s.contains(&hash)) {
+                    continue;
+                }
+                let field = match (name, target) {
+                    (
+                        Some(name),
+                        Target::Args(None) | Target::Headers(None) | Target::Cookies(None),
+                    ) => {
+                        format!("{}:{}", target, name)
+                    }
+                    _ => target.to_string(),
+                };
+                let masked = match name {
+                    Some(n) if config.mask_fields.iter().any(|m| m.eq_ignore_ascii_case(n)) => {
+                        vec![(0, value.len())]
+                    }
+                    Some(_) => Vec::new(),
+                    None => sensitive_ranges(value, config),
+                };
+                return Some(MatchedData {
+                    rule: rule.id,
+                    field,
+                    offset: span.0,
+                    length: span.1 - span.0,
+                    context: match_context(value, span, config, &masked),
+                    family: payload_family(rule.id, value, span, !masked.is_empty()),
+                    value_hash: hash,
+                    severity: rule.severity,
+                });
+            }
+        }
+        None
+    }
+
+    // Valor com hash em suppressed não conta como match desta regra
+    fn matches(&mut self, rule: &Rule, suppressed: Option<&HashSet<String>>) -> bool {
+        rule.targets.iter().any(|target| {
+            let excluded = self.excluded(rule, target);
+            self.values(target, &rule.transforms)
+                .iter()
+                .enumerate()
+                .any(|(i, v)| {
+                    !excluded.contains(&i)
+                        && rule.operator.matches(v)
+                        && suppressed.is_none_or(|s| !s.contains(&value_hash(v)))
+                })
+        })
+    }
+
+    // Posições (na ordem de raw) dos valores que a regra exclui
+    fn excluded(&self, rule: &Rule, target: &Target) -> Vec<usize> {
+        if rule.exclusions.is_empty() {
+            return Vec::new();
+        }
+        self.raw(target)
+            .into_iter()
+            .enumerate()
+            .filter(|(_, (name, _))| target.excluded(*name, &rule.exclusions))
+            .map(|(i, _)| i)
+            .collect()
+    }
 }
 
 impl WafEngine {
-    pub fn new() -> Self {
+    pub fn new(rules: RuleSet, metrics: Arc<Metrics>) -> Self {
+        Self::with_rules(Arc::new(rules), metrics)
+    }
+
+    fn with_rules(rules: Arc<RuleSet>, metrics: Arc<Metrics>) -> Self {
         WafEngine {
-            sqli_signatures: vec![
-                "drop table",
-                "or 1=1",
-                "union select",
-                "--",
-                "sleep(",
-                "pg_sleep",
-                "waitfor delay",
-                "select * from",
-            ],
-            xss_signatures: vec![
-                "<script>",
-                "javascript:",
-                "onerror=",
-                "onload=",
-                "alert(",
-                "document.cookie",
-                "vbscript:",
-            ],
-            traversal_signatures: vec![
-                "../",
-                "..\\",
-                "/etc/passwd",
-                "c:\\windows",
-                "%2e%2e%2f",
-                ".env",
-                "config.php",
-            ],
+            rules,
             allowed_methods: vec!["GET", "POST", "HEAD"],
+            metrics,
+            block_cache: BlockCache {
+                hasher: RandomState::new(),
+                entries: Mutex::new(HashMap::new()),
+            },
         }
     }
 
+    // Mesmas regras, cache próprio: o engine de um core
+    pub fn replica(&self) -> Self {
+        Self::with_rules(self.rules.clone(), self.metrics.clone())
+    }
+
+    pub fn rules(&self) -> &RuleSet {
+        &self.rules
+    }
+
+    fn route_for(&self, host: &str, path: &str) -> Option<&Route> {
+        self.rules.routes.iter().find(|r| r.matches(host, path))
+    }
+
+    pub fn route(&self, req: &Request) -> Option<&Route> {
+        let host = normalize_host(req.header("Host").unwrap_or(""));
+        self.route_for(&host, &url_decode(req.path_only()))
+    }
+
+    // (perfil, regras avaliadas) de um request liberado: todas as que o perfil permite
+    pub fn coverage(&self, req: &Request, route: Option<&Route>) -> (Option<&str>, usize) {
+        let host = normalize_host(req.header("Host").unwrap_or(""));
+        let profile = self.rules.profile_for(&host, route);
+        let evaluated = self
+            .rules
+            .rules
+            .iter()
+            .filter(|rule| profile.is_none_or(|(_, p)| p.allows(rule)))
+            .count();
+        (profile.map(|(name, _)| name), evaluated)
+    }
+
+    pub fn target_values(
+        &self,
+        req: &Request,
+        target: &Target,
+        chain: &[Transform],
+    ) -> Vec<String> {
+        Transformed::new(req, false).values(target, chain).to_vec()
+    }
+
+    // Custo isolado de uma regra (inclui a cadeia de transformações dela)
+    pub fn match_rule(&self, req: &Request, rule: &Rule) -> bool {
+        Transformed::new(req, false).matches(rule, None)
+    }
+
     pub fn inspect(&self, req: &Request) -> Verdict {
+        self.evaluate(&mut Transformed::new(req, false))
+    }
+
+    pub fn inspect_cached(&self, req: &Request) -> Verdict {
+        let key = self.block_cache.key(req);
+        if let Some((reason, matched)) = self.block_cache.get(key) {
+            debug!("Block verdict served from cache");
+            self.metrics
+                .inc("oblivion_block_cache_total", &[("result", "hit")]);
+            return Verdict::Block(reason, matched);
+        }
+
+        let verdict = self.inspect(req);
+        if let Verdict::Block(reason, matched) = &verdict {
+            let result = if self
+                .block_cache
+                .store(key, (reason.clone(), matched.clone()))
+            {
+                "store"
+            } else {
+                "full"
+            };
+            self.metrics
+                .inc("oblivion_block_cache_total", &[("result", result)]);
+        } else {
+            self.metrics
+                .inc("oblivion_block_cache_total", &[("result", "miss")]);
+        }
+        verdict
+    }
+
+    pub fn trace(&self, req: &Request) -> (Verdict, Vec<String>) {
+        let mut transformed = Transformed::new(req, true);
+        transformed.note(|| format!("request: {} {}", req.method, req.path));
+        for (k, v) in &req.headers {
+            transformed.note(|| format!("  header {}: {}", k, v));
+        }
+        transformed.note(|| format!("  body: {:?}", req.body));
+        let params = format!("  params: {:?}", transformed.params);
+        transformed.note(|| params);
+        let verdict = self.evaluate(&mut transformed);
+        transformed.note(|| format!("verdict: {:?}", verdict));
+        (verdict, transformed.trace.unwrap_or_default())
+    }
+
+    // Todo par alvo/cadeia usado pelas regras, sem depender do veredito
+    pub fn normalized(&self, req: &Request) -> Vec<String> {
+        let mut transformed = Transformed::new(req, true);
+        for rule in &self.rules.rules {
+            for target in &rule.targets {
+                transformed.values(target, &rule.transforms);
+            }
+        }
+        transformed.trace.unwrap_or_default()
+    }
+
+    // Método, framing e cabeçalhos obrigatórios: o que o backend poderia ler
+    // diferente do WAF. Vale antes das regras e não é afrouxado por detect_only
+    pub fn protocol_violation(&self, req: &Request) -> Option<String> {
         if !self.allowed_methods.contains(&req.method.as_str()) {
-            return Verdict::Block(format!("Method Not Allowed: {}", req.method));
+            return Some(format!("Method Not Allowed: {}", req.method));
+        }
+
+        // "chunked" puro é remontado antes da inspeção (e o CL junto, descartado);
+        // qualquer outra forma de TE é ambígua entre o WAF e o backend
+        if req.has_header("Transfer-Encoding") && !req.chunked() {
+            return Some("Smuggling Attempt: ambiguous Transfer-Encoding".to_string());
+        }
+
+        if req.has_header("Content-Length") && req.content_length().is_none() {
+            return Some("Protocol Anomaly: Invalid Content-Length".to_string());
+        }
+
+        if !req.has_header("Host") {
+            return Some("Protocol Anomaly: Missing Host Header".to_string());
+        }
+
+        None
+    }
+
+    fn evaluate(&self, transformed: &mut Transformed) -> Verdict {
+        let req = transformed.req;
+        if let Some(reason) = self.protocol_violation(req) {
+            return Verdict::Block(reason, None);
+        }
+
+        let decoded_path = url_decode(&req.path);
+        if decoded_path.contains('\0') || url_decode(&req.body).contains('\0') {
+            return Verdict::Block("Null Byte Injection Detected".to_string(), None);
         }
 
-        if req.headers.contains_key("Content-Length")
-            && req.headers.contains_key("Transfer-Encoding")
+        if decoded_path.contains('\r') || decoded_path.contains('\n') {
+            return Verdict::Block("CRLF Injection Detected".to_string(), None);
+        }
+
+        let host = normalize_host(req.header("Host").unwrap_or(""));
+        let route_path = url_decode(req.path_only());
+        let route = self.route_for(&host, &route_path);
+        transformed.note(|| {
+            format!(
+                "host={:?} normalized_path={:?} route={:?}",
+                host,
+                route_path,
+                route.map(|r| &r.pattern)
+            )
+        });
+
+        if let Some(route) = route
+            && let Err(reason) = route.check_params(&transformed.params)
         {
-            return Verdict::Block("Smuggling Attempt: CL and TE headers present".to_string());
+            return Verdict::Block(format!("Parameter Policy: {}", reason), None);
         }
 
-        if !req.headers.contains_key("Host") {
-            return Verdict::Block("Protocol Anomaly: Missing Host Header".to_string());
+        let profile = self.rules.profile_for(&host, route);
+        transformed.note(|| format!("profile={:?}", profile.map(|(name, _)| name)));
+        if let Some((name, profile)) = profile
+            && let Err(reason) = profile.check_limits(req, &transformed.params)
+        {
+            return Verdict::Block(format!("Profile '{}' Limit: {}", name, reason), None);
         }
 
-        let normalize = |input: &str| -> Result<String, String> {
-            let mut decoded = input.to_string();
-            let mut loop_count = 0;
+        self.apply_rules(transformed, profile, false)
+    }
+
+    // Resposta liberada pelo upstream: só as regras com alvo RESPONSE_BODY, com o
+    // perfil (e as supressões) do request que a pediu
+    pub fn inspect_response(&self, req: &Request, body: &str) -> Verdict {
+        let mut transformed = Transformed::new(req, false);
+        transformed.response = Some(body);
+        let host = normalize_host(req.header("Host").unwrap_or(""));
+        let route = self.route_for(&host, &url_decode(req.path_only()));
+        let profile = self.rules.profile_for(&host, route);
+        self.apply_rules(&mut transformed, profile, true)
+    }
 
-            loop {
-                if decoded.contains('\0') {
-                    return Err("Null Byte Injection Detected".to_string());
-                }
+    fn apply_rules(
+        &self,
+        transformed: &mut Transformed,
+        profile: Option<(&str, &Profile)>,
+        response: bool,
+    ) -> Verdict {
+        // Anomaly scoring (arquivo de regras ou perfil): regras block somam pontos
+        let threshold = profile
+            .and_then(|(_, p)| p.anomaly_threshold)
+            .or(self.rules.anomaly_scoring.map(|a| a.threshold));
+        let mut score = 0;
+        let mut contributors: Vec<u32> = Vec::new();
+        // O match que mais pesou vai para o evento
+        let mut heaviest: Option<(u32, Option<MatchedData>)> = None;
+        for rule in &self.rules.rules {
+            if response && !rule.targets.contains(&Target::ResponseBody) {
+                continue;
+            }
+            if profile.is_some_and(|(_, p)| !p.allows(rule)) {
+                transformed.note(|| format!("rule {}: disabled by profile", rule.id));
+                continue;
+            }
+            let suppressed = self.rules.suppressions.get(&rule.id);
+            let matched = transformed.matches(rule, suppressed);
+            if !matched && suppressed.is_some() && transformed.matches(rule, None) {
+                transformed.note(|| format!("rule {}: suppressed for this value", rule.id));
+                self.metrics.inc(
+                    "oblivion_rule_suppressed_total",
+                    &[("rule", &rule.id.to_string())],
+                );
+                continue;
+            }
+            transformed.note(|| {
+                format!(
+                    "rule {}: {} on {} -> {}",
+                    rule.id,
+                    rule.description(),
+                    rule.targets
+                        .iter()
+                        .map(|t| t.to_string())
+                        .collect::<Vec<_>>()
+                        .join("|"),
+                    if matched { "MATCH" } else { "no match" }
+                )
+            });
+            if !matched {
+                continue;
+            }
 
-                let with_spaces = decoded.replace('+', " ");
-                match percent_decode_str(&with_spaces).decode_utf8() {
-                    Ok(d) => {
-                        let new_val = d.to_string();
-                        if new_val == decoded || loop_count > 5 {
-                            break;
-                        }
-                        decoded = new_val;
+            let action = self.rules.action_for(rule);
+            self.metrics.inc(
+                "oblivion_rule_matches_total",
+                &[("rule", &rule.id.to_string()), ("action", action.label())],
+            );
+            for tag in &rule.tags {
+                self.metrics.inc(
+                    "oblivion_rule_tag_matches_total",
+                    &[("tag", tag), ("action", action.label())],
+                );
+            }
+
+            let reason = format!("{}: {}", rule.category.label(), rule.description());
+            let matched = transformed.locate(rule, &self.rules.matched_data, suppressed);
+            match (action, threshold) {
+                (Action::Block, None) => return Verdict::Block(reason, matched),
+                (Action::Block, Some(threshold)) => {
+                    let points = rule.anomaly_score();
+                    score += points;
+                    contributors.push(rule.id);
+                    transformed.note(|| {
+                        format!(
+                            "rule {}: +{} anomaly points ({}/{})",
+                            rule.id, points, score, threshold
+                        )
+                    });
+                    if heaviest.as_ref().is_none_or(|(p, _)| points > *p) {
+                        heaviest = Some((points, matched));
+                    }
+                    if score >= threshold {
+                        break;
                     }
-                    Err(_) => break,
                 }
-                loop_count += 1;
+                (Action::Log, _) => {
+                    let matched = matched.as_ref();
+                    warn!(
+                        rule = rule.id,
+                        reason = %reason,
+                        field = matched.map(|m| m.field.as_str()),
+                        offset = matched.map(|m| m.offset),
+                        length = matched.map(|m| m.length),
+                        context = matched.map(|m| m.context.escape_debug().to_string()),
+                        value_hash = matched.map(|m| m.value_hash.as_str()),
+                        "Rule matched (log only)"
+                    )
+                }
             }
-            Ok(decoded.to_lowercase())
-        };
+        }
 
-        let clean_path = match normalize(&req.path) {
-            Ok(s) => s,
-            Err(reason) => return Verdict::Block(reason),
-        };
+        if let Some(threshold) = threshold
+            && !contributors.is_empty()
+        {
+            let rules = contributors
+                .iter()
+                .map(u32::to_string)
+                .collect::<Vec<_>>()
+                .join(",");
+            if score >= threshold {
+                self.metrics
+                    .inc("oblivion_anomaly_verdicts_total", &[("verdict", "block")]);
+                return Verdict::Block(
+                    format!(
+                        "Anomaly Score: {} >= {} (rules {})",
+                        score, threshold, rules
+                    ),
+                    heaviest.and_then(|(_, matched)| matched),
+                );
+            }
+            self.metrics
+                .inc("oblivion_anomaly_verdicts_total", &[("verdict", "allow")]);
+            info!(score, threshold, rules = %rules, "Anomaly score below threshold");
+        }
+        Verdict::Allow
+    }
+}
 
-        let clean_body = match normalize(&req.body) {
-            Ok(s) => s,
-            Err(reason) => return Verdict::Block(reason),
-        };
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
 
-        if clean_path.contains('\r') || clean_path.contains('\n') {
-            return Verdict::Block("CRLF Injection Detected".to_string());
-        }
+    use serde::Deserialize;
 
-        let payload_check = format!("{} {}", clean_path, clean_body);
+    use super::*;
+    use crate::cli::payload_request;
 
-        for sig in &self.sqli_signatures {
-            if payload_check.contains(sig) {
-                return Verdict::Block(format!("SQL Injection: '{}'", sig));
-            }
+    // Corpus versionado (tests/normalization.yaml); mudar uma saída esperada é
+    // mudar o contrato da normalização, então a versão sobe junto
+    const CORPUS: &str = include_str!("../tests/normalization.yaml");
+    const CORPUS_VERSION: u32 = 2;
+    const FAMILIES: &[&str] = &[
+        "double_encoding",
+        "utf7",
+        "overlong_utf8",
+        "mixed_case_hex",
+        "null_padding",
+        "sql_comments",
+    ];
+
+    #[derive(Deserialize)]
+    struct Corpus {
+        version: u32,
+        vectors: Vec<Vector>,
+    }
+
+    #[derive(Deserialize)]
+    struct Vector {
+        name: String,
+        family: String,
+        request: String,
+        transforms: String,
+        normalized: String,
+        verdict: String,
+        rule: Option<u32>,
+    }
+
+    #[test]
+    fn normalization_conformance() {
+        let corpus: Corpus = serde_yaml::from_str(CORPUS).unwrap();
+        assert_eq!(corpus.version, CORPUS_VERSION, "corpus version changed");
+        for family in FAMILIES {
+            assert!(
+                corpus.vectors.iter().any(|v| v.family == *family),
+                "no vectors left for {}",
+                family
+            );
         }
-        for sig in &self.xss_signatures {
-            if payload_check.contains(sig) {
-                return Verdict::Block(format!("XSS: '{}'", sig));
+
+        let rules = RuleSet::parse(include_str!("../rules/default.yaml"), Path::new(".")).unwrap();
+        let engine = WafEngine::new(rules, Metrics::new());
+        let mut failures = Vec::new();
+        for vector in &corpus.vectors {
+            let req = payload_request(&vector.request).unwrap();
+            let chain = Transform::parse_chain(&vector.transforms).unwrap();
+            let input = if req.body.is_empty() {
+                &req.path
+            } else {
+                &req.body
+            };
+            let normalized = apply_chain(&chain, input);
+            if normalized != vector.normalized {
+                failures.push(format!(
+                    "{}: normalized {:?}, expected {:?}",
+                    vector.name, normalized, vector.normalized
+                ));
             }
-        }
-        for sig in &self.traversal_signatures {
-            if payload_check.contains(sig) {
-                return Verdict::Block(format!("Path Traversal: '{}'", sig));
+
+            let (verdict, rule) = match engine.inspect(&req) {
+                Verdict::Allow => ("allow", None),
+                Verdict::Block(_, matched) => ("block", matched.map(|m| m.rule)),
+            };
+            if verdict != vector.verdict || vector.rule.is_some_and(|id| rule != Some(id)) {
+                failures.push(format!(
+                    "{}: {} (rule {:?}), expected {} (rule {:?})",
+                    vector.name, verdict, rule, vector.verdict, vector.rule
+                ));
             }
         }
+        assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+    }
 
-        Verdict::Allow
+    #[test]
+    fn protocol_violations_are_separate_from_rule_verdicts() {
+        let rules = RuleSet::parse(include_str!("../rules/default.yaml"), Path::new(".")).unwrap();
+        let engine = WafEngine::new(rules, Metrics::new());
+
+        let smuggled = Request::parse(
+            "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked, identity\r\n\r\n",
+        )
+        .unwrap();
+        assert!(matches!(engine.inspect(&smuggled), Verdict::Block(..)));
+        assert!(engine.protocol_violation(&smuggled).is_some());
+
+        let bad_length =
+            Request::parse("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: +5\r\n\r\n").unwrap();
+        assert!(engine.protocol_violation(&bad_length).is_some());
+
+        // Bloqueio de regra: é o que detect_only pode deixar passar
+        let sqli = payload_request("/search?q=%3Cscript%3Ealert(1)%3C/script%3E").unwrap();
+        assert!(matches!(engine.inspect(&sqli), Verdict::Block(..)));
+        assert!(engine.protocol_violation(&sqli).is_none());
+    }
+
+    #[test]
+    fn seclang_exclusions_filter_matched_fields() {
+        let mut rules = RuleSet::parse("rules: []", Path::new(".")).unwrap();
+        rules.rules = crate::seclang::translate(
+            r#"SecRule ARGS|REQUEST_HEADERS|!ARGS:token|!REQUEST_HEADERS:x-trace "@contains evil" "id:30,deny""#,
+            Path::new("."),
+        )
+        .rules;
+        let engine = WafEngine::new(rules, Metrics::new());
+
+        let inspect = |raw: &str| engine.inspect(&Request::parse(raw).unwrap());
+        assert!(matches!(
+            inspect("GET /?token=evil HTTP/1.1\r\nHost: a\r\nX-Trace: evil\r\n\r\n"),
+            Verdict::Allow
+        ));
+        match inspect("GET /?token=evil&q=evil HTTP/1.1\r\nHost: a\r\n\r\n") {
+            Verdict::Block(_, Some(m)) => assert_eq!(m.field, "ARGS:q"),
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(
+            inspect("GET / HTTP/1.1\r\nHost: a\r\nX-Other: evil\r\n\r\n"),
+            Verdict::Block(..)
+        ));
     }
 }
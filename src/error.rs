@@ -0,0 +1,72 @@
+use std::fmt;
+use std::io;
+
+// Códigos de saída estáveis para supervisores (systemd, k8s) separarem as causas
+const EXIT_TEST_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 64;
+const EXIT_PARSE: i32 = 65;
+const EXIT_UPSTREAM: i32 = 69;
+const EXIT_BIND: i32 = 71;
+const EXIT_TLS: i32 = 77;
+const EXIT_CONFIG: i32 = 78;
+
+#[derive(Debug)]
+pub enum Error {
+    Usage(String),
+    Config(String),
+    Tls(String),
+    Bind(String, io::Error),
+    Parse(String),
+    Upstream(String),
+    UpstreamTimeout(String),
+    // Connection refused: backend parado ou reiniciando
+    UpstreamRefused(String),
+    TestFailure(String),
+}
+
+impl Error {
+    pub fn category(&self) -> &'static str {
+        match self {
+            Error::Usage(_) => "usage",
+            Error::Config(_) => "config",
+            Error::Tls(_) => "tls",
+            Error::Bind(..) => "bind",
+            Error::Parse(_) => "parse",
+            Error::Upstream(_) | Error::UpstreamTimeout(_) | Error::UpstreamRefused(_) => {
+                "upstream"
+            }
+            Error::TestFailure(_) => "test",
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Usage(_) => EXIT_USAGE,
+            Error::Config(_) => EXIT_CONFIG,
+            Error::Tls(_) => EXIT_TLS,
+            Error::Bind(..) => EXIT_BIND,
+            Error::Parse(_) => EXIT_PARSE,
+            Error::Upstream(_) | Error::UpstreamTimeout(_) | Error::UpstreamRefused(_) => {
+                EXIT_UPSTREAM
+            }
+            Error::TestFailure(_) => EXIT_TEST_FAILURE,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Usage(msg)
+            | Error::Config(msg)
+            | Error::Tls(msg)
+            | Error::Parse(msg)
+            | Error::TestFailure(msg) => write!(f, "{}", msg),
+            Error::Bind(addr, e) => write!(f, "não foi possível escutar em {}: {}", addr, e),
+            Error::Upstream(msg) | Error::UpstreamRefused(msg) => write!(f, "upstream: {}", msg),
+            Error::UpstreamTimeout(addr) => write!(f, "upstream {}: connect timeout", addr),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,116 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::appender::Appender;
+use crate::config::Config;
+use crate::metrics::Metrics;
+use arc_swap::ArcSwap;
+use tokio::net::UnixDatagram;
+use tokio::sync::mpsc;
+
+// Arquivo/socket lento não pode segurar request: cheio = evento descartado
+const QUEUE_CAPACITY: usize = 10_000;
+
+pub enum Event<'a> {
+    Block {
+        ip: IpAddr,
+        reason: &'a str,
+        path: &'a str,
+    },
+    RateLimit {
+        ip: IpAddr,
+    },
+    Ban {
+        ip: IpAddr,
+        ttl: Duration,
+    },
+    Unban {
+        ip: IpAddr,
+    },
+}
+
+impl Event<'_> {
+    fn label(&self) -> &'static str {
+        match self {
+            Event::Block { .. } => "block",
+            Event::RateLimit { .. } => "rate_limit",
+            Event::Ban { .. } => "ban",
+            Event::Unban { .. } => "unban",
+        }
+    }
+
+    // Uma linha por evento, época no início (datepattern {EPOCH} do fail2ban).
+    // Texto do cliente vai com escape: nada de quebra de linha forjando evento
+    fn line(&self) -> String {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or(0.0);
+        let mut line = format!("{:.3} oblivion event={}", ts, self.label());
+        match self {
+            Event::Block { ip, reason, path } => {
+                line.push_str(&format!(" ip={} reason={:?} path={:?}", ip, reason, path))
+            }
+            Event::RateLimit { ip } | Event::Unban { ip } => line.push_str(&format!(" ip={}", ip)),
+            Event::Ban { ip, ttl } => line.push_str(&format!(" ip={} ttl={}", ip, ttl.as_secs())),
+        }
+        line.push('\n');
+        line
+    }
+}
+
+struct Entry {
+    line: String,
+    file: Option<String>,
+    socket: Option<String>,
+}
+
+// Eventos de bloqueio/ban para fail2ban e afins ([bans] event_log / event_socket)
+pub struct Events {
+    config: Arc<ArcSwap<Config>>,
+    tx: mpsc::Sender<Entry>,
+    metrics: Arc<Metrics>,
+}
+
+impl Events {
+    pub fn new(config: Arc<ArcSwap<Config>>, metrics: Arc<Metrics>) -> Arc<Self> {
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(write_loop(rx));
+        Arc::new(Events {
+            config,
+            tx,
+            metrics,
+        })
+    }
+
+    pub fn emit(&self, event: Event) {
+        let config = self.config.load();
+        let (file, socket) = (&config.bans.event_log, &config.bans.event_socket);
+        if file.is_none() && socket.is_none() {
+            return;
+        }
+        let entry = Entry {
+            line: event.line(),
+            file: file.clone(),
+            socket: socket.clone(),
+        };
+        if self.tx.try_send(entry).is_err() {
+            self.metrics.inc("oblivion_events_dropped_total", &[]);
+        }
+    }
+}
+
+async fn write_loop(mut rx: mpsc::Receiver<Entry>) {
+    let mut file = Appender::new("event log");
+    let socket = UnixDatagram::unbound().ok();
+    while let Some(entry) = rx.recv().await {
+        if let Some(path) = &entry.file {
+            file.write(path, &entry.line).await;
+        }
+        if let (Some(path), Some(socket)) = (&entry.socket, &socket) {
+            // Ninguém escutando não é erro: o consumidor pode subir depois
+            let _ = socket.send_to(entry.line.trim_end().as_bytes(), path).await;
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::Instant;
+
+const SHARD_COUNT: usize = 16;
+
+struct Shard<K> {
+    deadlines: HashMap<K, Instant>,
+    // Mesmas entradas ordenadas pelo vencimento: expirar é tirar da frente
+    queue: BTreeSet<(Instant, K)>,
+}
+
+impl<K: Copy + Eq + Hash + Ord> Shard<K> {
+    // Só as vencidas são visitadas: O(expiradas · log n), não O(total)
+    fn expire(&mut self, now: Instant) -> usize {
+        let mut expired = 0;
+        while let Some(&(until, key)) = self.queue.first() {
+            if until > now {
+                break;
+            }
+            self.queue.pop_first();
+            self.deadlines.remove(&key);
+            expired += 1;
+        }
+        expired
+    }
+}
+
+// Chave -> vencimento, em shards por hash da chave (como o RateLimiter), com
+// capacidade dividida entre eles. Entrada vencida some no próximo expire() ou
+// quando o shard precisa de espaço; até lá get() já não a enxerga
+pub struct ExpiryMap<K> {
+    shards: Vec<Mutex<Shard<K>>>,
+    shard_capacity: usize,
+}
+
+impl<K: Copy + Eq + Hash + Ord> ExpiryMap<K> {
+    pub fn new(capacity: usize) -> Self {
+        let mut shards = Vec::with_capacity(SHARD_COUNT);
+        for _ in 0..SHARD_COUNT {
+            shards.push(Mutex::new(Shard {
+                deadlines: HashMap::new(),
+                queue: BTreeSet::new(),
+            }));
+        }
+        ExpiryMap {
+            shards,
+            shard_capacity: capacity.div_ceil(SHARD_COUNT),
+        }
+    }
+
+    fn shard(&self, key: &K) -> &Mutex<Shard<K>> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[(hasher.finish() as usize) % SHARD_COUNT]
+    }
+
+    // Novo vencimento (substitui o anterior); false = shard cheio de entradas vivas
+    pub fn insert(&self, key: K, until: Instant) -> bool {
+        let mut shard = self.shard(&key).lock().unwrap();
+        if let Some(previous) = shard.deadlines.get(&key).copied() {
+            shard.queue.remove(&(previous, key));
+        } else if shard.deadlines.len() >= self.shard_capacity {
+            shard.expire(Instant::now());
+            if shard.deadlines.len() >= self.shard_capacity {
+                return false;
+            }
+        }
+        shard.deadlines.insert(key, until);
+        shard.queue.insert((until, key));
+        true
+    }
+
+    pub fn get(&self, key: &K) -> Option<Instant> {
+        let shard = self.shard(key).lock().unwrap();
+        shard
+            .deadlines
+            .get(key)
+            .copied()
+            .filter(|until| *until > Instant::now())
+    }
+
+    pub fn remove(&self, key: &K) -> bool {
+        let mut shard = self.shard(key).lock().unwrap();
+        match shard.deadlines.remove(key) {
+            Some(until) => {
+                shard.queue.remove(&(until, *key));
+                until > Instant::now()
+            }
+            None => false,
+        }
+    }
+
+    pub fn expire(&self) -> usize {
+        let now = Instant::now();
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().expire(now))
+            .sum()
+    }
+
+    // Vencimento mais próximo entre os shards: quando vale a pena o próximo expire()
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.shards
+            .iter()
+            .filter_map(|shard| shard.lock().unwrap().queue.first().map(|(until, _)| *until))
+            .min()
+    }
+
+    pub fn live(&self) -> Vec<(K, Instant)> {
+        let now = Instant::now();
+        let mut out = Vec::new();
+        for shard in &self.shards {
+            let shard = shard.lock().unwrap();
+            out.extend(
+                shard
+                    .deadlines
+                    .iter()
+                    .filter(|(_, until)| **until > now)
+                    .map(|(key, until)| (*key, *until)),
+            );
+        }
+        out
+    }
+}
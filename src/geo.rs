@@ -0,0 +1,167 @@
+use std::net::IpAddr;
+
+use serde::Deserialize;
+
+use crate::error::Error;
+use crate::keying::Cidr;
+
+// Faixa da base: país em ISO 3166 alfa-2 (maiúsculas)
+struct Range {
+    start: u128,
+    end: u128,
+    asn: u32,
+    country: [u8; 2],
+}
+
+// De onde vem um IP segundo a base
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Origin {
+    pub asn: u32,
+    country: [u8; 2],
+}
+
+impl Origin {
+    pub fn country(&self) -> &str {
+        std::str::from_utf8(&self.country).unwrap_or("--")
+    }
+}
+
+// IP -> (ASN, país) no formato do iptoasn.com (ip2asn-combined.tsv):
+// início \t fim \t ASN \t país \t descrição, uma faixa por linha
+pub struct GeoDb {
+    ranges: Vec<Range>,
+}
+
+// v4 e v6 no mesmo espaço: v4 vira ::ffff:a.b.c.d
+fn key(ip: IpAddr) -> u128 {
+    match ip.to_canonical() {
+        IpAddr::V4(v4) => u128::from(v4.to_ipv6_mapped()),
+        IpAddr::V6(v6) => u128::from(v6),
+    }
+}
+
+impl GeoDb {
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let raw = std::fs::read_to_string(path)
+            .map_err(|e| Error::Config(format!("geo.database '{}' ilegível: {}", path, e)))?;
+        Self::parse(&raw).map_err(|(line, e)| {
+            Error::Config(format!("geo.database '{}': linha {}: {}", path, line, e))
+        })
+    }
+
+    fn parse(raw: &str) -> Result<Self, (usize, String)> {
+        let mut ranges = Vec::new();
+        for (i, line) in raw.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |what: &str| (i + 1, format!("{} inválido", what));
+            let mut fields = line.split('\t');
+            let mut next = || fields.next().unwrap_or_default().trim();
+            let start: IpAddr = next().parse().map_err(|_| invalid("início"))?;
+            let end: IpAddr = next().parse().map_err(|_| invalid("fim"))?;
+            let asn: u32 = next().parse().map_err(|_| invalid("ASN"))?;
+            let country = next().to_ascii_uppercase();
+            // ASN 0 / "None": faixa não roteada, fica de fora
+            if asn == 0 {
+                continue;
+            }
+            let country = match country.as_bytes() {
+                [a, b] if a.is_ascii_alphabetic() && b.is_ascii_alphabetic() => [*a, *b],
+                _ => [b'-', b'-'],
+            };
+            let (start, end) = (key(start), key(end));
+            if start > end {
+                return Err(invalid("intervalo"));
+            }
+            ranges.push(Range {
+                start,
+                end,
+                asn,
+                country,
+            });
+        }
+        ranges.sort_by_key(|r| r.start);
+        Ok(GeoDb { ranges })
+    }
+
+    pub fn ranges(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> Option<Origin> {
+        let ip = key(ip);
+        let i = self
+            .ranges
+            .partition_point(|r| r.start <= ip)
+            .checked_sub(1)?;
+        let range = &self.ranges[i];
+        (ip <= range.end).then_some(Origin {
+            asn: range.asn,
+            country: range.country,
+        })
+    }
+}
+
+// Quem passa: basta casar um dos critérios. Países e ASNs precisam de
+// [geo] database; identidades, de certificado de cliente validado
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessList {
+    pub cidrs: Vec<Cidr>,
+    pub countries: Vec<String>,
+    pub asns: Vec<u32>,
+    // CN ou SAN DNS do certificado de cliente
+    pub identities: Vec<String>,
+}
+
+impl AccessList {
+    pub fn is_empty(&self) -> bool {
+        self.cidrs.is_empty()
+            && self.countries.is_empty()
+            && self.asns.is_empty()
+            && self.identities.is_empty()
+    }
+
+    pub fn uses_geo(&self) -> bool {
+        !self.countries.is_empty() || !self.asns.is_empty()
+    }
+
+    pub fn validate(&self, section: &str) -> Result<(), String> {
+        match self
+            .countries
+            .iter()
+            .find(|c| c.len() != 2 || !c.bytes().all(|b| b.is_ascii_alphabetic()))
+        {
+            Some(country) => Err(format!(
+                "{}.countries: '{}' is not an ISO 3166 alpha-2 code",
+                section, country
+            )),
+            None => Ok(()),
+        }
+    }
+
+    pub fn matches(&self, ip: IpAddr, identities: &[String], geo: Option<&GeoDb>) -> bool {
+        if self.cidrs.iter().any(|c| c.contains(ip)) {
+            return true;
+        }
+        if identities
+            .iter()
+            .any(|id| self.identities.iter().any(|i| i.eq_ignore_ascii_case(id)))
+        {
+            return true;
+        }
+        let Some(origin) = self
+            .uses_geo()
+            .then(|| geo.and_then(|g| g.lookup(ip)))
+            .flatten()
+        else {
+            return false;
+        };
+        self.asns.contains(&origin.asn)
+            || self
+                .countries
+                .iter()
+                .any(|c| c.eq_ignore_ascii_case(origin.country()))
+    }
+}
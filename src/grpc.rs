@@ -0,0 +1,111 @@
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+
+use crate::config::GrpcConfig;
+use crate::http::Request;
+
+// Códigos de status do gRPC (doc/statuscodes.md) usados nas recusas
+pub const PERMISSION_DENIED: u8 = 7;
+const RESOURCE_EXHAUSTED: u8 = 8;
+const INTERNAL: u8 = 13;
+
+// grpc-message: fora de 0x20-0x7e e o próprio '%' vão codificados
+const MESSAGE: &AsciiSet = &CONTROLS.add(b'%');
+
+// Por que o request gRPC não segue; vira grpc-status na resposta
+#[derive(Debug)]
+pub struct Refusal {
+    pub code: u8,
+    pub label: &'static str,
+    pub message: String,
+}
+
+// application/grpc, application/grpc+proto, application/grpc+json...
+pub fn media_type(content_type: &str) -> bool {
+    let media = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    media == "application/grpc" || media.starts_with("application/grpc+")
+}
+
+pub fn requested(req: &Request) -> bool {
+    req.header("Content-Type").is_some_and(media_type)
+}
+
+// "/pacote.Serviço/Método" exato ou "/pacote.Serviço/*"; lista vazia aceita tudo
+pub fn method_allowed(path: &str, methods: &[String]) -> bool {
+    methods.is_empty()
+        || methods
+            .iter()
+            .any(|allowed| match allowed.strip_suffix("/*") {
+                Some(service) => path
+                    .strip_prefix(service)
+                    .and_then(|rest| rest.strip_prefix('/'))
+                    .is_some_and(|method| !method.is_empty() && !method.contains('/')),
+                None => allowed == path,
+            })
+}
+
+// Entrada de grpc.methods; "*" no lugar do método vale o serviço inteiro
+pub fn valid_method(entry: &str) -> bool {
+    let Some((service, method)) = entry
+        .strip_prefix('/')
+        .and_then(|rest| rest.split_once('/'))
+    else {
+        return false;
+    };
+    !service.is_empty() && !method.is_empty() && !method.contains('/')
+}
+
+// Corpo = mensagens com prefixo de 5 bytes (flag de compressão + tamanho em
+// big-endian). As não comprimidas viram o corpo que as regras veem, uma por linha
+pub fn inspect(req: &mut Request, body: &[u8], config: &GrpcConfig) -> Result<(), Refusal> {
+    let mut rest = body;
+    let mut payloads = Vec::new();
+    while !rest.is_empty() {
+        let Some((prefix, tail)) = rest.split_first_chunk::<5>() else {
+            return Err(malformed("truncated message prefix"));
+        };
+        let size = u32::from_be_bytes([prefix[1], prefix[2], prefix[3], prefix[4]]) as usize;
+        if size > config.max_message_size {
+            return Err(Refusal {
+                code: RESOURCE_EXHAUSTED,
+                label: "message_size",
+                message: format!(
+                    "message of {} bytes exceeds limit of {}",
+                    size, config.max_message_size
+                ),
+            });
+        }
+        if tail.len() < size {
+            return Err(malformed("truncated message"));
+        }
+        let (message, tail) = tail.split_at(size);
+        if prefix[0] == 0 {
+            payloads.push(String::from_utf8_lossy(message));
+        }
+        rest = tail;
+    }
+    req.body = payloads.join("\n");
+    req.body_as_form = Some(false);
+    Ok(())
+}
+
+fn malformed(reason: &str) -> Refusal {
+    Refusal {
+        code: INTERNAL,
+        label: "framing",
+        message: reason.to_string(),
+    }
+}
+
+// Só headers, sem corpo: no HTTP/2 sai como resposta trailers-only
+pub fn response(code: u8, message: &str) -> String {
+    format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/grpc\r\nGrpc-Status: {}\r\nGrpc-Message: {}\r\nContent-Length: 0\r\n\r\n",
+        code,
+        utf8_percent_encode(message, MESSAGE)
+    )
+}
@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use h2::client::{ResponseFuture, SendRequest};
+use h2::RecvStream;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio::sync::Mutex;
+use tokio::time::timeout;
+use tracing::{debug, info};
+
+use crate::config::UpstreamConfig;
+use crate::connect_upstream;
+use crate::error::Error;
+use crate::grpc;
+use crate::http::reason_phrase;
+use crate::upstream_tls::UpstreamTls;
+
+// Hop-by-hop do HTTP/1.1 são proibidos em HTTP/2; Host vira :authority
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+    "te",
+    "host",
+];
+const RESPONSE_PIPE: usize = 64 * 1024;
+
+struct Connection {
+    generation: u64,
+    sender: SendRequest<Bytes>,
+}
+
+// Uma conexão h2c por backend ([[site]] pode ter o seu), multiplexada entre os requests
+pub struct H2Pool {
+    conns: Mutex<HashMap<String, Connection>>,
+}
+
+impl H2Pool {
+    pub fn new() -> Arc<Self> {
+        Arc::new(H2Pool {
+            conns: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub async fn sender(
+        &self,
+        upstream: &UpstreamConfig,
+        tls: Option<&UpstreamTls>,
+        connect_timeout: Duration,
+    ) -> Result<SendRequest<Bytes>, Error> {
+        let addr = upstream.addr.as_str();
+        let cached = self
+            .conns
+            .lock()
+            .await
+            .get(addr)
+            .map(|c| (c.generation, c.sender.clone()));
+
+        // ready() falha com a conexão morta (GOAWAY, reset, backend reiniciado)
+        let stale = match cached {
+            Some((generation, sender)) => match sender.ready().await {
+                Ok(sender) => return Ok(sender),
+                Err(e) => {
+                    debug!(error = %e, "Upstream h2c connection lost");
+                    Some(generation)
+                }
+            },
+            None => None,
+        };
+
+        let mut conns = self.conns.lock().await;
+        // Outro request pode ter reconectado enquanto este esperava o lock
+        if let Some(current) = conns.get(addr)
+            && Some(current.generation) != stale
+        {
+            return current
+                .sender
+                .clone()
+                .ready()
+                .await
+                .map_err(|e| Error::Upstream(format!("{}: {}", addr, e)));
+        }
+
+        let stream = connect_upstream(upstream, tls, connect_timeout).await?;
+        let _ = stream.tcp().set_nodelay(true);
+        let (sender, connection) = h2::client::handshake(stream)
+            .await
+            .map_err(|e| Error::Upstream(format!("{}: h2c handshake: {}", addr, e)))?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                debug!(error = %e, "Upstream h2c connection closed");
+            }
+        });
+        let generation = conns.get(addr).map_or(0, |c| c.generation + 1);
+        info!(addr, generation, "Upstream h2c connection established");
+        conns.insert(
+            addr.to_string(),
+            Connection {
+                generation,
+                sender: sender.clone(),
+            },
+        );
+        sender
+            .ready()
+            .await
+            .map_err(|e| Error::Upstream(format!("{}: {}", addr, e)))
+    }
+}
+
+// Head HTTP/1.1 já filtrado (forward_head) -> request HTTP/2
+fn build_request(head: &[u8]) -> Result<http::Request<()>, String> {
+    let text = String::from_utf8_lossy(head);
+    let mut lines = text.split("\r\n");
+    let request_line = lines.next().unwrap_or("");
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(format!("invalid request line '{}'", request_line));
+    };
+
+    let mut authority = None;
+    let mut headers = Vec::new();
+    for line in lines.take_while(|l| !l.is_empty()) {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let (name, value) = (name.trim(), value.trim());
+        if name.eq_ignore_ascii_case("host") {
+            authority = Some(value.to_string());
+        }
+        // TE: trailers é o único TE aceito em HTTP/2 (RFC 9113 8.2.2); o gRPC manda sempre
+        let trailers = name.eq_ignore_ascii_case("te") && value.eq_ignore_ascii_case("trailers");
+        if trailers || !HOP_BY_HOP.iter().any(|h| name.eq_ignore_ascii_case(h)) {
+            headers.push((name.to_string(), value.to_string()));
+        }
+    }
+
+    let authority = authority.ok_or("missing Host header")?;
+    let mut builder = http::Request::builder()
+        .method(method)
+        .uri(format!("http://{}{}", authority, target))
+        .version(http::Version::HTTP_2);
+    for (name, value) in headers {
+        builder = builder.header(name, value);
+    }
+    builder.body(()).map_err(|e| e.to_string())
+}
+
+// Manda head + corpo (o já lido e o que falta do cliente) e devolve a resposta pendente
+pub async fn send<S>(
+    mut sender: SendRequest<Bytes>,
+    head: &[u8],
+    buffered: Vec<u8>,
+    content_length: u64,
+    client: &mut S,
+    body_timeout: Duration,
+) -> Result<ResponseFuture, String>
+where
+    S: AsyncRead + Unpin,
+{
+    let request = build_request(head)?;
+    let (response, mut body) = sender
+        .send_request(request, content_length == 0)
+        .map_err(|e| e.to_string())?;
+    if content_length == 0 {
+        return Ok(response);
+    }
+
+    let mut remaining = content_length;
+    let first = buffered.len().min(remaining as usize);
+    if first > 0 {
+        remaining -= first as u64;
+        body.send_data(Bytes::copy_from_slice(&buffered[..first]), remaining == 0)
+            .map_err(|e| e.to_string())?;
+    }
+    let mut chunk = vec![0u8; 16 * 1024];
+    while remaining > 0 {
+        let want = chunk.len().min(remaining as usize);
+        let n = match timeout(body_timeout, client.read(&mut chunk[..want])).await {
+            Ok(Ok(0)) => return Err("client closed before sending the body".to_string()),
+            Ok(Ok(n)) => n,
+            Ok(Err(e)) => return Err(e.to_string()),
+            Err(_) => return Err("client body timeout".to_string()),
+        };
+        remaining -= n as u64;
+        body.send_data(Bytes::copy_from_slice(&chunk[..n]), remaining == 0)
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(response)
+}
+
+// Resposta HTTP/2 -> bytes HTTP/1.1 num pipe limitado, para o relay de sempre
+// (rewrites, limite de tamanho, slow-read) tratar igual ao upstream HTTP/1.1.
+// gRPC vai chunked: o grpc-status chega nos trailers e atravessa o pipe com eles
+pub fn into_reader(response: http::Response<RecvStream>) -> DuplexStream {
+    let (parts, mut body) = response.into_parts();
+    let status = parts.status.as_u16();
+    let grpc = parts
+        .headers
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(grpc::media_type);
+    let mut head = format!("HTTP/1.1 {} {}\r\n", status, reason_phrase(status)).into_bytes();
+    for (name, value) in &parts.headers {
+        if grpc && name == "content-length" {
+            continue;
+        }
+        head.extend_from_slice(name.as_str().as_bytes());
+        head.extend_from_slice(b": ");
+        head.extend_from_slice(value.as_bytes());
+        head.extend_from_slice(b"\r\n");
+    }
+    if grpc {
+        head.extend_from_slice(b"Transfer-Encoding: chunked\r\n");
+    }
+    // Sem Content-Length o fim do corpo é o fechamento da conexão
+    head.extend_from_slice(b"Connection: close\r\n\r\n");
+
+    let (reader, mut writer) = tokio::io::duplex(RESPONSE_PIPE);
+    tokio::spawn(async move {
+        if writer.write_all(&head).await.is_err() {
+            return;
+        }
+        while let Some(data) = body.data().await {
+            let Ok(data) = data else {
+                debug!("Upstream h2c stream reset mid-response");
+                return;
+            };
+            let _ = body.flow_control().release_capacity(data.len());
+            let written = if grpc {
+                write_chunk(&mut writer, &data).await
+            } else {
+                writer.write_all(&data).await
+            };
+            if written.is_err() {
+                return;
+            }
+        }
+        if grpc {
+            let Ok(trailers) = body.trailers().await else {
+                debug!("Upstream h2c stream reset before trailers");
+                return;
+            };
+            let mut tail = b"0\r\n".to_vec();
+            for (name, value) in trailers.iter().flatten() {
+                tail.extend_from_slice(name.as_str().as_bytes());
+                tail.extend_from_slice(b": ");
+                tail.extend_from_slice(value.as_bytes());
+                tail.extend_from_slice(b"\r\n");
+            }
+            tail.extend_from_slice(b"\r\n");
+            if writer.write_all(&tail).await.is_err() {
+                return;
+            }
+        }
+        let _ = writer.shutdown().await;
+    });
+    reader
+}
+
+// Chunk vazio seria o fim do corpo: não sai
+async fn write_chunk(writer: &mut DuplexStream, data: &[u8]) -> std::io::Result<()> {
+    if data.is_empty() {
+        return Ok(());
+    }
+    writer
+        .write_all(format!("{:x}\r\n", data.len()).as_bytes())
+        .await?;
+    writer.write_all(data).await?;
+    writer.write_all(b"\r\n").await
+}
@@ -0,0 +1,408 @@
+use std::future::poll_fn;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use h2::server::SendResponse;
+use h2::{Reason, RecvStream, SendStream};
+use http::{HeaderMap, HeaderName, HeaderValue};
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, DuplexStream,
+    ReadHalf,
+};
+use tokio::time::timeout;
+use tracing::debug;
+
+use crate::http::response_headers;
+use crate::reject::Abortable;
+use crate::tls::HelloInfo;
+use crate::{handle_client, Context};
+
+// Streams simultâneos por conexão: cada um vira uma task com pipe próprio
+const MAX_CONCURRENT_STREAMS: u32 = 100;
+const REQUEST_PIPE: usize = 64 * 1024;
+const MAX_RESPONSE_HEAD: usize = 64 * 1024;
+// Proibidos em HTTP/2 (RFC 9113 8.2.2)
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+// Reset/Drop da política de bloqueio: o stream termina sem resposta e vira RST_STREAM
+impl Abortable for DuplexStream {
+    fn reset_on_close(&self) {}
+}
+
+// ALPN "h2": cada stream vira um request HTTP/1.1 num pipe em memória e passa
+// pelo handle_client de sempre (limites, regras, sessão, upstream); a resposta
+// HTTP/1.1 volta traduzida para o stream
+pub async fn serve<S>(
+    stream: S,
+    listener: SocketAddr,
+    peer_addr: SocketAddr,
+    hello: HelloInfo,
+    ctx: Arc<Context>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let header_timeout = ctx.config.load().client.header_timeout;
+    let handshake = h2::server::Builder::new()
+        .max_concurrent_streams(MAX_CONCURRENT_STREAMS)
+        .max_header_list_size(ctx.config.load().client.max_header_size as u32)
+        .handshake(stream);
+    let mut connection = match timeout(header_timeout, handshake).await {
+        Ok(Ok(connection)) => connection,
+        Ok(Err(e)) => {
+            debug!("HTTP/2 handshake failed from {}: {}", peer_addr, e);
+            return;
+        }
+        Err(_) => {
+            debug!("HTTP/2 preface timeout from {}", peer_addr);
+            return;
+        }
+    };
+    ctx.metrics.inc("oblivion_http2_connections_total", &[]);
+
+    // accept() também move os frames da conexão: tem que rodar até o fim
+    while let Some(result) = connection.accept().await {
+        match result {
+            Ok((request, respond)) => {
+                tokio::spawn(bridge(
+                    request,
+                    respond,
+                    listener,
+                    peer_addr,
+                    hello.clone(),
+                    ctx.clone(),
+                ));
+            }
+            Err(e) => {
+                debug!("HTTP/2 connection from {} ended: {}", peer_addr, e);
+                break;
+            }
+        }
+    }
+}
+
+// Head HTTP/1.1 equivalente, sem a linha em branco: Host primeiro, nomes como o
+// h2 manda (minúsculas) e os campos de Cookie juntos num só
+pub fn http1_head(parts: &http::request::Parts) -> Vec<u8> {
+    let target = parts
+        .uri
+        .path_and_query()
+        .map_or("/", |p| p.as_str())
+        .to_string();
+    let authority = parts.uri.authority().map(|a| a.to_string()).or_else(|| {
+        parts
+            .headers
+            .get("host")
+            .and_then(|h| h.to_str().ok())
+            .map(str::to_string)
+    });
+    let mut head = format!("{} {} HTTP/1.1\r\n", parts.method, target).into_bytes();
+    if let Some(authority) = &authority {
+        head.extend_from_slice(format!("Host: {}\r\n", authority).as_bytes());
+    }
+    // Cookie pode vir dividido em vários campos (RFC 9113 8.2.3)
+    let mut cookies = Vec::new();
+    for (name, value) in &parts.headers {
+        let name = name.as_str();
+        if name == "host" || HOP_BY_HOP.contains(&name) {
+            continue;
+        }
+        if name == "cookie" {
+            cookies.push(String::from_utf8_lossy(value.as_bytes()).to_string());
+            continue;
+        }
+        head.extend_from_slice(name.as_bytes());
+        head.extend_from_slice(b": ");
+        head.extend_from_slice(value.as_bytes());
+        head.extend_from_slice(b"\r\n");
+    }
+    if !cookies.is_empty() {
+        head.extend_from_slice(format!("Cookie: {}\r\n", cookies.join("; ")).as_bytes());
+    }
+    head
+}
+
+async fn bridge(
+    request: http::Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+    listener: SocketAddr,
+    peer_addr: SocketAddr,
+    hello: HelloInfo,
+    ctx: Arc<Context>,
+) {
+    let config = ctx.config.load_full();
+    let (parts, mut body) = request.into_parts();
+
+    let mut head = http1_head(&parts);
+
+    // Corpo sem content-length é juntado antes para o HTTP/1.1 ter o tamanho
+    let declared = parts.headers.contains_key("content-length");
+    let mut buffered = None;
+    if !body.is_end_stream() && !declared {
+        match collect_body(
+            &mut body,
+            config.client.max_body_size,
+            config.client.body_timeout,
+        )
+        .await
+        {
+            Ok(data) => {
+                head.extend_from_slice(format!("Content-Length: {}\r\n", data.len()).as_bytes());
+                buffered = Some(data);
+            }
+            Err(status) => {
+                let response = http::Response::builder().status(status).body(()).unwrap();
+                let _ = respond.send_response(response, true);
+                return;
+            }
+        }
+    }
+    // Um request por pipe: sem keep-alive do lado do handle_client
+    head.extend_from_slice(b"Connection: close\r\n\r\n");
+
+    let (client_side, proxy_side) = tokio::io::duplex(REQUEST_PIPE);
+    tokio::spawn(handle_client(proxy_side, listener, peer_addr, hello, ctx));
+    let (reader, mut writer) = tokio::io::split(client_side);
+
+    let body_timeout = config.client.body_timeout;
+    let upload = async move {
+        writer.write_all(&head).await?;
+        match buffered {
+            Some(data) => writer.write_all(&data).await?,
+            None if declared => {
+                while let Some(data) = timeout(body_timeout, body.data())
+                    .await
+                    .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))?
+                {
+                    let data = data.map_err(std::io::Error::other)?;
+                    let _ = body.flow_control().release_capacity(data.len());
+                    writer.write_all(&data).await?;
+                }
+            }
+            None => {}
+        }
+        // Sem shutdown: o handle_client fecharia a leitura achando que o cliente saiu
+        Ok::<_, std::io::Error>(writer)
+    };
+    let head_request = parts.method == http::Method::HEAD;
+    let download = relay(reader, &mut respond, head_request);
+
+    let (uploaded, relayed) = tokio::join!(upload, download);
+    if let Err(e) = uploaded {
+        debug!("HTTP/2 request body ended: {}", e);
+    }
+    if let Err(e) = relayed {
+        debug!("HTTP/2 stream ended: {}", e);
+        respond.send_reset(Reason::CANCEL);
+    }
+}
+
+async fn collect_body(
+    body: &mut RecvStream,
+    limit: u64,
+    body_timeout: Duration,
+) -> Result<Vec<u8>, u16> {
+    let mut out = Vec::new();
+    loop {
+        let Ok(next) = timeout(body_timeout, body.data()).await else {
+            return Err(408);
+        };
+        let Some(data) = next else {
+            return Ok(out);
+        };
+        let data = data.map_err(|_| 400u16)?;
+        let _ = body.flow_control().release_capacity(data.len());
+        if (out.len() + data.len()) as u64 > limit {
+            return Err(413);
+        }
+        out.extend_from_slice(&data);
+    }
+}
+
+enum Framing {
+    Empty,
+    Length(u64),
+    Chunked,
+    Close,
+}
+
+// Resposta HTTP/1.1 escrita pelo handle_client -> HEADERS + DATA no stream
+async fn relay(
+    reader: ReadHalf<DuplexStream>,
+    respond: &mut SendResponse<Bytes>,
+    head_request: bool,
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(reader);
+    let (status, head) = loop {
+        let head = read_head(&mut reader).await?;
+        let status = crate::http::response_status(&head)
+            .ok_or_else(|| std::io::Error::other("invalid response status line"))?;
+        // 1xx intermediário (100 Continue) não tem equivalente útil aqui
+        if !(100..200).contains(&status) {
+            break (status, head);
+        }
+    };
+
+    let mut builder = http::Response::builder().status(status);
+    for line in head.split(|b| *b == b'\n').skip(1) {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let Some(colon) = line.iter().position(|b| *b == b':') else {
+            continue;
+        };
+        let name = String::from_utf8_lossy(&line[..colon])
+            .trim()
+            .to_ascii_lowercase();
+        if HOP_BY_HOP.contains(&name.as_str()) {
+            continue;
+        }
+        builder = builder.header(name, line[colon + 1..].trim_ascii());
+    }
+    let response = builder.body(()).map_err(std::io::Error::other)?;
+
+    let chunked = response_headers(&head, "Transfer-Encoding")
+        .iter()
+        .any(|te| te.to_ascii_lowercase().contains("chunked"));
+    let length = response_headers(&head, "Content-Length")
+        .first()
+        .and_then(|l| l.parse::<u64>().ok());
+    let framing = if head_request || status == 204 || status == 304 {
+        Framing::Empty
+    } else if chunked {
+        Framing::Chunked
+    } else if let Some(length) = length {
+        Framing::Length(length)
+    } else {
+        Framing::Close
+    };
+
+    let empty = matches!(framing, Framing::Empty | Framing::Length(0));
+    let mut send = respond
+        .send_response(response, empty)
+        .map_err(std::io::Error::other)?;
+    if empty {
+        return Ok(());
+    }
+
+    let mut buffer = vec![0u8; 16 * 1024];
+    match framing {
+        Framing::Length(mut remaining) => {
+            while remaining > 0 {
+                let want = buffer.len().min(remaining as usize);
+                let n = reader.read(&mut buffer[..want]).await?;
+                if n == 0 {
+                    return Err(std::io::ErrorKind::UnexpectedEof.into());
+                }
+                remaining -= n as u64;
+                send_data(&mut send, &buffer[..n]).await?;
+            }
+        }
+        Framing::Chunked => loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).await?;
+            let size = line.split(';').next().unwrap_or_default().trim();
+            let mut remaining = u64::from_str_radix(size, 16)
+                .map_err(|_| std::io::Error::other("invalid chunk size"))?;
+            // Trailers do último chunk (grpc-status) viram o HEADERS final do stream
+            if remaining == 0 {
+                let trailers = read_trailers(&mut reader).await?;
+                if trailers.is_empty() {
+                    break;
+                }
+                return send.send_trailers(trailers).map_err(std::io::Error::other);
+            }
+            while remaining > 0 {
+                let want = buffer.len().min(remaining as usize);
+                let n = reader.read(&mut buffer[..want]).await?;
+                if n == 0 {
+                    return Err(std::io::ErrorKind::UnexpectedEof.into());
+                }
+                remaining -= n as u64;
+                send_data(&mut send, &buffer[..n]).await?;
+            }
+            let mut crlf = [0u8; 2];
+            reader.read_exact(&mut crlf).await?;
+        },
+        Framing::Close => loop {
+            let n = reader.read(&mut buffer).await?;
+            if n == 0 {
+                break;
+            }
+            send_data(&mut send, &buffer[..n]).await?;
+        },
+        Framing::Empty => {}
+    }
+    send.send_data(Bytes::new(), true)
+        .map_err(std::io::Error::other)
+}
+
+async fn read_head(reader: &mut BufReader<ReadHalf<DuplexStream>>) -> std::io::Result<Vec<u8>> {
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        // EOF antes do head = handle_client terminou sem responder (drop/reset)
+        if reader.read_until(b'\n', &mut head).await? == 0 {
+            return Err(std::io::Error::other("no response"));
+        }
+        if head.len() > MAX_RESPONSE_HEAD {
+            return Err(std::io::Error::other("response head too large"));
+        }
+    }
+    Ok(head)
+}
+
+async fn read_trailers(
+    reader: &mut BufReader<ReadHalf<DuplexStream>>,
+) -> std::io::Result<HeaderMap> {
+    let mut trailers = HeaderMap::new();
+    let mut read = 0;
+    loop {
+        let mut line = Vec::new();
+        let n = reader.read_until(b'\n', &mut line).await?;
+        read += n;
+        if read > MAX_RESPONSE_HEAD {
+            return Err(std::io::Error::other("response trailers too large"));
+        }
+        let line = line.strip_suffix(b"\n").unwrap_or(&line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            return Ok(trailers);
+        }
+        let Some(colon) = line.iter().position(|b| *b == b':') else {
+            continue;
+        };
+        let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(line[..colon].trim_ascii()),
+            HeaderValue::from_bytes(line[colon + 1..].trim_ascii()),
+        ) else {
+            continue;
+        };
+        if !HOP_BY_HOP.contains(&name.as_str()) {
+            trailers.append(name, value);
+        }
+    }
+}
+
+// Respeita o controle de fluxo do cliente: espera janela antes de cada pedaço
+async fn send_data(send: &mut SendStream<Bytes>, mut data: &[u8]) -> std::io::Result<()> {
+    while !data.is_empty() {
+        send.reserve_capacity(data.len());
+        let capacity = poll_fn(|cx| send.poll_capacity(cx))
+            .await
+            .ok_or_else(|| std::io::Error::other("stream closed"))?
+            .map_err(std::io::Error::other)?;
+        let n = capacity.min(data.len());
+        if n == 0 {
+            continue;
+        }
+        send.send_data(Bytes::copy_from_slice(&data[..n]), false)
+            .map_err(std::io::Error::other)?;
+        data = &data[n..];
+    }
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use std::borrow::Cow;
+
+use crate::config::ServerConfig;
+
+const ERROR_HEADER: &[u8] = b"X-Oblivion-Error";
+
+// Resposta gerada pelo WAF com a política de [server]: Server: (se configurado)
+// logo depois da status line e X-Oblivion-Error só com error_header. Só o head
+// muda; o corpo e o Content-Length seguem iguais
+pub fn stamp<'a>(config: &ServerConfig, response: &'a [u8]) -> Cow<'a, [u8]> {
+    if config.header.is_empty() && config.error_header {
+        return Cow::Borrowed(response);
+    }
+    let (Some(line_end), Some(head_end)) = (find(response, b"\r\n"), find(response, b"\r\n\r\n"))
+    else {
+        return Cow::Borrowed(response);
+    };
+    let mut out = Vec::with_capacity(response.len() + config.header.len() + 10);
+    out.extend_from_slice(&response[..line_end + 2]);
+    if !config.header.is_empty() {
+        out.extend_from_slice(b"Server: ");
+        out.extend_from_slice(config.header.as_bytes());
+        out.extend_from_slice(b"\r\n");
+    }
+    let mut rest = &response[line_end + 2..head_end + 2];
+    while let Some(end) = find(rest, b"\r\n") {
+        let line = &rest[..end + 2];
+        rest = &rest[end + 2..];
+        let name = line.split(|b| *b == b':').next().unwrap_or_default();
+        if name.eq_ignore_ascii_case(b"Server")
+            || (!config.error_header && name.eq_ignore_ascii_case(ERROR_HEADER))
+        {
+            continue;
+        }
+        out.extend_from_slice(line);
+    }
+    out.extend_from_slice(&response[head_end + 2..]);
+    Cow::Owned(out)
+}
+
+// Resposta local a OPTIONS * (options = "respond"): o que o servidor aceita, sem upstream
+pub fn options_response(config: &ServerConfig) -> Vec<u8> {
+    format!(
+        "HTTP/1.1 200 OK\r\nAllow: {}\r\nContent-Length: 0\r\n\r\n",
+        config.allow.join(", ")
+    )
+    .into_bytes()
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
@@ -0,0 +1,203 @@
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::time::timeout;
+
+use crate::config::InjectConfig;
+use crate::http::{insert_header, response_headers, response_status, strip_headers};
+
+const NONCE_BYTES: usize = 16;
+
+// Só HTML 200 sem compressão: o resto passa byte a byte
+pub fn eligible(head: &[u8]) -> bool {
+    let header = |name| response_headers(head, name);
+    response_status(head) == Some(200)
+        && header("Content-Type")
+            .first()
+            .is_some_and(|t| t.to_ascii_lowercase().starts_with("text/html"))
+        && header("Content-Encoding")
+            .iter()
+            .all(|e| e.eq_ignore_ascii_case("identity"))
+}
+
+enum Framing {
+    Length(u64),
+    Chunked,
+    Close,
+}
+
+// Lê o começo do corpo até achar </head> (ou </body> se o corpo acabar antes),
+// no máximo scan_limit bytes, e devolve head + prefixo do corpo com o snippet.
+// O resto da resposta continua vindo do upstream sem alteração. head tem o
+// cabeçalho completo e possivelmente parte do corpo
+pub async fn inject<R>(
+    upstream: &mut R,
+    head: Vec<u8>,
+    config: &InjectConfig,
+    read_timeout: Duration,
+) -> std::io::Result<(Vec<u8>, bool)>
+where
+    R: AsyncRead + Unpin,
+{
+    let Some(end) = head.windows(4).position(|w| w == b"\r\n\r\n") else {
+        return Ok((head, false));
+    };
+    let headers = &head[..end + 4];
+    let chunked = response_headers(headers, "Transfer-Encoding")
+        .iter()
+        .any(|te| te.to_ascii_lowercase().contains("chunked"));
+    let framing = match response_headers(headers, "Content-Length").first() {
+        _ if chunked => Framing::Chunked,
+        Some(len) => match len.parse() {
+            Ok(len) => Framing::Length(len),
+            Err(_) => return Ok((head, false)),
+        },
+        None => Framing::Close,
+    };
+
+    // raw = corpo como veio; body = corpo decodificado dos chunks completos
+    let mut raw = head[end + 4..].to_vec();
+    let mut body = Vec::new();
+    let mut consumed = 0;
+    let mut complete = false;
+    let mut buffer = [0u8; 8192];
+    loop {
+        match framing {
+            Framing::Length(len) => {
+                body = raw.clone();
+                complete = raw.len() as u64 >= len;
+            }
+            Framing::Chunked => {
+                while let Some((data, next)) = next_chunk(&raw[consumed..]) {
+                    if data.is_empty() {
+                        complete = true;
+                        break;
+                    }
+                    body.extend_from_slice(data);
+                    consumed += next;
+                }
+            }
+            Framing::Close => body = raw.clone(),
+        }
+        if complete || find(&body, b"</head>").is_some() || body.len() >= config.scan_limit {
+            break;
+        }
+        // Upstream parado no meio: desiste de injetar, deliver cuida do resto
+        let Ok(n) = timeout(read_timeout, upstream.read(&mut buffer)).await else {
+            break;
+        };
+        let n = n?;
+        if n == 0 {
+            complete = true;
+            break;
+        }
+        raw.extend_from_slice(&buffer[..n]);
+    }
+
+    let position =
+        find(&body, b"</head>").or_else(|| complete.then(|| find(&body, b"</body>")).flatten());
+    let Some(position) = position else {
+        let mut out = headers.to_vec();
+        out.extend_from_slice(&raw);
+        return Ok((out, false));
+    };
+
+    let mut snippet = config.snippet.clone();
+    let mut headers = headers.to_vec();
+    if snippet.contains("{nonce}") {
+        let mut raw_nonce = [0u8; NONCE_BYTES];
+        getrandom::getrandom(&mut raw_nonce).map_err(|e| std::io::Error::other(e.to_string()))?;
+        let nonce = STANDARD.encode(raw_nonce);
+        snippet = snippet.replace("{nonce}", &nonce);
+        headers = allow_nonce(&headers, &nonce);
+    }
+    body.splice(position..position, snippet.bytes());
+
+    let mut out = match framing {
+        Framing::Length(len) => {
+            let mut out = strip_headers(&headers, &["Content-Length"]).0;
+            insert_header(
+                &mut out,
+                "Content-Length",
+                &(len + snippet.len() as u64).to_string(),
+            );
+            out.extend_from_slice(&body);
+            out
+        }
+        // Prefixo decodificado vira um chunk só; o resto continua com os chunks originais
+        Framing::Chunked => {
+            let mut out = headers;
+            out.extend_from_slice(format!("{:x}\r\n", body.len()).as_bytes());
+            out.extend_from_slice(&body);
+            out.extend_from_slice(b"\r\n");
+            out.extend_from_slice(&raw[consumed..]);
+            out
+        }
+        Framing::Close => {
+            let mut out = headers;
+            out.extend_from_slice(&body);
+            out
+        }
+    };
+    out.shrink_to_fit();
+    Ok((out, true))
+}
+
+// (dados, bytes consumidos) de um chunk completo no início de input
+fn next_chunk(input: &[u8]) -> Option<(&[u8], usize)> {
+    let line_end = input.windows(2).position(|w| w == b"\r\n")?;
+    let size = std::str::from_utf8(&input[..line_end]).ok()?;
+    let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+    let start = line_end + 2;
+    if size == 0 {
+        return Some((&input[..0], start));
+    }
+    let data = input.get(start..start + size)?;
+    (input.get(start + size..start + size + 2)? == b"\r\n").then_some((data, start + size + 2))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|w| w.eq_ignore_ascii_case(needle))
+}
+
+// CSP do upstream bloquearia o script injetado: o nonce entra em script-src
+// (ou num script-src copiado de default-src)
+fn allow_nonce(headers: &[u8], nonce: &str) -> Vec<u8> {
+    let policies = response_headers(headers, "Content-Security-Policy");
+    if policies.is_empty() {
+        return headers.to_vec();
+    }
+    let source = format!("'nonce-{}'", nonce);
+    let mut out = strip_headers(headers, &["Content-Security-Policy"]).0;
+    for policy in policies {
+        let directives: Vec<&str> = policy.split(';').map(str::trim).collect();
+        let name = |d: &str| {
+            d.split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_ascii_lowercase()
+        };
+        let mut rewritten: Vec<String> = directives.iter().map(|d| d.to_string()).collect();
+        if let Some(i) = directives.iter().position(|d| name(d) == "script-src") {
+            rewritten[i] = format!("{} {}", directives[i], source);
+        } else if let Some(default) = directives.iter().find(|d| name(d) == "default-src") {
+            let values = default
+                .split_whitespace()
+                .skip(1)
+                .collect::<Vec<_>>()
+                .join(" ");
+            rewritten.push(format!("script-src {} {}", values, source));
+        }
+        let value = rewritten
+            .into_iter()
+            .filter(|d| !d.is_empty())
+            .collect::<Vec<_>>()
+            .join("; ");
+        insert_header(&mut out, "Content-Security-Policy", &value);
+    }
+    out
+}
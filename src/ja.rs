@@ -0,0 +1,317 @@
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::challenge::to_hex;
+use crate::reject::Abortable;
+use crate::tls::is_grease;
+
+// ClientHello cabe folgado nisso; o resto da conexão não é gravado
+const MAX_TAP: usize = 16 * 1024;
+
+const EXT_SNI: u16 = 0x0000;
+const EXT_GROUPS: u16 = 0x000a;
+const EXT_POINT_FORMATS: u16 = 0x000b;
+const EXT_SIGNATURE_ALGORITHMS: u16 = 0x000d;
+const EXT_ALPN: u16 = 0x0010;
+const EXT_SUPPORTED_VERSIONS: u16 = 0x002b;
+
+// Grava os bytes lidos enquanto alguém segura o outro Arc (o accept, até o
+// ClientHello chegar inteiro); depois vira um repasse direto
+pub struct HelloTap<S> {
+    inner: S,
+    tap: Option<Arc<Mutex<Vec<u8>>>>,
+}
+
+impl<S> HelloTap<S> {
+    pub fn new(inner: S) -> (Self, Arc<Mutex<Vec<u8>>>) {
+        let tap = Arc::new(Mutex::new(Vec::new()));
+        (
+            HelloTap {
+                inner,
+                tap: Some(tap.clone()),
+            },
+            tap,
+        )
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for HelloTap<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Some(tap) = &this.tap {
+            if Arc::strong_count(tap) == 1 {
+                this.tap = None;
+            } else {
+                let mut recorded = tap.lock().unwrap();
+                let new = &buf.filled()[before..];
+                let room = MAX_TAP.saturating_sub(recorded.len());
+                recorded.extend_from_slice(&new[..new.len().min(room)]);
+            }
+        }
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for HelloTap<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+impl<S: Abortable> Abortable for HelloTap<S> {
+    fn reset_on_close(&self) {
+        self.inner.reset_on_close();
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u16_list(&mut self, bytes: usize) -> Option<Vec<u16>> {
+        let mut list = Reader(self.take(bytes)?);
+        let mut out = Vec::new();
+        while let Some(value) = list.u16() {
+            out.push(value);
+        }
+        Some(out)
+    }
+}
+
+// Campos do ClientHello que entram no JA3/JA4, na ordem em que vieram
+#[derive(Default)]
+struct Hello {
+    version: u16,
+    ciphers: Vec<u16>,
+    extensions: Vec<u16>,
+    groups: Vec<u16>,
+    point_formats: Vec<u8>,
+    signature_algorithms: Vec<u16>,
+    supported_versions: Vec<u16>,
+    sni: bool,
+    alpn: Option<Vec<u8>>,
+}
+
+// Registros de handshake (o ClientHello pode vir partido em vários) -> Hello
+fn parse(raw: &[u8]) -> Option<Hello> {
+    let mut records = Reader(raw);
+    let mut handshake = Vec::new();
+    while let Some(header) = records.take(5) {
+        if header[0] != 0x16 {
+            break;
+        }
+        let length = u16::from_be_bytes([header[3], header[4]]) as usize;
+        handshake.extend_from_slice(records.take(length)?);
+        if handshake.len() >= 4 {
+            let wanted = u32::from_be_bytes([0, handshake[1], handshake[2], handshake[3]]);
+            if handshake.len() >= 4 + wanted as usize {
+                break;
+            }
+        }
+    }
+    let mut message = Reader(&handshake);
+    if message.u8()? != 1 {
+        return None;
+    }
+    let length = message.take(3)?;
+    let length = u32::from_be_bytes([0, length[0], length[1], length[2]]) as usize;
+    let mut body = Reader(message.take(length)?);
+
+    let mut hello = Hello {
+        version: body.u16()?,
+        ..Hello::default()
+    };
+    body.take(32)?;
+    let session_id = body.u8()? as usize;
+    body.take(session_id)?;
+    let ciphers = body.u16()? as usize;
+    hello.ciphers = body.u16_list(ciphers)?;
+    let compression = body.u8()? as usize;
+    body.take(compression)?;
+    // Sem extensões (SSLv3/TLS antigo) o hello termina aqui
+    let Some(total) = body.u16() else {
+        return Some(hello);
+    };
+    let mut extensions = Reader(body.take(total as usize)?);
+    while let Some(kind) = extensions.u16() {
+        let length = extensions.u16()? as usize;
+        let mut data = Reader(extensions.take(length)?);
+        hello.extensions.push(kind);
+        match kind {
+            EXT_SNI => hello.sni = true,
+            EXT_GROUPS => {
+                let length = data.u16()? as usize;
+                hello.groups = data.u16_list(length)?;
+            }
+            EXT_POINT_FORMATS => {
+                let length = data.u8()? as usize;
+                hello.point_formats = data.take(length)?.to_vec();
+            }
+            EXT_SIGNATURE_ALGORITHMS => {
+                let length = data.u16()? as usize;
+                hello.signature_algorithms = data.u16_list(length)?;
+            }
+            EXT_SUPPORTED_VERSIONS => {
+                let length = data.u8()? as usize;
+                hello.supported_versions = data.u16_list(length)?;
+            }
+            EXT_ALPN => {
+                data.u16()?;
+                let length = data.u8()? as usize;
+                hello.alpn = Some(data.take(length)?.to_vec());
+            }
+            _ => {}
+        }
+    }
+    Some(hello)
+}
+
+fn joined<T: ToString>(values: impl Iterator<Item = T>, separator: &str) -> String {
+    values
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+// md5 de "versão,suites,extensões,grupos,formatos" em decimal, sem GREASE
+fn ja3(hello: &Hello) -> String {
+    let clean = |list: &[u16]| joined(list.iter().filter(|v| !is_grease(**v)), "-");
+    let text = format!(
+        "{},{},{},{},{}",
+        hello.version,
+        clean(&hello.ciphers),
+        clean(&hello.extensions),
+        clean(&hello.groups),
+        joined(hello.point_formats.iter(), "-")
+    );
+    to_hex(&Md5::digest(text.as_bytes()))
+}
+
+fn truncated_sha256(text: &str) -> String {
+    if text.is_empty() {
+        return "000000000000".to_string();
+    }
+    to_hex(&Sha256::digest(text.as_bytes()))[..12].to_string()
+}
+
+// JA4 (FoxIO): t<versão><d|i><nº suites><nº extensões><ALPN>_<suites>_<extensões+assinaturas>.
+// Listas ordenadas: a ordem aleatória do Chrome não muda o resultado
+fn ja4(hello: &Hello) -> String {
+    let version = hello
+        .supported_versions
+        .iter()
+        .copied()
+        .filter(|v| !is_grease(*v))
+        .max()
+        .unwrap_or(hello.version);
+    let version = match version {
+        0x0304 => "13",
+        0x0303 => "12",
+        0x0302 => "11",
+        0x0301 => "10",
+        0x0300 => "s3",
+        _ => "00",
+    };
+    let mut ciphers: Vec<u16> = hello
+        .ciphers
+        .iter()
+        .copied()
+        .filter(|c| !is_grease(*c))
+        .collect();
+    let extensions: Vec<u16> = hello
+        .extensions
+        .iter()
+        .copied()
+        .filter(|e| !is_grease(*e))
+        .collect();
+    let alpn = match hello.alpn.as_deref() {
+        Some([first, .., last])
+            if first.is_ascii_alphanumeric() && last.is_ascii_alphanumeric() =>
+        {
+            format!("{}{}", *first as char, *last as char)
+        }
+        Some([only]) if only.is_ascii_alphanumeric() => {
+            format!("{}{}", *only as char, *only as char)
+        }
+        Some([first, .., last]) => {
+            let (first, last) = (format!("{:02x}", first), format!("{:02x}", last));
+            format!("{}{}", &first[..1], &last[1..])
+        }
+        _ => "00".to_string(),
+    };
+    let a = format!(
+        "t{}{}{:02}{:02}{}",
+        version,
+        if hello.sni { 'd' } else { 'i' },
+        ciphers.len().min(99),
+        extensions.len().min(99),
+        alpn
+    );
+
+    ciphers.sort_unstable();
+    let b = truncated_sha256(&joined(ciphers.iter().map(|c| format!("{:04x}", c)), ","));
+
+    let mut sorted: Vec<u16> = extensions
+        .into_iter()
+        .filter(|e| *e != EXT_SNI && *e != EXT_ALPN)
+        .collect();
+    sorted.sort_unstable();
+    let mut text = joined(sorted.iter().map(|e| format!("{:04x}", e)), ",");
+    if !hello.signature_algorithms.is_empty() && !text.is_empty() {
+        text.push('_');
+        text.push_str(&joined(
+            hello
+                .signature_algorithms
+                .iter()
+                .map(|s| format!("{:04x}", s)),
+            ",",
+        ));
+    }
+    let c = truncated_sha256(&text);
+    format!("{}_{}_{}", a, b, c)
+}
+
+// (JA3, JA4) dos bytes gravados pelo HelloTap; None se não for um ClientHello legível
+pub fn fingerprints(raw: &[u8]) -> Option<(String, String)> {
+    let hello = parse(raw)?;
+    Some((ja3(&hello), ja4(&hello)))
+}
@@ -2,24 +2,87 @@ use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
 use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
+use tracing::{debug, info};
+
+use crate::metrics::Metrics;
+use crate::profiles::AdaptiveRate;
+
 const SHARD_COUNT: usize = 16;
 
+// Buckets parados há mais de idle_ttl são descartados a cada interval
+#[derive(Clone, Copy)]
+pub struct GcConfig {
+    pub interval: Duration,
+    pub idle_ttl: Duration,
+}
+
 struct Bucket {
     tokens: f64,
     last_update: Instant,
+    // adaptive_rate: log2 do multiplicador do limite, decaindo para 0
+    score: f64,
+    scored_at: Instant,
+    // Segundos; 0 = cliente sem perfil adaptativo
+    half_life: f64,
+}
+
+impl Bucket {
+    fn score(&self, now: Instant) -> f64 {
+        if self.half_life <= 0.0 {
+            return 0.0;
+        }
+        let elapsed = now.duration_since(self.scored_at).as_secs_f64();
+        self.score * 0.5f64.powf(elapsed / self.half_life)
+    }
 }
 
 pub struct RateLimiter {
     shards: Vec<Mutex<HashMap<IpAddr, Bucket>>>,
-    rate: f64,
-    capacity: f64,
+    // Modo por core: fichas concedidas aqui desde a última publicação no barramento
+    outbox: Option<Mutex<HashMap<IpAddr, u32>>>,
+    // f64 em bits: reload troca os limites sem lock
+    rate: AtomicU64,
+    capacity: AtomicU64,
+    scale: AtomicU64,
+    name: &'static str,
+    gc: GcConfig,
+    metrics: Arc<Metrics>,
 }
 
 impl RateLimiter {
-    pub fn new(rate: f64, capacity: f64) -> Arc<Self> {
+    pub fn new(
+        name: &'static str,
+        rate: f64,
+        capacity: f64,
+        gc: GcConfig,
+        metrics: Arc<Metrics>,
+    ) -> Arc<Self> {
+        Self::build(name, rate, capacity, gc, metrics, None)
+    }
+
+    // Limiter de um core: o que ele concede sai no barramento para os outros
+    pub fn per_core(
+        name: &'static str,
+        rate: f64,
+        capacity: f64,
+        gc: GcConfig,
+        metrics: Arc<Metrics>,
+    ) -> Arc<Self> {
+        Self::build(name, rate, capacity, gc, metrics, Some(Mutex::default()))
+    }
+
+    fn build(
+        name: &'static str,
+        rate: f64,
+        capacity: f64,
+        gc: GcConfig,
+        metrics: Arc<Metrics>,
+        outbox: Option<Mutex<HashMap<IpAddr, u32>>>,
+    ) -> Arc<Self> {
         let mut shards = Vec::with_capacity(SHARD_COUNT);
         for _ in 0..SHARD_COUNT {
             shards.push(Mutex::new(HashMap::new()));
@@ -27,15 +90,19 @@ impl RateLimiter {
 
         let limiter = Arc::new(RateLimiter {
             shards,
-            rate,
-            capacity,
+            outbox,
+            rate: AtomicU64::new(rate.to_bits()),
+            capacity: AtomicU64::new(capacity.to_bits()),
+            scale: AtomicU64::new(1.0f64.to_bits()),
+            name,
+            gc,
+            metrics,
         });
 
         let limiter_clone = limiter.clone();
         tokio::spawn(async move {
             loop {
-                // Roda a cada 60 segundos
-                tokio::time::sleep(Duration::from_secs(60)).await;
+                tokio::time::sleep(gc.interval).await;
                 limiter_clone.cleanup();
             }
         });
@@ -49,49 +116,135 @@ impl RateLimiter {
         (hasher.finish() as usize) % SHARD_COUNT
     }
 
-    pub fn check(&self, ip: IpAddr) -> bool {
+    pub fn set_scale(&self, scale: f64) {
+        self.scale.store(scale.to_bits(), Ordering::Release);
+    }
+
+    // Buckets existentes só são cortados ao novo teto no próximo check
+    pub fn set_limits(&self, rate: f64, capacity: f64) {
+        self.rate.store(rate.to_bits(), Ordering::Release);
+        self.capacity.store(capacity.to_bits(), Ordering::Release);
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    // Bucket do IP já reabastecido até agora, sob o lock do shard
+    fn with_bucket<T>(&self, ip: IpAddr, f: impl FnOnce(&mut Bucket) -> T) -> T {
+        let scale = f64::from_bits(self.scale.load(Ordering::Acquire));
+        let rate = f64::from_bits(self.rate.load(Ordering::Acquire)) * scale;
+        let capacity = f64::from_bits(self.capacity.load(Ordering::Acquire)) * scale;
+
         let shard_idx = self.get_shard_index(ip);
         let mut shard = self.shards[shard_idx].lock().unwrap();
 
+        let now = Instant::now();
         let bucket = shard.entry(ip).or_insert(Bucket {
-            tokens: self.capacity,
-            last_update: Instant::now(),
+            tokens: capacity,
+            last_update: now,
+            score: 0.0,
+            scored_at: now,
+            half_life: 0.0,
         });
 
-        let now = Instant::now();
+        let factor = bucket.score(now).exp2();
+        let (rate, capacity) = (rate * factor, capacity * factor);
         let duration = now.duration_since(bucket.last_update).as_secs_f64();
-        let new_tokens = duration * self.rate;
+        let new_tokens = duration * rate;
 
         if new_tokens > 0.0 {
-            bucket.tokens = (bucket.tokens + new_tokens).min(self.capacity);
+            bucket.tokens = (bucket.tokens + new_tokens).min(capacity);
             bucket.last_update = now;
         }
+        f(bucket)
+    }
 
-        if bucket.tokens >= 1.0 {
-            bucket.tokens -= 1.0;
-            true
-        } else {
-            false
+    pub fn check(&self, ip: IpAddr) -> bool {
+        let allowed = self.with_bucket(ip, |bucket| {
+            if bucket.tokens >= 1.0 {
+                bucket.tokens -= 1.0;
+                true
+            } else {
+                false
+            }
+        });
+        if allowed && let Some(outbox) = &self.outbox {
+            *outbox.lock().unwrap().entry(ip).or_insert(0) += 1;
         }
+        allowed
+    }
+
+    // Histórico do cliente no limite dele (delta > 0 recompensa, < 0 pune);
+    // devolve o multiplicador que passa a valer
+    pub fn adjust(&self, ip: IpAddr, delta: f64, adaptive: &AdaptiveRate) -> f64 {
+        self.with_bucket(ip, |bucket| {
+            let now = Instant::now();
+            bucket.score = (bucket.score(now) + delta)
+                .clamp(adaptive.min_scale.log2(), adaptive.max_scale.log2());
+            bucket.scored_at = now;
+            bucket.half_life = adaptive.half_life;
+            bucket.score.exp2()
+        })
+    }
+
+    // Fichas que o mesmo cliente gastou em outro core
+    pub fn debit(&self, ip: IpAddr, tokens: u32) {
+        self.with_bucket(ip, |bucket| {
+            bucket.tokens = (bucket.tokens - tokens as f64).max(0.0);
+        });
+    }
+
+    pub fn drain_outbox(&self) -> Vec<(IpAddr, u32)> {
+        self.outbox
+            .as_ref()
+            .map(|outbox| outbox.lock().unwrap().drain().collect())
+            .unwrap_or_default()
     }
 
     fn cleanup(&self) {
-        let threshold = Duration::from_secs(600); // 10 minutos
-        let now = Instant::now();
-        let mut removed_count = 0;
+        let start = Instant::now();
+        let mut removed = 0;
+        let mut remaining = 0;
 
         for shard in &self.shards {
             let mut map = shard.lock().unwrap();
 
             let len_before = map.len();
-            map.retain(|_, bucket| now.duration_since(bucket.last_update) < threshold);
-            removed_count += len_before - map.len();
+            // Cliente punido fica até o score decair: sumir por idle_ttl não limpa a ficha
+            map.retain(|_, bucket| {
+                start.duration_since(bucket.last_update) < self.gc.idle_ttl
+                    || bucket.score(start) < -0.01
+            });
+            removed += len_before - map.len();
+            remaining += map.len();
         }
 
-        if removed_count > 0 {
-            println!(
-                "[GC] Rate Limiter cleanup: removed {} inactive IPs",
-                removed_count
+        let elapsed = start.elapsed();
+        let labels = [("limiter", self.name)];
+        self.metrics.inc("oblivion_limiter_gc_runs_total", &labels);
+        self.metrics
+            .add("oblivion_limiter_gc_removed_total", &labels, removed as u64);
+        self.metrics.add(
+            "oblivion_limiter_gc_duration_us_total",
+            &labels,
+            elapsed.as_micros() as u64,
+        );
+
+        if removed > 0 {
+            info!(
+                limiter = self.name,
+                removed,
+                remaining,
+                ?elapsed,
+                "Rate limiter cleanup"
+            );
+        } else {
+            debug!(
+                limiter = self.name,
+                remaining,
+                ?elapsed,
+                "Rate limiter cleanup"
             );
         }
     }
@@ -0,0 +1,40 @@
+use std::net::SocketAddr;
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::TcpListener;
+
+use crate::error::Error;
+
+const BACKLOG: i32 = 1024;
+
+// IPV6_V6ONLY sempre explícito: o padrão do sistema (net.ipv6.bindv6only) varia.
+// O socket sai fora do runtime: no modo por core cada thread registra o seu, e
+// reuse_port deixa vários no mesmo endereço (o kernel reparte as conexões)
+pub fn bind(
+    addr: SocketAddr,
+    v6_only: bool,
+    reuse_port: bool,
+) -> Result<std::net::TcpListener, Error> {
+    let fail = |e| Error::Bind(addr.to_string(), e);
+    let socket =
+        Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP)).map_err(fail)?;
+    socket.set_reuse_address(true).map_err(fail)?;
+    if reuse_port {
+        socket.set_reuse_port(true).map_err(fail)?;
+    }
+    if addr.is_ipv6() {
+        socket.set_only_v6(v6_only).map_err(fail)?;
+    }
+    socket.bind(&addr.into()).map_err(fail)?;
+    socket.listen(BACKLOG).map_err(fail)?;
+    socket.set_nonblocking(true).map_err(fail)?;
+    Ok(socket.into())
+}
+
+// No runtime de quem chama
+pub fn register(listener: std::net::TcpListener) -> Result<TcpListener, Error> {
+    let addr = listener
+        .local_addr()
+        .map_or_else(|_| "-".to_string(), |a| a.to_string());
+    TcpListener::from_std(listener).map_err(|e| Error::Bind(addr, e))
+}
@@ -0,0 +1,176 @@
+use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use tracing::info;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+// Linhas na fila até o writer; cheia = linha descartada e contada, nunca espera
+const LOG_QUEUE: usize = 64 * 1024;
+
+pub struct LogControl {
+    handle: reload::Handle<EnvFilter, Registry>,
+    base: String,
+    current: Mutex<String>,
+    generation: AtomicU64,
+    pipeline: Arc<Pipeline>,
+}
+
+// Disco lento ou flood de log não seguram o request: o evento formatado vai para
+// um canal limitado e uma thread própria escreve no stdout
+struct Pipeline {
+    tx: Mutex<Option<SyncSender<Vec<u8>>>>,
+    worker: Mutex<Option<JoinHandle<()>>>,
+    dropped: AtomicU64,
+}
+
+impl Pipeline {
+    fn start() -> Arc<Self> {
+        let (tx, rx) = mpsc::sync_channel(LOG_QUEUE);
+        let worker = std::thread::Builder::new()
+            .name("oblivion-log".to_string())
+            .spawn(move || write_loop(rx))
+            .expect("❌ Erro: thread de log não iniciou");
+        Arc::new(Pipeline {
+            tx: Mutex::new(Some(tx)),
+            worker: Mutex::new(Some(worker)),
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    fn send(&self, line: &[u8]) {
+        let tx = self.tx.lock().unwrap().clone();
+        let Some(tx) = tx else {
+            // Depois do flush (saindo): direto, não há mais writer
+            let _ = std::io::stdout().write_all(line);
+            return;
+        };
+        match tx.try_send(line.to_vec()) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+fn write_loop(rx: Receiver<Vec<u8>>) {
+    let mut out = BufWriter::new(std::io::stdout());
+    while let Ok(line) = rx.recv() {
+        let _ = out.write_all(&line);
+        // Fila vazia: o que já saiu vai para o terminal/arquivo agora
+        while let Ok(line) = rx.try_recv() {
+            let _ = out.write_all(&line);
+        }
+        let _ = out.flush();
+    }
+    let _ = out.flush();
+}
+
+#[derive(Clone)]
+struct PipelineWriter(Arc<Pipeline>);
+
+impl Write for PipelineWriter {
+    // O fmt escreve cada evento inteiro numa chamada só
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.send(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for PipelineWriter {
+    type Writer = PipelineWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+// RUST_LOG continua valendo como base; o admin só acrescenta diretivas por cima
+pub fn init(level: Option<&str>) -> Arc<LogControl> {
+    let level = level.unwrap_or("info");
+    let base = match std::env::var("RUST_LOG") {
+        Ok(env) if !env.trim().is_empty() => format!("{},{}", level, env.trim()),
+        _ => level.to_string(),
+    };
+    let filter = EnvFilter::try_new(&base).unwrap_or_else(|_| EnvFilter::new("info"));
+    let (filter, handle) = reload::Layer::new(filter);
+    let pipeline = Pipeline::start();
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().with_writer(PipelineWriter(pipeline.clone())))
+        .init();
+
+    Arc::new(LogControl {
+        handle,
+        current: Mutex::new(base.clone()),
+        base,
+        generation: AtomicU64::new(0),
+        pipeline,
+    })
+}
+
+impl LogControl {
+    pub fn current(&self) -> String {
+        self.current.lock().unwrap().clone()
+    }
+
+    fn apply(&self, directives: &str) -> Result<(), String> {
+        let filter = EnvFilter::try_new(directives).map_err(|e| e.to_string())?;
+        self.handle.reload(filter).map_err(|e| e.to_string())?;
+        *self.current.lock().unwrap() = directives.to_string();
+        Ok(())
+    }
+
+    // Com ttl, volta à base sozinho; um set posterior cancela a volta pendente
+    pub fn set(
+        self: &Arc<Self>,
+        directives: &str,
+        ttl: Option<Duration>,
+    ) -> Result<String, String> {
+        let combined = format!("{},{}", self.base, directives);
+        self.apply(&combined)?;
+        let generation = self.generation.fetch_add(1, Ordering::AcqRel) + 1;
+        info!(filter = %combined, ttl = ?ttl, "Log filter changed");
+
+        if let Some(ttl) = ttl {
+            let control = self.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(ttl).await;
+                if control.generation.load(Ordering::Acquire) == generation {
+                    control.reset();
+                }
+            });
+        }
+        Ok(combined)
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.pipeline.dropped.load(Ordering::Relaxed)
+    }
+
+    // Saída do processo: fecha o canal e espera o writer esvaziar a fila
+    pub fn flush(&self) {
+        self.pipeline.tx.lock().unwrap().take();
+        if let Some(worker) = self.pipeline.worker.lock().unwrap().take() {
+            let _ = worker.join();
+        }
+    }
+
+    pub fn reset(&self) -> String {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        let _ = self.apply(&self.base);
+        info!(filter = %self.base, "Log filter reset");
+        self.base.clone()
+    }
+}
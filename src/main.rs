@@ -1,219 +1,2693 @@
-use std::fs::File;
-use std::io::BufReader;
+use std::collections::VecDeque;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::time::timeout;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::time::{timeout, timeout_at};
 use tracing::{debug, error, info, instrument, warn};
-use tracing_subscriber;
 
-use tokio_rustls::rustls::{self, Certificate, PrivateKey};
-use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::server::Acceptor;
+use tokio_rustls::LazyConfigAcceptor;
 
+mod accept;
+mod access_log;
+mod acme;
+mod admin;
+mod appender;
+mod auth;
+mod automaton;
+mod bans;
+mod bot;
+mod campaigns;
+mod capture;
+mod challenge;
+mod cli;
+mod coalesce;
+mod config;
+mod cookies;
+mod cores;
+mod ddos;
 mod engine;
+mod error;
+mod events;
+mod expiry;
+mod geo;
+mod grpc;
+mod h2c;
+mod h2server;
 mod http;
+mod identity;
+mod inject;
+mod ja;
+mod keying;
 mod limiter;
+mod listener;
+mod logging;
+mod metrics;
+mod mirror;
+mod plain;
+mod profiles;
+mod proxy_protocol;
+mod redirects;
+mod reject;
+mod reload;
+mod replay;
+mod response;
+mod routes;
+mod rules;
+mod seclang;
+mod session;
+mod shield;
+mod signed;
+mod sniff;
+mod temp_rules;
+mod tls;
+mod upstream;
+mod upstream_tls;
+mod watermark;
+mod websocket;
 
+use accept::{AcceptDecision, AcceptGuard};
+use access_log::{AccessLog, Metered};
+use acme::Acme;
+use admin::Admin;
+use arc_swap::ArcSwap;
+use auth::{Authenticator, Mechanism};
+use bans::{BanList, KernelFilter};
+use bot::BotDetector;
+use campaigns::Campaigns;
+use capture::Capture;
+use challenge::{Challenge, CLEARANCE_COOKIE};
+use clap::Parser;
+use cli::Cli;
+use coalesce::{Coalescer, Fetch};
+use config::{
+    CleartextPolicy, ClientConfig, Config, CookieConfig, InjectConfig, OptionsPolicy, PlainMode,
+    ResponseConfig, RuntimeMode, ServerConfig, TlsVersion, UpstreamConfig, UpstreamProtocol,
+};
+use cores::Bus;
+use ddos::{DdosDetector, Decision};
 use engine::{Verdict, WafEngine};
-use http::Request;
+use error::Error;
+use events::{Event, Events};
+use geo::GeoDb;
+use h2c::H2Pool;
+use http::{
+    insert_header, response_body_length, response_headers, response_status, strip_headers,
+    Dechunker, Request, CHUNK_FRAMING_ALLOWANCE,
+};
+use ja::HelloTap;
+use keying::client_key;
 use limiter::RateLimiter;
+use logging::LogControl;
+use metrics::{path_template, Metrics};
+use mirror::{Mirror, Record};
+use profiles::normalize_host;
+use redirects::{RedirectAction, RedirectPolicy};
+use reject::{reject, Abortable, RejectPolicy};
+use reload::{load_site_rules, CertWatcher, ConfigReloader, Reloader, SiteEngines};
+use replay::NonceCache;
+use response::{Check, Inspection, Outcome};
+use routes::{Route, StatusRewrite};
+use rules::RuleSet;
+use session::Sessions;
+use shield::Shield;
+use sniff::ContentMismatch;
+use temp_rules::TempRules;
+use tls::{admin_tls_config, certificate_names, load_tls_config, HelloInfo, ListenerTls};
+use upstream::{connect_retrying, Admission, Failure, RetryBudget};
+use upstream_tls::{UpstreamStream, UpstreamTls};
+use watermark::{WatermarkMode, Watermarks};
+use websocket::{Inspect, Side, Violation};
 
-const LISTENER_ADDR: &str = "0.0.0.0:4433";
-const UPSTREAM_ADDR: &str = "127.0.0.1:8000";
-const MAX_HEADER_SIZE: usize = 8192;
-const MAX_BODY_SIZE: u64 = 10 * 1024 * 1024;
+const CONFIG_PATH: &str = "oblivion.toml";
+pub(crate) const DEADLINE_HEADER: &str = "X-Deadline-Ms";
+const DECISION_HEADER: &str = "X-Oblivion-Decision";
 
-const CLIENT_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
-const UPSTREAM_CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+// Headers fora da chave de cache que permitem envenenar caches downstream
+const UNKEYED_HEADERS: [&str; 4] = [
+    "X-Forwarded-Host",
+    "X-Original-URL",
+    "X-Rewrite-URL",
+    "X-Forwarded-Scheme",
+];
 
-fn load_tls_config() -> Arc<rustls::ServerConfig> {
-    let cert_file =
-        File::open("cert.pem").expect("❌ Erro: 'cert.pem' não encontrado. Gere com openssl.");
-    let mut cert_reader = BufReader::new(cert_file);
-    let certs = rustls_pemfile::certs(&mut cert_reader)
-        .unwrap()
-        .into_iter()
-        .map(Certificate)
-        .collect();
+// Linha de status do upstream lida antes de decidir pelo túnel do upgrade
+const MAX_STATUS_LINE: usize = 8192;
 
-    let key_file =
-        File::open("key.pem").expect("❌ Erro: 'key.pem' não encontrado. Gere com openssl.");
-    let mut key_reader = BufReader::new(key_file);
-    let keys: Vec<PrivateKey> = rustls_pemfile::pkcs8_private_keys(&mut key_reader)
-        .unwrap()
-        .into_iter()
-        .map(PrivateKey)
-        .collect();
+pub(crate) const GATEWAY_TIMEOUT: &[u8] =
+    b"HTTP/1.1 504 Gateway Timeout\r\nContent-Length: 16\r\n\r\nUpstream Timeout";
+pub(crate) const BAD_GATEWAY: &[u8] =
+    b"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 14\r\n\r\nUpstream Error";
 
-    let key = keys
-        .first()
-        .expect("❌ Erro: Nenhuma chave privada encontrada em 'key.pem'")
-        .clone();
+#[derive(Clone)]
+pub(crate) struct Context {
+    config: Arc<ArcSwap<Config>>,
+    engine: Arc<ArcSwap<WafEngine>>,
+    sites: Arc<ArcSwap<SiteEngines>>,
+    limiter: Arc<RateLimiter>,
+    fingerprint_limiter: Arc<RateLimiter>,
+    admission: Arc<Admission>,
+    retries: Arc<RetryBudget>,
+    h2c: Arc<H2Pool>,
+    coalescer: Arc<Coalescer>,
+    guard: Arc<AcceptGuard>,
+    shield: Arc<Shield>,
+    challenge: Challenge,
+    bot: Arc<BotDetector>,
+    ddos: Arc<DdosDetector>,
+    nonces: Arc<NonceCache>,
+    sessions: Arc<Sessions>,
+    auth: Arc<Authenticator>,
+    capture: Arc<Capture>,
+    metrics: Arc<Metrics>,
+    conn_limiter: Arc<RateLimiter>,
+    bans: Arc<BanList>,
+    events: Arc<Events>,
+    mirror: Arc<Mirror>,
+    access_log: Arc<AccessLog>,
+    campaigns: Arc<Campaigns>,
+    acme: Arc<Acme>,
+    temp_rules: Arc<TempRules>,
+    watermarks: Arc<Watermarks>,
+    geo: Option<Arc<GeoDb>>,
+}
 
-    let config = rustls::ServerConfig::builder()
-        .with_safe_defaults()
-        .with_no_client_auth()
-        .with_single_cert(certs, key)
-        .expect("❌ Erro: Configuração TLS inválida");
+// Handshake TLS do upstream https conta dentro do connect_timeout
+async fn connect_upstream(
+    upstream: &UpstreamConfig,
+    tls: Option<&UpstreamTls>,
+    connect_timeout: Duration,
+) -> Result<UpstreamStream, Error> {
+    let addr = upstream.endpoint();
+    let connect = async {
+        let tcp = TcpStream::connect(addr).await.map_err(|e| {
+            let message = format!("{}: {}", addr, e);
+            match e.kind() {
+                std::io::ErrorKind::ConnectionRefused => Error::UpstreamRefused(message),
+                _ => Error::Upstream(message),
+            }
+        })?;
+        match tls {
+            Some(tls) => tls.handshake(tcp, addr).await,
+            None => Ok(UpstreamStream::Plain(tcp)),
+        }
+    };
+    match timeout(connect_timeout, connect).await {
+        Ok(result) => result,
+        Err(_) => Err(Error::UpstreamTimeout(addr.to_string())),
+    }
+}
 
-    Arc::new(config)
+// Mais bytes do corpo no acumulador; false = cliente fechou, erro ou body_timeout
+async fn read_body<S>(
+    stream: &mut S,
+    accumulator: &mut Vec<u8>,
+    chunk: &mut [u8],
+    client: &ClientConfig,
+) -> bool
+where
+    S: AsyncRead + Unpin,
+{
+    match timeout(client.body_timeout, stream.read(chunk)).await {
+        Ok(Ok(0)) => false,
+        Ok(Ok(n)) => {
+            accumulator.extend_from_slice(&chunk[..n]);
+            true
+        }
+        Ok(Err(e)) => {
+            debug!("Socket read error: {}", e);
+            false
+        }
+        Err(_) => {
+            warn!("Connection dropped: Client body timeout");
+            false
+        }
+    }
+}
+
+// Content-Type que não bate com o corpo: JSON que é multipart, imagem que é PHP...
+// Some = motivo do bloqueio
+fn inspect_content(
+    req: &mut Request,
+    body: &[u8],
+    route: Option<&Route>,
+    config: &Config,
+    metrics: &Metrics,
+) -> Option<String> {
+    req.body = String::from_utf8_lossy(body).to_string();
+    let action = route
+        .and_then(|r| r.content_mismatch)
+        .unwrap_or(config.policy.content_mismatch);
+    if action == ContentMismatch::Off {
+        return None;
+    }
+    let mismatch = sniff::check(req.header("Content-Type"), body)?;
+    let (declared, detected) = (mismatch.declared.label(), mismatch.detected.label());
+    warn!(
+        declared,
+        detected,
+        part = mismatch.part.as_deref(),
+        action = action.label(),
+        "Content-Type mismatch"
+    );
+    metrics.inc(
+        "oblivion_content_type_mismatch_total",
+        &[("declared", declared), ("detected", detected)],
+    );
+    match action {
+        ContentMismatch::Block => Some(format!(
+            "Content-Type Mismatch: declared {}, body looks like {}",
+            declared, detected
+        )),
+        // O corpo é inspecionado como o que ele é, não como o que diz ser
+        ContentMismatch::Inspect if mismatch.part.is_none() => {
+            req.body_as_form = Some(mismatch.detected == sniff::Kind::Form);
+            None
+        }
+        _ => None,
+    }
 }
 
-#[instrument(skip(stream, engine), fields(peer_addr, method, path))]
-async fn handle_client<S>(mut stream: S, peer_addr: SocketAddr, engine: Arc<WafEngine>)
+async fn refuse_grpc<S>(
+    stream: &mut S,
+    refusal: grpc::Refusal,
+    server: &ServerConfig,
+    metrics: &Metrics,
+) where
+    S: AsyncWrite + Unpin,
+{
+    warn!(
+        code = refusal.code,
+        reason = %refusal.message,
+        "gRPC request refused"
+    );
+    metrics.inc("oblivion_grpc_refused_total", &[("reason", refusal.label)]);
+    respond(
+        stream,
+        server,
+        grpc::response(refusal.code, &refusal.message).as_bytes(),
+    )
+    .await;
+}
+
+// Toda resposta gerada aqui (não vinda do upstream) sai com a política de [server]
+async fn respond<S>(stream: &mut S, server: &ServerConfig, response: &[u8])
 where
-    S: AsyncRead + AsyncWrite + Unpin,
+    S: AsyncWrite + Unpin,
 {
-    tracing::Span::current().record("peer_addr", &tracing::field::display(peer_addr));
+    let _ = stream.write_all(&identity::stamp(server, response)).await;
+}
 
-    let mut accumulator: Vec<u8> = Vec::new();
-    let mut buffer = [0u8; 1024];
-    let request_str: String;
+fn failure_response(e: &Error) -> &'static [u8] {
+    match e {
+        Error::UpstreamTimeout(_) => GATEWAY_TIMEOUT,
+        _ => BAD_GATEWAY,
+    }
+}
 
-    loop {
-        let read_result = timeout(CLIENT_HEADER_TIMEOUT, stream.read(&mut buffer)).await;
+// Head que vai para o upstream e os headers removidos no caminho
+fn forward_head(
+    req: &Request,
+    route: Option<&Route>,
+    upstream: &UpstreamConfig,
+    internal: bool,
+) -> (Vec<u8>, Vec<String>) {
+    let allowed = route.map_or(&[][..], |r| &r.allow_headers[..]);
+    let unkeyed: Vec<&str> = UNKEYED_HEADERS
+        .into_iter()
+        .filter(|h| !allowed.iter().any(|a| a.eq_ignore_ascii_case(h)))
+        .collect();
+    let normalize = !internal
+        && route
+            .and_then(|r| r.normalize)
+            .unwrap_or(upstream.normalize);
+    let serialized = if normalize {
+        req.canonical_head(upstream.canonical_header_case)
+    } else {
+        req.serialize_head(upstream.canonical_header_case)
+    };
+    strip_headers(&serialized, &unkeyed)
+}
+
+pub(crate) async fn handle_client<S>(
+    stream: S,
+    listener: SocketAddr,
+    peer_addr: SocketAddr,
+    hello: HelloInfo,
+    ctx: Arc<Context>,
+) where
+    S: AsyncRead + AsyncWrite + Abortable + Unpin,
+{
+    // Keep-alive: cada request da conexão passa pelo pipeline inteiro; o que já
+    // foi lido além do request atual (pipelining) é o começo do próximo
+    let mut stream = Metered::new(stream);
+    let mut pending = Vec::new();
+    let mut served = 0;
+    while let Some(rest) = handle_request(
+        &mut stream,
+        pending,
+        served,
+        listener,
+        peer_addr,
+        &hello,
+        &ctx,
+    )
+    .await
+    {
+        let (access, sent) = stream.finish();
+        ctx.access_log.record(&access, peer_addr.ip(), sent);
+        pending = rest;
+        served += 1;
+    }
+    // Linha do último request, com o status e os bytes que saíram
+    let (access, sent) = stream.finish();
+    ctx.access_log.record(&access, peer_addr.ip(), sent);
+}
+
+// Um request da conexão; Some(bytes seguintes) se ela continua aberta
+#[instrument(
+    name = "handle_client",
+    skip(stream, accumulator, served, hello, ctx),
+    fields(peer_addr, method, path, session)
+)]
+async fn handle_request<S>(
+    stream: &mut Metered<S>,
+    mut accumulator: Vec<u8>,
+    served: u64,
+    listener: SocketAddr,
+    peer_addr: SocketAddr,
+    hello: &HelloInfo,
+    ctx: &Context,
+) -> Option<Vec<u8>>
+where
+    S: AsyncRead + AsyncWrite + Abortable + Unpin,
+{
+    tracing::Span::current().record("peer_addr", tracing::field::display(peer_addr));
+    // Snapshot por request, como o engine: SIGHUP no meio não muda os limites deste
+    let config = ctx.config.load_full();
+    let client = client_key(peer_addr.ip(), config.rate_limit.ipv6_prefix);
+    let started = Instant::now();
+
+    let mut buffer = [0u8; 1024];
+    let header_len = loop {
+        if let Some(i) = accumulator.windows(4).position(|w| w == b"\r\n\r\n") {
+            break i + 4;
+        }
+        // Entre requests a conexão pode ficar parada até o keepalive_timeout;
+        // começado o head, vale o header_timeout
+        let idle = served > 0 && accumulator.is_empty();
+        let wait = if idle {
+            config.client.keepalive_timeout
+        } else {
+            config.client.header_timeout
+        };
+        let read_result = timeout(wait, stream.read(&mut buffer)).await;
 
         let n = match read_result {
+            Err(_) if idle => {
+                debug!(served, "Keep-alive connection idle");
+                return None;
+            }
             Err(_) => {
                 warn!("Connection dropped: Client header timeout (Slowloris protection)");
-                return;
+                return None;
             }
-            Ok(Ok(0)) => return,
+            Ok(Ok(0)) => return None,
             Ok(Ok(n)) => n,
             Ok(Err(e)) => {
                 debug!("Socket read error: {}", e);
-                return;
+                return None;
             }
         };
 
-        if accumulator.len() + n > MAX_HEADER_SIZE {
+        if accumulator.len() + n > config.client.max_header_size {
             warn!("DoS attempt: Header size exceeded limit");
-            return;
+            return None;
         }
         accumulator.extend_from_slice(&buffer[..n]);
+    };
 
-        if let Some(i) = accumulator.windows(4).position(|w| w == b"\r\n\r\n") {
-            let header_len = i + 4;
-            request_str = String::from_utf8_lossy(&accumulator[..header_len]).to_string();
-            break;
+    let request_str = String::from_utf8_lossy(&accumulator[..header_len]).to_string();
+    let mut req = match Request::parse(&request_str) {
+        Ok(req) => req,
+        Err(e) => {
+            warn!(category = e.category(), error = %e, "Invalid HTTP Protocol");
+            respond(
+                stream,
+                &config.server,
+                b"HTTP/1.1 400 Bad Request\r\n\r\nInvalid HTTP",
+            )
+            .await;
+            return None;
+        }
+    };
+
+    tracing::Span::current().record("method", &req.method);
+    tracing::Span::current().record("path", &req.path);
+    req.ja3 = hello.ja3.clone();
+    req.ja4 = hello.ja4.clone();
+    ctx.access_log.begin(&mut stream.access, &req);
+
+    // [[site]] pelo Host: upstream, limites e regras daquela aplicação
+    let config = config.site(req.header("Host")).cloned().unwrap_or(config);
+    // [internal]: sem rate limit, desafio, normalização nem regras; log e métricas ficam
+    let internal = config.internal.matches(peer_addr.ip(), &hello.client_names);
+
+    if !internal && !ctx.limiter.check(client) {
+        warn!(
+            policy = config.policy.rate_limit.label(),
+            "Request rate limit exceeded"
+        );
+        ctx.metrics.inc(
+            "oblivion_refused_total",
+            &[("stage", "request"), ("reason", "request_rate")],
+        );
+        ctx.events.emit(Event::RateLimit { ip: peer_addr.ip() });
+        stream.access.verdict = "rate_limit";
+        reject(
+            stream,
+            config.policy.rate_limit,
+            &identity::stamp(
+                &config.server,
+                b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: 1\r\nContent-Length: 0\r\n\r\n",
+            ),
+            config.policy.silent_drop_hold,
+        )
+        .await;
+        return None;
+    }
+
+    // JA3/JA4 de ferramenta conhecida: além do limite normal, um bem mais apertado
+    if !internal
+        && hello.fingerprint_in(&config.rate_limit.fingerprints)
+        && !ctx.fingerprint_limiter.check(client)
+    {
+        warn!(
+            ja3 = hello.ja3.as_deref(),
+            ja4 = hello.ja4.as_deref(),
+            policy = config.policy.rate_limit.label(),
+            "Fingerprint rate limit exceeded"
+        );
+        ctx.metrics.inc(
+            "oblivion_refused_total",
+            &[("stage", "request"), ("reason", "fingerprint_rate")],
+        );
+        ctx.events.emit(Event::RateLimit { ip: peer_addr.ip() });
+        stream.access.verdict = "rate_limit";
+        reject(
+            stream,
+            config.policy.rate_limit,
+            &identity::stamp(
+                &config.server,
+                b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: 5\r\nContent-Length: 0\r\n\r\n",
+            ),
+            config.policy.silent_drop_hold,
+        )
+        .await;
+        return None;
+    }
+
+    // Asterisk-form (RFC 9112 3.2.4): só OPTIONS, e a pergunta é sobre o servidor
+    if req.path == "*" {
+        let options = req.method.eq_ignore_ascii_case("OPTIONS");
+        let policy = if options {
+            config.server.options
+        } else {
+            OptionsPolicy::Reject
+        };
+        ctx.metrics.inc(
+            "oblivion_options_asterisk_total",
+            &[
+                ("method", if options { "options" } else { "other" }),
+                ("policy", policy.label()),
+            ],
+        );
+        match policy {
+            OptionsPolicy::Respond => {
+                debug!("OPTIONS * answered locally");
+                respond(
+                    stream,
+                    &config.server,
+                    &identity::options_response(&config.server),
+                )
+                .await;
+                // Sem corpo a conexão segue; com corpo fechar é mais simples que drenar
+                let persist = !req.chunked()
+                    && req.content_length().unwrap_or(0) == 0
+                    && req.keep_alive()
+                    && served + 1 < config.client.keepalive_requests;
+                return persist.then(|| accumulator.split_off(header_len));
+            }
+            OptionsPolicy::Reject if options => {
+                respond(
+                    stream,
+                    &config.server,
+                    b"HTTP/1.1 501 Not Implemented\r\nContent-Length: 0\r\n\r\n",
+                )
+                .await;
+                return None;
+            }
+            OptionsPolicy::Reject => {
+                warn!("Asterisk-form target on a method other than OPTIONS");
+                respond(
+                    stream,
+                    &config.server,
+                    b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n",
+                )
+                .await;
+                return None;
+            }
+            OptionsPolicy::Forward => {}
+        }
+    }
+
+    // ID aleatório: correlaciona o navegador entre IPs sem carregar dado pessoal
+    let session = if internal {
+        None
+    } else {
+        ctx.sessions
+            .resolve(&req, &config.session, !hello.plaintext)
+    };
+    if let Some(session) = &session {
+        tracing::Span::current().record("session", &session.id[..12]);
+    }
+    let set_cookie = session.as_ref().and_then(|s| s.set_cookie.as_deref());
+
+    // Cookies só com Secure em TLS: no listener HTTP o navegador os descartaria
+    let secure = !hello.plaintext;
+    let challenge_page = || {
+        ctx.challenge.response(
+            client,
+            session.as_ref(),
+            config.challenge.difficulty,
+            secure,
+        )
+    };
+
+    // challenge.exempt: passa como quem já resolveu o desafio
+    let exempt = &config.challenge.exempt;
+    let cleared = (!exempt.is_empty()
+        && exempt.matches(peer_addr.ip(), &hello.client_names, ctx.geo.as_deref()))
+        || ctx.challenge.verify(
+            client,
+            session.as_ref(),
+            req.cookie(CLEARANCE_COOKIE),
+            config.challenge.difficulty,
+        );
+    // Sob ataque, além do desafio, o que custa CPU e não decide o request sai do caminho
+    let under_attack = ctx.shield.under_attack();
+    if !internal && under_attack && !cleared {
+        debug!("Under attack: challenging client");
+        stream.access.verdict = "challenge";
+        respond(stream, &config.server, &challenge_page()).await;
+        return None;
+    }
+
+    if config.ddos.enabled && !internal {
+        let fingerprint = ddos::fingerprint(&req, hello);
+        match ctx.ddos.observe(&fingerprint, &config.ddos) {
+            Decision::Pass => {}
+            Decision::Challenge if cleared => {}
+            Decision::Challenge => {
+                debug!("DDoS cluster mitigation: challenging client");
+                ctx.metrics.inc(
+                    "oblivion_ddos_mitigated_requests_total",
+                    &[("action", "challenge")],
+                );
+                stream.access.verdict = "challenge";
+                respond(stream, &config.server, &challenge_page()).await;
+                return None;
+            }
+            Decision::RateLimited => {
+                debug!("DDoS cluster mitigation: cluster rate limit exceeded");
+                ctx.metrics.inc(
+                    "oblivion_ddos_mitigated_requests_total",
+                    &[("action", "rate_limit")],
+                );
+                stream.access.verdict = "rate_limit";
+                reject(
+                    stream,
+                    config.policy.rate_limit,
+                    &identity::stamp(&config.server, b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: 1\r\nContent-Length: 0\r\n\r\n"),
+                    config.policy.silent_drop_hold,
+                )
+                .await;
+                return None;
+            }
+        }
+    }
+
+    // Sessão recém-criada não identifica ninguém: cliente sem cookie ganha uma por request
+    let bot = ctx.bot.observe(
+        client,
+        session
+            .as_ref()
+            .filter(|s| s.set_cookie.is_none())
+            .map(|s| s.id.as_str()),
+        &req,
+        hello,
+    );
+    for signal in &bot.signals {
+        ctx.metrics
+            .inc("oblivion_bot_signals_total", &[("signal", signal)]);
+    }
+    if !internal && bot.score >= config.challenge.bot_score && !cleared {
+        warn!(score = bot.score, signals = ?bot.signals, "Automation suspected: challenging client");
+        stream.access.verdict = "challenge";
+        respond(stream, &config.server, &challenge_page()).await;
+        return None;
+    }
+
+    // Um snapshot por request: reload no meio não troca as regras debaixo dele
+    let engine = ctx
+        .sites
+        .load()
+        .get(&config.files.rules)
+        .cloned()
+        .unwrap_or_else(|| ctx.engine.load_full());
+    let route = engine.route(&req);
+
+    // Exigência de credencial da rota, antes de ler o corpo e de qualquer inspeção
+    let accept = route
+        .and_then(|r| r.auth.as_deref())
+        .unwrap_or(&config.auth.default);
+    match ctx.auth.check(
+        &req,
+        &hello.client_names,
+        accept,
+        route.map_or(&[][..], |r| &r.auth_principals[..]),
+        &config.auth,
+    ) {
+        Ok(Some(granted)) => debug!(
+            mechanism = granted.mechanism.label(),
+            principal = granted.principal.as_deref(),
+            "Authenticated request"
+        ),
+        Ok(None) => {}
+        Err(e) => {
+            warn!(code = e.code(), "Authentication requirement not met");
+            ctx.metrics
+                .inc("oblivion_auth_rejections_total", &[("code", e.code())]);
+            respond(
+                stream,
+                &config.server,
+                &e.response(accept.contains(&Mechanism::Jwt)),
+            )
+            .await;
+            return None;
         }
     }
 
-    match Request::parse(&request_str) {
-        Ok(req) => {
-            tracing::Span::current().record("method", &req.method);
-            tracing::Span::current().record("path", &req.path);
+    let body_limit = route
+        .and_then(|r| r.max_body_size)
+        .unwrap_or(config.client.max_body_size);
+    // Transfer-Encoding: chunked manda no tamanho (RFC 9112 6.3): Content-Length
+    // junto é ignorado e não segue para o upstream
+    let chunked = req.chunked();
+    let mut content_length = if chunked { None } else { req.content_length() };
+
+    if content_length.is_some_and(|cl| cl > body_limit) {
+        warn!(
+            content_length,
+            limit = body_limit,
+            "Request body exceeds limit"
+        );
+        respond(
+            stream,
+            &config.server,
+            b"HTTP/1.1 413 Payload Too Large\r\nContent-Length: 0\r\n\r\n",
+        )
+        .await;
+        return None;
+    }
+
+    // gRPC: método fora de grpc.methods nem chega a ler o corpo
+    let grpc = !internal && grpc::requested(&req);
+    if grpc && !grpc::method_allowed(req.path_only(), &config.grpc.methods) {
+        refuse_grpc(
+            stream,
+            grpc::Refusal {
+                code: grpc::PERMISSION_DENIED,
+                label: "method",
+                message: format!("method {} not allowed", req.path_only()),
+            },
+            &config.server,
+            &ctx.metrics,
+        )
+        .await;
+        return None;
+    }
 
-            match engine.inspect(&req) {
-                Verdict::Allow => {
-                    info!("Proxying request");
+    let mut sniff_block = None;
+    let mut grpc_refusal = None;
+    // Corpo chunked inspecionado: (remontado, tamanho bruto no stream)
+    let mut dechunked = None;
+    // Rotas de upload grande (inspect_body: false) vão direto pro túnel
+    let inspect_body = !internal && route.is_none_or(|r| r.inspect_body);
+    let mut chunk = vec![0u8; 16 * 1024];
+    if inspect_body && chunked {
+        let limit = body_limit.min(config.client.inspect_limit());
+        let mut decoder = Dechunker::default();
+        let raw_len = loop {
+            let fed = decoder.feed(&accumulator[header_len..]);
+            // O bruto também tem teto: framing inflado cresce o buffer sem crescer o corpo
+            let raw = (accumulator.len() - header_len) as u64;
+            if decoder.body.len() as u64 > limit
+                || matches!(fed, Ok(None)) && raw > limit + CHUNK_FRAMING_ALLOWANCE
+            {
+                warn!(
+                    decoded = decoder.body.len(),
+                    raw, limit, "Chunked request body exceeds limit"
+                );
+                respond(
+                    stream,
+                    &config.server,
+                    b"HTTP/1.1 413 Payload Too Large\r\nContent-Length: 0\r\n\r\n",
+                )
+                .await;
+                return None;
+            }
+            match fed {
+                Ok(Some(raw_len)) => break raw_len,
+                Ok(None) => {
+                    if !read_body(stream, &mut accumulator, &mut chunk, &config.client).await {
+                        return None;
+                    }
                 }
-                Verdict::Block(reason) => {
-                    warn!(reason = %reason, "Blocked malicious request");
-                    let msg = format!(
-                        "HTTP/1.1 403 Forbidden\r\nContent-Length: {}\r\n\r\nBLOCK: {}",
-                        7 + reason.len(),
-                        reason
-                    );
-                    let _ = stream.write_all(msg.as_bytes()).await;
-                    return;
+                Err(reason) => {
+                    warn!(reason, "Malformed chunked request body");
+                    ctx.metrics
+                        .inc("oblivion_malformed_chunked_total", &[("reason", reason)]);
+                    respond(
+                        stream,
+                        &config.server,
+                        b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n",
+                    )
+                    .await;
+                    return None;
                 }
             }
+        };
+        sniff_block = inspect_content(&mut req, &decoder.body, route, &config, &ctx.metrics);
+        if grpc {
+            grpc_refusal = grpc::inspect(&mut req, &decoder.body, &config.grpc).err();
         }
-        Err(e) => {
-            warn!(error = %e, "Invalid HTTP Protocol");
-            let _ = stream
-                .write_all(b"HTTP/1.1 400 Bad Request\r\n\r\nInvalid HTTP")
+        dechunked = Some((decoder.body, raw_len));
+    } else if inspect_body && let Some(cl) = content_length.filter(|cl| *cl > 0) {
+        if cl > config.client.inspect_limit() {
+            warn!(content_length = cl, "Request body too large to inspect");
+            respond(
+                stream,
+                &config.server,
+                b"HTTP/1.1 413 Payload Too Large\r\nContent-Length: 0\r\n\r\n",
+            )
+            .await;
+            return None;
+        }
+
+        let body_end = header_len + cl as usize;
+        while accumulator.len() < body_end {
+            if !read_body(stream, &mut accumulator, &mut chunk, &config.client).await {
+                return None;
+            }
+        }
+        let body = &accumulator[header_len..body_end];
+        sniff_block = inspect_content(&mut req, body, route, &config, &ctx.metrics);
+        if grpc {
+            grpc_refusal = grpc::inspect(&mut req, body, &config.grpc).err();
+        }
+    }
+    if let Some(refusal) = grpc_refusal {
+        refuse_grpc(stream, refusal, &config.server, &ctx.metrics).await;
+        return None;
+    }
+
+    // Regra temporária é decisão do operador na hora: bloqueia mesmo em detect_only
+    let mut temporary = false;
+    let verdict = if internal {
+        Verdict::Allow
+    } else if let Some(reason) = sniff_block {
+        Verdict::Block(reason, None)
+    } else if let Some((id, reason)) = ctx.temp_rules.check(&req) {
+        temporary = true;
+        Verdict::Block(format!("Temporary Rule {}: {}", id, reason), None)
+    } else if !under_attack && ctx.capture.claim(peer_addr.ip(), &req.path) {
+        let (verdict, lines) = engine.trace(&req);
+        ctx.capture.record(peer_addr, &lines).await;
+        verdict
+    } else if route
+        .and_then(|r| r.block_cache)
+        .unwrap_or(config.policy.block_cache)
+    {
+        engine.inspect_cached(&req)
+    } else {
+        engine.inspect(&req)
+    };
+
+    // [policy] detect_only: o bloqueio vira registro do que teria acontecido e o request segue;
+    // framing e protocolo inválidos seguem bloqueados, o backend leria outro request
+    let detect = config.policy.detect_only
+        && !temporary
+        && matches!(verdict, Verdict::Block(..))
+        && engine.protocol_violation(&req).is_none();
+
+    // Perfil com adaptive_rate: o veredito mexe no limite de requests deste cliente
+    let adaptive = if internal || detect {
+        None
+    } else {
+        let host = normalize_host(req.header("Host").unwrap_or(""));
+        engine
+            .rules()
+            .profile_for(&host, route)
+            .and_then(|(_, profile)| profile.adaptive_rate)
+    };
+    if let Some(adaptive) = &adaptive {
+        let delta = match verdict {
+            Verdict::Allow => adaptive.reward,
+            Verdict::Block(..) => -adaptive.penalty,
+        };
+        let factor = ctx.limiter.adjust(client, delta, adaptive);
+        if delta < 0.0 {
+            debug!(factor, "Adaptive rate limit lowered");
+        }
+    }
+
+    let outcome = match verdict {
+        Verdict::Allow if internal => "internal",
+        Verdict::Allow => "allow",
+        Verdict::Block(..) if detect => "detect",
+        Verdict::Block(..) => "block",
+    };
+    ctx.metrics.inc(
+        "oblivion_requests_total",
+        &[("path", &path_template(&req.path)), ("verdict", outcome)],
+    );
+    let (reason, matched) = match &verdict {
+        Verdict::Allow => (None, None),
+        Verdict::Block(reason, matched) => (Some(reason.as_str()), matched.as_ref()),
+    };
+    stream.access.verdict = outcome;
+    stream.access.rule = matched.map(|m| m.rule);
+    // Sob ataque o mirror fica de fora: uma linha por request é exatamente o que não cabe
+    if !under_attack {
+        ctx.mirror.emit(Record {
+            ip: peer_addr.ip(),
+            listener,
+            method: &req.method,
+            host: req.header("Host"),
+            path: &req.path,
+            user_agent: req.header("User-Agent"),
+            session: session.as_ref().map(|s| s.id.as_str()),
+            tls: format!("{:016x}", hello.fingerprint),
+            ja3: hello.ja3.as_deref(),
+            ja4: hello.ja4.as_deref(),
+            body_size: req.body.len(),
+            verdict: outcome,
+            reason,
+            rule: matched.map(|m| m.rule),
+            field: matched.map(|m| m.field.as_str()),
+        });
+    }
+
+    match verdict {
+        Verdict::Allow if internal => info!("Proxying internal request without inspection"),
+        Verdict::Allow => {
+            ctx.guard.mark_good(client);
+            info!("Proxying request");
+        }
+        Verdict::Block(reason, matched) if detect => {
+            let matched = matched.as_ref();
+            warn!(
+                reason = %reason,
+                rule = matched.map(|m| m.rule),
+                field = matched.map(|m| m.field.as_str()),
+                context = matched.map(|m| m.context.escape_debug().to_string()),
+                severity = matched.and_then(|m| m.severity).map(|s| s.label()),
+                "Detect only: request would have been blocked"
+            );
+            info!("Proxying request");
+        }
+        Verdict::Block(reason, matched) => {
+            let policy = route
+                .and_then(|r| r.block_policy)
+                .unwrap_or(config.policy.block);
+            let matched = matched.as_ref();
+            warn!(
+                reason = %reason,
+                policy = policy.label(),
+                rule = matched.map(|m| m.rule),
+                field = matched.map(|m| m.field.as_str()),
+                offset = matched.map(|m| m.offset),
+                length = matched.map(|m| m.length),
+                context = matched.map(|m| m.context.escape_debug().to_string()),
+                value_hash = matched.map(|m| m.value_hash.as_str()),
+                severity = matched.and_then(|m| m.severity).map(|s| s.label()),
+                "Blocked malicious request"
+            );
+            ctx.events.emit(Event::Block {
+                ip: peer_addr.ip(),
+                reason: &reason,
+                path: &req.path,
+            });
+            ctx.bans.strike(peer_addr.ip(), &config.bans);
+            if let Some(matched) = matched {
+                ctx.campaigns.record(peer_addr.ip(), &req.path, matched);
+            }
+            // Cliente gRPC entende grpc-status, não a página de 403
+            let msg = if grpc {
+                grpc::response(grpc::PERMISSION_DENIED, &format!("BLOCK: {}", reason))
+            } else {
+                format!(
+                    "HTTP/1.1 403 Forbidden\r\nContent-Length: {}\r\n\r\nBLOCK: {}",
+                    7 + reason.len(),
+                    reason
+                )
+            };
+            reject(
+                stream,
+                policy,
+                &identity::stamp(&config.server, msg.as_bytes()),
+                config.policy.silent_drop_hold,
+            )
+            .await;
+            return None;
+        }
+    }
+
+    if let Some(policy) = route.and_then(|r| r.replay.as_ref())
+        && let Err(e) = ctx.nonces.check(&req, policy)
+    {
+        warn!(code = e.code(), "Replay protection rejected request");
+        ctx.metrics
+            .inc("oblivion_replay_rejections_total", &[("code", e.code())]);
+        respond(stream, &config.server, &e.response()).await;
+        return None;
+    }
+
+    if let Some(policy) = route.and_then(|r| r.signed_url.as_ref())
+        && let Err(e) = policy.verify(&req)
+    {
+        warn!(code = e.code(), "Signed URL rejected");
+        ctx.metrics.inc(
+            "oblivion_signed_url_rejections_total",
+            &[("code", e.code())],
+        );
+        respond(stream, &config.server, &e.response()).await;
+        return None;
+    }
+
+    let (mut head, stripped) = forward_head(&req, route, &config.upstream, internal);
+    for header in &stripped {
+        debug!(header = %header, "Stripped unkeyed header");
+        ctx.metrics
+            .inc("oblivion_headers_stripped_total", &[("header", header)]);
+    }
+
+    // Cópia vinda do cliente nunca chega ao backend, ligado ou não
+    head = strip_headers(&head, &[DECISION_HEADER]).0;
+    if config.upstream.decision_header && !internal {
+        let (profile, evaluated) = engine.coverage(&req, route);
+        let decision = format!(
+            "score={}; rules={}; profile={}; time={}us",
+            bot.score,
+            evaluated,
+            profile.unwrap_or("-"),
+            started.elapsed().as_micros()
+        );
+        insert_header(&mut head, DECISION_HEADER, &decision);
+    }
+
+    // Upgrade (WebSocket) leva o Connection do cliente; o resto vai com close:
+    // o fim da resposta fica claro e o próximo request do cliente nunca segue
+    // direto pela conexão do upstream
+    let upgrade = req.has_header("Upgrade");
+    if !upgrade {
+        head = strip_headers(&head, &["Connection", "Keep-Alive"]).0;
+        insert_header(&mut head, "Connection", "close");
+    }
+    // Mensagem comprimida não dá para inspecionar: sem extensão, vem em claro
+    let websocket = upgrade && websocket::requested(&req);
+    let inspect_frames = websocket && config.websocket.inspect && !internal;
+    if inspect_frames {
+        head = strip_headers(&head, &["Sec-WebSocket-Extensions"]).0;
+    }
+    if chunked {
+        head = strip_headers(&head, &["Content-Length"]).0;
+    }
+    // Remontado, o corpo segue com Content-Length: WAF e upstream leem o mesmo corpo
+    let framed = dechunked.is_some() || !req.has_header("Transfer-Encoding");
+    if let Some((body, raw_len)) = dechunked {
+        head = strip_headers(&head, &["Transfer-Encoding"]).0;
+        insert_header(&mut head, "Content-Length", &body.len().to_string());
+        content_length = Some(body.len() as u64);
+        accumulator.splice(header_len..header_len + raw_len, body);
+    }
+    // Onde este request termina no stream; chunked não remontado só no fechamento
+    let body_end = framed.then(|| header_len + content_length.unwrap_or(0) as usize);
+    let leftover = match body_end {
+        Some(end) if accumulator.len() > end => accumulator.split_off(end),
+        _ => Vec::new(),
+    };
+    // CL e TE juntos: atendido, mas a conexão fecha depois (RFC 9112 6.3)
+    let keep_alive = body_end.is_some()
+        && !(chunked && req.has_header("Content-Length"))
+        && !upgrade
+        && req.keep_alive()
+        && served + 1 < config.client.keepalive_requests;
+
+    // Token por cliente/sessão na resposta: conteúdo vazado aponta quem o pediu
+    let watermark = route.and_then(|r| r.watermark).map(|_| {
+        let session = session.as_ref().map(|s| s.id.as_str());
+        ctx.watermarks.mark(peer_addr.ip(), session, &req.path)
+    });
+
+    // Regras RESPONSE_BODY; só roda em rota com inspeção da resposta
+    let check_response = |body: &str| match engine.inspect_response(&req, body) {
+        Verdict::Allow => None,
+        Verdict::Block(reason, matched) if config.policy.detect_only => {
+            warn!(
+                reason = %reason,
+                rule = matched.as_ref().map(|m| m.rule),
+                "Detect only: upstream response would have been blocked"
+            );
+            None
+        }
+        Verdict::Block(reason, matched) => {
+            let matched = matched.as_ref();
+            warn!(
+                reason = %reason,
+                rule = matched.map(|m| m.rule),
+                offset = matched.map(|m| m.offset),
+                context = matched.map(|m| m.context.escape_debug().to_string()),
+                "Blocked upstream response"
+            );
+            ctx.events.emit(Event::Block {
+                ip: peer_addr.ip(),
+                reason: &reason,
+                path: &req.path,
+            });
+            Some(reason)
+        }
+    };
+
+    let timeouts = route.map(|r| &r.timeouts);
+    // SSE/long-poll: o head pode demorar tanto quanto um evento, e só os
+    // timeouts da própria rota valem
+    let streaming = route.is_some_and(|r| r.streaming);
+    let mut connect_timeout = timeouts
+        .and_then(|t| t.connect())
+        .unwrap_or(config.upstream.connect_timeout);
+    let mut first_byte_timeout = timeouts
+        .and_then(|t| t.first_byte())
+        .unwrap_or(if streaming {
+            config.client.stream_idle_timeout
+        } else {
+            config.upstream.first_byte_timeout
+        });
+    let total_timeout = timeouts.and_then(|t| t.total());
+    let response_limit = route
+        .and_then(|r| r.max_response_size)
+        .unwrap_or(config.upstream.max_response_size);
+
+    let deadline = timeouts
+        .and_then(|t| t.deadline())
+        .or(config.upstream.deadline.filter(|_| !streaming))
+        .map(|d| started + d);
+    let remaining = || deadline.map(|d| d.saturating_duration_since(Instant::now()));
+
+    // Rota cacheável: GETs idênticos simultâneos viram um único fetch no upstream
+    if route.is_some_and(|r| r.coalesce)
+        && let Some(key) = coalesce::key(&req)
+    {
+        let fetch = Fetch {
+            config: config.clone(),
+            head,
+            connect_timeout,
+            first_byte_timeout,
+            total_timeout,
+            deadline,
+            max_size: response_limit,
+            admission: ctx.admission.clone(),
+            retries: ctx.retries.clone(),
+            metrics: ctx.metrics.clone(),
+            h2c: ctx.h2c.clone(),
+        };
+        let (outcome, role) = ctx.coalescer.join(key, fetch).await;
+        debug!(role = role.label(), "Coalesced upstream fetch");
+        ctx.metrics.inc(
+            "oblivion_coalesced_requests_total",
+            &[("role", role.label())],
+        );
+        match outcome {
+            Ok(response) => {
+                let mut responded = false;
+                let result = relay_response(
+                    &mut &response[..],
+                    stream,
+                    ResponseEdits::new(
+                        route,
+                        &req,
+                        &config,
+                        set_cookie,
+                        watermark.as_deref(),
+                        keep_alive,
+                        &ctx.metrics,
+                    )
+                    .checked(&check_response)
+                    .shed(under_attack),
+                    ResponseLimits {
+                        first_byte_timeout,
+                        max_size: response_limit,
+                        client: &config.client,
+                        server: &config.server,
+                        streaming,
+                    },
+                    &mut responded,
+                    None,
+                )
                 .await;
-            return;
+                match result {
+                    Ok(persist) => return persist.then_some(leftover),
+                    Err(e) => debug!("Coalesced delivery ended: {}", e),
+                }
+            }
+            Err(failure) => {
+                respond(stream, &config.server, failure).await;
+            }
+        }
+        return None;
+    }
+
+    let permit = match ctx.admission.acquire().await {
+        Ok(permit) => Some(permit),
+        Err(e) => {
+            warn!(error = %e, queued = ctx.admission.queued(), "Upstream admission rejected");
+            ctx.metrics.inc(
+                "oblivion_refused_total",
+                &[("stage", "request"), ("reason", "upstream_concurrency")],
+            );
+            respond(stream, &config.server, b"HTTP/1.1 503 Service Unavailable\r\nRetry-After: 1\r\nContent-Length: 13\r\n\r\nUpstream Busy").await;
+            return None;
+        }
+    };
+    if let Some(left) = remaining() {
+        if left.is_zero() {
+            warn!(category = "upstream", elapsed = ?started.elapsed(), "Request deadline exceeded before upstream");
+            respond(stream, &config.server, GATEWAY_TIMEOUT).await;
+            return None;
         }
+        connect_timeout = connect_timeout.min(left);
     }
 
-    let connect_result = timeout(UPSTREAM_CONNECT_TIMEOUT, TcpStream::connect(UPSTREAM_ADDR)).await;
+    if config.upstream.protocol == UpstreamProtocol::H2c {
+        // HTTP/2 precisa do tamanho do corpo; chunked não remontado só passa pelo túnel HTTP/1.1
+        if body_end.is_none() {
+            warn!("Chunked request body cannot be forwarded over h2c");
+            respond(
+                stream,
+                &config.server,
+                b"HTTP/1.1 411 Length Required\r\nContent-Length: 0\r\n\r\n",
+            )
+            .await;
+            return None;
+        }
+        let tls = config.upstream_tls().map(Arc::as_ref);
+        let connect = || ctx.h2c.sender(&config.upstream, tls, connect_timeout);
+        let sender =
+            match connect_retrying(&config.upstream, &ctx.retries, &ctx.metrics, connect).await {
+                Ok(sender) => sender,
+                Err(e) => {
+                    error!(category = e.category(), error = %e, "Upstream connection failed");
+                    respond(stream, &config.server, failure_response(&e)).await;
+                    return None;
+                }
+            };
+        if let Some(left) = remaining() {
+            head = strip_headers(&head, &[DEADLINE_HEADER]).0;
+            insert_header(&mut head, DEADLINE_HEADER, &left.as_millis().to_string());
+            first_byte_timeout = first_byte_timeout.min(left);
+        }
+
+        let buffered = accumulator.split_off(header_len);
+        let mut responded = false;
+        let exchange = async {
+            let pending = h2c::send(
+                sender,
+                &head,
+                buffered,
+                content_length.unwrap_or(0),
+                &mut *stream,
+                config.client.body_timeout,
+            )
+            .await
+            .map_err(std::io::Error::other)?;
+            let response = match timeout(first_byte_timeout, pending).await {
+                Ok(response) => response.map_err(|e| {
+                    Failure::Reset.record(&ctx.metrics);
+                    std::io::Error::other(e)
+                })?,
+                Err(_) => {
+                    warn!(category = "upstream", timeout = ?first_byte_timeout, "Upstream first byte timeout");
+                    Failure::FirstByteTimeout.record(&ctx.metrics);
+                    responded = true;
+                    stream
+                        .write_all(&identity::stamp(&config.server, GATEWAY_TIMEOUT))
+                        .await?;
+                    return Err(std::io::ErrorKind::TimedOut.into());
+                }
+            };
+            let mut upstream = h2c::into_reader(response);
+            relay_response(
+                &mut upstream,
+                &mut *stream,
+                ResponseEdits::new(
+                    route,
+                    &req,
+                    &config,
+                    set_cookie,
+                    watermark.as_deref(),
+                    keep_alive,
+                    &ctx.metrics,
+                )
+                .checked(&check_response)
+                .shed(under_attack),
+                ResponseLimits {
+                    first_byte_timeout,
+                    max_size: response_limit,
+                    client: &config.client,
+                    server: &config.server,
+                    streaming,
+                },
+                &mut responded,
+                permit,
+            )
+            .await
+        };
+        let result = match total_timeout {
+            Some(total) => match timeout(total, exchange).await {
+                Ok(result) => result,
+                Err(_) => {
+                    warn!(category = "upstream", timeout = ?total, "Upstream total timeout exceeded");
+                    Failure::TotalTimeout.record(&ctx.metrics);
+                    if !responded {
+                        respond(stream, &config.server, GATEWAY_TIMEOUT).await;
+                    }
+                    return None;
+                }
+            },
+            None => exchange.await,
+        };
+        match result {
+            Ok(persist) => return persist.then_some(leftover),
+            Err(e) => {
+                debug!("h2c exchange ended: {}", e);
+                if !responded {
+                    respond(stream, &config.server, BAD_GATEWAY).await;
+                }
+            }
+        }
+        return None;
+    }
 
-    match connect_result {
-        Ok(Ok(mut upstream_stream)) => {
+    let tls = config.upstream_tls().map(Arc::as_ref);
+    let connect = || connect_upstream(&config.upstream, tls, connect_timeout);
+    match connect_retrying(&config.upstream, &ctx.retries, &ctx.metrics, connect).await {
+        Ok(mut upstream_stream) => {
+            // Orçamento medido depois do connect; valor do cliente nunca passa
+            if let Some(left) = remaining() {
+                head = strip_headers(&head, &[DEADLINE_HEADER]).0;
+                insert_header(&mut head, DEADLINE_HEADER, &left.as_millis().to_string());
+                first_byte_timeout = first_byte_timeout.min(left);
+            }
+            // Parte do corpo ainda no socket do cliente; chunked vai até o limite
+            let mut body_left = body_end.map_or(body_limit, |end| {
+                end.saturating_sub(accumulator.len()) as u64
+            });
+            accumulator.splice(..header_len, head);
             if let Err(e) = upstream_stream.write_all(&accumulator).await {
-                error!("Failed to send headers to upstream: {}", e);
-                return;
+                error!(category = "upstream", error = %e, "Failed to send headers to upstream");
+                Failure::Reset.record(&ctx.metrics);
+                return None;
+            }
+
+            let (mut client_read, mut client_write) = tokio::io::split(&mut *stream);
+            let (mut upstream_read, mut upstream_write) = tokio::io::split(upstream_stream);
+
+            let mut responded = false;
+            let tunnel = async {
+                let mut early = Vec::new();
+                if upgrade {
+                    tokio::io::copy(&mut (&mut client_read).take(body_left), &mut upstream_write)
+                        .await?;
+                    body_left = 0;
+                    early = match status_line(&mut upstream_read, first_byte_timeout).await {
+                        Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                            warn!(category = "upstream", timeout = ?first_byte_timeout, "Upstream first byte timeout");
+                            Failure::FirstByteTimeout.record(&ctx.metrics);
+                            responded = true;
+                            client_write
+                                .write_all(&identity::stamp(&config.server, GATEWAY_TIMEOUT))
+                                .await?;
+                            return Err(e);
+                        }
+                        result => result?,
+                    };
+                    if response_status(&early) == Some(101) && websocket {
+                        responded = true;
+                        let (head, rest) =
+                            response_head(&mut upstream_read, early, first_byte_timeout).await?;
+                        client_write.write_all(&head).await?;
+                        // Com inspeção a extensão foi tirada do pedido: RSV continua proibido
+                        let extensions = !inspect_frames
+                            && String::from_utf8_lossy(&head)
+                                .to_ascii_lowercase()
+                                .contains("\r\nsec-websocket-extensions:");
+                        ctx.metrics.inc(
+                            "oblivion_websocket_connections_total",
+                            &[("inspect", if inspect_frames { "true" } else { "false" })],
+                        );
+                        debug!(inspect = inspect_frames, "WebSocket established");
+                        let inspect =
+                            |text: &str| match engine.inspect(&websocket::message(&req, text)) {
+                                Verdict::Allow => None,
+                                Verdict::Block(reason, matched) if config.policy.detect_only => {
+                                    warn!(
+                                        reason = %reason,
+                                        rule = matched.as_ref().map(|m| m.rule),
+                                        "Detect only: WebSocket message would have been blocked"
+                                    );
+                                    None
+                                }
+                                Verdict::Block(reason, matched) => {
+                                    warn!(
+                                        reason = %reason,
+                                        rule = matched.as_ref().map(|m| m.rule),
+                                        "Blocked malicious WebSocket message"
+                                    );
+                                    ctx.events.emit(Event::Block {
+                                        ip: peer_addr.ip(),
+                                        reason: &reason,
+                                        path: &req.path,
+                                    });
+                                    ctx.bans.strike(peer_addr.ip(), &config.bans);
+                                    Some(reason)
+                                }
+                            };
+                        let max_message = config.websocket.max_message_size;
+                        let mut upstream_read = (&rest[..]).chain(&mut upstream_read);
+                        let result = tokio::try_join!(
+                            websocket::pump(
+                                &mut client_read,
+                                &mut upstream_write,
+                                Side::Client,
+                                max_message,
+                                extensions,
+                                inspect_frames.then_some(&inspect as Inspect),
+                                &ctx.metrics
+                            ),
+                            websocket::pump(
+                                &mut upstream_read,
+                                &mut client_write,
+                                Side::Server,
+                                max_message,
+                                extensions,
+                                None,
+                                &ctx.metrics
+                            )
+                        );
+                        // O WAF fecha o lado do cliente com o código do motivo
+                        if let Err(e) = &result
+                            && let Some(violation) = Violation::of(e)
+                        {
+                            warn!(code = violation.code, reason = %violation.reason, "Closing WebSocket");
+                            ctx.metrics.inc(
+                                "oblivion_websocket_closed_total",
+                                &[("reason", violation.label)],
+                            );
+                            let close = websocket::close_frame(violation.code, violation.label);
+                            let _ = client_write.write_all(&close).await;
+                        }
+                        result?;
+                        return Ok(false);
+                    }
+                    // Outro upgrade aceito: dali em diante são só bytes, nos dois sentidos
+                    if response_status(&early) == Some(101) {
+                        responded = true;
+                        client_write.write_all(&early).await?;
+                        let mut client_read = client_read.take(body_limit);
+                        tokio::try_join!(
+                            tokio::io::copy(&mut client_read, &mut upstream_write),
+                            tokio::io::copy(&mut upstream_read, &mut client_write)
+                        )?;
+                        return Ok(false);
+                    }
+                }
+                let mut client_body = client_read.take(body_left);
+                let mut upstream_read = (&early[..]).chain(upstream_read);
+                let (sent, persist) = tokio::try_join!(
+                    tokio::io::copy(&mut client_body, &mut upstream_write),
+                    relay_response(
+                        &mut upstream_read,
+                        &mut client_write,
+                        ResponseEdits::new(
+                            route,
+                            &req,
+                            &config,
+                            set_cookie,
+                            watermark.as_deref(),
+                            keep_alive,
+                            &ctx.metrics
+                        )
+                        .checked(&check_response)
+                        .shed(under_attack),
+                        ResponseLimits {
+                            first_byte_timeout,
+                            max_size: response_limit,
+                            client: &config.client,
+                            server: &config.server,
+                            streaming,
+                        },
+                        &mut responded,
+                        permit
+                    )
+                )?;
+                // Cliente que fechou antes de mandar o corpo todo não tem próximo request
+                Ok::<_, std::io::Error>(persist && sent == body_left)
+            };
+            let result = match total_timeout {
+                Some(total) => match timeout(total, tunnel).await {
+                    Ok(result) => result,
+                    Err(_) => {
+                        warn!(category = "upstream", timeout = ?total, "Upstream total timeout exceeded");
+                        Failure::TotalTimeout.record(&ctx.metrics);
+                        if !responded {
+                            let _ = client_write
+                                .write_all(&identity::stamp(&config.server, GATEWAY_TIMEOUT))
+                                .await;
+                        }
+                        return None;
+                    }
+                },
+                None => tunnel.await,
+            };
+
+            match result {
+                Ok(persist) => persist.then_some(leftover),
+                Err(e) => {
+                    debug!("Tunnel closed: {}", e);
+                    None
+                }
+            }
+        }
+        Err(e) => {
+            error!(category = e.category(), error = %e, "Upstream connection failed");
+            respond(stream, &config.server, failure_response(&e)).await;
+            None
+        }
+    }
+}
+
+// Head inteiro do 101 a partir do que já foi lido; o resto já é frame
+async fn response_head<R>(
+    upstream: &mut R,
+    mut head: Vec<u8>,
+    wait: Duration,
+) -> std::io::Result<(Vec<u8>, Vec<u8>)>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut buffer = [0u8; 1024];
+    loop {
+        if let Some(end) = head.windows(4).position(|w| w == b"\r\n\r\n") {
+            let rest = head.split_off(end + 4);
+            return Ok((head, rest));
+        }
+        if head.len() >= MAX_STATUS_LINE {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "101 response head too large",
+            ));
+        }
+        let n = timeout(wait, upstream.read(&mut buffer))
+            .await
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))??;
+        if n == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        head.extend_from_slice(&buffer[..n]);
+    }
+}
+
+// Só a linha de status: basta para saber se o upgrade foi aceito
+async fn status_line<R>(upstream: &mut R, wait: Duration) -> std::io::Result<Vec<u8>>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut line = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !line.windows(2).any(|w| w == b"\r\n") && line.len() < MAX_STATUS_LINE {
+        let n = timeout(wait, upstream.read(&mut buffer))
+            .await
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))??;
+        if n == 0 {
+            break;
+        }
+        line.extend_from_slice(&buffer[..n]);
+    }
+    Ok(line)
+}
+
+// Limites da resposta: os da rota já resolvidos contra os globais
+struct ResponseLimits<'a> {
+    first_byte_timeout: Duration,
+    max_size: u64,
+    client: &'a ClientConfig,
+    // Respostas geradas no meio do caminho (502/504, 403 da resposta)
+    server: &'a ServerConfig,
+    // Rota de streaming: sem delivery_deadline, cai só com o upstream calado
+    // por stream_idle_timeout
+    streaming: bool,
+}
+
+// O que muda no head da resposta antes de chegar ao cliente
+struct ResponseEdits<'a> {
+    rewrites: &'a [StatusRewrite],
+    set_cookie: Option<&'a str>,
+    cookies: &'a CookieConfig,
+    redirect: Option<&'a RedirectPolicy>,
+    // Host do request: redirect para ele mesmo sempre passa
+    host: Option<&'a str>,
+    // Só GET em path coberto por [inject]
+    inject: Option<&'a InjectConfig>,
+    // Rota com watermark: token no header e/ou em comentário HTML
+    watermark: Option<(WatermarkMode, &'a str)>,
+    watermark_header: &'a str,
+    scan_limit: usize,
+    // Rota (ou [response]) com inspeção do corpo da resposta: (config, descomprime)
+    inspect_response: Option<(&'a ResponseConfig, bool)>,
+    check: Option<Check<'a>>,
+    // Cliente quer (e pode) continuar na conexão depois desta resposta
+    keep_alive: bool,
+    head_request: bool,
+    metrics: &'a Metrics,
+}
+
+impl<'a> ResponseEdits<'a> {
+    fn new(
+        route: Option<&'a Route>,
+        req: &'a Request,
+        config: &'a Config,
+        set_cookie: Option<&'a str>,
+        watermark: Option<&'a str>,
+        keep_alive: bool,
+        metrics: &'a Metrics,
+    ) -> Self {
+        let head_request = req.method.eq_ignore_ascii_case("HEAD");
+        // Streaming não segura nenhum byte do corpo: nem inspeção nem edição de HTML
+        let streaming = route.is_some_and(|r| r.streaming);
+        ResponseEdits {
+            rewrites: route.map_or(&[][..], |r| &r.status_rewrites[..]),
+            set_cookie,
+            cookies: &config.cookies,
+            redirect: route.and_then(|r| r.redirect.as_ref()),
+            host: req.header("Host"),
+            inject: Some(&config.inject).filter(|inject| {
+                !streaming
+                    && !inject.snippet.is_empty()
+                    && req.method == "GET"
+                    && (inject.paths.is_empty()
+                        || inject.paths.iter().any(|p| p.matches(req.path_only())))
+            }),
+            watermark: route
+                .and_then(|r| r.watermark)
+                .map(|mode| match mode {
+                    WatermarkMode::Both if streaming => WatermarkMode::Header,
+                    mode => mode,
+                })
+                .filter(|mode| !streaming || mode.header())
+                .zip(watermark),
+            watermark_header: &config.watermark.header,
+            scan_limit: config.inject.scan_limit,
+            inspect_response: route
+                .and_then(|r| r.inspect_response)
+                .unwrap_or(config.response.inspect)
+                .then(|| {
+                    let decompress = route
+                        .and_then(|r| r.decompress_response)
+                        .unwrap_or(config.response.decompress);
+                    (&config.response, decompress)
+                })
+                .filter(|_| !head_request && !streaming),
+            check: None,
+            keep_alive,
+            head_request,
+            metrics,
+        }
+    }
+
+    // Regras RESPONSE_BODY; sem isso a resposta não é inspecionada
+    fn checked(self, check: Check<'a>) -> Self {
+        ResponseEdits {
+            check: Some(check),
+            ..self
+        }
+    }
+
+    // Under attack: resposta passa sem descomprimir, inspecionar nem injetar HTML
+    fn shed(self, under_attack: bool) -> Self {
+        if !under_attack {
+            return self;
+        }
+        ResponseEdits {
+            inspect_response: None,
+            inject: None,
+            ..self
+        }
+    }
+}
+
+// O primeiro byte tem prazo próprio; o head inteiro é lido antes de repassar.
+// Ok(true) = a conexão com o cliente pode seguir para o próximo request
+async fn relay_response<R, W>(
+    upstream: &mut R,
+    client: &mut W,
+    edits: ResponseEdits<'_>,
+    limits: ResponseLimits<'_>,
+    responded: &mut bool,
+    permit: Option<OwnedSemaphorePermit>,
+) -> std::io::Result<bool>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut head = Vec::new();
+    let mut buffer = [0u8; 1024];
+
+    let first_byte_timeout = limits.first_byte_timeout;
+    let reset = |e| {
+        Failure::Reset.record(edits.metrics);
+        e
+    };
+    let n = match timeout(first_byte_timeout, upstream.read(&mut buffer)).await {
+        Ok(n) => n.map_err(reset)?,
+        Err(_) => {
+            warn!(category = "upstream", timeout = ?first_byte_timeout, "Upstream first byte timeout");
+            Failure::FirstByteTimeout.record(edits.metrics);
+            *responded = true;
+            client
+                .write_all(&identity::stamp(limits.server, GATEWAY_TIMEOUT))
+                .await?;
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "upstream first byte timeout",
+            ));
+        }
+    };
+    if n == 0 {
+        Failure::Reset.record(edits.metrics);
+        return Ok(false);
+    }
+    head.extend_from_slice(&buffer[..n]);
+
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") && head.len() < limits.client.max_header_size {
+        let n = upstream.read(&mut buffer).await.map_err(reset)?;
+        if n == 0 {
+            break;
+        }
+        head.extend_from_slice(&buffer[..n]);
+    }
+    // Sem status line ou head inteiro não há o que repassar ao cliente
+    if response_status(&head).is_none() || !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        warn!(category = "upstream", "Malformed upstream response");
+        Failure::Malformed.record(edits.metrics);
+        *responded = true;
+        client
+            .write_all(&identity::stamp(limits.server, BAD_GATEWAY))
+            .await?;
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "malformed upstream response",
+        ));
+    }
+
+    if let Some(status) = response_status(&head)
+        && let Some(rewrite) = edits.rewrites.iter().find(|r| r.status == status)
+    {
+        debug!(status, to = rewrite.to, "Rewriting upstream response");
+        *responded = true;
+        client
+            .write_all(&identity::stamp(limits.server, &rewrite.render(status)))
+            .await?;
+        client.shutdown().await?;
+        return Ok(false);
+    }
+
+    if let Some(policy) = edits.redirect
+        && response_status(&head).is_some_and(|status| (300..400).contains(&status))
+        && let Some(end) = head.windows(4).position(|w| w == b"\r\n\r\n")
+    {
+        let locations = response_headers(&head[..end + 4], "Location");
+        if let Some(location) = locations.iter().find(|l| !policy.allows(l, edits.host)) {
+            let action = match policy.action {
+                RedirectAction::Block => "block",
+                RedirectAction::Rewrite => "rewrite",
+            };
+            warn!(location = %location, action, "Upstream redirect outside allowlist");
+            edits
+                .metrics
+                .inc("oblivion_redirects_refused_total", &[("action", action)]);
+            if policy.action == RedirectAction::Block {
+                *responded = true;
+                client
+                    .write_all(&identity::stamp(limits.server, BAD_GATEWAY))
+                    .await?;
+                client.shutdown().await?;
+                return Ok(false);
             }
+            let mut rewritten = strip_headers(&head[..end + 4], &["Location"]).0;
+            insert_header(&mut rewritten, "Location", &policy.fallback);
+            head.splice(..end + 4, rewritten);
+        }
+    }
 
-            let (mut client_read, mut client_write) = tokio::io::split(stream);
-            let (mut upstream_read, mut upstream_write) = upstream_stream.split();
+    if edits.cookies.enabled()
+        && let Some(end) = head.windows(4).position(|w| w == b"\r\n\r\n")
+    {
+        let (hardened, rewritten, dropped) = cookies::harden(&head[..end + 4], edits.cookies);
+        if rewritten + dropped > 0 {
+            debug!(rewritten, dropped, "Hardened upstream Set-Cookie");
+            head.splice(..end + 4, hardened);
+        }
+    }
+
+    // Cookie de sessão novo entra como último header da resposta do upstream
+    if let Some(cookie) = edits.set_cookie
+        && let Some(end) = head.windows(4).position(|w| w == b"\r\n\r\n")
+    {
+        let line = format!("Set-Cookie: {}\r\n", cookie);
+        head.splice(end + 2..end + 2, line.into_bytes());
+    }
+
+    if let Some((mode, token)) = edits.watermark
+        && mode.header()
+        && let Some(end) = head.windows(4).position(|w| w == b"\r\n\r\n")
+    {
+        let line = format!("{}: {}\r\n", edits.watermark_header, token);
+        head.splice(end + 2..end + 2, line.into_bytes());
+    }
+
+    // Corpo comprimido chega aberto às edições de HTML abaixo e só é
+    // recomprimido se alguma delas mudou o corpo
+    let mut recode = None;
+    if let (Some((config, decompress)), Some(check)) = (edits.inspect_response, edits.check) {
+        let inspection = Inspection {
+            config,
+            decompress,
+            check,
+        };
+        let outcome = response::inspect(
+            upstream,
+            head,
+            &inspection,
+            limits.first_byte_timeout,
+            edits.metrics,
+        )
+        .await?;
+        head = match outcome {
+            Outcome::Pass(response) => response,
+            Outcome::Decoded {
+                response,
+                original,
+                coding,
+            } => {
+                recode = Some((coding, original));
+                response
+            }
+            Outcome::Block(reason) => {
+                *responded = true;
+                let msg = format!(
+                    "HTTP/1.1 403 Forbidden\r\nContent-Length: {}\r\nConnection: close\r\n\r\nBLOCK: {}",
+                    7 + reason.len(),
+                    reason
+                );
+                client
+                    .write_all(&identity::stamp(limits.server, msg.as_bytes()))
+                    .await?;
+                client.shutdown().await?;
+                return Ok(false);
+            }
+        };
+    }
+    let mut edited = false;
+
+    if let Some((mode, token)) = edits.watermark
+        && mode.html()
+        && inject::eligible(&head)
+    {
+        let comment = InjectConfig {
+            snippet: format!("<!-- {} -->", token),
+            paths: Vec::new(),
+            scan_limit: edits.scan_limit,
+        };
+        let marked;
+        (head, marked) =
+            inject::inject(upstream, head, &comment, limits.first_byte_timeout).await?;
+        edited |= marked;
+    }
+
+    if let Some(config) = edits.inject
+        && inject::eligible(&head)
+    {
+        let injected;
+        (head, injected) =
+            inject::inject(upstream, head, config, limits.first_byte_timeout).await?;
+        if injected {
+            edits.metrics.inc("oblivion_html_injections_total", &[]);
+        }
+        edited |= injected;
+    }
+
+    if let Some((coding, original)) = recode {
+        head = if edited {
+            response::encode(head, coding).await?
+        } else {
+            original
+        };
+    }
+
+    // O upstream recebeu close: se a conexão com o cliente segue depende do
+    // framing desta resposta. Com o tamanho no head, o fim dela é exato
+    let mut persist = false;
+    let mut total = None;
+    if let Some(end) = head.windows(4).position(|w| w == b"\r\n\r\n") {
+        let headers = &head[..end + 4];
+        let length = response_body_length(headers, edits.head_request);
+        let chunked = response_headers(headers, "Transfer-Encoding")
+            .iter()
+            .any(|te| te.to_ascii_lowercase().contains("chunked"));
+        persist = edits.keep_alive
+            && (length.is_some() || chunked)
+            && response_status(headers).is_some_and(|status| status >= 200);
+        let mut rewritten = strip_headers(headers, &["Connection", "Keep-Alive"]).0;
+        if !persist {
+            insert_header(&mut rewritten, "Connection", "close");
+        } else if rewritten.starts_with(b"HTTP/1.0 ") {
+            // Versão é do salto: cliente HTTP/1.0-sem-keep-alive fecharia a conexão
+            rewritten[..8].copy_from_slice(b"HTTP/1.1");
+        }
+        total = length.map(|length| rewritten.len() as u64 + length);
+        head.splice(..end + 4, rewritten);
+    }
+
+    *responded = true;
+    deliver(upstream, client, head, total, limits, permit, edits.metrics).await?;
+    Ok(persist)
+}
 
-            let mut client_read_limited = client_read.take(MAX_BODY_SIZE);
+// Cliente lento não pode prender o upstream: a resposta é bufferizada (até um limite)
+// e o slot de admissão é liberado assim que o upstream termina
+async fn deliver<R, W>(
+    upstream: &mut R,
+    client: &mut W,
+    head: Vec<u8>,
+    total: Option<u64>,
+    limits: ResponseLimits<'_>,
+    mut permit: Option<OwnedSemaphorePermit>,
+    metrics: &Metrics,
+) -> std::io::Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let (response_limit, streaming, server, limits) = (
+        limits.max_size,
+        limits.streaming,
+        limits.server,
+        limits.client,
+    );
+    let start = Instant::now();
+    let deadline = start + limits.delivery_deadline;
+    let mut received = head.len() as u64;
+    let mut pending: VecDeque<u8> = head.into();
+    let mut out: Vec<u8> = Vec::new();
+    let mut buffer = vec![0u8; 16 * 1024];
+    let mut upstream_done = false;
+    // Resposta de tamanho conhecido acaba no último byte dela: o que o upstream
+    // mandar depois não pode virar a "próxima resposta" do cliente
+    if let Some(total) = total
+        && received >= total
+    {
+        pending.truncate(total as usize);
+        received = total;
+        upstream_done = true;
+        permit.take();
+    }
+    let mut delivered: u64 = 0;
+    let mut last_progress = start;
+    let mut last_received = start;
+    let slow = |delivered: u64, reason: &str| {
+        warn!(
+            category = "client",
+            delivered, reason, "Slow reader dropped"
+        );
+        std::io::Error::new(std::io::ErrorKind::TimedOut, reason.to_string())
+    };
 
-            let result = tokio::try_join!(
-                tokio::io::copy(&mut client_read_limited, &mut upstream_write),
-                tokio::io::copy(&mut upstream_read, &mut client_write)
+    while !(upstream_done && pending.is_empty() && out.is_empty()) {
+        if received > response_limit {
+            warn!(
+                category = "upstream",
+                limit = response_limit,
+                delivered,
+                "Upstream response size limit exceeded"
             );
+            // Nada chegou ao cliente ainda: dá pra responder 502; senão só resta fechar
+            if delivered == 0 {
+                client
+                    .write_all(&identity::stamp(server, BAD_GATEWAY))
+                    .await?;
+            }
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "upstream response too large",
+            ));
+        }
+        if out.is_empty() {
+            let n = pending.len().min(buffer.len());
+            out.extend(pending.drain(..n));
+        }
 
-            if let Err(e) = result {
-                debug!("Tunnel closed: {}", e);
+        tokio::select! {
+            n = upstream.read(&mut buffer), if !upstream_done && pending.len() < limits.slow_read_buffer => {
+                let n = n.inspect_err(|_| Failure::Reset.record(metrics))?;
+                if n == 0 {
+                    // Content-Length prometido e não cumprido: caiu no meio do corpo
+                    if total.is_some_and(|total| received < total) {
+                        Failure::Reset.record(metrics);
+                    }
+                    upstream_done = true;
+                    permit.take();
+                } else {
+                    last_received = Instant::now();
+                    let n = total.map_or(n, |total| n.min((total - received) as usize));
+                    received += n as u64;
+                    pending.extend(&buffer[..n]);
+                    if total == Some(received) {
+                        upstream_done = true;
+                        permit.take();
+                    }
+                }
+            }
+            n = client.write(&out), if !out.is_empty() => {
+                let n = n?;
+                if n == 0 {
+                    return Err(std::io::ErrorKind::WriteZero.into());
+                }
+                out.drain(..n);
+                delivered += n as u64;
+                last_progress = Instant::now();
+            }
+            _ = tokio::time::sleep_until(deadline.into()), if !streaming => {
+                return Err(slow(delivered, "response delivery deadline exceeded"));
+            }
+            _ = tokio::time::sleep_until((last_received + limits.stream_idle_timeout).into()), if streaming && !upstream_done => {
+                debug!(delivered, "Streaming response idle, closing");
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "streaming response idle timeout",
+                ));
+            }
+            _ = tokio::time::sleep_until((last_progress + limits.write_stall).into()), if !out.is_empty() => {
+                return Err(slow(delivered, "client stopped reading"));
             }
         }
-        Ok(Err(e)) => {
-            error!(upstream = UPSTREAM_ADDR, error = %e, "Upstream connection failed");
-            let _ = stream
-                .write_all(b"HTTP/1.1 502 Bad Gateway\r\n\r\nUpstream Error")
-                .await;
+
+        // Buffer cheio = o gargalo é o cliente; abaixo da taxa mínima ele sai
+        let elapsed = start.elapsed();
+        if pending.len() >= limits.slow_read_buffer
+            && elapsed > limits.slow_read_grace
+            && (delivered as f64) < limits.min_read_rate * elapsed.as_secs_f64()
+        {
+            return Err(slow(delivered, "client read rate below minimum"));
         }
-        Err(_) => {
-            error!(upstream = UPSTREAM_ADDR, "Upstream connection timed out");
-            let _ = stream
-                .write_all(b"HTTP/1.1 504 Gateway Timeout\r\n\r\nUpstream Timeout")
-                .await;
+    }
+
+    client.flush().await?;
+    Ok(delivered)
+}
+
+// Antes do TLS não existe resposta HTTP: Respond vira Close
+fn refuse_connection(tcp_stream: TcpStream, guard: &Arc<AcceptGuard>, config: &Config) {
+    let hold = config.policy.silent_drop_hold;
+    match config.accept.policy {
+        RejectPolicy::Respond | RejectPolicy::Close => {}
+        RejectPolicy::Reset => tcp_stream.reset_on_close(),
+        RejectPolicy::Drop => {
+            // O slot conta o socket segurado no limite de conexões ativas
+            let slot = guard.open();
+            tokio::spawn(async move {
+                let _slot = slot;
+                let _stream = tcp_stream;
+                tokio::time::sleep(hold).await;
+            });
         }
     }
 }
 
 #[tokio::main]
-async fn main() -> std::io::Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive(tracing::Level::INFO.into()),
-        )
-        .init();
+async fn main() {
+    // --help/--version saem com 0; erro de uso mantém o código de Usage
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) => {
+            let _ = e.print();
+            let code = if e.use_stderr() {
+                Error::Usage(String::new()).exit_code()
+            } else {
+                0
+            };
+            std::process::exit(code);
+        }
+    };
+    let logging = logging::init(cli.log_level.as_deref());
 
-    let tls_config = load_tls_config();
-    let acceptor = TlsAcceptor::from(tls_config);
+    let result = run(cli, logging.clone()).await;
+    if let Err(e) = &result
+        && !matches!(e, Error::Usage(_) | Error::TestFailure(_))
+    {
+        error!(category = e.category(), exit_code = e.exit_code(), error = %e, "Fatal error");
+    }
+    // O que ainda está na fila de log sai antes do processo
+    logging.flush();
+    if let Err(e) = result {
+        eprintln!("❌ Erro: {}", e);
+        std::process::exit(e.exit_code());
+    }
+}
+
+// Arquivo + flags; o SIGHUP relê pelo mesmo caminho
+fn load_config(cli: &Cli) -> Result<Config, Error> {
+    let path = cli.config.as_deref().unwrap_or(CONFIG_PATH);
+    let mut config = Config::read(path, cli.config.is_some())?;
+    cli.apply(&mut config);
+    config.validated(path)
+}
 
-    let listener = TcpListener::bind(LISTENER_ADDR).await?;
-    info!(
-        "🔐 OBLIVION WAF (HTTPS) rodando em {} -> Protegendo {}",
-        LISTENER_ADDR, UPSTREAM_ADDR
+async fn run(mut cli: Cli, logging: Arc<LogControl>) -> Result<(), Error> {
+    let config = Arc::new(load_config(&cli)?);
+
+    if let Some(command) = cli.command.take() {
+        return cli::execute(command, &config);
+    }
+    if cli.check_config {
+        return cli::check_config(&config, cli.config.as_deref().unwrap_or(CONFIG_PATH));
+    }
+    let shared_config = Arc::new(ArcSwap::new(config.clone()));
+
+    let rules = RuleSet::load_or_default(&config.files.rules)
+        .map_err(|e| Error::Config(format!("regras inválidas: {}", e)))?;
+    info!(count = rules.rules.len(), "Rules loaded");
+    let site_rules = load_site_rules(&config)
+        .map_err(|e| Error::Config(format!("regras de site inválidas: {}", e)))?;
+
+    let metrics = Metrics::new();
+    let engine = Arc::new(ArcSwap::from_pointee(WafEngine::new(
+        rules,
+        metrics.clone(),
+    )));
+    let sites: Arc<ArcSwap<SiteEngines>> = Arc::new(ArcSwap::from_pointee(
+        site_rules
+            .into_iter()
+            .map(|(path, set)| (path, Arc::new(WafEngine::new(set, metrics.clone()))))
+            .collect(),
+    ));
+
+    // Modo por core: cada endereço tem um socket por core (SO_REUSEPORT)
+    let per_core = config.runtime.mode == RuntimeMode::PerCore;
+    let workers = config.runtime.workers();
+    let mut sockets: Vec<cores::Sockets> = (0..workers).map(|_| Vec::new()).collect();
+
+    // Cada endereço com seu certificado; todos dividem engine, limiters e guard
+    let mut tls_slots = Vec::new();
+    for listener_config in &config.listeners {
+        let tls_config = Arc::new(ArcSwap::new(load_tls_config(listener_config)?));
+        let addr = listener_config
+            .addr
+            .parse()
+            .map_err(|_| Error::Config(format!("endereço inválido: {}", listener_config.addr)))?;
+        for core in &mut sockets {
+            let listener = listener::bind(addr, config.v6_only(listener_config), per_core)?;
+            core.push((listener, Some(tls_config.clone())));
+        }
+        info!(
+            "🔐 OBLIVION WAF (HTTPS) rodando em {} -> Protegendo {}",
+            addr, config.upstream.addr
+        );
+        tls_slots.push(tls_config);
+    }
+    if let Some(addr) = &config.plain_http.addr {
+        let addr = addr
+            .parse()
+            .map_err(|_| Error::Config(format!("endereço inválido: {}", addr)))?;
+        for core in &mut sockets {
+            core.push((listener::bind(addr, false, per_core)?, None));
+        }
+        info!(mode = ?config.plain_http.mode, "🌐 HTTP sem TLS em {}", addr);
+    }
+    if per_core {
+        info!(
+            workers,
+            "⚙️  Modo por core: {} threads com estado próprio", workers
+        );
+    }
+    for (site, resolved) in config.resolved_sites() {
+        info!(hosts = ?site.hosts, upstream = %resolved.upstream.addr, rules = %resolved.files.rules, "Site configured");
+    }
+
+    let rate_limit = &config.rate_limit;
+    // Um por core; no modo shared, um só
+    let limiters = |name, rate, burst| -> Vec<Arc<RateLimiter>> {
+        (0..workers)
+            .map(|_| {
+                if per_core {
+                    RateLimiter::per_core(name, rate, burst, rate_limit.gc(), metrics.clone())
+                } else {
+                    RateLimiter::new(name, rate, burst, rate_limit.gc(), metrics.clone())
+                }
+            })
+            .collect()
+    };
+    let request_limiters = limiters("request", rate_limit.request_rate, rate_limit.request_burst);
+    let conn_limiters = limiters(
+        "connection",
+        rate_limit.connection_rate,
+        rate_limit.connection_burst,
     );
+    let fingerprint_limiters = limiters(
+        "fingerprint",
+        rate_limit.fingerprint_rate,
+        rate_limit.fingerprint_burst,
+    );
+
+    let admission = Admission::new(
+        config.upstream.max_connections,
+        config.upstream.queue_depth,
+        config.upstream.queue_timeout,
+    );
+
+    let shield = Shield::new(
+        rate_limit.under_attack_scale,
+        request_limiters
+            .iter()
+            .chain(&conn_limiters)
+            .cloned()
+            .collect(),
+        admission.clone(),
+    );
+
+    let capture = Capture::new(&config.files.capture);
+
+    let kernel_filter = if config.bans.kernel_filter {
+        let mut ports = Vec::new();
+        for (listener, _) in &sockets[0] {
+            let port = listener.local_addr().map_or(0, |a| a.port());
+            if !ports.contains(&port) {
+                ports.push(port);
+            }
+        }
+        Some(KernelFilter {
+            ports,
+            min_ttl: config.bans.kernel_min_ttl,
+        })
+    } else {
+        None
+    };
+    let events = Events::new(shared_config.clone(), metrics.clone());
+    let mirror = Mirror::new(shared_config.clone(), metrics.clone());
+    let bans = BanList::new(
+        rate_limit.ipv6_prefix,
+        kernel_filter,
+        events.clone(),
+        metrics.clone(),
+    )
+    .await?;
+
+    let bus = per_core.then(Bus::new);
+    let reloader = Reloader {
+        config: shared_config.clone(),
+        engine: engine.clone(),
+        sites: sites.clone(),
+        metrics: metrics.clone(),
+        bus: bus.clone(),
+    };
+    tokio::spawn(
+        CertWatcher {
+            config: shared_config.clone(),
+            tls: tls_slots.clone(),
+            metrics: metrics.clone(),
+        }
+        .run(),
+    );
+    let config_reloader = ConfigReloader {
+        load: Box::new(move || load_config(&cli)),
+        config: shared_config.clone(),
+        tls: tls_slots,
+        request_limiters: request_limiters.clone(),
+        connection_limiters: conn_limiters.clone(),
+        fingerprint_limiters: fingerprint_limiters.clone(),
+        rules: reloader.clone(),
+    };
+    tokio::spawn(async move {
+        use tokio::signal::unix::{signal, SignalKind};
+        let Ok(mut hup) = signal(SignalKind::hangup()) else {
+            return;
+        };
+        while hup.recv().await.is_some() {
+            info!("SIGHUP: reloading configuration");
+            let _ = config_reloader.reload();
+        }
+    });
+
+    let ddos = DdosDetector::new(metrics.clone());
+    let campaigns = Campaigns::new(metrics.clone());
+    let acme = Acme::new(shared_config.clone(), metrics.clone());
+    let sessions = Sessions::new(&config.session)?;
+    let auth = Authenticator::new(&config.auth)?;
+    let temp_rules = TempRules::new(shared_config.clone(), metrics.clone());
+    let watermarks = Watermarks::new(shared_config.clone(), metrics.clone())?;
+
+    let geo = match &config.geo.database {
+        Some(path) => {
+            let geo = Arc::new(GeoDb::load(path)?);
+            info!("🌍 Base geo: {} faixas de {}", geo.ranges(), path);
+            Some(geo)
+        }
+        None => None,
+    };
+    let access_log = AccessLog::new(shared_config.clone(), metrics.clone(), geo.clone());
 
-    let engine = Arc::new(WafEngine::new());
+    let admin_tls = admin_tls_config(&config.admin)?;
+    let admin = Arc::new(Admin {
+        shield: shield.clone(),
+        capture: capture.clone(),
+        engine: engine.clone(),
+        reloader: reloader.clone(),
+        metrics: metrics.clone(),
+        logging,
+        bans: bans.clone(),
+        ddos: ddos.clone(),
+        campaigns: campaigns.clone(),
+        temp_rules: temp_rules.clone(),
+        token: admin::load_token(&config.admin)?,
+        allow: config.admin.allow.clone(),
+        geo: geo.clone(),
+    });
+    let admin_addr = config.admin.addr.clone();
+    tokio::spawn(async move {
+        if let Err(e) = admin::serve(&admin_addr, admin, admin_tls).await {
+            error!(category = e.category(), error = %e, "Admin API failed to start");
+        }
+    });
+
+    let signal_shield = shield.clone();
+    tokio::spawn(async move {
+        use tokio::signal::unix::{signal, SignalKind};
+        let Ok(mut usr1) = signal(SignalKind::user_defined1()) else {
+            return;
+        };
+        while usr1.recv().await.is_some() {
+            signal_shield.toggle();
+        }
+    });
 
-    let limiter = RateLimiter::new(5.0, 10.0);
+    let guard = AcceptGuard::new(
+        config.accept.rate_ceiling,
+        config.accept.max_active,
+        config.accept.emergency_threshold,
+        config.accept.allowlist.clone(),
+    );
+
+    let ctx = Arc::new(Context {
+        config: shared_config,
+        engine,
+        sites,
+        limiter: request_limiters[0].clone(),
+        fingerprint_limiter: fingerprint_limiters[0].clone(),
+        admission,
+        retries: RetryBudget::new(),
+        h2c: H2Pool::new(),
+        coalescer: Coalescer::new(),
+        guard: guard.clone(),
+        shield,
+        challenge: Challenge::new(config.challenge.ttl),
+        bot: BotDetector::new(),
+        ddos: ddos.clone(),
+        nonces: NonceCache::new(config.challenge.nonce_capacity),
+        sessions,
+        auth,
+        capture,
+        metrics,
+        conn_limiter: conn_limiters[0].clone(),
+        bans,
+        events,
+        mirror,
+        access_log,
+        campaigns,
+        acme,
+        temp_rules,
+        watermarks,
+        geo,
+    });
+
+    let mut accept_loops = tokio::task::JoinSet::new();
+    match &bus {
+        None => {
+            for (listener, tls_config) in sockets.pop().unwrap_or_default() {
+                let listener = listener::register(listener)?;
+                accept_loops.spawn(accept_loop(listener, tls_config, ctx.clone()));
+            }
+        }
+        // Cada core é uma thread; o loop daqui só espera elas terminarem
+        Some(bus) => {
+            for (core, listeners) in sockets.into_iter().enumerate() {
+                let core_ctx = cores::context(
+                    &ctx,
+                    [
+                        request_limiters[core].clone(),
+                        conn_limiters[core].clone(),
+                        fingerprint_limiters[core].clone(),
+                    ],
+                );
+                let done = cores::spawn(
+                    core,
+                    core_ctx,
+                    ctx.clone(),
+                    listeners,
+                    bus,
+                    config.runtime.sync_interval,
+                )?;
+                accept_loops.spawn(async move {
+                    let _ = done.await;
+                });
+            }
+        }
+    }
+    // Os loops só terminam em pânico; SIGTERM/SIGINT encerram (e o log é esvaziado)
+    let accept = async {
+        while let Some(result) = accept_loops.join_next().await {
+            if let Err(e) = result {
+                error!(error = %e, "Accept loop stopped");
+            }
+        }
+    };
+    tokio::select! {
+        _ = accept => {}
+        signal = shutdown_signal() => info!(signal, "Shutting down"),
+    }
+    Ok(())
+}
+
+async fn shutdown_signal() -> &'static str {
+    use tokio::signal::unix::{signal, SignalKind};
+    let (Ok(mut term), Ok(mut int)) = (
+        signal(SignalKind::terminate()),
+        signal(SignalKind::interrupt()),
+    ) else {
+        return std::future::pending().await;
+    };
+    tokio::select! {
+        _ = term.recv() => "SIGTERM",
+        _ = int.recv() => "SIGINT",
+    }
+}
+
+// Antes de qualquer byte da aplicação: ban, teto de accept e limite de conexões
+// por cliente. None = recusada (fechada ou entregue à política de recusa)
+fn admit(
+    tcp_stream: TcpStream,
+    peer_addr: SocketAddr,
+    label: &str,
+    config: &Config,
+    ctx: &Context,
+) -> Option<TcpStream> {
+    // Banido: FIN direto, sem TLS
+    if ctx.bans.is_banned(peer_addr.ip()) {
+        debug!("Dropping banned client {}", peer_addr);
+        ctx.metrics.inc(
+            "oblivion_refused_total",
+            &[("stage", "connection"), ("reason", "banned")],
+        );
+        return None;
+    }
+
+    let client = client_key(peer_addr.ip(), config.rate_limit.ipv6_prefix);
+    let decision = ctx.guard.check(peer_addr.ip(), client);
+    if let Some(reason) = decision.refusal() {
+        ctx.metrics.inc(
+            "oblivion_refused_total",
+            &[("stage", "connection"), ("reason", reason)],
+        );
+    }
+    match decision {
+        AcceptDecision::Admit => {}
+        // Sob flood global segurar ou responder só piora: sempre FIN direto
+        AcceptDecision::RateCeiling => {
+            debug!("Accept rate ceiling reached, dropping {}", peer_addr);
+            return None;
+        }
+        AcceptDecision::Emergency => {
+            debug!("Emergency mode: dropping unknown client {}", peer_addr);
+            return None;
+        }
+    }
+
+    // Antes do handshake TLS: flood de conexões nunca chega a mandar request
+    if !config.internal.matches(peer_addr.ip(), &[]) && !ctx.conn_limiter.check(client) {
+        debug!(
+            policy = config.accept.policy.label(),
+            "Connection rate limit exceeded for {}", peer_addr
+        );
+        ctx.metrics.inc(
+            "oblivion_refused_total",
+            &[("stage", "connection"), ("reason", "connection_rate")],
+        );
+        refuse_connection(tcp_stream, &ctx.guard, config);
+        return None;
+    }
+
+    ctx.metrics.inc(
+        "oblivion_accepted_connections_total",
+        &[("listener", label)],
+    );
+    Some(tcp_stream)
+}
+
+// tls ausente = listener HTTP puro ([plain_http])
+async fn accept_loop(
+    listener: TcpListener,
+    tls: Option<Arc<ArcSwap<ListenerTls>>>,
+    ctx: Arc<Context>,
+) {
+    let guard = &ctx.guard;
+    // Logs e métricas dizem por qual endereço o cliente entrou
+    let Ok(local_addr) = listener.local_addr() else {
+        return;
+    };
+    let label = local_addr.to_string();
 
     loop {
+        let config = ctx.config.load_full();
+        if guard.saturated() {
+            warn!(active = guard.active(), "FD pressure: pausing accept");
+            ctx.metrics.inc(
+                "oblivion_refused_total",
+                &[("stage", "accept"), ("reason", "fd_pressure")],
+            );
+            while guard.saturated() {
+                tokio::time::sleep(config.accept.pause).await;
+            }
+        }
+
         let (tcp_stream, peer_addr) = match listener.accept().await {
-            Ok(s) => s,
+            // Dual-stack entrega IPv4 como ::ffff:a.b.c.d
+            Ok((s, addr)) => (s, SocketAddr::new(addr.ip().to_canonical(), addr.port())),
             Err(e) => {
+                // EMFILE/ENFILE: sem pausa isso vira busy loop
                 debug!("Accept error: {}", e);
+                tokio::time::sleep(config.accept.pause).await;
                 continue;
             }
         };
 
-        let acceptor = acceptor.clone();
-        let engine = engine.clone();
-        let limiter = limiter.clone();
+        // PROXY protocol: o cliente de verdade só se sabe lendo o header, o que
+        // fica para a task; aqui só passa quem pode mandá-lo
+        let proxied = config.proxy_protocol(local_addr);
+        let tcp_stream = match proxied {
+            Some(from) if !from.is_empty() && !from.iter().any(|c| c.contains(peer_addr.ip())) => {
+                debug!("PROXY header not accepted from {}", peer_addr);
+                ctx.metrics.inc(
+                    "oblivion_refused_total",
+                    &[("stage", "connection"), ("reason", "proxy_untrusted")],
+                );
+                continue;
+            }
+            Some(_) => tcp_stream,
+            None => match admit(tcp_stream, peer_addr, &label, &config, &ctx) {
+                Some(tcp_stream) => tcp_stream,
+                None => continue,
+            },
+        };
+        let proxied = proxied.is_some();
+        let tls_config = tls.as_ref().map(|tls| tls.load_full());
+        let ctx = ctx.clone();
+        let label = label.clone();
+        let slot = guard.open();
 
         tokio::spawn(async move {
-            if !limiter.check(peer_addr.ip()) {
-                warn!("Rate limit exceeded for {}", peer_addr);
+            let _slot = slot;
+            let (tcp_stream, peer_addr) = if proxied {
+                let mut tcp_stream = tcp_stream;
+                let header = timeout(
+                    config.client.handshake_timeout,
+                    proxy_protocol::read_header(&mut tcp_stream),
+                )
+                .await
+                .unwrap_or(Err("timeout"));
+                let client = match header {
+                    Ok(client) => client.unwrap_or(peer_addr),
+                    Err(reason) => {
+                        debug!(reason, "Invalid PROXY header from {}", peer_addr);
+                        ctx.metrics.inc(
+                            "oblivion_proxy_protocol_errors_total",
+                            &[("reason", reason)],
+                        );
+                        return;
+                    }
+                };
+                match admit(tcp_stream, client, &label, &config, &ctx) {
+                    Some(tcp_stream) => (tcp_stream, client),
+                    None => return,
+                }
+            } else {
+                (tcp_stream, peer_addr)
+            };
+            // [plain_http]: sem ClientHello, SNI nem ALPN
+            let Some(tls_config) = tls_config else {
+                match config.plain_http.mode {
+                    PlainMode::Redirect => plain::redirect(tcp_stream, &config).await,
+                    PlainMode::Proxy => {
+                        let hello = HelloInfo::plaintext();
+                        handle_client(tcp_stream, local_addr, peer_addr, hello, ctx).await;
+                    }
+                }
+                return;
+            };
+            // Um prazo só para ClientHello + handshake: TCP aberto e mudo não prende a task
+            let handshake_deadline = tokio::time::Instant::now() + config.client.handshake_timeout;
+            let abandoned = |stage: &str| {
+                debug!(stage, "TLS handshake timeout from {}", peer_addr);
+                ctx.metrics
+                    .inc("oblivion_tls_handshake_timeouts_total", &[("stage", stage)]);
+            };
+            let (tcp_stream, tap) = HelloTap::new(tcp_stream);
+            let mut accept = LazyConfigAcceptor::new(Acceptor::default(), tcp_stream);
+            let start = match timeout_at(handshake_deadline, &mut accept).await {
+                Ok(Ok(start)) => start,
+                Ok(Err(e)) => {
+                    // HTTP em claro na porta TLS é cliente mal configurado, não ataque ao handshake
+                    let raw = std::mem::take(&mut *tap.lock().unwrap());
+                    drop(tap);
+                    if let Some(method) = plain::cleartext_method(&raw)
+                        && let Some(stream) = accept.take_io()
+                    {
+                        let policy = config
+                            .listeners
+                            .iter()
+                            .find(|l| l.addr.parse::<SocketAddr>().ok() == Some(local_addr))
+                            .map_or(CleartextPolicy::Redirect, |l| l.cleartext);
+                        info!(
+                            method,
+                            policy = policy.label(),
+                            "Cleartext HTTP on TLS port from {}",
+                            peer_addr
+                        );
+                        ctx.metrics.inc(
+                            "oblivion_tls_cleartext_total",
+                            &[
+                                ("listener", &local_addr.to_string()),
+                                ("policy", policy.label()),
+                            ],
+                        );
+                        plain::cleartext(stream, raw, local_addr.port(), policy, &config).await;
+                        return;
+                    }
+                    debug!("TLS Handshake failed from {}: {}", peer_addr, e);
+                    return;
+                }
+                Err(_) => {
+                    abandoned("client_hello");
+                    return;
+                }
+            };
+            let mut hello = HelloInfo::from_hello(&start.client_hello());
+            let raw = std::mem::take(&mut *tap.lock().unwrap());
+            drop(tap);
+            if let Some((ja3, ja4)) = ja::fingerprints(&raw) {
+                debug!(ja3 = %ja3, ja4 = %ja4, "TLS client fingerprint");
+                hello.ja3 = Some(ja3);
+                hello.ja4 = Some(ja4);
+            }
+            // Ainda sem troca de chaves: recusar aqui custa só o parse do ClientHello
+            let sni = hello.server_name.as_deref();
+            let listener_config = config
+                .listeners
+                .iter()
+                .find(|l| l.addr.parse::<SocketAddr>().ok() == Some(local_addr));
+            let refused = listener_config.and_then(|l| match sni {
+                None if l.require_sni => Some("missing"),
+                Some(name) if l.strict_sni && !config.sni_allowed(l, name) => Some("unknown"),
+                _ => None,
+            });
+            if let Some(reason) = refused {
+                debug!(
+                    server_name = sni,
+                    reason, "Refusing TLS client hello from {}", peer_addr
+                );
+                ctx.metrics.inc(
+                    "oblivion_sni_refused_total",
+                    &[("listener", &local_addr.to_string()), ("reason", reason)],
+                );
                 return;
             }
-
-            match acceptor.accept(tcp_stream).await {
+            let min_tls = listener_config.map_or(TlsVersion::Tls12, |l| l.min_tls);
+            let http2 = listener_config.is_none_or(|l| l.http2);
+            let server = ctx
+                .acme
+                .select(&hello, min_tls, http2)
+                .unwrap_or_else(|| tls_config.select(sni));
+            let Ok(result) = timeout_at(handshake_deadline, start.into_stream(server)).await else {
+                abandoned("handshake");
+                return;
+            };
+            if let Ok(tls_stream) = &result
+                && let Some(cert) = tls_stream
+                    .get_ref()
+                    .1
+                    .peer_certificates()
+                    .and_then(|c| c.first())
+            {
+                hello.client_names = certificate_names(&cert.0);
+            }
+            match result {
+                Ok(tls_stream) if tls_stream.get_ref().1.alpn_protocol() == Some(b"h2") => {
+                    h2server::serve(tls_stream, local_addr, peer_addr, hello, ctx).await;
+                }
                 Ok(tls_stream) => {
-                    handle_client(tls_stream, peer_addr, engine).await;
+                    handle_client(tls_stream, local_addr, peer_addr, hello, ctx).await;
                 }
                 Err(e) => {
                     debug!("TLS Handshake failed from {}: {}", peer_addr, e);
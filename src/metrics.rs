@@ -0,0 +1,154 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+// Labels vêm de dados do atacante (path, regra, país): sem teto o /metrics explode
+const MAX_LABEL_VALUES: usize = 100;
+const MAX_LABEL_LENGTH: usize = 64;
+const MAX_PATH_SEGMENTS: usize = 4;
+const OTHER: &str = "other";
+
+#[derive(Default)]
+pub struct Metrics {
+    series: RwLock<BTreeMap<String, AtomicU64>>,
+    label_values: Mutex<HashMap<(String, String), HashSet<String>>>,
+}
+
+// /users/123/orders/9f86d081884c7d65 -> /users/:id/orders/:id
+pub fn path_template(path: &str) -> String {
+    let path = path.split(['?', '#']).next().unwrap_or("");
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+    let mut out = String::new();
+    for segment in segments.iter().take(MAX_PATH_SEGMENTS) {
+        out.push('/');
+        let is_id = segment.chars().all(|c| c.is_ascii_digit())
+            || (segment.len() >= 16 && segment.chars().all(|c| c.is_ascii_hexdigit() || c == '-'))
+            || segment.len() > 32;
+        out.push_str(if is_id { ":id" } else { segment });
+    }
+    if segments.len() > MAX_PATH_SEGMENTS {
+        out.push_str("/*");
+    }
+    if out.is_empty() {
+        out.push('/');
+    }
+    out
+}
+
+fn series_key(name: &str, labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return name.to_string();
+    }
+    let mut key = format!("{}{{", name);
+    for (i, (k, v)) in labels.iter().enumerate() {
+        if i > 0 {
+            key.push(',');
+        }
+        let v = v
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n");
+        let _ = write!(key, "{}=\"{}\"", k, v);
+    }
+    key.push('}');
+    key
+}
+
+// Desfaz o escape de series_key: a="x",b="y\"z"} -> valor de b
+fn label_value(labels: &str, label: &str) -> Option<String> {
+    let mut rest = labels;
+    loop {
+        let (key, after) = rest.split_once("=\"")?;
+        let mut value = String::new();
+        let mut chars = after.char_indices();
+        let end = loop {
+            match chars.next()? {
+                (i, '"') => break i,
+                (_, '\\') => match chars.next()?.1 {
+                    'n' => value.push('\n'),
+                    c => value.push(c),
+                },
+                (_, c) => value.push(c),
+            }
+        };
+        if key == label {
+            return Some(value);
+        }
+        rest = after[end + 1..].strip_prefix(',')?;
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Metrics::default())
+    }
+
+    fn bound_label(&self, name: &str, label: &str, value: &str) -> String {
+        let value: String = value.chars().take(MAX_LABEL_LENGTH).collect();
+        let mut known = self.label_values.lock().unwrap();
+        let values = known
+            .entry((name.to_string(), label.to_string()))
+            .or_default();
+        if values.contains(&value) {
+            return value;
+        }
+        if values.len() >= MAX_LABEL_VALUES {
+            return OTHER.to_string();
+        }
+        values.insert(value.clone());
+        value
+    }
+
+    pub fn add(&self, name: &str, labels: &[(&str, &str)], value: u64) {
+        let key = series_key(name, labels);
+        if let Some(counter) = self.series.read().unwrap().get(&key) {
+            counter.fetch_add(value, Ordering::Relaxed);
+            return;
+        }
+
+        let bounded: Vec<(&str, String)> = labels
+            .iter()
+            .map(|(k, v)| (*k, self.bound_label(name, k, v)))
+            .collect();
+        let bounded: Vec<(&str, &str)> = bounded.iter().map(|(k, v)| (*k, v.as_str())).collect();
+        let key = series_key(name, &bounded);
+        self.series
+            .write()
+            .unwrap()
+            .entry(key)
+            .or_default()
+            .fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn inc(&self, name: &str, labels: &[(&str, &str)]) {
+        self.add(name, labels, 1);
+    }
+
+    // Soma das séries de uma métrica agrupadas pelo valor de um label (maior primeiro)
+    pub fn totals_by(&self, name: &str, label: &str) -> Vec<(String, u64)> {
+        let prefix = format!("{}{{", name);
+        let mut totals: BTreeMap<String, u64> = BTreeMap::new();
+        for (key, value) in self.series.read().unwrap().iter() {
+            let Some(labels) = key.strip_prefix(&prefix) else {
+                continue;
+            };
+            if let Some(value_of) = label_value(labels, label) {
+                *totals.entry(value_of).or_default() += value.load(Ordering::Relaxed);
+            }
+        }
+        let mut totals: Vec<_> = totals.into_iter().collect();
+        totals.sort_by_key(|(_, total)| Reverse(*total));
+        totals
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (key, value) in self.series.read().unwrap().iter() {
+            let _ = writeln!(out, "{} {}", key, value.load(Ordering::Relaxed));
+        }
+        out
+    }
+}
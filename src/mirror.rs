@@ -0,0 +1,252 @@
+use std::future::pending;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use arc_swap::ArcSwap;
+use serde::Serialize;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::time::{sleep_until, timeout, Instant};
+use tracing::{info, warn};
+
+use crate::config::Config;
+use crate::metrics::Metrics;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+const RETRY_BACKOFF: Duration = Duration::from_secs(5);
+
+// Um request inspecionado e o veredito, para análise offline / treino de modelo
+#[derive(Serialize)]
+pub struct Record<'a> {
+    pub ip: IpAddr,
+    pub listener: SocketAddr,
+    pub method: &'a str,
+    pub host: Option<&'a str>,
+    pub path: &'a str,
+    pub user_agent: Option<&'a str>,
+    pub session: Option<&'a str>,
+    pub tls: String,
+    pub ja3: Option<&'a str>,
+    pub ja4: Option<&'a str>,
+    pub body_size: usize,
+    pub verdict: &'a str,
+    pub reason: Option<&'a str>,
+    pub rule: Option<u32>,
+    pub field: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    ts: f64,
+    #[serde(flatten)]
+    record: Record<'a>,
+}
+
+// Espelho dos vereditos para um subject NATS ([mirror]). O caminho do request
+// só faz try_send: sink lento ou fora do ar vira descarte contado, nunca espera
+pub struct Mirror {
+    config: Arc<ArcSwap<Config>>,
+    tx: mpsc::Sender<Vec<u8>>,
+    metrics: Arc<Metrics>,
+}
+
+impl Mirror {
+    pub fn new(config: Arc<ArcSwap<Config>>, metrics: Arc<Metrics>) -> Arc<Self> {
+        let capacity = config.load().mirror.queue_capacity;
+        let (tx, rx) = mpsc::channel(capacity);
+        tokio::spawn(publish_loop(rx, config.clone(), metrics.clone()));
+        Arc::new(Mirror {
+            config,
+            tx,
+            metrics,
+        })
+    }
+
+    pub fn emit(&self, record: Record) {
+        if self.config.load().mirror.nats.is_none() {
+            return;
+        }
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or(0.0);
+        let Ok(payload) = serde_json::to_vec(&Envelope { ts, record }) else {
+            return;
+        };
+        if self.tx.try_send(payload).is_err() {
+            self.metrics
+                .inc("oblivion_mirror_dropped_total", &[("reason", "queue_full")]);
+        }
+    }
+}
+
+// Protocolo NATS em texto: CONNECT uma vez, PUB por mensagem, PONG a cada PING
+struct Nats {
+    addr: String,
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    line: String,
+}
+
+impl Nats {
+    async fn connect(addr: &str) -> Result<Nats, String> {
+        let stream = timeout(CONNECT_TIMEOUT, TcpStream::connect(addr))
+            .await
+            .map_err(|_| "connect timeout".to_string())?
+            .map_err(|e| e.to_string())?;
+        let _ = stream.set_nodelay(true);
+        let (reader, writer) = stream.into_split();
+        let mut nats = Nats {
+            addr: addr.to_string(),
+            reader: BufReader::new(reader),
+            writer,
+            line: String::new(),
+        };
+        // O servidor abre com INFO; TLS/auth obrigatórios não são suportados
+        nats.read_line().await?;
+        if !nats.line.starts_with("INFO ") {
+            return Err(format!("unexpected greeting '{}'", nats.line.trim_end()));
+        }
+        nats.line.clear();
+        let connect = concat!(
+            r#"CONNECT {"verbose":false,"pedantic":false,"name":"oblivion","lang":"rust","version":""#,
+            env!("CARGO_PKG_VERSION"),
+            r#""}"#,
+            "\r\n"
+        );
+        nats.write(connect.as_bytes()).await?;
+        Ok(nats)
+    }
+
+    // Linha fica no buffer até ser tratada: cancelar no select não perde bytes
+    async fn read_line(&mut self) -> Result<(), String> {
+        match self.reader.read_line(&mut self.line).await {
+            Ok(0) => Err("connection closed".to_string()),
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), String> {
+        timeout(WRITE_TIMEOUT, self.writer.write_all(bytes))
+            .await
+            .map_err(|_| "write timeout".to_string())?
+            .map_err(|e| e.to_string())
+    }
+
+    // Mensagem do servidor fora de um PUB: PING responde, -ERR derruba a conexão
+    async fn handle_line(&mut self) -> Result<(), String> {
+        let line = std::mem::take(&mut self.line);
+        match line.trim_end() {
+            "PING" => self.write(b"PONG\r\n").await,
+            err if err.starts_with("-ERR") => Err(err.to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn frame(subject: &str, batch: &[Vec<u8>]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(batch.iter().map(|m| m.len() + subject.len() + 16).sum());
+    for message in batch {
+        out.extend_from_slice(format!("PUB {} {}\r\n", subject, message.len()).as_bytes());
+        out.extend_from_slice(message);
+        out.extend_from_slice(b"\r\n");
+    }
+    out
+}
+
+async fn publish_loop(
+    mut rx: mpsc::Receiver<Vec<u8>>,
+    config: Arc<ArcSwap<Config>>,
+    metrics: Arc<Metrics>,
+) {
+    let mut conn: Option<Nats> = None;
+    let mut retry_at = Instant::now();
+    let mut batch = Vec::new();
+    loop {
+        let settings = config.load().mirror.clone();
+        // Batch fecha cheio ou no flush_interval depois da primeira mensagem
+        let mut flush_at: Option<Instant> = None;
+        while batch.len() < settings.batch_size {
+            tokio::select! {
+                message = rx.recv() => match message {
+                    Some(message) => {
+                        batch.push(message);
+                        flush_at.get_or_insert(Instant::now() + settings.flush_interval);
+                    }
+                    None => return,
+                },
+                _ = async {
+                    match flush_at {
+                        Some(at) => sleep_until(at).await,
+                        None => pending().await,
+                    }
+                } => break,
+                result = async {
+                    match conn.as_mut() {
+                        Some(nats) => nats.read_line().await,
+                        None => pending().await,
+                    }
+                } => {
+                    let nats = conn.as_mut().expect("only polled with a connection");
+                    let handled = match result {
+                        Ok(()) => nats.handle_line().await,
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = handled {
+                        warn!(addr = %nats.addr, error = %e, "Mirror sink connection lost");
+                        conn = None;
+                    }
+                }
+            }
+        }
+        let Some(addr) = settings.nats.as_deref() else {
+            batch.clear();
+            conn = None;
+            continue;
+        };
+
+        if conn.as_ref().is_some_and(|c| c.addr != addr) {
+            conn = None;
+        }
+        if conn.is_none() && Instant::now() >= retry_at {
+            match Nats::connect(addr).await {
+                Ok(nats) => {
+                    info!(addr, subject = %settings.subject, "Mirror sink connected");
+                    conn = Some(nats);
+                }
+                Err(e) => {
+                    warn!(addr, error = %e, "Mirror sink unavailable");
+                    retry_at = Instant::now() + RETRY_BACKOFF;
+                }
+            }
+        }
+        let count = batch.len() as u64;
+        let sent = match conn.as_mut() {
+            Some(nats) => match nats.write(&frame(&settings.subject, &batch)).await {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!(addr, error = %e, "Mirror sink write failed");
+                    conn = None;
+                    retry_at = Instant::now() + RETRY_BACKOFF;
+                    false
+                }
+            },
+            None => false,
+        };
+        batch.clear();
+        if sent {
+            metrics.add("oblivion_mirror_events_total", &[], count);
+        } else {
+            metrics.add(
+                "oblivion_mirror_dropped_total",
+                &[("reason", "sink_error")],
+                count,
+            );
+        }
+    }
+}
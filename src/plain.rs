@@ -0,0 +1,139 @@
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tracing::debug;
+
+use crate::config::{CleartextPolicy, Config};
+use crate::http::Request;
+use crate::identity;
+
+// Métodos que abrem um request HTTP/1.x (e o preface do h2c) em texto puro
+const METHODS: [&str; 10] = [
+    "GET", "POST", "HEAD", "PUT", "DELETE", "OPTIONS", "PATCH", "CONNECT", "TRACE", "PRI",
+];
+
+// Completa o head a partir do que já foi lido; None = timeout, EOF ou grande demais
+async fn read_head<S>(stream: &mut S, mut head: Vec<u8>, config: &Config) -> Option<Vec<u8>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut buffer = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = match timeout(config.client.header_timeout, stream.read(&mut buffer)).await {
+            Ok(Ok(n)) if n > 0 => n,
+            _ => return None,
+        };
+        if head.len() + n > config.client.max_header_size {
+            debug!("Plain HTTP header size exceeded limit");
+            return None;
+        }
+        head.extend_from_slice(&buffer[..n]);
+    }
+    Some(head)
+}
+
+// Mesmo Host e path em https://; 443 some da URL
+fn location(req: &Request, port: u16) -> Option<String> {
+    let host = req.header("Host").and_then(host_only)?;
+    if !req.path.starts_with('/') {
+        return None;
+    }
+    Some(match port {
+        443 => format!("https://{}{}", host, req.path),
+        port => format!("https://{}:{}{}", host, port, req.path),
+    })
+}
+
+// Lê só o head e manda para o mesmo Host em https://; corpo e regras ficam de fora
+pub async fn redirect(mut stream: TcpStream, config: &Config) {
+    let Some(head) = read_head(&mut stream, Vec::new(), config).await else {
+        return;
+    };
+    let port = config.plain_http.https_port.unwrap_or_else(|| {
+        config
+            .listeners
+            .first()
+            .and_then(|l| l.addr.rsplit(':').next()?.parse().ok())
+            .unwrap_or(443)
+    });
+    let location = Request::parse(&String::from_utf8_lossy(&head))
+        .ok()
+        .and_then(|req| location(&req, port));
+    let response = match location {
+        Some(location) => format!(
+            "HTTP/1.1 301 Moved Permanently\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            location
+        ),
+        None => "HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            .to_string(),
+    };
+    let _ = stream
+        .write_all(&identity::stamp(&config.server, response.as_bytes()))
+        .await;
+    let _ = stream.shutdown().await;
+}
+
+// Primeiros bytes da porta TLS que são HTTP em claro: o método
+pub fn cleartext_method(raw: &[u8]) -> Option<&'static str> {
+    METHODS.into_iter().find(|method| {
+        raw.strip_prefix(method.as_bytes())
+            .is_some_and(|rest| rest.first() == Some(&b' '))
+    })
+}
+
+// HTTP em claro na porta TLS: resposta legível no lugar do alerta TLS. raw é o
+// que o accept já leu; port, a da própria porta TLS
+pub async fn cleartext<S>(
+    mut stream: S,
+    raw: Vec<u8>,
+    port: u16,
+    policy: CleartextPolicy,
+    config: &Config,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    if policy == CleartextPolicy::Close {
+        return;
+    }
+    let Some(head) = read_head(&mut stream, raw, config).await else {
+        return;
+    };
+    let req = Request::parse(&String::from_utf8_lossy(&head)).ok();
+    let location = req
+        .as_ref()
+        .filter(|req| {
+            policy == CleartextPolicy::Redirect && matches!(req.method.as_str(), "GET" | "HEAD")
+        })
+        .and_then(|req| location(req, port));
+    let response = match location {
+        Some(location) => format!(
+            "HTTP/1.1 301 Moved Permanently\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            location
+        ),
+        None => {
+            let body = "This port speaks HTTPS: send the request over TLS (https://).\n";
+            format!(
+                "HTTP/1.1 400 Bad Request\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+    };
+    let _ = stream
+        .write_all(&identity::stamp(&config.server, response.as_bytes()))
+        .await;
+    let _ = stream.shutdown().await;
+}
+
+// Host sem porta; só caracteres de nome ou IPv6 entre colchetes
+fn host_only(host: &str) -> Option<&str> {
+    let host = match host.strip_prefix('[') {
+        Some(v6) => &host[..v6.find(']')? + 2],
+        None => host.split(':').next()?,
+    };
+    let valid = !host.is_empty()
+        && host
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-.[]:".contains(&b));
+    valid.then_some(host)
+}
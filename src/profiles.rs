@@ -0,0 +1,104 @@
+use serde::Deserialize;
+
+use crate::http::Request;
+use crate::rules::{Category, Rule};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Profile {
+    pub categories: Option<Vec<Category>>,
+    #[serde(default)]
+    pub exclude_rules: Vec<u32>,
+    #[serde(default)]
+    pub exclude_tags: Vec<String>,
+    pub max_uri_length: Option<usize>,
+    pub max_params: Option<usize>,
+    pub adaptive_rate: Option<AdaptiveRate>,
+    // Liga (ou troca o threshold do) anomaly scoring para este perfil
+    pub anomaly_threshold: Option<u32>,
+}
+
+// Limite de requests que acompanha o comportamento do cliente: request limpo
+// soma reward ao score, bloqueio tira penalty; o limite vale base * 2^score,
+// preso entre min_scale e max_scale, e o score volta a 0 com meia-vida half_life
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdaptiveRate {
+    pub min_scale: f64,
+    pub max_scale: f64,
+    pub reward: f64,
+    pub penalty: f64,
+    // Segundos
+    pub half_life: f64,
+}
+
+impl Default for AdaptiveRate {
+    fn default() -> Self {
+        AdaptiveRate {
+            min_scale: 0.1,
+            max_scale: 4.0,
+            reward: 0.01,
+            penalty: 1.0,
+            half_life: 600.0,
+        }
+    }
+}
+
+impl AdaptiveRate {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.min_scale) || self.min_scale == 0.0 {
+            return Err("min_scale must be in (0, 1]".to_string());
+        }
+        if !(1.0..f64::INFINITY).contains(&self.max_scale) {
+            return Err("max_scale must be at least 1".to_string());
+        }
+        if !(self.reward >= 0.0 && self.penalty >= 0.0) {
+            return Err("reward and penalty cannot be negative".to_string());
+        }
+        if self.half_life.is_nan() || self.half_life <= 0.0 {
+            return Err("half_life must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Profile {
+    pub fn allows(&self, rule: &Rule) -> bool {
+        if let Some(categories) = &self.categories
+            && !categories.contains(&rule.category)
+        {
+            return false;
+        }
+        !self.exclude_rules.contains(&rule.id)
+            && !rule.tags.iter().any(|t| self.exclude_tags.contains(t))
+    }
+
+    pub fn check_limits(&self, req: &Request, params: &[(String, String)]) -> Result<(), String> {
+        if let Some(max) = self.max_uri_length
+            && req.path.len() > max
+        {
+            return Err(format!("URI longer than {} bytes", max));
+        }
+        if let Some(max) = self.max_params
+            && params.len() > max
+        {
+            return Err(format!("more than {} parameters", max));
+        }
+        Ok(())
+    }
+}
+
+pub fn normalize_host(host: &str) -> String {
+    let host = host.trim();
+    // [::1]:443 mantém os colchetes; host:porta perde a porta
+    let without_port = match host.rsplit_once(':') {
+        Some((h, port)) if !h.is_empty() && port.chars().all(|c| c.is_ascii_digit()) => {
+            if h.contains(':') && !h.ends_with(']') {
+                host
+            } else {
+                h
+            }
+        }
+        _ => host,
+    };
+    without_port.trim_end_matches('.').to_lowercase()
+}
@@ -0,0 +1,199 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+// Assinatura do v2 (12 bytes); o v1 é texto começando com "PROXY "
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+// Linha v1 inteira, CRLF incluso (spec, seção 2.1)
+const V1_MAX: usize = 107;
+
+// Header PROXY lido sem consumir nada além dele: o resto do stream é o
+// ClientHello (ou o request). Ok(None) = LOCAL/UNKNOWN (health check do próprio
+// LB): vale o endereço do socket. Err = motivo, já no formato de label
+pub async fn read_header<S>(stream: &mut S) -> Result<Option<SocketAddr>, &'static str>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut start = [0u8; 12];
+    stream
+        .read_exact(&mut start)
+        .await
+        .map_err(|_| "truncated")?;
+    if start == V2_SIGNATURE {
+        return read_v2(stream).await;
+    }
+    if !start.starts_with(b"PROXY ") {
+        return Err("missing");
+    }
+    let mut line = start.to_vec();
+    let mut byte = [0u8; 1];
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX {
+            return Err("malformed");
+        }
+        stream
+            .read_exact(&mut byte)
+            .await
+            .map_err(|_| "truncated")?;
+        line.push(byte[0]);
+    }
+    parse_v1(&line[..line.len() - 2]).ok_or("malformed")
+}
+
+// "PROXY TCP4 origem destino porta_origem porta_destino"
+fn parse_v1(line: &[u8]) -> Option<Option<SocketAddr>> {
+    let line = std::str::from_utf8(line).ok()?;
+    let mut fields = line.split(' ').skip(1);
+    let family = fields.next()?;
+    if family == "UNKNOWN" {
+        return Some(None);
+    }
+    let (Some(source), Some(_), Some(port), Some(_), None) = (
+        fields.next(),
+        fields.next(),
+        fields.next(),
+        fields.next(),
+        fields.next(),
+    ) else {
+        return None;
+    };
+    let ip: IpAddr = match family {
+        "TCP4" => IpAddr::V4(source.parse().ok()?),
+        "TCP6" => IpAddr::V6(source.parse().ok()?),
+        _ => return None,
+    };
+    Some(Some(SocketAddr::new(ip.to_canonical(), port.parse().ok()?)))
+}
+
+async fn read_v2<S>(stream: &mut S) -> Result<Option<SocketAddr>, &'static str>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut head = [0u8; 4];
+    stream
+        .read_exact(&mut head)
+        .await
+        .map_err(|_| "truncated")?;
+    let [version_command, family, high, low] = head;
+    if version_command >> 4 != 2 {
+        return Err("malformed");
+    }
+    // Endereços e TLVs; os TLVs ficam de fora
+    let mut body = vec![0u8; u16::from_be_bytes([high, low]) as usize];
+    stream
+        .read_exact(&mut body)
+        .await
+        .map_err(|_| "truncated")?;
+    match version_command & 0x0f {
+        0x0 => return Ok(None),
+        0x1 => {}
+        _ => return Err("malformed"),
+    }
+    let port = |at: usize| u16::from_be_bytes([body[at], body[at + 1]]);
+    match family {
+        // TCP sobre IPv4: origem, destino, porta de origem, porta de destino
+        0x11 if body.len() >= 12 => {
+            let ip = Ipv4Addr::new(body[0], body[1], body[2], body[3]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port(8))))
+        }
+        0x21 if body.len() >= 36 => {
+            let octets: [u8; 16] = body[..16].try_into().map_err(|_| "malformed")?;
+            let ip = IpAddr::V6(Ipv6Addr::from(octets)).to_canonical();
+            Ok(Some(SocketAddr::new(ip, port(32))))
+        }
+        // AF_UNSPEC e sockets unix não dizem nada útil sobre o cliente
+        0x00 | 0x31 | 0x32 => Ok(None),
+        _ => Err("malformed"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Lê o header e devolve o que sobrou no stream: o ClientHello não pode ser tocado
+    async fn read(input: &[u8]) -> (Result<Option<SocketAddr>, &'static str>, Vec<u8>) {
+        let mut stream = input;
+        let header = read_header(&mut stream).await;
+        (header, stream.to_vec())
+    }
+
+    fn v2(command: u8, family: u8, body: &[u8]) -> Vec<u8> {
+        let len = (body.len() as u16).to_be_bytes();
+        [
+            &V2_SIGNATURE[..],
+            &[0x20 | command, family, len[0], len[1]],
+            body,
+        ]
+        .concat()
+    }
+
+    #[tokio::test]
+    async fn v1_tcp4_tcp6_and_unknown() {
+        let (addr, rest) = read(b"PROXY TCP4 203.0.113.7 10.0.0.1 51000 443\r\n\x16\x03").await;
+        assert_eq!(addr, Ok(Some("203.0.113.7:51000".parse().unwrap())));
+        assert_eq!(rest, b"\x16\x03");
+
+        let (addr, _) = read(b"PROXY TCP6 2001:db8::7 2001:db8::1 51000 443\r\n").await;
+        assert_eq!(addr, Ok(Some("[2001:db8::7]:51000".parse().unwrap())));
+
+        let (addr, rest) = read(b"PROXY UNKNOWN\r\nGET").await;
+        assert_eq!(addr, Ok(None));
+        assert_eq!(rest, b"GET");
+    }
+
+    #[tokio::test]
+    async fn v1_rejects_long_or_malformed_lines() {
+        let long = [&b"PROXY TCP4 "[..], &[b'1'; 200], b"\r\n"].concat();
+        assert_eq!(read(&long).await.0, Err("malformed"));
+        for line in [
+            &b"PROXY TCP4 203.0.113.7 10.0.0.1 51000\r\n"[..],
+            b"PROXY TCP4 2001:db8::7 10.0.0.1 51000 443\r\n",
+            b"PROXY UDP4 203.0.113.7 10.0.0.1 51000 443\r\n",
+            b"PROXY TCP4 203.0.113.7 10.0.0.1 99999 443\r\n",
+        ] {
+            assert_eq!(read(line).await.0, Err("malformed"), "{:?}", line);
+        }
+        assert_eq!(read(b"PROXY TCP4 203.0.113.7").await.0, Err("truncated"));
+        assert_eq!(read(b"GET / HTTP/1.1\r\n\r\n").await.0, Err("missing"));
+    }
+
+    #[tokio::test]
+    async fn v2_local_and_proxy() {
+        let (addr, rest) = read(&[v2(0x0, 0x00, &[]), b"\x16".to_vec()].concat()).await;
+        assert_eq!(addr, Ok(None));
+        assert_eq!(rest, b"\x16");
+
+        let ipv4 = [[203, 0, 113, 7], [10, 0, 0, 1]].concat();
+        let body = [&ipv4[..], &51000u16.to_be_bytes(), &443u16.to_be_bytes()].concat();
+        let (addr, rest) = read(&[v2(0x1, 0x11, &body), b"\x16".to_vec()].concat()).await;
+        assert_eq!(addr, Ok(Some("203.0.113.7:51000".parse().unwrap())));
+        assert_eq!(rest, b"\x16");
+
+        let source: Ipv6Addr = "2001:db8::7".parse().unwrap();
+        let body = [
+            &source.octets()[..],
+            &Ipv6Addr::LOCALHOST.octets(),
+            &51000u16.to_be_bytes(),
+            &443u16.to_be_bytes(),
+            // TLV no fim é ignorado
+            &[0x04, 0x00, 0x01, 0xff],
+        ]
+        .concat();
+        let (addr, _) = read(&v2(0x1, 0x21, &body)).await;
+        assert_eq!(addr, Ok(Some("[2001:db8::7]:51000".parse().unwrap())));
+    }
+
+    #[tokio::test]
+    async fn v2_rejects_truncated_or_short_bodies() {
+        let full = v2(0x1, 0x11, &[0; 12]);
+        assert_eq!(read(&full[..full.len() - 5]).await.0, Err("truncated"));
+        assert_eq!(read(&full[..14]).await.0, Err("truncated"));
+        assert_eq!(read(&v2(0x1, 0x11, &[0; 4])).await.0, Err("malformed"));
+        assert_eq!(read(&v2(0x1, 0x21, &[0; 12])).await.0, Err("malformed"));
+        assert_eq!(read(&v2(0x2, 0x11, &[0; 12])).await.0, Err("malformed"));
+        let mut version1 = v2(0x1, 0x11, &[0; 12]);
+        version1[12] = 0x11;
+        assert_eq!(read(&version1).await.0, Err("malformed"));
+    }
+}
@@ -0,0 +1,99 @@
+use serde::Deserialize;
+
+use crate::config::SiteConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RedirectAction {
+    // Resposta inteira trocada por 502
+    #[default]
+    Block,
+    // Location trocado por fallback, o resto da resposta passa
+    Rewrite,
+}
+
+// 3xx do upstream só pode mandar para o próprio Host ou para allow_hosts
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RedirectPolicy {
+    // "exemplo.com" ou "*.exemplo.com" (só subdomínios)
+    #[serde(default)]
+    pub allow_hosts: Vec<String>,
+    #[serde(default)]
+    pub action: RedirectAction,
+    #[serde(default = "default_fallback")]
+    pub fallback: String,
+}
+
+fn default_fallback() -> String {
+    "/".to_string()
+}
+
+impl RedirectPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.fallback.starts_with('/') || self.fallback.starts_with("//") {
+            return Err(format!(
+                "redirect: fallback '{}' must be a local path",
+                self.fallback
+            ));
+        }
+        if self.fallback.bytes().any(|b| b.is_ascii_control()) {
+            return Err("redirect: fallback contains control characters".to_string());
+        }
+        Ok(())
+    }
+
+    // host = header Host do request, como veio
+    pub fn allows(&self, location: &str, host: Option<&str>) -> bool {
+        let location = location.trim();
+        // CR/LF codificado é sinal de header injection refletido pelo upstream
+        let lower = location.to_ascii_lowercase();
+        if location.bytes().any(|b| b.is_ascii_control())
+            || lower.contains("%0d")
+            || lower.contains("%0a")
+        {
+            return false;
+        }
+        // Navegador trata "\" como "/": "/\evil.com" vira "//evil.com"
+        let location = location.replace('\\', "/");
+        let rest = if let Some(rest) = location.strip_prefix("//") {
+            rest
+        } else if location.starts_with('/')
+            || location.starts_with('?')
+            || location.starts_with('#')
+        {
+            return true;
+        } else if let Some((scheme, rest)) = location.split_once("://") {
+            if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+                return false;
+            }
+            rest
+        } else {
+            // Relativo ao path atual ("pagina2") fica no mesmo host; esquema
+            // sem "//" (javascript:, data:) não
+            return !location
+                .split(['/', '?', '#'])
+                .next()
+                .unwrap_or_default()
+                .contains(':');
+        };
+
+        let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+        // userinfo ("bom.com@evil.com") não conta como host
+        let target = host_only(authority.rsplit('@').next().unwrap_or_default());
+        if target.is_empty() {
+            return false;
+        }
+        host.is_some_and(|h| host_only(h) == target)
+            || SiteConfig::pattern_matches(&self.allow_hosts, &target)
+    }
+}
+
+// "Exemplo.com:8443" -> "exemplo.com", "[::1]:443" -> "::1"
+fn host_only(authority: &str) -> String {
+    let host = match authority.strip_prefix('[') {
+        Some(v6) => v6.split(']').next().unwrap_or_default(),
+        None => authority.split(':').next().unwrap_or_default(),
+    };
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+use socket2::SockRef;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::server::TlsStream;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RejectPolicy {
+    // Resposta HTTP limpa (o padrão; melhor para monitoramento)
+    Respond,
+    // FIN sem resposta
+    Close,
+    // RST: ferramentas de ataque veem "connection reset"
+    Reset,
+    // Nada: o cliente espera até desistir (segura o socket por hold)
+    Drop,
+}
+
+impl RejectPolicy {
+    pub fn label(&self) -> &'static str {
+        match self {
+            RejectPolicy::Respond => "respond",
+            RejectPolicy::Close => "close",
+            RejectPolicy::Reset => "reset",
+            RejectPolicy::Drop => "drop",
+        }
+    }
+}
+
+pub trait Abortable {
+    // SO_LINGER zero: o próximo close sai como RST
+    fn reset_on_close(&self);
+}
+
+impl Abortable for TcpStream {
+    fn reset_on_close(&self) {
+        let _ = SockRef::from(self).set_linger(Some(Duration::ZERO));
+    }
+}
+
+impl<IO: Abortable> Abortable for TlsStream<IO> {
+    fn reset_on_close(&self) {
+        self.get_ref().0.reset_on_close();
+    }
+}
+
+pub async fn reject<S>(stream: &mut S, policy: RejectPolicy, response: &[u8], hold: Duration)
+where
+    S: AsyncWrite + Abortable + Unpin,
+{
+    match policy {
+        RejectPolicy::Respond => {
+            let _ = stream.write_all(response).await;
+        }
+        RejectPolicy::Close => {
+            let _ = stream.shutdown().await;
+        }
+        RejectPolicy::Reset => stream.reset_on_close(),
+        RejectPolicy::Drop => tokio::time::sleep(hold).await,
+    }
+}
@@ -0,0 +1,656 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::os::unix::fs::MetadataExt;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use arc_swap::ArcSwap;
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+use crate::config::{Config, ListenerConfig};
+use crate::cores::{Bus, Message};
+use crate::engine::WafEngine;
+use crate::error::Error;
+use crate::limiter::RateLimiter;
+use crate::metrics::Metrics;
+use crate::routes::Route;
+use crate::rules::{Rule, RuleSet};
+use crate::tls::{load_tls_config, ListenerTls};
+
+// "Route { path: X, host: None, timeouts: T { .. } }" -> [("path", "X"), ("host", "None"), ..]
+// Separa só no nível de cima; aspas e colchetes aninhados ficam inteiros
+fn fields(value: &impl Debug) -> Vec<(String, String)> {
+    let repr = format!("{:?}", value);
+    let Some(inner) = repr
+        .split_once(" { ")
+        .and_then(|(_, rest)| rest.strip_suffix(" }"))
+    else {
+        return vec![(String::new(), repr)];
+    };
+
+    let mut out = Vec::new();
+    let (mut depth, mut quoted, mut escaped) = (0i32, false, false);
+    let mut start = 0;
+    let bytes = inner.as_bytes();
+    for (i, b) in bytes.iter().enumerate() {
+        match b {
+            _ if escaped => escaped = false,
+            b'\\' if quoted => escaped = true,
+            b'"' => quoted = !quoted,
+            b'(' | b'[' | b'{' if !quoted => depth += 1,
+            b')' | b']' | b'}' if !quoted => depth -= 1,
+            b',' if !quoted && depth == 0 && bytes.get(i + 1) == Some(&b' ') => {
+                out.push(&inner[start..i]);
+                start = i + 2;
+            }
+            _ => {}
+        }
+    }
+    out.push(&inner[start..]);
+    out.into_iter()
+        .map(|f| match f.split_once(": ") {
+            Some((k, v)) => (k.to_string(), v.to_string()),
+            None => (String::new(), f.to_string()),
+        })
+        .collect()
+}
+
+fn changed_fields(old: &impl Debug, new: &impl Debug) -> Vec<String> {
+    let old = fields(old);
+    let new = fields(new);
+    let mut changes = Vec::new();
+    for (key, value) in &new {
+        match old.iter().find(|(k, _)| k == key) {
+            Some((_, before)) if before == value => {}
+            Some((_, before)) => changes.push(format!("{}: {} -> {}", key, before, value)),
+            None => changes.push(format!("{}: {}", key, value)),
+        }
+    }
+    changes
+}
+
+fn diff_maps<T: Debug>(
+    kind: &str,
+    old: &BTreeMap<String, &T>,
+    new: &BTreeMap<String, &T>,
+    out: &mut Vec<String>,
+) {
+    for (key, value) in new {
+        match old.get(key) {
+            None => out.push(format!("{} {} added", kind, key)),
+            Some(before) => {
+                let changes = changed_fields(before, value);
+                if !changes.is_empty() {
+                    out.push(format!("{} {} modified: {}", kind, key, changes.join("; ")));
+                }
+            }
+        }
+    }
+    for key in old.keys().filter(|k| !new.contains_key(*k)) {
+        out.push(format!("{} {} removed", kind, key));
+    }
+}
+
+fn rules_by_id(set: &RuleSet) -> BTreeMap<String, &Rule> {
+    set.rules.iter().map(|r| (r.id.to_string(), r)).collect()
+}
+
+fn routes_by_path(set: &RuleSet) -> BTreeMap<String, &Route> {
+    set.routes
+        .iter()
+        .map(|r| {
+            (
+                format!("{}{}", r.host.as_deref().unwrap_or(""), r.pattern),
+                r,
+            )
+        })
+        .collect()
+}
+
+fn by_key<T>(map: &HashMap<String, T>) -> BTreeMap<String, &T> {
+    map.iter().map(|(k, v)| (k.clone(), v)).collect()
+}
+
+// Uma linha por mudança efetiva; vazio = nada a aplicar
+pub fn diff(old: &RuleSet, new: &RuleSet) -> Vec<String> {
+    let mut out = Vec::new();
+    diff_maps("rule", &rules_by_id(old), &rules_by_id(new), &mut out);
+    diff_maps(
+        "route",
+        &routes_by_path(old),
+        &routes_by_path(new),
+        &mut out,
+    );
+    diff_maps(
+        "profile",
+        &by_key(&old.profiles),
+        &by_key(&new.profiles),
+        &mut out,
+    );
+    diff_maps("host", &by_key(&old.hosts), &by_key(&new.hosts), &mut out);
+    diff_maps(
+        "tag_action",
+        &by_key(&old.tag_actions),
+        &by_key(&new.tag_actions),
+        &mut out,
+    );
+
+    if old.default_profile != new.default_profile {
+        out.push(format!(
+            "default_profile: {:?} -> {:?}",
+            old.default_profile, new.default_profile
+        ));
+    }
+    let matched_data = changed_fields(&old.matched_data, &new.matched_data);
+    if !matched_data.is_empty() {
+        out.push(format!(
+            "matched_data modified: {}",
+            matched_data.join("; ")
+        ));
+    }
+    out
+}
+
+// Engines dos [[site]] com arquivo de regras próprio, por caminho do arquivo
+pub type SiteEngines = HashMap<String, Arc<WafEngine>>;
+
+// Arquivo de cada site precisa existir: sem fallback embutido, erro de digitação
+// no caminho não pode virar "site sem regras"
+pub fn load_site_rules(config: &Config) -> Result<BTreeMap<String, RuleSet>, String> {
+    let mut out = BTreeMap::new();
+    for site in &config.sites {
+        if let Some(path) = &site.rules
+            && *path != config.files.rules
+            && !out.contains_key(path)
+        {
+            out.insert(path.clone(), RuleSet::load(path)?);
+        }
+    }
+    Ok(out)
+}
+
+#[derive(Clone)]
+pub struct Reloader {
+    pub config: Arc<ArcSwap<Config>>,
+    pub engine: Arc<ArcSwap<WafEngine>>,
+    pub sites: Arc<ArcSwap<SiteEngines>>,
+    pub metrics: Arc<Metrics>,
+    // Modo por core: avisa os cores para refazerem as réplicas
+    pub bus: Option<Bus>,
+}
+
+impl Reloader {
+    // Arquivo inválido não derruba nada: o engine atual continua valendo
+    pub fn reload(&self, dry_run: bool) -> Result<Vec<String>, String> {
+        let config = self.config.load();
+        let loaded = RuleSet::load_or_default(&config.files.rules)
+            .and_then(|rules| Ok((rules, load_site_rules(&config)?)));
+        let (rules, sites) = loaded.inspect_err(|e| {
+            warn!(error = %e, "Reload rejected, keeping current rules");
+        })?;
+        Ok(self.install(rules, sites, dry_run))
+    }
+
+    pub fn install(
+        &self,
+        rules: RuleSet,
+        sites: BTreeMap<String, RuleSet>,
+        dry_run: bool,
+    ) -> Vec<String> {
+        let mut changes = diff(self.engine.load().rules(), &rules);
+        let current = self.sites.load();
+        for (path, set) in &sites {
+            match current.get(path) {
+                Some(engine) => changes.extend(
+                    diff(engine.rules(), set)
+                        .into_iter()
+                        .map(|change| format!("{}: {}", path, change)),
+                ),
+                None => changes.push(format!("site rules {} added", path)),
+            }
+        }
+        for path in current.keys().filter(|p| !sites.contains_key(*p)) {
+            changes.push(format!("site rules {} removed", path));
+        }
+        for change in &changes {
+            info!(dry_run, "Config change: {}", change);
+        }
+        if dry_run {
+            info!(changes = changes.len(), "Reload dry run");
+            return changes;
+        }
+        if changes.is_empty() {
+            info!("Reload: no changes");
+            return changes;
+        }
+        self.engine
+            .store(Arc::new(WafEngine::new(rules, self.metrics.clone())));
+        self.sites.store(Arc::new(
+            sites
+                .into_iter()
+                .map(|(path, set)| (path, Arc::new(WafEngine::new(set, self.metrics.clone()))))
+                .collect(),
+        ));
+        if let Some(bus) = &self.bus {
+            bus.publish(Message::Rules);
+        }
+        info!(changes = changes.len(), "Rules reloaded");
+        changes
+    }
+}
+
+fn pin<T: PartialEq + Clone + Debug>(name: &str, current: &T, new: &mut T, out: &mut Vec<String>) {
+    if current != new {
+        out.push(format!("{}: {:?} -> {:?}", name, current, new));
+        *new = current.clone();
+    }
+}
+
+// Sockets, pools e tabelas já alocados não mudam com o processo rodando:
+// esses campos ficam com o valor atual e o reload avisa
+fn pin_restart_only(current: &Config, new: &mut Config) -> Vec<String> {
+    let mut out = Vec::new();
+    let bound = |c: &Config| {
+        c.listeners
+            .iter()
+            .map(|l| (l.addr.clone(), l.v6_only))
+            .collect::<Vec<_>>()
+    };
+    if bound(current) != bound(new) {
+        out.push(format!(
+            "listener: {:?} -> {:?}",
+            bound(current),
+            bound(new)
+        ));
+        // Certificado novo ainda vale para os endereços que continuam
+        new.listeners = current
+            .listeners
+            .iter()
+            .map(|l| {
+                new.listeners.iter().find(|n| n.addr == l.addr).map_or_else(
+                    || l.clone(),
+                    |n| ListenerConfig {
+                        v6_only: l.v6_only,
+                        ..n.clone()
+                    },
+                )
+            })
+            .collect();
+    }
+
+    let (c, n) = (current, new);
+    pin("admin.addr", &c.admin.addr, &mut n.admin.addr, &mut out);
+    pin(
+        "admin.token_file",
+        &c.admin.token_file,
+        &mut n.admin.token_file,
+        &mut out,
+    );
+    pin("admin.allow", &c.admin.allow, &mut n.admin.allow, &mut out);
+    let tls = |c: &Config| {
+        (
+            c.admin.cert.clone(),
+            c.admin.key.clone(),
+            c.admin.client_ca.clone(),
+        )
+    };
+    if tls(c) != tls(n) {
+        out.push(format!("admin TLS: {:?} -> {:?}", tls(c), tls(n)));
+        (n.admin.cert, n.admin.key, n.admin.client_ca) = tls(c);
+    }
+    pin("geo", &c.geo, &mut n.geo, &mut out);
+    pin(
+        "auth.jwt_secret_file",
+        &c.auth.jwt_secret_file,
+        &mut n.auth.jwt_secret_file,
+        &mut out,
+    );
+    pin(
+        "auth.api_keys_file",
+        &c.auth.api_keys_file,
+        &mut n.auth.api_keys_file,
+        &mut out,
+    );
+    pin(
+        "plain_http.addr",
+        &c.plain_http.addr,
+        &mut n.plain_http.addr,
+        &mut out,
+    );
+    pin(
+        "upstream.max_connections",
+        &c.upstream.max_connections,
+        &mut n.upstream.max_connections,
+        &mut out,
+    );
+    pin(
+        "upstream.queue_depth",
+        &c.upstream.queue_depth,
+        &mut n.upstream.queue_depth,
+        &mut out,
+    );
+    pin(
+        "upstream.queue_timeout",
+        &c.upstream.queue_timeout,
+        &mut n.upstream.queue_timeout,
+        &mut out,
+    );
+    pin(
+        "files.capture",
+        &c.files.capture,
+        &mut n.files.capture,
+        &mut out,
+    );
+    pin(
+        "rate_limit.ipv6_prefix",
+        &c.rate_limit.ipv6_prefix,
+        &mut n.rate_limit.ipv6_prefix,
+        &mut out,
+    );
+    pin(
+        "rate_limit.gc_interval",
+        &c.rate_limit.gc_interval,
+        &mut n.rate_limit.gc_interval,
+        &mut out,
+    );
+    pin(
+        "rate_limit.idle_ttl",
+        &c.rate_limit.idle_ttl,
+        &mut n.rate_limit.idle_ttl,
+        &mut out,
+    );
+    pin(
+        "rate_limit.under_attack_scale",
+        &c.rate_limit.under_attack_scale,
+        &mut n.rate_limit.under_attack_scale,
+        &mut out,
+    );
+    pin(
+        "accept.rate_ceiling",
+        &c.accept.rate_ceiling,
+        &mut n.accept.rate_ceiling,
+        &mut out,
+    );
+    pin(
+        "accept.max_active",
+        &c.accept.max_active,
+        &mut n.accept.max_active,
+        &mut out,
+    );
+    pin(
+        "accept.emergency_threshold",
+        &c.accept.emergency_threshold,
+        &mut n.accept.emergency_threshold,
+        &mut out,
+    );
+    pin(
+        "accept.allowlist",
+        &c.accept.allowlist,
+        &mut n.accept.allowlist,
+        &mut out,
+    );
+    pin(
+        "bans.kernel_filter",
+        &c.bans.kernel_filter,
+        &mut n.bans.kernel_filter,
+        &mut out,
+    );
+    pin(
+        "bans.kernel_min_ttl",
+        &c.bans.kernel_min_ttl,
+        &mut n.bans.kernel_min_ttl,
+        &mut out,
+    );
+    pin(
+        "challenge.ttl",
+        &c.challenge.ttl,
+        &mut n.challenge.ttl,
+        &mut out,
+    );
+    pin(
+        "challenge.nonce_capacity",
+        &c.challenge.nonce_capacity,
+        &mut n.challenge.nonce_capacity,
+        &mut out,
+    );
+    pin(
+        "session.secret_file",
+        &c.session.secret_file,
+        &mut n.session.secret_file,
+        &mut out,
+    );
+    pin(
+        "watermark.secret_file",
+        &c.watermark.secret_file,
+        &mut n.watermark.secret_file,
+        &mut out,
+    );
+    pin("runtime", &c.runtime, &mut n.runtime, &mut out);
+    pin(
+        "mirror.queue_capacity",
+        &c.mirror.queue_capacity,
+        &mut n.mirror.queue_capacity,
+        &mut out,
+    );
+    out
+}
+
+fn config_diff(old: &Config, new: &Config) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut section = |name: &str, changes: Vec<String>| {
+        if !changes.is_empty() {
+            out.push(format!("{} modified: {}", name, changes.join("; ")));
+        }
+    };
+    for (i, (before, after)) in old.listeners.iter().zip(&new.listeners).enumerate() {
+        section(&format!("listener {}", i), changed_fields(before, after));
+    }
+    section("upstream", changed_fields(&old.upstream, &new.upstream));
+    section("files", changed_fields(&old.files, &new.files));
+    section("client", changed_fields(&old.client, &new.client));
+    section(
+        "rate_limit",
+        changed_fields(&old.rate_limit, &new.rate_limit),
+    );
+    section("accept", changed_fields(&old.accept, &new.accept));
+    section("policy", changed_fields(&old.policy, &new.policy));
+    section("bans", changed_fields(&old.bans, &new.bans));
+    section("challenge", changed_fields(&old.challenge, &new.challenge));
+    section("ddos", changed_fields(&old.ddos, &new.ddos));
+    section("session", changed_fields(&old.session, &new.session));
+    section("cookies", changed_fields(&old.cookies, &new.cookies));
+    section("inject", changed_fields(&old.inject, &new.inject));
+    section("watermark", changed_fields(&old.watermark, &new.watermark));
+    section("internal", changed_fields(&old.internal, &new.internal));
+    section(
+        "plain_http",
+        changed_fields(&old.plain_http, &new.plain_http),
+    );
+    section("mirror", changed_fields(&old.mirror, &new.mirror));
+    section("acme", changed_fields(&old.acme, &new.acme));
+    section("websocket", changed_fields(&old.websocket, &new.websocket));
+    section("grpc", changed_fields(&old.grpc, &new.grpc));
+    section("response", changed_fields(&old.response, &new.response));
+    section("auth", changed_fields(&old.auth, &new.auth));
+    section("server", changed_fields(&old.server, &new.server));
+    section(
+        "access_log",
+        changed_fields(&old.access_log, &new.access_log),
+    );
+    for (i, (before, after)) in old.sites.iter().zip(&new.sites).enumerate() {
+        section(&format!("site {}", i), changed_fields(before, after));
+    }
+    if old.sites.len() != new.sites.len() {
+        out.push(format!(
+            "site: {} -> {} sites",
+            old.sites.len(),
+            new.sites.len()
+        ));
+    }
+    out
+}
+
+pub type ConfigLoader = Box<dyn Fn() -> Result<Config, Error> + Send + Sync>;
+
+// SIGHUP: config do processo, certificados e regras. Conexões em andamento
+// seguem com o snapshot que pegaram; as novas já usam o novo
+pub struct ConfigReloader {
+    pub load: ConfigLoader,
+    pub config: Arc<ArcSwap<Config>>,
+    pub tls: Vec<Arc<ArcSwap<ListenerTls>>>,
+    // Um de cada por core
+    pub request_limiters: Vec<Arc<RateLimiter>>,
+    pub connection_limiters: Vec<Arc<RateLimiter>>,
+    pub fingerprint_limiters: Vec<Arc<RateLimiter>>,
+    pub rules: Reloader,
+}
+
+impl ConfigReloader {
+    pub fn reload(&self) -> Result<usize, String> {
+        let result = self.apply();
+        let outcome = if result.is_ok() { "ok" } else { "error" };
+        self.rules
+            .metrics
+            .inc("oblivion_config_reloads_total", &[("result", outcome)]);
+        result
+    }
+
+    fn apply(&self) -> Result<usize, String> {
+        let current = self.config.load_full();
+        let mut config = (self.load)().map_err(|e| {
+            warn!(error = %e, "Config reload rejected, keeping current config");
+            e.to_string()
+        })?;
+        for change in pin_restart_only(&current, &mut config) {
+            warn!("Config change requires restart, ignored: {}", change);
+        }
+        // Sites herdam do global: refaz depois de fixar o que não muda em runtime
+        config.resolve_sites().map_err(|e| {
+            warn!(error = %e, "Config reload rejected, keeping current config");
+            e
+        })?;
+
+        // Tudo que pode falhar vem antes da primeira troca: nada de reload pela metade
+        let rules = RuleSet::load_or_default(&config.files.rules)
+            .and_then(|rules| Ok((rules, load_site_rules(&config)?)))
+            .map_err(|e| {
+                warn!(error = %e, "Config reload rejected, keeping current config");
+                e
+            })?;
+        let tls = config
+            .listeners
+            .iter()
+            .map(load_tls_config)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| {
+                warn!(error = %e, "Config reload rejected, keeping current config");
+                e.to_string()
+            })?;
+
+        let changes = config_diff(&current, &config);
+        for change in &changes {
+            info!("Config change: {}", change);
+        }
+        for (slot, server) in self.tls.iter().zip(tls) {
+            slot.store(server);
+        }
+        let rl = &config.rate_limit;
+        for limiter in &self.request_limiters {
+            limiter.set_limits(rl.request_rate, rl.request_burst);
+        }
+        for limiter in &self.connection_limiters {
+            limiter.set_limits(rl.connection_rate, rl.connection_burst);
+        }
+        for limiter in &self.fingerprint_limiters {
+            limiter.set_limits(rl.fingerprint_rate, rl.fingerprint_burst);
+        }
+        self.config.store(Arc::new(config));
+
+        let rules = self.rules.install(rules.0, rules.1, false);
+        info!(
+            changes = changes.len(),
+            rule_changes = rules.len(),
+            "Configuration reloaded"
+        );
+        Ok(changes.len() + rules.len())
+    }
+}
+
+// (mtime, tamanho, inode) de cada arquivo; None = ausente ou ilegível
+type Stamps = Vec<(String, Option<(SystemTime, u64, u64)>)>;
+
+fn cert_stamps(config: &Config) -> Stamps {
+    config
+        .listeners
+        .iter()
+        .flat_map(|l| {
+            std::iter::once((&l.cert, &l.key)).chain(l.sni.iter().map(|s| (&s.cert, &s.key)))
+        })
+        .flat_map(|(cert, key)| [cert, key])
+        .chain(config.listeners.iter().filter_map(|l| l.client_ca.as_ref()))
+        .map(|path| {
+            let stamp = std::fs::metadata(path)
+                .ok()
+                .and_then(|m| Some((m.modified().ok()?, m.len(), m.ino())));
+            (path.clone(), stamp)
+        })
+        .collect()
+}
+
+// Certificado renovado por fora (certbot, cert-manager, symlink trocado) entra
+// sem SIGHUP. A troca espera dois polls iguais: cert e chave raramente são
+// gravados no mesmo instante, e o par pela metade não pode ir para o listener
+pub struct CertWatcher {
+    pub config: Arc<ArcSwap<Config>>,
+    pub tls: Vec<Arc<ArcSwap<ListenerTls>>>,
+    pub metrics: Arc<Metrics>,
+}
+
+impl CertWatcher {
+    pub async fn run(self) {
+        let mut applied = cert_stamps(&self.config.load());
+        let mut pending: Option<Stamps> = None;
+        loop {
+            sleep(self.config.load().files.cert_poll_interval).await;
+            let config = self.config.load_full();
+            let current = cert_stamps(&config);
+            if current == applied {
+                pending = None;
+                continue;
+            }
+            if pending.as_ref() != Some(&current) {
+                pending = Some(current);
+                continue;
+            }
+            pending = None;
+            let changed: Vec<&str> = current
+                .iter()
+                .filter(|entry| !applied.contains(entry))
+                .map(|(path, _)| path.as_str())
+                .collect();
+            let loaded = config
+                .listeners
+                .iter()
+                .map(load_tls_config)
+                .collect::<Result<Vec<_>, _>>();
+            // Falha também marca como visto: tenta de novo só na próxima mudança
+            match loaded {
+                Ok(tls) => {
+                    for (slot, server) in self.tls.iter().zip(tls) {
+                        slot.store(server);
+                    }
+                    info!(files = ?changed, "Certificates reloaded from disk");
+                    self.metrics
+                        .inc("oblivion_cert_reloads_total", &[("result", "ok")]);
+                }
+                Err(e) => {
+                    warn!(files = ?changed, error = %e, "Certificate change rejected, keeping current certificates");
+                    self.metrics
+                        .inc("oblivion_cert_reloads_total", &[("result", "error")]);
+                }
+            }
+            applied = current;
+        }
+    }
+}
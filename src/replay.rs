@@ -0,0 +1,152 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Deserialize;
+
+use crate::http::Request;
+
+const MIN_NONCE_LENGTH: usize = 8;
+const MAX_NONCE_LENGTH: usize = 128;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReplayPolicy {
+    #[serde(default = "default_timestamp_header")]
+    pub timestamp_header: String,
+    #[serde(default = "default_nonce_header")]
+    pub nonce_header: String,
+    #[serde(default = "default_max_skew")]
+    pub max_skew: u64,
+}
+
+fn default_timestamp_header() -> String {
+    "X-Timestamp".to_string()
+}
+
+fn default_nonce_header() -> String {
+    "X-Nonce".to_string()
+}
+
+fn default_max_skew() -> u64 {
+    300
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    MissingTimestamp,
+    InvalidTimestamp,
+    TimestampSkew,
+    MissingNonce,
+    InvalidNonce,
+    NonceReused,
+    CacheFull,
+}
+
+impl ReplayError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ReplayError::MissingTimestamp => "missing_timestamp",
+            ReplayError::InvalidTimestamp => "invalid_timestamp",
+            ReplayError::TimestampSkew => "timestamp_skew",
+            ReplayError::MissingNonce => "missing_nonce",
+            ReplayError::InvalidNonce => "invalid_nonce",
+            ReplayError::NonceReused => "nonce_reused",
+            ReplayError::CacheFull => "replay_cache_full",
+        }
+    }
+
+    pub fn response(&self) -> Vec<u8> {
+        let (status, body) = match self {
+            // Cache cheio não é culpa do cliente, mas também não dá para aceitar sem checar
+            ReplayError::CacheFull => (
+                "503 Service Unavailable",
+                "{\"error\":\"replay_cache_full\"}".to_string(),
+            ),
+            _ => (
+                "401 Unauthorized",
+                format!("{{\"error\":\"{}\"}}", self.code()),
+            ),
+        };
+        format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nX-Oblivion-Error: {}\r\nContent-Length: {}\r\n\r\n{}",
+            status,
+            self.code(),
+            body.len(),
+            body
+        )
+        .into_bytes()
+    }
+}
+
+struct Seen {
+    expiry: HashMap<String, u64>,
+    order: VecDeque<(u64, String)>,
+}
+
+pub struct NonceCache {
+    seen: Mutex<Seen>,
+    capacity: usize,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl NonceCache {
+    pub fn new(capacity: usize) -> Arc<Self> {
+        Arc::new(NonceCache {
+            seen: Mutex::new(Seen {
+                expiry: HashMap::new(),
+                order: VecDeque::new(),
+            }),
+            capacity,
+        })
+    }
+
+    pub fn check(&self, req: &Request, policy: &ReplayPolicy) -> Result<(), ReplayError> {
+        let timestamp: u64 = req
+            .header(&policy.timestamp_header)
+            .ok_or(ReplayError::MissingTimestamp)?
+            .parse()
+            .map_err(|_| ReplayError::InvalidTimestamp)?;
+        let now = now_secs();
+        if timestamp.abs_diff(now) > policy.max_skew {
+            return Err(ReplayError::TimestampSkew);
+        }
+
+        let nonce = req
+            .header(&policy.nonce_header)
+            .ok_or(ReplayError::MissingNonce)?;
+        if !(MIN_NONCE_LENGTH..=MAX_NONCE_LENGTH).contains(&nonce.len())
+            || !nonce.bytes().all(|b| b.is_ascii_graphic())
+        {
+            return Err(ReplayError::InvalidNonce);
+        }
+
+        let mut seen = self.seen.lock().unwrap();
+        // Depois da expiração o timestamp já seria rejeitado: o nonce pode sair do cache
+        while let Some((expiry, _)) = seen.order.front()
+            && *expiry < now
+        {
+            let (_, old) = seen.order.pop_front().unwrap();
+            if seen.expiry.get(&old).is_some_and(|e| *e < now) {
+                seen.expiry.remove(&old);
+            }
+        }
+
+        if seen.expiry.get(nonce).is_some_and(|e| *e >= now) {
+            return Err(ReplayError::NonceReused);
+        }
+        if seen.expiry.len() >= self.capacity {
+            return Err(ReplayError::CacheFull);
+        }
+
+        let expiry = timestamp + policy.max_skew;
+        seen.expiry.insert(nonce.to_string(), expiry);
+        seen.order.push_back((expiry, nonce.to_string()));
+        Ok(())
+    }
+}
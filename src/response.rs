@@ -0,0 +1,306 @@
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::task::spawn_blocking;
+use tokio::time::timeout;
+
+use crate::config::ResponseConfig;
+use crate::http::{
+    insert_header, response_body_length, response_headers, response_status, strip_headers,
+    Dechunker,
+};
+use crate::metrics::Metrics;
+
+// Qualidade/janela do br na recompressão: perto do que servidores usam on-the-fly
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coding {
+    Identity,
+    Gzip,
+    Deflate,
+    Br,
+}
+
+impl Coding {
+    // Uma codificação só; empilhadas (gzip, br) ou desconhecidas (zstd) = None
+    fn of(headers: &[u8]) -> Option<Coding> {
+        let codings: Vec<String> = response_headers(headers, "Content-Encoding")
+            .iter()
+            .flat_map(|v| v.split(','))
+            .map(|c| c.trim().to_ascii_lowercase())
+            .filter(|c| !c.is_empty() && c != "identity")
+            .collect();
+        match codings.as_slice() {
+            [] => Some(Coding::Identity),
+            [one] => match one.as_str() {
+                "gzip" | "x-gzip" => Some(Coding::Gzip),
+                "deflate" => Some(Coding::Deflate),
+                "br" => Some(Coding::Br),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Coding::Identity => "identity",
+            Coding::Gzip => "gzip",
+            Coding::Deflate => "deflate",
+            Coding::Br => "br",
+        }
+    }
+}
+
+pub type Check<'a> = &'a (dyn Fn(&str) -> Option<String> + Sync);
+
+pub struct Inspection<'a> {
+    pub config: &'a ResponseConfig,
+    pub decompress: bool,
+    // Regras RESPONSE_BODY; Some(motivo) = bloquear
+    pub check: Check<'a>,
+}
+
+pub enum Outcome {
+    // Resposta como veio (inteira ou só o começo já lido): o resto segue do upstream
+    Pass(Vec<u8>),
+    // Corpo descomprimido liberado: resposta sem Content-Encoding e com
+    // Content-Length, mais a original para quando nada mudar o corpo
+    Decoded {
+        response: Vec<u8>,
+        original: Vec<u8>,
+        coding: Coding,
+    },
+    Block(String),
+}
+
+enum Framing {
+    Length(usize),
+    Chunked(Dechunker),
+    Close,
+}
+
+fn skipped(metrics: &Metrics, reason: &str) {
+    metrics.inc(
+        "oblivion_response_inspection_skipped_total",
+        &[("reason", reason)],
+    );
+}
+
+// Lê o corpo inteiro (até max_body_size) e passa pelas regras. head tem o
+// cabeçalho completo e possivelmente parte do corpo
+pub async fn inspect<R>(
+    upstream: &mut R,
+    head: Vec<u8>,
+    inspection: &Inspection<'_>,
+    read_timeout: Duration,
+    metrics: &Metrics,
+) -> std::io::Result<Outcome>
+where
+    R: AsyncRead + Unpin,
+{
+    let config = inspection.config;
+    let Some(end) = head.windows(4).position(|w| w == b"\r\n\r\n") else {
+        return Ok(Outcome::Pass(head));
+    };
+    let headers = &head[..end + 4];
+    let content_type = response_headers(headers, "Content-Type")
+        .first()
+        .map(|t| t.to_ascii_lowercase())
+        .unwrap_or_default();
+    // SSE não tem fim: segurar o corpo seria segurar o stream inteiro
+    if content_type.starts_with("text/event-stream") {
+        skipped(metrics, "streaming");
+        return Ok(Outcome::Pass(head));
+    }
+    if response_status(headers).is_none_or(|status| status < 200)
+        || response_body_length(headers, false) == Some(0)
+        || !config
+            .content_types
+            .iter()
+            .any(|prefix| content_type.starts_with(&prefix.to_ascii_lowercase()))
+    {
+        return Ok(Outcome::Pass(head));
+    }
+    let coding = match Coding::of(headers) {
+        Some(Coding::Identity) => Coding::Identity,
+        Some(coding) if inspection.decompress => coding,
+        _ => {
+            skipped(metrics, "encoding");
+            return Ok(Outcome::Pass(head));
+        }
+    };
+    let chunked = response_headers(headers, "Transfer-Encoding")
+        .iter()
+        .any(|te| te.to_ascii_lowercase().contains("chunked"));
+    let mut framing = match response_body_length(headers, false) {
+        _ if chunked => Framing::Chunked(Dechunker::default()),
+        Some(length) if length > config.max_body_size as u64 => {
+            skipped(metrics, "size");
+            return Ok(Outcome::Pass(head));
+        }
+        Some(length) => Framing::Length(length as usize),
+        None => Framing::Close,
+    };
+
+    // raw = corpo como veio do upstream
+    let mut raw = head[end + 4..].to_vec();
+    let mut buffer = [0u8; 16 * 1024];
+    loop {
+        let complete = match &mut framing {
+            Framing::Length(length) => {
+                raw.truncate(*length);
+                raw.len() == *length
+            }
+            Framing::Chunked(decoder) => match decoder.feed(&raw) {
+                Ok(Some(used)) => {
+                    raw.truncate(used);
+                    true
+                }
+                Ok(None) => false,
+                // Chunked quebrado: deliver repassa e o cliente decide
+                Err(_) => return Ok(Outcome::Pass([&head[..end + 4], &raw].concat())),
+            },
+            Framing::Close => false,
+        };
+        if complete {
+            break;
+        }
+        if raw.len() > config.max_body_size {
+            skipped(metrics, "size");
+            return Ok(Outcome::Pass([&head[..end + 4], &raw].concat()));
+        }
+        // Upstream parado no meio: desiste da inspeção, deliver cuida do resto
+        let Ok(n) = timeout(read_timeout, upstream.read(&mut buffer)).await else {
+            skipped(metrics, "timeout");
+            return Ok(Outcome::Pass([&head[..end + 4], &raw].concat()));
+        };
+        let n = n?;
+        if n == 0 {
+            if !matches!(framing, Framing::Close) {
+                return Ok(Outcome::Pass([&head[..end + 4], &raw].concat()));
+            }
+            break;
+        }
+        raw.extend_from_slice(&buffer[..n]);
+    }
+
+    let body = match &mut framing {
+        Framing::Chunked(decoder) => std::mem::take(&mut decoder.body),
+        _ => raw.clone(),
+    };
+    let original = [&head[..end + 4], &raw].concat();
+    let plain = match coding {
+        Coding::Identity => body,
+        coding => {
+            let limits = config.clone();
+            let decoded = spawn_blocking(move || decode(coding, &body, &limits)).await;
+            match decoded.unwrap_or(Err("decode")) {
+                Ok(plain) => plain,
+                Err(reason) => {
+                    skipped(metrics, reason);
+                    return Ok(Outcome::Pass(original));
+                }
+            }
+        }
+    };
+    metrics.inc(
+        "oblivion_response_inspections_total",
+        &[("encoding", coding.label())],
+    );
+    if let Some(reason) = (inspection.check)(&String::from_utf8_lossy(&plain)) {
+        return Ok(Outcome::Block(reason));
+    }
+    if coding == Coding::Identity {
+        return Ok(Outcome::Pass(original));
+    }
+
+    let mut response = strip_headers(
+        &head[..end + 4],
+        &["Content-Encoding", "Content-Length", "Transfer-Encoding"],
+    )
+    .0;
+    insert_header(&mut response, "Content-Length", &plain.len().to_string());
+    response.extend_from_slice(&plain);
+    Ok(Outcome::Decoded {
+        response,
+        original,
+        coding,
+    })
+}
+
+// Descompressão limitada em tamanho e tempo; Err = label do motivo. Roda no
+// pool de blocking, o prazo checado a cada bloco só segura a bomba
+fn decode(coding: Coding, body: &[u8], config: &ResponseConfig) -> Result<Vec<u8>, &'static str> {
+    let attempt = |mut reader: Box<dyn Read + '_>| {
+        let started = Instant::now();
+        let mut plain = Vec::new();
+        let mut buffer = [0u8; 16 * 1024];
+        loop {
+            let n = reader.read(&mut buffer).map_err(|_| "decode")?;
+            if n == 0 {
+                return Ok(plain);
+            }
+            if plain.len() + n > config.max_decompressed_size {
+                return Err("decompressed_size");
+            }
+            if started.elapsed() > config.decompress_timeout {
+                return Err("timeout");
+            }
+            plain.extend_from_slice(&buffer[..n]);
+        }
+    };
+    match coding {
+        Coding::Identity => Ok(body.to_vec()),
+        Coding::Gzip => attempt(Box::new(GzDecoder::new(body))),
+        // "deflate" no HTTP é zlib (RFC 9110 8.4.1.2), mas há servidor mandando deflate cru
+        Coding::Deflate => attempt(Box::new(ZlibDecoder::new(body)))
+            .or_else(|_| attempt(Box::new(DeflateDecoder::new(body)))),
+        Coding::Br => attempt(Box::new(brotli::Decompressor::new(body, 4096))),
+    }
+}
+
+// Resposta identity com Content-Length (a de Decoded, talvez editada) de volta
+// na codificação original, fora do worker async
+pub async fn encode(response: Vec<u8>, coding: Coding) -> std::io::Result<Vec<u8>> {
+    spawn_blocking(move || recompress(&response, coding))
+        .await
+        .map_err(std::io::Error::other)?
+}
+
+fn recompress(response: &[u8], coding: Coding) -> std::io::Result<Vec<u8>> {
+    let Some(end) = response.windows(4).position(|w| w == b"\r\n\r\n") else {
+        return Ok(response.to_vec());
+    };
+    let body = &response[end + 4..];
+    let compressed = match coding {
+        Coding::Identity => return Ok(response.to_vec()),
+        Coding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(body)?;
+            encoder.finish()?
+        }
+        Coding::Deflate => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(body)?;
+            encoder.finish()?
+        }
+        Coding::Br => {
+            let mut encoder =
+                brotli::CompressorWriter::new(Vec::new(), 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+            encoder.write_all(body)?;
+            encoder.into_inner()
+        }
+    };
+    let mut out = strip_headers(&response[..end + 4], &["Content-Length"]).0;
+    insert_header(&mut out, "Content-Encoding", coding.label());
+    insert_header(&mut out, "Content-Length", &compressed.len().to_string());
+    out.extend_from_slice(&compressed);
+    Ok(out)
+}
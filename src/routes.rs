@@ -0,0 +1,292 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::auth::Mechanism;
+use crate::http::reason_phrase;
+use crate::redirects::RedirectPolicy;
+use crate::reject::RejectPolicy;
+use crate::replay::ReplayPolicy;
+use crate::signed::SignedUrlPolicy;
+use crate::sniff::ContentMismatch;
+use crate::watermark::WatermarkMode;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct PathPattern {
+    prefix: String,
+    wildcard: bool,
+}
+
+impl TryFrom<String> for PathPattern {
+    type Error = String;
+
+    fn try_from(raw: String) -> Result<Self, Self::Error> {
+        if !raw.starts_with('/') {
+            return Err(format!("route path '{}' must start with '/'", raw));
+        }
+        match raw.strip_suffix('*') {
+            Some(prefix) => Ok(PathPattern {
+                prefix: prefix.to_string(),
+                wildcard: true,
+            }),
+            None => Ok(PathPattern {
+                prefix: raw,
+                wildcard: false,
+            }),
+        }
+    }
+}
+
+impl std::fmt::Display for PathPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.prefix, if self.wildcard { "*" } else { "" })
+    }
+}
+
+impl PathPattern {
+    pub fn matches(&self, path: &str) -> bool {
+        if self.wildcard {
+            path.starts_with(&self.prefix)
+        } else {
+            path == self.prefix
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CharClass {
+    #[default]
+    Any,
+    Numeric,
+    Alpha,
+    Alphanumeric,
+    Email,
+    Uuid,
+}
+
+impl CharClass {
+    pub fn accepts(&self, value: &str) -> bool {
+        match self {
+            CharClass::Any => true,
+            CharClass::Numeric => value.chars().all(|c| c.is_ascii_digit()),
+            CharClass::Alpha => value.chars().all(|c| c.is_ascii_alphabetic()),
+            CharClass::Alphanumeric => value.chars().all(|c| c.is_ascii_alphanumeric()),
+            CharClass::Email => {
+                let Some((local, domain)) = value.split_once('@') else {
+                    return false;
+                };
+                !local.is_empty()
+                    && domain.contains('.')
+                    && !domain.starts_with('.')
+                    && !domain.ends_with('.')
+                    && local
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "._%+-".contains(c))
+                    && domain
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || ".-".contains(c))
+            }
+            CharClass::Uuid => {
+                value.len() == 36
+                    && value.char_indices().all(|(i, c)| match i {
+                        8 | 13 | 18 | 23 => c == '-',
+                        _ => c.is_ascii_hexdigit(),
+                    })
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ParamPolicy {
+    pub name: String,
+    #[serde(default)]
+    pub required: bool,
+    pub max_length: Option<usize>,
+    #[serde(default)]
+    pub class: CharClass,
+}
+
+// {status} e {reason} são do upstream; nada vindo do cliente entra no template
+#[derive(Debug, Clone, Deserialize)]
+pub struct StatusRewrite {
+    pub status: u16,
+    pub to: Option<u16>,
+    #[serde(default = "default_content_type")]
+    pub content_type: String,
+    #[serde(default)]
+    pub body: String,
+}
+
+fn default_content_type() -> String {
+    "application/json".to_string()
+}
+
+impl StatusRewrite {
+    pub fn validate(&self) -> Result<(), String> {
+        for code in std::iter::once(self.status).chain(self.to) {
+            if !(100..=599).contains(&code) {
+                return Err(format!("status rewrite: invalid status code {}", code));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn render(&self, upstream_status: u16) -> Vec<u8> {
+        let status = self.to.unwrap_or(upstream_status);
+        let body = self
+            .body
+            .replace("{status}", &upstream_status.to_string())
+            .replace("{reason}", reason_phrase(upstream_status));
+        format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            reason_phrase(status),
+            self.content_type,
+            body.len(),
+            body
+        )
+        .into_bytes()
+    }
+}
+
+// Segundos (aceita fração); ausente = padrão global
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct UpstreamTimeouts {
+    pub connect: Option<f64>,
+    pub first_byte: Option<f64>,
+    pub total: Option<f64>,
+    pub deadline: Option<f64>,
+}
+
+impl UpstreamTimeouts {
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, secs) in [
+            ("connect", self.connect),
+            ("first_byte", self.first_byte),
+            ("total", self.total),
+            ("deadline", self.deadline),
+        ] {
+            if let Some(secs) = secs
+                && !(secs.is_finite() && secs > 0.0)
+            {
+                return Err(format!(
+                    "timeouts: '{}' must be a positive number of seconds",
+                    name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn connect(&self) -> Option<Duration> {
+        self.connect.map(Duration::from_secs_f64)
+    }
+
+    pub fn first_byte(&self) -> Option<Duration> {
+        self.first_byte.map(Duration::from_secs_f64)
+    }
+
+    pub fn total(&self) -> Option<Duration> {
+        self.total.map(Duration::from_secs_f64)
+    }
+
+    pub fn deadline(&self) -> Option<Duration> {
+        self.deadline.map(Duration::from_secs_f64)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Route {
+    #[serde(rename = "path")]
+    pub pattern: PathPattern,
+    pub host: Option<String>,
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub params: Vec<ParamPolicy>,
+    #[serde(default)]
+    pub strict_params: bool,
+    #[serde(default = "default_true")]
+    pub inspect_body: bool,
+    pub max_body_size: Option<u64>,
+    pub max_response_size: Option<u64>,
+    #[serde(default)]
+    pub status_rewrites: Vec<StatusRewrite>,
+    #[serde(default)]
+    pub allow_headers: Vec<String>,
+    pub normalize: Option<bool>,
+    pub block_cache: Option<bool>,
+    #[serde(default)]
+    pub coalesce: bool,
+    // SSE/long-poll: resposta repassada sem buffer nem inspeção, sem
+    // delivery_deadline e com [client] stream_idle_timeout
+    #[serde(default)]
+    pub streaming: bool,
+    pub replay: Option<ReplayPolicy>,
+    pub signed_url: Option<SignedUrlPolicy>,
+    pub redirect: Option<RedirectPolicy>,
+    #[serde(default)]
+    pub timeouts: UpstreamTimeouts,
+    pub block_policy: Option<RejectPolicy>,
+    pub content_mismatch: Option<ContentMismatch>,
+    pub watermark: Option<WatermarkMode>,
+    // Ausente = [response] inspect / decompress
+    pub inspect_response: Option<bool>,
+    pub decompress_response: Option<bool>,
+    // Credenciais aceitas (basta uma); ausente = [auth] default
+    pub auth: Option<Vec<Mechanism>>,
+    // Principais admitidos (certificado, sub do JWT, nome da chave); vazio = qualquer um
+    #[serde(default)]
+    pub auth_principals: Vec<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Route {
+    pub fn matches(&self, host: &str, path: &str) -> bool {
+        self.host
+            .as_ref()
+            .is_none_or(|h| h.eq_ignore_ascii_case(host))
+            && self.pattern.matches(path)
+    }
+
+    pub fn check_params(&self, params: &[(String, String)]) -> Result<(), String> {
+        for policy in &self.params {
+            let values: Vec<&str> = params
+                .iter()
+                .filter(|(k, _)| *k == policy.name)
+                .map(|(_, v)| v.as_str())
+                .collect();
+
+            if values.is_empty() && policy.required {
+                return Err(format!("missing required parameter '{}'", policy.name));
+            }
+
+            for value in values {
+                if policy.max_length.is_some_and(|max| value.len() > max) {
+                    return Err(format!("parameter '{}' too long", policy.name));
+                }
+                if !policy.class.accepts(value) {
+                    return Err(format!(
+                        "parameter '{}' outside {:?} class",
+                        policy.name, policy.class
+                    ));
+                }
+            }
+        }
+
+        if self.strict_params
+            && let Some((k, _)) = params
+                .iter()
+                .find(|(k, _)| !self.params.iter().any(|p| p.name == *k))
+        {
+            return Err(format!("unexpected parameter '{}'", k));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1040 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use percent_encoding::percent_decode_str;
+use regex::{Regex, RegexBuilder};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::auth::Mechanism;
+use crate::challenge::to_hex;
+use crate::profiles::Profile;
+use crate::routes::Route;
+use crate::seclang;
+
+const DEFAULT_RULES: &str = include_str!("../rules/default.yaml");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    Sqli,
+    Xss,
+    Traversal,
+    Other,
+}
+
+impl Category {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Category::Sqli => "SQL Injection",
+            Category::Xss => "XSS",
+            Category::Traversal => "Path Traversal",
+            Category::Other => "Rule",
+        }
+    }
+}
+
+// Gravidade no estilo do ModSecurity/CRS (severity:'CRITICAL' ou severity:2)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Emergency,
+    Alert,
+    Critical,
+    Error,
+    Warning,
+    Notice,
+    Info,
+    Debug,
+}
+
+impl Severity {
+    const ALL: [Severity; 8] = [
+        Severity::Emergency,
+        Severity::Alert,
+        Severity::Critical,
+        Severity::Error,
+        Severity::Warning,
+        Severity::Notice,
+        Severity::Info,
+        Severity::Debug,
+    ];
+
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw.parse::<usize>() {
+            Ok(level) => Severity::ALL.get(level).copied(),
+            Err(_) => Severity::ALL
+                .into_iter()
+                .find(|s| s.label().eq_ignore_ascii_case(raw)),
+        }
+    }
+
+    // Pontos no modo anomaly scoring, como os tx.*_anomaly_score do CRS
+    pub fn anomaly_score(&self) -> u32 {
+        match self {
+            Severity::Emergency | Severity::Alert | Severity::Critical => 5,
+            Severity::Error => 4,
+            Severity::Warning => 3,
+            Severity::Notice => 2,
+            Severity::Info | Severity::Debug => 0,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Severity::Emergency => "emergency",
+            Severity::Alert => "alert",
+            Severity::Critical => "critical",
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Notice => "notice",
+            Severity::Info => "info",
+            Severity::Debug => "debug",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Block,
+    Log,
+}
+
+impl Action {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Action::Block => "block",
+            Action::Log => "log",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transform {
+    UrlDecode,
+    Lowercase,
+    RemoveWhitespace,
+    CompressSlashes,
+    RemoveComments,
+    CompressWhitespace,
+    NormalizeQuotes,
+    Utf7Decode,
+}
+
+impl Transform {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name.trim() {
+            "urlDecode" => Ok(Transform::UrlDecode),
+            "lowercase" => Ok(Transform::Lowercase),
+            "removeWhitespace" => Ok(Transform::RemoveWhitespace),
+            "compressSlashes" => Ok(Transform::CompressSlashes),
+            "removeComments" => Ok(Transform::RemoveComments),
+            "compressWhitespace" => Ok(Transform::CompressWhitespace),
+            "normalizeQuotes" => Ok(Transform::NormalizeQuotes),
+            "utf7Decode" => Ok(Transform::Utf7Decode),
+            other => Err(format!("unknown transformation '{}'", other)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Transform::UrlDecode => "urlDecode",
+            Transform::Lowercase => "lowercase",
+            Transform::RemoveWhitespace => "removeWhitespace",
+            Transform::CompressSlashes => "compressSlashes",
+            Transform::RemoveComments => "removeComments",
+            Transform::CompressWhitespace => "compressWhitespace",
+            Transform::NormalizeQuotes => "normalizeQuotes",
+            Transform::Utf7Decode => "utf7Decode",
+        }
+    }
+
+    pub fn chain_name(chain: &[Transform]) -> String {
+        if chain.is_empty() {
+            return "none".to_string();
+        }
+        chain
+            .iter()
+            .map(Transform::name)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    pub fn parse_chain(chain: &str) -> Result<Vec<Self>, String> {
+        chain
+            .split(',')
+            .filter(|t| !t.trim().is_empty() && t.trim() != "none")
+            .map(Transform::parse)
+            .collect()
+    }
+
+    pub fn apply(&self, input: &str) -> String {
+        match self {
+            Transform::UrlDecode => url_decode(input),
+            Transform::Lowercase => input.to_lowercase(),
+            Transform::RemoveWhitespace => input.chars().filter(|c| !c.is_whitespace()).collect(),
+            Transform::CompressSlashes => {
+                let mut out = String::with_capacity(input.len());
+                for c in input.chars() {
+                    if (c == '/' || c == '\\') && out.ends_with(c) {
+                        continue;
+                    }
+                    out.push(c);
+                }
+                out
+            }
+            Transform::RemoveComments => remove_sql_comments(input),
+            Transform::CompressWhitespace => {
+                let mut out = String::with_capacity(input.len());
+                for c in input.chars() {
+                    if c.is_whitespace() {
+                        if !out.ends_with(' ') {
+                            out.push(' ');
+                        }
+                    } else {
+                        out.push(c);
+                    }
+                }
+                out
+            }
+            Transform::NormalizeQuotes => input
+                .chars()
+                .map(|c| match c {
+                    '"' | '`' | '´' | '‘' | '’' | '“' | '”' => '\'',
+                    c => c,
+                })
+                .collect(),
+            Transform::Utf7Decode => utf7_decode(input),
+        }
+    }
+}
+
+// Comentários viram um espaço (o MySQL trata `/**/` como whitespace), exceto
+// `/*! ... */`, cujo conteúdo o MySQL executa e por isso é mantido. Nenhum
+// comentário passa de um `&`: `x=%23&id=1 UNION SELECT` não pode apagar o
+// parâmetro seguinte. `#`/`-- ` vão até o fim da linha ou do parâmetro; `/*`
+// sem `*/` antes do próximo `&` é só o token
+fn remove_sql_comments(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+
+    let boundary = |s: &str| s.find('&').unwrap_or(s.len());
+    // Até onde já se sabe que não há `*/`: uma fila de `/*` não refaz a busca
+    let mut unclosed_until = 0;
+
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("/*") {
+            let at = input.len() - after.len();
+            let close = if at < unclosed_until {
+                None
+            } else {
+                let limit = boundary(after);
+                let close = after[..limit].find("*/");
+                if close.is_none() {
+                    unclosed_until = at + limit;
+                }
+                close
+            };
+            match close {
+                Some(end) => {
+                    if let Some(exec) = after[..end].strip_prefix('!') {
+                        out.push(' ');
+                        out.push_str(exec.trim_start_matches(|c: char| c.is_ascii_digit()));
+                    }
+                    out.push(' ');
+                    rest = &after[end + 2..];
+                }
+                None => {
+                    out.push(' ');
+                    rest = after;
+                }
+            }
+        } else if rest.starts_with('#') || rest.starts_with("-- ") {
+            out.push(' ');
+            let limit = boundary(rest);
+            let end = rest[..limit].find('\n').unwrap_or(limit);
+            rest = &rest[end..];
+        } else {
+            let c = rest.chars().next().unwrap();
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    out
+}
+
+// Decodifica até a string estabilizar (double encoding: %2527 -> %27 -> ').
+// '+' é espaço só na primeira passada: o que veio de %2B é '+' literal (UTF-7).
+// Byte que não é UTF-8 não segura o resto do valor encodado (%27%20OR%201=1%FF)
+pub fn url_decode(input: &str) -> String {
+    let mut decoded = input.replace('+', " ");
+    for _ in 0..6 {
+        let bytes: Vec<u8> = percent_decode_str(&decoded).collect();
+        let d = lenient_utf8(&bytes);
+        if d == decoded {
+            break;
+        }
+        decoded = d;
+    }
+    decoded
+}
+
+// UTF-8 overlong (%c0%ae = '.', %e0%80%af = '/') vira o caractere que um
+// decoder leniente do backend enxergaria; o resto inválido vira U+FFFD
+fn lenient_utf8(bytes: &[u8]) -> String {
+    let continuation = |b: u8| b & 0xC0 == 0x80;
+    let mut folded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let overlong = match bytes[i..] {
+            [b0 @ (0xC0 | 0xC1), b1, ..] if continuation(b1) => {
+                Some((((b0 as u32 & 0x1F) << 6) | (b1 as u32 & 0x3F), 2))
+            }
+            [0xE0, b1 @ 0x80..=0x9F, b2, ..] if continuation(b2) => {
+                Some((((b1 as u32 & 0x3F) << 6) | (b2 as u32 & 0x3F), 3))
+            }
+            _ => None,
+        };
+        match overlong.and_then(|(cp, len)| char::from_u32(cp).map(|c| (c, len))) {
+            Some((c, len)) => {
+                let mut buf = [0u8; 4];
+                folded.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                i += len;
+            }
+            None => {
+                folded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&folded).into_owned()
+}
+
+// UTF-7 (RFC 2152): "+ADw-script+AD4-" é "<script>" para quem lê a página como
+// UTF-7. Trecho que não decodifica em UTF-16 válido fica como veio
+fn utf7_decode(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find('+') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        if let Some(tail) = after.strip_prefix('-') {
+            out.push('+');
+            rest = tail;
+            continue;
+        }
+        let end = after
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '+' || c == '/'))
+            .unwrap_or(after.len());
+        match utf7_run(&after[..end]) {
+            Some(decoded) => {
+                out.push_str(&decoded);
+                rest = after[end..].strip_prefix('-').unwrap_or(&after[end..]);
+            }
+            None => {
+                out.push('+');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+// Base64 modificado (sem padding) -> UTF-16BE
+fn utf7_run(run: &str) -> Option<String> {
+    let mut bits = 0u32;
+    let mut count = 0;
+    let mut units = Vec::new();
+    let mut pending = Vec::new();
+    for c in run.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            _ => 63,
+        };
+        bits = (bits << 6) | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            pending.push((bits >> count) as u8);
+            bits &= (1 << count) - 1;
+        }
+        if let [hi, lo] = pending[..] {
+            units.push(u16::from_be_bytes([hi, lo]));
+            pending.clear();
+        }
+    }
+    if units.is_empty() {
+        return None;
+    }
+    String::from_utf16(&units).ok()
+}
+
+pub fn apply_chain(chain: &[Transform], input: &str) -> String {
+    chain.iter().fold(input.to_string(), |acc, t| t.apply(&acc))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Uri,
+    Path,
+    QueryString,
+    Args(Option<String>),
+    ArgsNames,
+    Headers(Option<String>),
+    HeaderNames,
+    Cookies(Option<String>),
+    Body,
+    // Só na inspeção da resposta; no request não tem valor
+    ResponseBody,
+    Method,
+    Ja3,
+    Ja4,
+}
+
+impl Target {
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let (name, selector) = match raw.trim().split_once(':') {
+            Some((n, sel)) => (n, Some(sel.trim().to_string())),
+            None => (raw.trim(), None),
+        };
+        let target = match name {
+            "REQUEST_URI" | "REQUEST_URI_RAW" => Target::Uri,
+            "REQUEST_FILENAME" => Target::Path,
+            "QUERY_STRING" => Target::QueryString,
+            "ARGS" | "ARGS_GET" | "ARGS_POST" => Target::Args(selector.clone()),
+            "ARGS_NAMES" | "ARGS_GET_NAMES" | "ARGS_POST_NAMES" => Target::ArgsNames,
+            "REQUEST_HEADERS" => Target::Headers(selector.clone()),
+            "REQUEST_HEADERS_NAMES" => Target::HeaderNames,
+            "REQUEST_COOKIES" => Target::Cookies(selector.clone()),
+            "REQUEST_BODY" => Target::Body,
+            "RESPONSE_BODY" => Target::ResponseBody,
+            "REQUEST_METHOD" => Target::Method,
+            "TLS_JA3" => Target::Ja3,
+            "TLS_JA4" => Target::Ja4,
+            other => return Err(format!("unsupported variable '{}'", other)),
+        };
+        let takes_selector = matches!(
+            target,
+            Target::Args(_) | Target::Headers(_) | Target::Cookies(_)
+        );
+        if selector.is_some() && !takes_selector {
+            return Err(format!("variable '{}' does not take a selector", name));
+        }
+        Ok(target)
+    }
+
+    pub fn parse_list(list: &str) -> Result<Vec<Self>, String> {
+        list.split('|').map(Target::parse).collect()
+    }
+
+    // Valor `name` desta coleção cai em alguma exclusão (!ARGS:foo do SecLang);
+    // exclusão sem seletor tira a coleção inteira
+    pub fn excluded(&self, name: Option<&str>, exclusions: &[Target]) -> bool {
+        exclusions.iter().any(|ex| {
+            if std::mem::discriminant(ex) != std::mem::discriminant(self) {
+                return false;
+            }
+            match (ex, name) {
+                (Target::Args(Some(sel)) | Target::Cookies(Some(sel)), Some(name)) => sel == name,
+                (Target::Headers(Some(sel)), Some(name)) => sel.eq_ignore_ascii_case(name),
+                (
+                    Target::Args(Some(_)) | Target::Headers(Some(_)) | Target::Cookies(Some(_)),
+                    None,
+                ) => false,
+                _ => true,
+            }
+        })
+    }
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (name, selector) = match self {
+            Target::Uri => ("REQUEST_URI", None),
+            Target::Path => ("REQUEST_FILENAME", None),
+            Target::QueryString => ("QUERY_STRING", None),
+            Target::Args(sel) => ("ARGS", sel.as_ref()),
+            Target::ArgsNames => ("ARGS_NAMES", None),
+            Target::Headers(sel) => ("REQUEST_HEADERS", sel.as_ref()),
+            Target::HeaderNames => ("REQUEST_HEADERS_NAMES", None),
+            Target::Cookies(sel) => ("REQUEST_COOKIES", sel.as_ref()),
+            Target::Body => ("REQUEST_BODY", None),
+            Target::ResponseBody => ("RESPONSE_BODY", None),
+            Target::Method => ("REQUEST_METHOD", None),
+            Target::Ja3 => ("TLS_JA3", None),
+            Target::Ja4 => ("TLS_JA4", None),
+        };
+        match selector {
+            Some(sel) => write!(f, "{}:{}", name, sel),
+            None => write!(f, "{}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Operator {
+    Contains(String),
+    Regex(Regex),
+    PhraseMatch(Vec<String>),
+    Equals(String),
+    BeginsWith(String),
+    EndsWith(String),
+}
+
+impl Operator {
+    // O crate regex é tempo linear (sem backtracking): lookaround e backreferences
+    // nem compilam. Os limites abaixo seguram padrões que explodem o autômato.
+    pub fn regex(pattern: &str) -> Result<Self, String> {
+        RegexBuilder::new(pattern)
+            .size_limit(REGEX_SIZE_LIMIT)
+            .dfa_size_limit(REGEX_DFA_SIZE_LIMIT)
+            .nest_limit(REGEX_NEST_LIMIT)
+            .build()
+            .map(Operator::Regex)
+            .map_err(|e| match e {
+                regex::Error::CompiledTooBig(limit) => format!(
+                    "regex rejected: compiled program exceeds {} bytes (unbounded repetition?)",
+                    limit
+                ),
+                regex::Error::Syntax(msg) => format!(
+                    "regex rejected (only linear-time constructs are allowed): {}",
+                    msg.lines().last().unwrap_or(&msg)
+                ),
+                other => format!("regex rejected: {}", other),
+            })
+    }
+
+    pub fn matches(&self, value: &str) -> bool {
+        match self {
+            Operator::Contains(p) => value.contains(p.as_str()),
+            Operator::Regex(re) => re.is_match(value),
+            Operator::PhraseMatch(phrases) => {
+                let value = value.to_lowercase();
+                phrases.iter().any(|p| value.contains(p.as_str()))
+            }
+            Operator::Equals(p) => value == p,
+            Operator::BeginsWith(p) => value.starts_with(p.as_str()),
+            Operator::EndsWith(p) => value.ends_with(p.as_str()),
+        }
+    }
+
+    // Posição (bytes) do match; PhraseMatch conta sobre o valor em minúsculas
+    pub fn find(&self, value: &str) -> Option<(usize, usize)> {
+        match self {
+            Operator::Contains(p) => value.find(p.as_str()).map(|i| (i, i + p.len())),
+            Operator::Regex(re) => re.find(value).map(|m| (m.start(), m.end())),
+            Operator::PhraseMatch(phrases) => {
+                let value = value.to_lowercase();
+                phrases
+                    .iter()
+                    .filter_map(|p| value.find(p.as_str()).map(|i| (i, i + p.len())))
+                    .min()
+            }
+            Operator::Equals(p) => (value == p).then_some((0, value.len())),
+            Operator::BeginsWith(p) => value.starts_with(p.as_str()).then_some((0, p.len())),
+            Operator::EndsWith(p) => value
+                .ends_with(p.as_str())
+                .then_some((value.len() - p.len(), value.len())),
+        }
+    }
+}
+
+const REGEX_SIZE_LIMIT: usize = 8 * 1024 * 1024;
+const REGEX_DFA_SIZE_LIMIT: usize = 4 * 1024 * 1024;
+const REGEX_NEST_LIMIT: u32 = 100;
+
+#[derive(Debug, Deserialize)]
+struct RawRule {
+    id: u32,
+    category: Category,
+    // Vira o motivo do bloqueio ("<descrição> (id N)") no lugar do padrão
+    description: Option<String>,
+    severity: Option<Severity>,
+    // Pontos no modo anomaly scoring; sem isso, os da severity (5 sem severity)
+    score: Option<u32>,
+    // log = só registra o que teria bloqueado (vale mais que tag_actions)
+    action: Option<Action>,
+    // false = carregada e validada, mas fora do engine (em rules.d, desliga o ID anterior)
+    #[serde(default = "default_enabled")]
+    enabled: bool,
+    pattern: Option<String>,
+    regex: Option<String>,
+    #[serde(default = "default_transforms")]
+    transforms: String,
+    #[serde(default = "default_targets")]
+    targets: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    tests: RuleTests,
+}
+
+// Payloads no formato do corpus ("<uri>" ou "<METHOD> <uri> [body]"), rodados por
+// `oblivion rules test`: match = a regra tem que casar, pass = não pode casar
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RuleTests {
+    #[serde(default, rename = "match")]
+    pub matches: Vec<String>,
+    #[serde(default)]
+    pub pass: Vec<String>,
+}
+
+impl RuleTests {
+    pub fn is_empty(&self) -> bool {
+        self.matches.is_empty() && self.pass.is_empty()
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_transforms() -> String {
+    "urlDecode,lowercase".to_string()
+}
+
+fn default_targets() -> String {
+    "REQUEST_URI|REQUEST_BODY".to_string()
+}
+
+// Trecho em volta do match que vai para o evento; campos sensíveis saem mascarados
+#[derive(Debug, Clone, Deserialize)]
+pub struct MatchedDataConfig {
+    #[serde(default = "default_context")]
+    pub context: usize,
+    #[serde(default = "default_mask_fields")]
+    pub mask_fields: Vec<String>,
+}
+
+impl Default for MatchedDataConfig {
+    fn default() -> Self {
+        MatchedDataConfig {
+            context: default_context(),
+            mask_fields: default_mask_fields(),
+        }
+    }
+}
+
+fn default_context() -> usize {
+    24
+}
+
+fn default_mask_fields() -> Vec<String> {
+    [
+        "password",
+        "passwd",
+        "token",
+        "secret",
+        "authorization",
+        "cookie",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+impl RawRule {
+    fn build(self) -> Result<Rule, String> {
+        let id = self.id;
+        let transforms =
+            Transform::parse_chain(&self.transforms).map_err(|e| format!("rule {}: {}", id, e))?;
+        let targets =
+            Target::parse_list(&self.targets).map_err(|e| format!("rule {}: {}", id, e))?;
+        let (pattern, operator) = match (self.pattern, self.regex) {
+            (Some(p), None) => (p.clone(), Operator::Contains(p)),
+            (None, Some(re)) => (
+                re.clone(),
+                Operator::regex(&re).map_err(|e| format!("rule {}: {}", id, e))?,
+            ),
+            _ => {
+                return Err(format!(
+                    "rule {}: exactly one of 'pattern' or 'regex' is required",
+                    id
+                ));
+            }
+        };
+        Ok(Rule {
+            id,
+            category: self.category,
+            pattern,
+            operator,
+            targets,
+            exclusions: Vec::new(),
+            transforms,
+            msg: self.description,
+            severity: self.severity,
+            score: self.score,
+            action: self.action,
+            tags: self.tags,
+            tests: self.tests,
+        })
+    }
+}
+
+// Arquivo de rules.d: regras novas, substituição por ID e IDs desligados
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawFragment {
+    #[serde(default)]
+    rules: Vec<RawRule>,
+    #[serde(default)]
+    disable: Vec<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawRuleFile {
+    rules: Vec<RawRule>,
+    // Diretório (relativo a este arquivo) com fragmentos carregados em ordem léxica
+    include: Option<String>,
+    #[serde(default)]
+    routes: Vec<Route>,
+    #[serde(default)]
+    seclang: Vec<String>,
+    #[serde(default)]
+    tag_actions: HashMap<String, Action>,
+    #[serde(default)]
+    profiles: HashMap<String, Profile>,
+    #[serde(default)]
+    hosts: HashMap<String, String>,
+    default_profile: Option<String>,
+    #[serde(default)]
+    matched_data: MatchedDataConfig,
+    #[serde(default)]
+    suppress: Vec<Suppression>,
+    anomaly_scoring: Option<AnomalyScoring>,
+}
+
+// Modo CRS: regra de ação block soma pontos em vez de bloquear sozinha; o
+// request só cai quando a soma chega ao threshold (perfil pode trocar)
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AnomalyScoring {
+    pub threshold: u32,
+}
+
+// Falso positivo conhecido: a regra deixa de valer só para esse valor exato
+// (sha256 do valor já transformado, como sai no value_hash do bloqueio)
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Suppression {
+    rule: u32,
+    value_sha256: String,
+}
+
+pub fn value_hash(value: &str) -> String {
+    to_hex(&Sha256::digest(value.as_bytes()))
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub id: u32,
+    pub category: Category,
+    pub pattern: String,
+    pub operator: Operator,
+    pub targets: Vec<Target>,
+    // Campos tirados dos alvos (só vem do SecLang)
+    pub exclusions: Vec<Target>,
+    pub transforms: Vec<Transform>,
+    pub msg: Option<String>,
+    pub severity: Option<Severity>,
+    pub score: Option<u32>,
+    pub action: Option<Action>,
+    pub tags: Vec<String>,
+    pub tests: RuleTests,
+}
+
+impl Rule {
+    pub fn anomaly_score(&self) -> u32 {
+        self.score
+            .unwrap_or_else(|| self.severity.map_or(5, |s| s.anomaly_score()))
+    }
+
+    pub fn description(&self) -> String {
+        match &self.msg {
+            Some(msg) => format!("{} (id {})", msg, self.id),
+            None => format!("'{}'", self.pattern),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RuleSet {
+    pub rules: Vec<Rule>,
+    pub routes: Vec<Route>,
+    pub tag_actions: HashMap<String, Action>,
+    pub profiles: HashMap<String, Profile>,
+    pub hosts: HashMap<String, String>,
+    pub default_profile: Option<String>,
+    pub matched_data: MatchedDataConfig,
+    pub anomaly_scoring: Option<AnomalyScoring>,
+    pub suppressions: HashMap<u32, HashSet<String>>,
+}
+
+// rules.d/*.yaml em ordem léxica (10-base.yaml antes de 20-site.yaml): arquivo
+// posterior substitui a regra de mesmo ID no lugar ou desliga IDs anteriores
+fn load_fragments(dir: &Path, rules: &mut Vec<Rule>) -> Result<(), String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.is_file()
+                && matches!(
+                    path.extension().and_then(|e| e.to_str()),
+                    Some("yaml" | "yml" | "json")
+                )
+        })
+        .collect();
+    files.sort();
+
+    for path in files {
+        let source = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let fragment: RawFragment =
+            from_source(&path, &source).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut ids: Vec<u32> = Vec::with_capacity(fragment.rules.len());
+        let (mut added, mut replaced) = (0, 0);
+        let mut disabled = fragment.disable.len();
+        for raw in fragment.rules {
+            if ids.contains(&raw.id) {
+                return Err(format!("{}: duplicate rule id {}", path.display(), raw.id));
+            }
+            ids.push(raw.id);
+            let enabled = raw.enabled;
+            let rule = raw
+                .build()
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            if !enabled {
+                rules.retain(|r| r.id != rule.id);
+                disabled += 1;
+                continue;
+            }
+            match rules.iter_mut().find(|r| r.id == rule.id) {
+                Some(existing) => {
+                    *existing = rule;
+                    replaced += 1;
+                }
+                None => {
+                    rules.push(rule);
+                    added += 1;
+                }
+            }
+        }
+        for id in &fragment.disable {
+            let before = rules.len();
+            rules.retain(|r| r.id != *id);
+            // Regra que sumiu do arquivo base não impede o resto de carregar
+            if rules.len() == before {
+                warn!(file = %path.display(), id, "rules.d: disabled rule id not found");
+            }
+        }
+        info!(
+            file = %path.display(),
+            added,
+            replaced,
+            disabled,
+            "Rule fragment loaded"
+        );
+    }
+    Ok(())
+}
+
+impl RuleSet {
+    // action da própria regra, depois a primeira tag com override; sem nenhum a regra bloqueia
+    pub fn action_for(&self, rule: &Rule) -> Action {
+        rule.action
+            .or_else(|| {
+                rule.tags
+                    .iter()
+                    .find_map(|tag| self.tag_actions.get(tag).copied())
+            })
+            .unwrap_or(Action::Block)
+    }
+
+    // Rota > vhost > default_profile; sem nenhum, todas as regras valem
+    pub fn profile_for(&self, host: &str, route: Option<&Route>) -> Option<(&str, &Profile)> {
+        let name = route
+            .and_then(|r| r.profile.as_ref())
+            .or_else(|| self.hosts.get(host))
+            .or(self.default_profile.as_ref())?;
+        self.profiles
+            .get_key_value(name)
+            .map(|(k, v)| (k.as_str(), v))
+    }
+
+    pub fn parse(source: &str, base_dir: &Path) -> Result<Self, String> {
+        let raw: RawRuleFile = serde_yaml::from_str(source).map_err(|e| e.to_string())?;
+        RuleSet::build(raw, base_dir)
+    }
+
+    fn build(raw: RawRuleFile, base_dir: &Path) -> Result<Self, String> {
+        let mut rules = Vec::with_capacity(raw.rules.len());
+        let mut disabled = Vec::new();
+        for rule in raw.rules {
+            if !rule.enabled {
+                disabled.push(rule.id);
+            }
+            rules.push(rule.build()?);
+        }
+
+        for file in &raw.seclang {
+            let path = base_dir.join(file);
+            let source =
+                fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let translation = seclang::translate(&source, path.parent().unwrap_or(base_dir));
+            for skipped in &translation.skipped {
+                warn!(file = %path.display(), "SecLang: {}", skipped);
+            }
+            for weakened in &translation.weakened {
+                warn!(file = %path.display(), "SecLang rule weakened: {}", weakened);
+            }
+            for broadened in &translation.broadened {
+                warn!(file = %path.display(), "SecLang rule broadened: {}", broadened);
+            }
+            let imported = translation.rules.len();
+            rules.extend(translation.rules);
+            // SecRuleRemoveById vale para tudo carregado até aqui, como no ModSecurity
+            let before = rules.len();
+            rules.retain(|r| !translation.removed.iter().any(|ids| ids.contains(&r.id)));
+            info!(
+                file = %path.display(),
+                imported,
+                skipped = translation.skipped.len(),
+                weakened = translation.weakened.len(),
+                removed = before - rules.len(),
+                "SecLang rules imported"
+            );
+        }
+
+        for (i, rule) in rules.iter().enumerate() {
+            if rules[..i].iter().any(|r| r.id == rule.id) {
+                return Err(format!("duplicate rule id {}", rule.id));
+            }
+        }
+        rules.retain(|r| !disabled.contains(&r.id));
+        if let Some(dir) = &raw.include {
+            load_fragments(&base_dir.join(dir), &mut rules)?;
+        }
+
+        let hosts: HashMap<String, String> = raw
+            .hosts
+            .into_iter()
+            .map(|(host, profile)| (host.to_lowercase(), profile))
+            .collect();
+        let referenced = raw
+            .routes
+            .iter()
+            .filter_map(|r| r.profile.as_ref())
+            .chain(hosts.values())
+            .chain(raw.default_profile.iter());
+        for name in referenced {
+            if !raw.profiles.contains_key(name) {
+                return Err(format!("unknown profile '{}'", name));
+            }
+        }
+
+        if raw.anomaly_scoring.is_some_and(|a| a.threshold == 0) {
+            return Err("anomaly_scoring: threshold must be positive".to_string());
+        }
+        for (name, profile) in &raw.profiles {
+            if profile.anomaly_threshold == Some(0) {
+                return Err(format!(
+                    "profile '{}': anomaly_threshold must be positive",
+                    name
+                ));
+            }
+            if let Some(adaptive) = &profile.adaptive_rate {
+                adaptive
+                    .validate()
+                    .map_err(|e| format!("profile '{}': adaptive_rate: {}", name, e))?;
+            }
+        }
+
+        for route in &raw.routes {
+            route.timeouts.validate()?;
+            if !route.auth_principals.is_empty()
+                && route
+                    .auth
+                    .as_ref()
+                    .is_some_and(|a| a.contains(&Mechanism::SessionPresent))
+            {
+                return Err(
+                    "auth: session_present has no principal to match auth_principals".to_string(),
+                );
+            }
+            if route.streaming && route.coalesce {
+                return Err("streaming: cannot be combined with coalesce".to_string());
+            }
+            if let Some(policy) = &route.signed_url {
+                policy.validate()?;
+            }
+            if let Some(policy) = &route.redirect {
+                policy.validate()?;
+            }
+            for rewrite in &route.status_rewrites {
+                rewrite.validate()?;
+            }
+        }
+
+        let mut suppressions: HashMap<u32, HashSet<String>> = HashMap::new();
+        for s in raw.suppress {
+            if !rules.iter().any(|r| r.id == s.rule) {
+                return Err(format!("suppress: unknown rule id {}", s.rule));
+            }
+            let hash = s.value_sha256.to_ascii_lowercase();
+            if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(format!(
+                    "suppress: rule {}: value_sha256 must be 64 hex characters",
+                    s.rule
+                ));
+            }
+            suppressions.entry(s.rule).or_default().insert(hash);
+        }
+
+        Ok(RuleSet {
+            rules,
+            routes: raw.routes,
+            tag_actions: raw.tag_actions,
+            profiles: raw.profiles,
+            hosts,
+            default_profile: raw.default_profile,
+            matched_data: raw.matched_data,
+            anomaly_scoring: raw.anomaly_scoring,
+            suppressions,
+        })
+    }
+
+    pub fn load_or_default(path: &str) -> Result<Self, String> {
+        if !Path::new(path).exists() {
+            return RuleSet::parse(DEFAULT_RULES, Path::new("."));
+        }
+        RuleSet::load(path)
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let path = Path::new(path);
+        let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let base_dir = path.parent().unwrap_or(Path::new("."));
+        from_source(path, &source)
+            .and_then(|raw| RuleSet::build(raw, base_dir))
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+// Arquivo de regras em YAML ou, pela extensão .json, JSON (gerado por outra ferramenta)
+fn from_source<T: DeserializeOwned>(path: &Path, source: &str) -> Result<T, String> {
+    if path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("json"))
+    {
+        serde_json::from_str(source).map_err(|e| e.to_string())
+    } else {
+        serde_yaml::from_str(source).map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,448 @@
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use crate::rules::{Action, Category, Operator, Rule, RuleTests, Severity, Target, Transform};
+
+pub struct Translation {
+    pub rules: Vec<Rule>,
+    pub skipped: Vec<String>,
+    // Regras carregadas sem alguma transformação t: que não temos: rodam mais fracas
+    pub weakened: Vec<String>,
+    // Exclusões (!REQUEST_COOKIES:/regex/) que não aplicamos: a regra olha campos a mais
+    pub broadened: Vec<String>,
+    // SecRuleRemoveById: IDs (ou faixas "1-5") tirados do que já foi carregado
+    pub removed: Vec<RangeInclusive<u32>>,
+}
+
+// Junta linhas terminadas em `\` e descarta comentários
+fn directives(source: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut current = String::new();
+
+    for line in source.lines() {
+        let line = line.trim();
+        if current.is_empty() && (line.is_empty() || line.starts_with('#')) {
+            continue;
+        }
+        match line.strip_suffix('\\') {
+            Some(partial) => {
+                current.push_str(partial);
+                current.push(' ');
+            }
+            None => {
+                current.push_str(line);
+                out.push(std::mem::take(&mut current));
+            }
+        }
+    }
+    if !current.is_empty() {
+        out.push(current);
+    }
+    out
+}
+
+fn tokenize(directive: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut chars = directive.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut token = String::new();
+        if c == '"' {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' if chars.peek() == Some(&'"') => token.push(chars.next().unwrap()),
+                    '"' => break,
+                    c => token.push(c),
+                }
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                token.push(c);
+                chars.next();
+            }
+        }
+        tokens.push(token);
+    }
+    tokens
+}
+
+// Separa "id:1,msg:'a, b',t:none" respeitando aspas simples
+fn split_actions(actions: &str) -> Vec<(String, String)> {
+    let mut out = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+
+    for c in actions.chars().chain(std::iter::once(',')) {
+        match c {
+            '\'' => quoted = !quoted,
+            ',' if !quoted => {
+                let item = current.trim();
+                if !item.is_empty() {
+                    let (k, v) = item.split_once(':').unwrap_or((item, ""));
+                    out.push((k.trim().to_string(), v.trim().to_string()));
+                }
+                current.clear();
+            }
+            c => current.push(c),
+        }
+    }
+    out
+}
+
+fn transform(name: &str) -> Result<Option<Transform>, String> {
+    // Só equivalentes exatos; o resto volta None e a regra é marcada como enfraquecida
+    let t = match name {
+        "lowercase" => Transform::Lowercase,
+        "urlDecode" => Transform::UrlDecode,
+        "removeWhitespace" => Transform::RemoveWhitespace,
+        "compressWhitespace" => Transform::CompressWhitespace,
+        "removeComments" => Transform::RemoveComments,
+        "urlDecodeUni" | "replaceComments" | "removeCommentsChar" | "normalizePath"
+        | "normalisePath" | "normalizePathWin" | "normalisePathWin" | "htmlEntityDecode"
+        | "jsDecode" | "cssDecode" | "utf8toUnicode" | "removeNulls" | "replaceNulls"
+        | "escapeSeqDecode" | "cmdLine" | "trim" | "trimLeft" | "trimRight" => {
+            return Ok(None);
+        }
+        other => return Err(format!("unsupported transformation t:{}", other)),
+    };
+    Ok(Some(t))
+}
+
+// @pmFromFile: uma frase por linha (pode ter espaços), relativa ao arquivo .conf
+fn phrases_from_file(file: &str, base_dir: &Path) -> Result<Vec<String>, String> {
+    let path = base_dir.join(file);
+    let source = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(source
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_lowercase)
+        .collect())
+}
+
+fn operator(raw: &str, base_dir: &Path) -> Result<(String, Operator), String> {
+    if raw.starts_with('!') {
+        return Err("negated operators are not supported".to_string());
+    }
+    let (name, arg) = match raw.strip_prefix('@') {
+        Some(rest) => rest.split_once(' ').unwrap_or((rest, "")),
+        None => ("rx", raw),
+    };
+    let arg = arg.to_string();
+    let op = match name {
+        "rx" => Operator::regex(&arg)?,
+        "pm" => Operator::PhraseMatch(arg.split_whitespace().map(|p| p.to_lowercase()).collect()),
+        "pmFromFile" | "pmf" => Operator::PhraseMatch(phrases_from_file(&arg, base_dir)?),
+        "contains" => Operator::Contains(arg.clone()),
+        "streq" => Operator::Equals(arg.clone()),
+        "beginsWith" => Operator::BeginsWith(arg.clone()),
+        "endsWith" => Operator::EndsWith(arg.clone()),
+        other => return Err(format!("unsupported operator @{}", other)),
+    };
+    Ok((arg, op))
+}
+
+// (alvos, exclusões, exclusões que não dá para aplicar)
+type Variables = (Vec<Target>, Vec<Target>, Vec<String>);
+
+fn targets(raw: &str) -> Result<Variables, String> {
+    let mut out = Vec::new();
+    let mut exclusions = Vec::new();
+    let mut ignored = Vec::new();
+    for var in raw.split('|') {
+        let regex_selector = var
+            .split_once(':')
+            .is_some_and(|(_, sel)| sel.starts_with('/'));
+        // Exclusão que não entendemos deixa a regra mais ampla, nunca mais fraca
+        if let Some(excluded) = var.strip_prefix('!') {
+            match Target::parse(excluded) {
+                Ok(target) if !regex_selector => exclusions.push(target),
+                _ => ignored.push(var.to_string()),
+            }
+            continue;
+        }
+        if var.starts_with('&') {
+            return Err(format!("collection counts ({}) are not supported", var));
+        }
+        if regex_selector {
+            return Err(format!("regex selectors ({}) are not supported", var));
+        }
+        out.push(Target::parse(var)?);
+    }
+    if out.is_empty() {
+        return Err("no usable variables".to_string());
+    }
+    Ok((out, exclusions, ignored))
+}
+
+fn category(tags: &[String]) -> Category {
+    for tag in tags {
+        match tag.as_str() {
+            "attack-sqli" => return Category::Sqli,
+            "attack-xss" => return Category::Xss,
+            "attack-lfi" | "attack-rfi" => return Category::Traversal,
+            _ => {}
+        }
+    }
+    Category::Other
+}
+
+// Ok(regra, transformações que ficaram de fora, exclusões ignoradas)
+type Translated = (Rule, Vec<String>, Vec<String>);
+
+fn translate_rule(tokens: &[String], base_dir: &Path) -> Result<Translated, String> {
+    let vars = tokens.get(1).ok_or("missing variables")?;
+    let op = tokens.get(2).ok_or("missing operator")?;
+    let actions = split_actions(tokens.get(3).map(String::as_str).unwrap_or(""));
+
+    let mut id = None;
+    let mut msg = None;
+    let mut severity = None;
+    let mut tags = Vec::new();
+    let mut chain = Vec::new();
+    let mut missing = Vec::new();
+    let mut disruptive = false;
+
+    for (key, value) in &actions {
+        let value = value.trim_matches('\'');
+        match key.as_str() {
+            "id" => id = Some(value.parse::<u32>().map_err(|_| "invalid id")?),
+            "msg" => msg = Some(value.to_string()),
+            "tag" => tags.push(value.to_string()),
+            "severity" => {
+                severity = Some(Severity::parse(value).ok_or("invalid severity")?);
+            }
+            "phase" if matches!(value, "3" | "4" | "5" | "response" | "logging") => {
+                return Err("response phase rules are not supported".to_string());
+            }
+            "t" if value == "none" => {
+                chain.clear();
+                missing.clear();
+            }
+            "t" => match transform(value)? {
+                Some(t) => chain.push(t),
+                None => missing.push(value.to_string()),
+            },
+            // Viram regra de bloqueio: com anomaly_scoring somam pela severity,
+            // sem ele bloqueiam direto
+            "deny" | "block" | "drop" => disruptive = true,
+            "pass" | "allow" => return Err("non-disruptive rule".to_string()),
+            _ => {}
+        }
+    }
+
+    if !disruptive {
+        return Err("non-disruptive rule".to_string());
+    }
+    let id = id.ok_or("missing id")?;
+    let (targets, exclusions, ignored) = targets(vars).map_err(|e| format!("id {}: {}", id, e))?;
+    let (pattern, operator) = operator(op, base_dir).map_err(|e| format!("id {}: {}", id, e))?;
+
+    let rule = Rule {
+        id,
+        category: category(&tags),
+        pattern,
+        operator,
+        targets,
+        exclusions,
+        transforms: chain,
+        msg,
+        severity,
+        score: None,
+        action: None,
+        tags,
+        tests: RuleTests::default(),
+    };
+    Ok((rule, missing, ignored))
+}
+
+// "942100 942200-942299" -> faixas; token inválido vai para skipped
+fn removed_ids(tokens: &[String], skipped: &mut Vec<String>) -> Vec<RangeInclusive<u32>> {
+    let mut out = Vec::new();
+    for token in tokens.iter().flat_map(|t| t.split_whitespace()) {
+        let range = match token.split_once('-') {
+            Some((from, to)) => from.parse().ok().zip(to.parse().ok()),
+            None => token.parse().ok().map(|id| (id, id)),
+        };
+        match range {
+            Some((from, to)) if from <= to => out.push(from..=to),
+            _ => skipped.push(format!("SecRuleRemoveById: invalid id '{}'", token)),
+        }
+    }
+    out
+}
+
+// base_dir: diretório do .conf, de onde saem os arquivos de @pmFromFile
+pub fn translate(source: &str, base_dir: &Path) -> Translation {
+    let mut rules = Vec::new();
+    let mut skipped = Vec::new();
+    let mut weakened = Vec::new();
+    let mut broadened = Vec::new();
+    let mut removed = Vec::new();
+    let mut in_chain = false;
+    // SecRuleEngine DetectionOnly: as regras seguintes só registram
+    let mut detection_only = false;
+
+    for directive in directives(source) {
+        let tokens = tokenize(&directive);
+        let Some(name) = tokens.first() else {
+            continue;
+        };
+        if name == "SecRuleRemoveById" {
+            removed.extend(removed_ids(&tokens[1..], &mut skipped));
+            continue;
+        }
+        if name == "SecRuleEngine" {
+            match tokens.get(1).map(String::as_str) {
+                Some("DetectionOnly") => detection_only = true,
+                Some("On") => detection_only = false,
+                _ => skipped.push(format!("{}: unsupported engine mode", short(&directive))),
+            }
+            continue;
+        }
+        if name != "SecRule" {
+            continue;
+        }
+
+        let chained = tokens
+            .get(3)
+            .is_some_and(|a| split_actions(a).iter().any(|(k, _)| k == "chain"));
+        if in_chain || chained {
+            if !in_chain {
+                skipped.push(format!(
+                    "{}: chained rules are not supported",
+                    short(&directive)
+                ));
+            }
+            in_chain = chained;
+            continue;
+        }
+
+        match translate_rule(&tokens, base_dir) {
+            Ok((mut rule, missing, ignored)) => {
+                if !missing.is_empty() {
+                    weakened.push(format!(
+                        "id {}: runs without t:{}",
+                        rule.id,
+                        missing.join(", t:")
+                    ));
+                }
+                if !ignored.is_empty() {
+                    broadened.push(format!(
+                        "id {}: ignores exclusion {}",
+                        rule.id,
+                        ignored.join(", ")
+                    ));
+                }
+                if detection_only {
+                    rule.action = Some(Action::Log);
+                }
+                rules.push(rule)
+            }
+            Err(reason) => skipped.push(format!("{}: {}", short(&directive), reason)),
+        }
+    }
+
+    Translation {
+        rules,
+        skipped,
+        weakened,
+        broadened,
+        removed,
+    }
+}
+
+fn short(directive: &str) -> String {
+    directive.chars().take(60).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translated(directive: &str) -> Result<Translated, String> {
+        translate_rule(&tokenize(directive), Path::new("."))
+    }
+
+    #[test]
+    fn tokenize_keeps_quoted_arguments_whole() {
+        assert_eq!(
+            tokenize(r#"SecRule ARGS "@rx a\"b c" "id:1,msg:'x y'""#),
+            ["SecRule", "ARGS", r#"@rx a"b c"#, "id:1,msg:'x y'"]
+        );
+        assert_eq!(tokenize("  SecRuleEngine   On "), ["SecRuleEngine", "On"]);
+    }
+
+    #[test]
+    fn split_actions_respects_single_quotes() {
+        let actions = split_actions("id:942100, msg:'SQLi, libinjection', t:none,deny,,");
+        assert_eq!(
+            actions,
+            [
+                ("id".to_string(), "942100".to_string()),
+                ("msg".to_string(), "SQLi, libinjection".to_string()),
+                ("t".to_string(), "none".to_string()),
+                ("deny".to_string(), String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn translate_rule_maps_actions() {
+        let (rule, missing, ignored) = translated(
+            "SecRule ARGS|REQUEST_HEADERS:User-Agent \"@pm union select\" \
+             \"id:10,deny,severity:CRITICAL,tag:'attack-sqli',t:none,t:lowercase,t:htmlEntityDecode\"",
+        )
+        .unwrap();
+        assert_eq!(rule.id, 10);
+        assert_eq!(rule.category, Category::Sqli);
+        assert_eq!(
+            rule.targets,
+            [
+                Target::Args(None),
+                Target::Headers(Some("User-Agent".into()))
+            ]
+        );
+        assert_eq!(rule.transforms, [Transform::Lowercase]);
+        assert_eq!(missing, ["htmlEntityDecode"]);
+        assert!(ignored.is_empty());
+
+        assert!(translated(r#"SecRule ARGS "@rx a" "id:11,pass""#).is_err());
+        assert!(translated(r#"SecRule ARGS "@rx a" "deny""#).is_err());
+        assert!(translated(r#"SecRule &ARGS "@rx a" "id:12,deny""#).is_err());
+        assert!(translated(r#"SecRule ARGS:/^x/ "@rx a" "id:13,deny""#).is_err());
+    }
+
+    #[test]
+    fn exclusions_become_filters_or_are_reported() {
+        let (rule, _, ignored) = translated(
+            r#"SecRule ARGS|REQUEST_COOKIES|!ARGS:token|!REQUEST_COOKIES:/__utm/ "@rx a" "id:20,deny""#,
+        )
+        .unwrap();
+        assert_eq!(rule.targets, [Target::Args(None), Target::Cookies(None)]);
+        assert_eq!(rule.exclusions, [Target::Args(Some("token".into()))]);
+        assert_eq!(ignored, ["!REQUEST_COOKIES:/__utm/"]);
+
+        let translation = translate(
+            r#"SecRule ARGS|!REQUEST_COOKIES:/__utm/ "@rx a" "id:21,deny""#,
+            Path::new("."),
+        );
+        assert_eq!(translation.rules.len(), 1);
+        assert_eq!(
+            translation.broadened,
+            ["id 21: ignores exclusion !REQUEST_COOKIES:/__utm/"]
+        );
+
+        // Só exclusões: não sobra alvo
+        assert!(translated(r#"SecRule !ARGS:a "@rx a" "id:22,deny""#).is_err());
+    }
+}
@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::challenge::{from_hex, to_hex, unix_now};
+use crate::config::SessionConfig;
+use crate::error::Error;
+use crate::http::Request;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const ID_BYTES: usize = 16;
+const MIN_SECRET: usize = 32;
+
+pub struct Session {
+    pub id: String,
+    // Preenchido quando o cookie é novo: vai na resposta que o cliente receber
+    pub set_cookie: Option<String>,
+}
+
+pub struct Sessions {
+    secret: Vec<u8>,
+}
+
+impl Sessions {
+    pub fn new(config: &SessionConfig) -> Result<Arc<Self>, Error> {
+        let secret = match &config.secret_file {
+            Some(path) => {
+                let raw = std::fs::read(path).map_err(|e| {
+                    Error::Config(format!("session.secret_file '{}' ilegível: {}", path, e))
+                })?;
+                let secret = raw.trim_ascii().to_vec();
+                if secret.len() < MIN_SECRET {
+                    return Err(Error::Config(format!(
+                        "session.secret_file '{}': segredo curto demais (mínimo {} bytes)",
+                        path, MIN_SECRET
+                    )));
+                }
+                secret
+            }
+            None => {
+                let mut secret = vec![0u8; MIN_SECRET];
+                getrandom::getrandom(&mut secret)
+                    .expect("❌ Erro: sem fonte de entropia para o cookie de sessão");
+                secret
+            }
+        };
+        Ok(Arc::new(Sessions { secret }))
+    }
+
+    fn sign(&self, id: &str, issued: u64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.secret).expect("HMAC aceita qualquer chave");
+        mac.update(id.as_bytes());
+        mac.update(b"|");
+        mac.update(issued.to_string().as_bytes());
+        mac
+    }
+
+    // "<id>.<emitido em>.<hmac>"; expirado ou adulterado = cliente sem sessão
+    fn verify(&self, value: &str, config: &SessionConfig) -> Option<String> {
+        let mut parts = value.split('.');
+        let (Some(id), Some(issued), Some(tag), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return None;
+        };
+        let issued = issued.parse::<u64>().ok()?;
+        // Emissão absurda que estoura a soma também é sessão inválida
+        let expires = issued.checked_add(config.lifetime.as_secs())?;
+        if id.len() != ID_BYTES * 2 || expires < unix_now() {
+            return None;
+        }
+        let tag = from_hex(tag)?;
+        self.sign(id, issued).verify_slice(&tag).ok()?;
+        Some(id.to_string())
+    }
+
+    // secure = conexão TLS; em texto puro o navegador descartaria um cookie Secure
+    pub fn resolve(&self, req: &Request, config: &SessionConfig, secure: bool) -> Option<Session> {
+        if !config.enabled || config.opt_out.iter().any(|p| p.matches(req.path_only())) {
+            return None;
+        }
+        if config.respect_dnt
+            && (req.header("DNT") == Some("1") || req.header("Sec-GPC") == Some("1"))
+        {
+            return None;
+        }
+        if let Some(id) = req
+            .cookie(&config.cookie)
+            .and_then(|value| self.verify(value, config))
+        {
+            return Some(Session {
+                id,
+                set_cookie: None,
+            });
+        }
+
+        let mut raw = [0u8; ID_BYTES];
+        getrandom::getrandom(&mut raw).ok()?;
+        let id = to_hex(&raw);
+        let issued = unix_now();
+        let tag = self.sign(&id, issued).finalize().into_bytes();
+        let set_cookie = format!(
+            "{}={}.{}.{}; Path=/; Max-Age={}; HttpOnly{}; SameSite={}",
+            config.cookie,
+            id,
+            issued,
+            to_hex(&tag),
+            config.lifetime.as_secs(),
+            if secure { "; Secure" } else { "" },
+            config.same_site.label()
+        );
+        Some(Session {
+            id,
+            set_cookie: Some(set_cookie),
+        })
+    }
+}
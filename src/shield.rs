@@ -0,0 +1,59 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use tracing::warn;
+
+use crate::limiter::RateLimiter;
+use crate::upstream::Admission;
+
+pub struct Shield {
+    under_attack: AtomicBool,
+    strict_scale: f64,
+    limiters: Vec<Arc<RateLimiter>>,
+    admission: Arc<Admission>,
+}
+
+impl Shield {
+    pub fn new(
+        strict_scale: f64,
+        limiters: Vec<Arc<RateLimiter>>,
+        admission: Arc<Admission>,
+    ) -> Arc<Self> {
+        Arc::new(Shield {
+            under_attack: AtomicBool::new(false),
+            strict_scale,
+            limiters,
+            admission,
+        })
+    }
+
+    pub fn under_attack(&self) -> bool {
+        self.under_attack.load(Ordering::Acquire)
+    }
+
+    pub fn set_under_attack(&self, enabled: bool) {
+        if self.under_attack.swap(enabled, Ordering::AcqRel) == enabled {
+            return;
+        }
+
+        let scale = if enabled { self.strict_scale } else { 1.0 };
+        for limiter in &self.limiters {
+            limiter.set_scale(scale);
+        }
+        // Fila de upstream segura recursos; sob ataque é melhor falhar rápido
+        self.admission.set_queueing(!enabled);
+
+        if enabled {
+            warn!(
+                scale,
+                "🚨 Under attack mode ENABLED: challenging all new clients; response inspection, HTML injection, capture and mirror paused"
+            );
+        } else {
+            warn!("Under attack mode disabled");
+        }
+    }
+
+    pub fn toggle(&self) {
+        self.set_under_attack(!self.under_attack());
+    }
+}
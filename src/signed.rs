@@ -0,0 +1,132 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::challenge::from_hex;
+use crate::http::Request;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const MIN_SECRET_LENGTH: usize = 16;
+
+// Assinatura = hex(HMAC-SHA256(secret, "<path>?<query sem o parâmetro da assinatura>"))
+#[derive(Debug, Clone, Deserialize)]
+pub struct SignedUrlPolicy {
+    pub secret: String,
+    #[serde(default = "default_expires_param")]
+    pub expires_param: String,
+    #[serde(default = "default_signature_param")]
+    pub signature_param: String,
+}
+
+fn default_expires_param() -> String {
+    "expires".to_string()
+}
+
+fn default_signature_param() -> String {
+    "signature".to_string()
+}
+
+#[derive(Debug)]
+pub enum SignedUrlError {
+    MissingSignature,
+    MissingExpiry,
+    InvalidExpiry,
+    Expired,
+    InvalidSignature,
+}
+
+impl SignedUrlError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            SignedUrlError::MissingSignature => "missing_signature",
+            SignedUrlError::MissingExpiry => "missing_expiry",
+            SignedUrlError::InvalidExpiry => "invalid_expiry",
+            SignedUrlError::Expired => "link_expired",
+            SignedUrlError::InvalidSignature => "invalid_signature",
+        }
+    }
+
+    pub fn response(&self) -> Vec<u8> {
+        let body = format!("{{\"error\":\"{}\"}}", self.code());
+        format!(
+            "HTTP/1.1 403 Forbidden\r\nContent-Type: application/json\r\nX-Oblivion-Error: {}\r\nCache-Control: no-store\r\nContent-Length: {}\r\n\r\n{}",
+            self.code(),
+            body.len(),
+            body
+        )
+        .into_bytes()
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl SignedUrlPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.secret.len() < MIN_SECRET_LENGTH {
+            return Err(format!(
+                "signed_url: secret must be at least {} bytes",
+                MIN_SECRET_LENGTH
+            ));
+        }
+        if self.expires_param == self.signature_param {
+            return Err("signed_url: expires_param and signature_param must differ".to_string());
+        }
+        Ok(())
+    }
+
+    // Os pares são assinados crus, na ordem em que vieram: quem gera o link não precisa
+    // reproduzir a nossa decodificação
+    pub fn verify(&self, req: &Request) -> Result<(), SignedUrlError> {
+        let mut signature = None;
+        let mut expires = None;
+        let mut signed = Vec::new();
+        for pair in req
+            .query()
+            .unwrap_or("")
+            .split('&')
+            .filter(|p| !p.is_empty())
+        {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            if key == self.signature_param {
+                if signature.replace(value).is_some() {
+                    return Err(SignedUrlError::InvalidSignature);
+                }
+                continue;
+            }
+            if key == self.expires_param && expires.replace(value).is_some() {
+                return Err(SignedUrlError::InvalidExpiry);
+            }
+            signed.push(pair);
+        }
+
+        let signature = signature
+            .filter(|s| !s.is_empty())
+            .ok_or(SignedUrlError::MissingSignature)?;
+        let expires: u64 = expires
+            .ok_or(SignedUrlError::MissingExpiry)?
+            .parse()
+            .map_err(|_| SignedUrlError::InvalidExpiry)?;
+        if expires < unix_now() {
+            return Err(SignedUrlError::Expired);
+        }
+
+        let tag = from_hex(&signature.to_ascii_lowercase())
+            .filter(|t| !t.is_empty())
+            .ok_or(SignedUrlError::InvalidSignature)?;
+        let mut mac =
+            HmacSha256::new_from_slice(self.secret.as_bytes()).expect("HMAC aceita qualquer chave");
+        mac.update(req.path_only().as_bytes());
+        mac.update(b"?");
+        mac.update(signed.join("&").as_bytes());
+        mac.verify_slice(&tag)
+            .map_err(|_| SignedUrlError::InvalidSignature)
+    }
+}
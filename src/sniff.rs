@@ -0,0 +1,280 @@
+use serde::Deserialize;
+
+// O que fazer quando o Content-Type declarado não bate com o corpo
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentMismatch {
+    Off,
+    // Só log e métrica
+    Log,
+    // Inspeciona o corpo pelo tipo real (formulário disfarçado vira ARGS)
+    Inspect,
+    Block,
+}
+
+impl ContentMismatch {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ContentMismatch::Off => "off",
+            ContentMismatch::Log => "log",
+            ContentMismatch::Inspect => "inspect",
+            ContentMismatch::Block => "block",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Json,
+    Form,
+    Multipart,
+    Xml,
+    Html,
+    Script,
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+    Pdf,
+    Zip,
+    Executable,
+    Text,
+    Binary,
+}
+
+impl Kind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Kind::Json => "json",
+            Kind::Form => "form",
+            Kind::Multipart => "multipart",
+            Kind::Xml => "xml",
+            Kind::Html => "html",
+            Kind::Script => "script",
+            Kind::Png => "png",
+            Kind::Jpeg => "jpeg",
+            Kind::Gif => "gif",
+            Kind::Webp => "webp",
+            Kind::Pdf => "pdf",
+            Kind::Zip => "zip",
+            Kind::Executable => "executable",
+            Kind::Text => "text",
+            Kind::Binary => "binary",
+        }
+    }
+
+    fn image(&self) -> bool {
+        matches!(self, Kind::Png | Kind::Jpeg | Kind::Gif | Kind::Webp)
+    }
+}
+
+pub struct Mismatch {
+    pub declared: Kind,
+    pub detected: Kind,
+    // Parte do multipart (name/filename) onde apareceu; None = o corpo todo
+    pub part: Option<String>,
+}
+
+// Marcadores de código de servidor/navegador: num upload de imagem é webshell
+const SCRIPT_MARKERS: &[&[u8]] = &[b"<?php", b"<?=", b"<%", b"<script"];
+
+// Tipo que o Content-Type promete; None = sem expectativa (text/plain, octet-stream...)
+fn promised(content_type: &str) -> Option<Kind> {
+    let media = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let kind = match media.as_str() {
+        "application/json" => Kind::Json,
+        "application/x-www-form-urlencoded" => Kind::Form,
+        "multipart/form-data" | "multipart/mixed" => Kind::Multipart,
+        "application/xml" | "text/xml" => Kind::Xml,
+        "image/png" => Kind::Png,
+        "image/jpeg" | "image/jpg" | "image/pjpeg" => Kind::Jpeg,
+        "image/gif" => Kind::Gif,
+        "image/webp" => Kind::Webp,
+        "application/pdf" => Kind::Pdf,
+        "application/zip" | "application/x-zip-compressed" => Kind::Zip,
+        m if m.ends_with("+json") => Kind::Json,
+        m if m.ends_with("+xml") => Kind::Xml,
+        _ => return None,
+    };
+    Some(kind)
+}
+
+fn starts_with_ci(data: &[u8], prefix: &[u8]) -> bool {
+    data.len() >= prefix.len() && data[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn has_script(data: &[u8]) -> bool {
+    SCRIPT_MARKERS.iter().any(|marker| {
+        data.windows(marker.len())
+            .any(|w| w.eq_ignore_ascii_case(marker))
+    })
+}
+
+// k=v&k2=v2 sem espaço nem controle, chaves com cara de nome de campo
+fn looks_like_form(data: &[u8]) -> bool {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return false;
+    };
+    let text = text.trim_end_matches(['\r', '\n']);
+    text.contains('=')
+        && text.split('&').all(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            !key.is_empty()
+                && key
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"_.-[]%+".contains(&b))
+                && value.bytes().all(|b| b.is_ascii_graphic())
+        })
+}
+
+// Pelos bytes: assinaturas de arquivo primeiro, depois a forma do texto
+pub fn sniff(body: &[u8]) -> Kind {
+    let magic: &[(&[u8], Kind)] = &[
+        (b"\x89PNG\r\n\x1a\n", Kind::Png),
+        (b"\xff\xd8\xff", Kind::Jpeg),
+        (b"GIF87a", Kind::Gif),
+        (b"GIF89a", Kind::Gif),
+        (b"%PDF-", Kind::Pdf),
+        (b"PK\x03\x04", Kind::Zip),
+        (b"\x7fELF", Kind::Executable),
+        (b"MZ", Kind::Executable),
+    ];
+    if let Some((_, kind)) = magic.iter().find(|(m, _)| body.starts_with(m)) {
+        return *kind;
+    }
+    if body.len() >= 12 && body.starts_with(b"RIFF") && &body[8..12] == b"WEBP" {
+        return Kind::Webp;
+    }
+
+    let text = body.trim_ascii_start();
+    if starts_with_ci(text, b"<?php") || starts_with_ci(text, b"<?=") || text.starts_with(b"<%") {
+        return Kind::Script;
+    }
+    if starts_with_ci(text, b"<!doctype html") || starts_with_ci(text, b"<html") {
+        return Kind::Html;
+    }
+    if starts_with_ci(text, b"<script") {
+        return Kind::Script;
+    }
+    if text.starts_with(b"<?xml") || text.starts_with(b"<") {
+        return Kind::Xml;
+    }
+    if text.starts_with(b"{") || text.starts_with(b"[") {
+        return Kind::Json;
+    }
+    if body.starts_with(b"--") && find(body, b"\r\n").is_some_and(|end| end > 2) {
+        return Kind::Multipart;
+    }
+    if looks_like_form(body) {
+        return Kind::Form;
+    }
+    match std::str::from_utf8(body) {
+        Ok(text) if !text.chars().any(|c| c.is_control() && !c.is_whitespace()) => Kind::Text,
+        _ => Kind::Binary,
+    }
+}
+
+// O corpo (ou a parte) é o que foi declarado? Imagem com código embutido
+// (GIF89a<?php ...) não é imagem
+fn consistent(declared: Kind, body: &[u8]) -> Result<(), Kind> {
+    let detected = sniff(body);
+    let ok = match declared {
+        Kind::Json => detected == Kind::Json,
+        Kind::Form => matches!(detected, Kind::Form | Kind::Text),
+        Kind::Xml => matches!(detected, Kind::Xml | Kind::Html),
+        kind if kind.image() => detected == kind && !has_script(body),
+        kind => detected == kind,
+    };
+    if ok {
+        return Ok(());
+    }
+    if detected.image() && has_script(body) {
+        return Err(Kind::Script);
+    }
+    Err(detected)
+}
+
+fn param<'a>(content_type: &'a str, name: &str) -> Option<&'a str> {
+    content_type.split(';').skip(1).find_map(|p| {
+        let (key, value) = p.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim().trim_matches('"'))
+    })
+}
+
+// (cabeçalhos, conteúdo) de cada parte; None se o corpo não usa o boundary declarado
+fn parts<'a>(body: &'a [u8], boundary: &str) -> Option<Vec<(&'a [u8], &'a [u8])>> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let mut rest = &body[find(body, &delimiter)? + delimiter.len()..];
+    let mut parts = Vec::new();
+    while !rest.starts_with(b"--") {
+        rest = rest.strip_prefix(b"\r\n")?;
+        let head_end = find(rest, b"\r\n\r\n")?;
+        let content = &rest[head_end + 4..];
+        let mut closing = b"\r\n".to_vec();
+        closing.extend_from_slice(&delimiter);
+        let end = find(content, &closing)?;
+        parts.push((&rest[..head_end], &content[..end]));
+        rest = &content[end + closing.len()..];
+    }
+    Some(parts)
+}
+
+fn part_header<'a>(head: &'a [u8], name: &str) -> Option<&'a str> {
+    std::str::from_utf8(head)
+        .ok()?
+        .split("\r\n")
+        .find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.trim()
+                .eq_ignore_ascii_case(name)
+                .then_some(value.trim())
+        })
+}
+
+pub fn check(content_type: Option<&str>, body: &[u8]) -> Option<Mismatch> {
+    if body.is_empty() {
+        return None;
+    }
+    let content_type = content_type?;
+    let declared = promised(content_type)?;
+    if let Err(detected) = consistent(declared, body) {
+        return Some(Mismatch {
+            declared,
+            detected,
+            part: None,
+        });
+    }
+    if declared != Kind::Multipart {
+        return None;
+    }
+    let Some(parts) = param(content_type, "boundary").and_then(|b| parts(body, b)) else {
+        return Some(Mismatch {
+            declared,
+            detected: sniff(body),
+            part: None,
+        });
+    };
+    parts.into_iter().find_map(|(head, content)| {
+        let declared = part_header(head, "Content-Type").and_then(promised)?;
+        let detected = consistent(declared, content).err()?;
+        let disposition = part_header(head, "Content-Disposition").unwrap_or_default();
+        Some(Mismatch {
+            declared,
+            detected,
+            part: param(disposition, "filename")
+                .or_else(|| param(disposition, "name"))
+                .map(str::to_string),
+        })
+    })
+}
@@ -0,0 +1,297 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::challenge::unix_now;
+use crate::config::Config;
+use crate::http::Request;
+use crate::metrics::Metrics;
+use crate::routes::PathPattern;
+use crate::rules::{url_decode, Operator};
+
+// Regra de incidente não vira permanente por esquecimento
+pub const MAX_TTL: Duration = Duration::from_secs(7 * 24 * 3600);
+const MAX_RULES: usize = 256;
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+// Bloqueio pontual empurrado pelo admin durante um incidente: path (sintaxe de
+// rota) e, opcionalmente, um parâmetro (query ou form) cujo valor casa com regex
+pub struct TempRule {
+    pub id: u64,
+    pub path: PathPattern,
+    pub param: Option<String>,
+    pub regex: Option<String>,
+    operator: Option<Operator>,
+    pub author: String,
+    pub reason: String,
+    pub expires_at: u64,
+}
+
+impl TempRule {
+    fn matches(&self, path: &str, params: &[(String, String)]) -> bool {
+        if !self.path.matches(path) {
+            return false;
+        }
+        let Some(param) = &self.param else {
+            return true;
+        };
+        params
+            .iter()
+            .filter(|(k, _)| k == param)
+            .any(|(_, v)| self.operator.as_ref().is_none_or(|op| op.matches(v)))
+    }
+}
+
+// Uma linha JSON por mudança: quem, o quê, quando. Também é de onde as regras
+// ainda válidas voltam depois de um restart
+#[derive(Serialize, Deserialize)]
+struct AuditRecord {
+    ts: u64,
+    action: String,
+    id: u64,
+    author: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    admin: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    param: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    regex: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+}
+
+pub struct NewRule {
+    pub path: String,
+    pub param: Option<String>,
+    pub regex: Option<String>,
+    pub ttl: Duration,
+    pub author: String,
+    pub reason: String,
+}
+
+pub struct TempRules {
+    config: Arc<ArcSwap<Config>>,
+    // Leitura sem lock no caminho do request; escrita (admin, expiração) serializa em changes
+    rules: ArcSwap<Vec<Arc<TempRule>>>,
+    changes: Mutex<()>,
+    next_id: AtomicU64,
+    metrics: Arc<Metrics>,
+}
+
+impl TempRules {
+    pub fn new(config: Arc<ArcSwap<Config>>, metrics: Arc<Metrics>) -> Arc<Self> {
+        let temp = Arc::new(TempRules {
+            config,
+            rules: ArcSwap::from_pointee(Vec::new()),
+            changes: Mutex::new(()),
+            next_id: AtomicU64::new(1),
+            metrics,
+        });
+        temp.restore();
+        tokio::spawn(temp.clone().sweep());
+        temp
+    }
+
+    pub fn check(&self, req: &Request) -> Option<(u64, String)> {
+        let rules = self.rules.load();
+        if rules.is_empty() {
+            return None;
+        }
+        let now = unix_now();
+        let path = url_decode(req.path_only());
+        let params = req.params();
+        let rule = rules
+            .iter()
+            .find(|r| r.expires_at > now && r.matches(&path, &params))?;
+        self.metrics.inc("oblivion_temp_rule_matches_total", &[]);
+        Some((rule.id, rule.reason.clone()))
+    }
+
+    pub fn list(&self) -> Vec<Arc<TempRule>> {
+        let now = unix_now();
+        self.rules
+            .load()
+            .iter()
+            .filter(|r| r.expires_at > now)
+            .cloned()
+            .collect()
+    }
+
+    pub fn add(&self, new: NewRule, admin: SocketAddr) -> Result<Arc<TempRule>, String> {
+        let path = PathPattern::try_from(new.path)?;
+        let operator = new.regex.as_deref().map(Operator::regex).transpose()?;
+        if operator.is_some() && new.param.is_none() {
+            return Err("regex needs a param".to_string());
+        }
+        let _changes = self.changes.lock().unwrap();
+        if self.rules.load().len() >= MAX_RULES {
+            return Err(format!("at most {} temporary rules", MAX_RULES));
+        }
+        let rule = Arc::new(TempRule {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            path,
+            param: new.param,
+            regex: new.regex,
+            operator,
+            author: new.author,
+            reason: new.reason,
+            expires_at: unix_now() + new.ttl.as_secs(),
+        });
+        // Sem trilha gravada a regra não entra
+        self.audit(&AuditRecord {
+            ts: unix_now(),
+            action: "add".to_string(),
+            id: rule.id,
+            author: rule.author.clone(),
+            admin: Some(admin.to_string()),
+            path: Some(rule.path.to_string()),
+            param: rule.param.clone(),
+            regex: rule.regex.clone(),
+            reason: Some(rule.reason.clone()),
+            expires_at: Some(rule.expires_at),
+        })?;
+        let mut rules = self.rules.load().as_ref().clone();
+        rules.push(rule.clone());
+        self.rules.store(Arc::new(rules));
+        info!(id = rule.id, author = %rule.author, path = %rule.path, ttl = new.ttl.as_secs(), "Temporary rule added");
+        self.metrics
+            .inc("oblivion_temp_rules_total", &[("action", "add")]);
+        Ok(rule)
+    }
+
+    pub fn remove(&self, id: u64, author: &str, admin: SocketAddr) -> Result<bool, String> {
+        let _changes = self.changes.lock().unwrap();
+        let current = self.rules.load();
+        if !current.iter().any(|r| r.id == id) {
+            return Ok(false);
+        }
+        self.audit(&AuditRecord {
+            ts: unix_now(),
+            action: "remove".to_string(),
+            id,
+            author: author.to_string(),
+            admin: Some(admin.to_string()),
+            path: None,
+            param: None,
+            regex: None,
+            reason: None,
+            expires_at: None,
+        })?;
+        let rules = current.iter().filter(|r| r.id != id).cloned().collect();
+        self.rules.store(Arc::new(rules));
+        info!(id, author, "Temporary rule removed");
+        self.metrics
+            .inc("oblivion_temp_rules_total", &[("action", "remove")]);
+        Ok(true)
+    }
+
+    async fn sweep(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let _changes = self.changes.lock().unwrap();
+            let now = unix_now();
+            let current = self.rules.load();
+            let (expired, kept): (Vec<_>, Vec<_>) =
+                current.iter().cloned().partition(|r| r.expires_at <= now);
+            if expired.is_empty() {
+                continue;
+            }
+            for rule in &expired {
+                self.metrics
+                    .inc("oblivion_temp_rules_total", &[("action", "expire")]);
+                info!(id = rule.id, author = %rule.author, "Temporary rule expired");
+                let record = AuditRecord {
+                    ts: now,
+                    action: "expire".to_string(),
+                    id: rule.id,
+                    author: "oblivion".to_string(),
+                    admin: None,
+                    path: None,
+                    param: None,
+                    regex: None,
+                    reason: None,
+                    expires_at: None,
+                };
+                if let Err(e) = self.audit(&record) {
+                    warn!(error = %e, "Temporary rule audit write failed");
+                }
+            }
+            self.rules.store(Arc::new(kept));
+        }
+    }
+
+    fn audit(&self, record: &AuditRecord) -> Result<(), String> {
+        let path = self.config.load().files.temp_rules_audit.clone();
+        let line = serde_json::to_string(record).map_err(|e| e.to_string())? + "\n";
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .map_err(|e| format!("{}: {}", path, e))
+    }
+
+    // Replay da trilha: add sem remove/expire posterior e ainda no prazo volta a valer
+    fn restore(&self) {
+        let path = self.config.load().files.temp_rules_audit.clone();
+        let Ok(source) = fs::read_to_string(&path) else {
+            return;
+        };
+        let now = unix_now();
+        let mut rules: Vec<Arc<TempRule>> = Vec::new();
+        let mut last_id = 0;
+        for line in source.lines().filter(|l| !l.trim().is_empty()) {
+            let Ok(record) = serde_json::from_str::<AuditRecord>(line) else {
+                warn!(file = %path, "Unreadable temporary rule audit line skipped");
+                continue;
+            };
+            last_id = last_id.max(record.id);
+            if record.action != "add" {
+                rules.retain(|r| r.id != record.id);
+                continue;
+            }
+            let rule = record
+                .path
+                .clone()
+                .ok_or("missing path".to_string())
+                .and_then(PathPattern::try_from)
+                .and_then(|path| {
+                    let operator = record.regex.as_deref().map(Operator::regex).transpose()?;
+                    Ok(TempRule {
+                        id: record.id,
+                        path,
+                        param: record.param,
+                        regex: record.regex,
+                        operator,
+                        author: record.author,
+                        reason: record.reason.unwrap_or_default(),
+                        expires_at: record.expires_at.unwrap_or(0),
+                    })
+                });
+            match rule {
+                Ok(rule) => rules.push(Arc::new(rule)),
+                Err(e) => {
+                    warn!(file = %path, id = record.id, error = %e, "Temporary rule not restored")
+                }
+            }
+        }
+        rules.retain(|r| r.expires_at > now);
+        if !rules.is_empty() {
+            info!(count = rules.len(), file = %path, "Temporary rules restored");
+        }
+        self.next_id.store(last_id + 1, Ordering::Relaxed);
+        self.rules.store(Arc::new(rules));
+    }
+}
@@ -0,0 +1,224 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::timeout;
+use tracing::debug;
+
+use crate::config::UpstreamConfig;
+use crate::error::Error;
+use crate::metrics::Metrics;
+
+#[derive(Debug)]
+pub enum AdmissionError {
+    QueueFull,
+    QueueTimeout,
+}
+
+impl std::fmt::Display for AdmissionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdmissionError::QueueFull => write!(f, "upstream queue full"),
+            AdmissionError::QueueTimeout => write!(f, "timed out waiting for upstream slot"),
+        }
+    }
+}
+
+pub struct Admission {
+    slots: Arc<Semaphore>,
+    waiting: AtomicUsize,
+    queue_depth: usize,
+    queue_timeout: Duration,
+    queueing: AtomicBool,
+}
+
+impl Admission {
+    pub fn new(max_connections: usize, queue_depth: usize, queue_timeout: Duration) -> Arc<Self> {
+        Arc::new(Admission {
+            slots: Arc::new(Semaphore::new(max_connections)),
+            waiting: AtomicUsize::new(0),
+            queue_depth,
+            queue_timeout,
+            queueing: AtomicBool::new(true),
+        })
+    }
+
+    pub async fn acquire(&self) -> Result<OwnedSemaphorePermit, AdmissionError> {
+        if let Ok(permit) = self.slots.clone().try_acquire_owned() {
+            return Ok(permit);
+        }
+
+        // Fila desabilitada (depth 0) ou cheia: rejeita na hora
+        if !self.queueing.load(Ordering::Acquire) {
+            return Err(AdmissionError::QueueFull);
+        }
+        let position = self.waiting.fetch_add(1, Ordering::AcqRel);
+        if position >= self.queue_depth {
+            self.waiting.fetch_sub(1, Ordering::AcqRel);
+            return Err(AdmissionError::QueueFull);
+        }
+
+        let result = timeout(self.queue_timeout, self.slots.clone().acquire_owned()).await;
+        self.waiting.fetch_sub(1, Ordering::AcqRel);
+
+        match result {
+            Ok(Ok(permit)) => Ok(permit),
+            _ => Err(AdmissionError::QueueTimeout),
+        }
+    }
+
+    pub fn set_queueing(&self, enabled: bool) {
+        self.queueing.store(enabled, Ordering::Release);
+    }
+
+    pub fn queued(&self) -> usize {
+        self.waiting.load(Ordering::Acquire)
+    }
+}
+
+// Falhas do upstream por tipo: cada uma vira oblivion_upstream_errors_total{kind}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    ConnectRefused,
+    ConnectTimeout,
+    // Outros erros de connect: rede, DNS, TLS, handshake h2c
+    Connect,
+    // Conexão caiu depois do request mandado (no meio do corpo ou da resposta)
+    Reset,
+    FirstByteTimeout,
+    TotalTimeout,
+    // Resposta sem status line/head HTTP válido
+    Malformed,
+}
+
+impl Failure {
+    pub fn of(e: &Error) -> Failure {
+        match e {
+            Error::UpstreamRefused(_) => Failure::ConnectRefused,
+            Error::UpstreamTimeout(_) => Failure::ConnectTimeout,
+            _ => Failure::Connect,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Failure::ConnectRefused => "connect_refused",
+            Failure::ConnectTimeout => "connect_timeout",
+            Failure::Connect => "connect",
+            Failure::Reset => "reset",
+            Failure::FirstByteTimeout => "first_byte_timeout",
+            Failure::TotalTimeout => "total_timeout",
+            Failure::Malformed => "malformed",
+        }
+    }
+
+    pub fn record(self, metrics: &Metrics) {
+        metrics.inc("oblivion_upstream_errors_total", &[("kind", self.label())]);
+    }
+
+    // Só antes de mandar qualquer byte repetir é seguro para qualquer método;
+    // timeout de connect não repete: dobraria a espera justo com o backend sobrecarregado
+    fn retryable(&self) -> bool {
+        matches!(self, Failure::ConnectRefused | Failure::Connect)
+    }
+}
+
+// Janela fixa do orçamento: requests e retries zeram a cada uma
+const BUDGET_WINDOW: Duration = Duration::from_secs(10);
+const RETRY_BACKOFF: Duration = Duration::from_millis(50);
+
+#[derive(Default)]
+struct Window {
+    start: Option<Instant>,
+    requests: u64,
+    retries: u64,
+}
+
+// Orçamento global de retries: na janela, no máximo retry_budget × requests
+// (ou retry_min). Num blip sobra orçamento; num backend fora de verdade os
+// retries acabam em vez de multiplicar a carga
+pub struct RetryBudget {
+    window: Mutex<Window>,
+}
+
+impl RetryBudget {
+    pub fn new() -> Arc<Self> {
+        Arc::new(RetryBudget {
+            window: Mutex::new(Window::default()),
+        })
+    }
+
+    fn current(&self) -> std::sync::MutexGuard<'_, Window> {
+        let mut window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        if window
+            .start
+            .is_none_or(|start| now - start >= BUDGET_WINDOW)
+        {
+            *window = Window {
+                start: Some(now),
+                ..Window::default()
+            };
+        }
+        window
+    }
+
+    fn deposit(&self) {
+        self.current().requests += 1;
+    }
+
+    fn withdraw(&self, config: &UpstreamConfig) -> bool {
+        let mut window = self.current();
+        let allowed = ((window.requests as f64 * config.retry_budget) as u64)
+            .max(u64::from(config.retry_min));
+        if window.retries >= allowed {
+            return false;
+        }
+        window.retries += 1;
+        true
+    }
+}
+
+// Connect (TCP, TLS, h2c) com até upstream.retries novas tentativas, cada uma
+// paga pelo orçamento; toda falha é contada pelo tipo
+pub async fn connect_retrying<T, F, Fut>(
+    config: &UpstreamConfig,
+    budget: &RetryBudget,
+    metrics: &Metrics,
+    mut connect: F,
+) -> Result<T, Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    budget.deposit();
+    let mut attempt = 0;
+    loop {
+        let e = match connect().await {
+            Ok(connected) => return Ok(connected),
+            Err(e) => e,
+        };
+        let failure = Failure::of(&e);
+        failure.record(metrics);
+        if !failure.retryable() || attempt >= config.retries {
+            return Err(e);
+        }
+        if !budget.withdraw(config) {
+            debug!(kind = failure.label(), "Upstream retry budget exhausted");
+            metrics.inc(
+                "oblivion_upstream_retries_total",
+                &[("outcome", "budget_exhausted")],
+            );
+            return Err(e);
+        }
+        metrics.inc(
+            "oblivion_upstream_retries_total",
+            &[("outcome", "attempted")],
+        );
+        debug!(attempt, kind = failure.label(), error = %e, "Retrying upstream connect");
+        tokio::time::sleep(RETRY_BACKOFF * 2u32.pow(attempt)).await;
+        attempt += 1;
+    }
+}
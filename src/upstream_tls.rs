@@ -0,0 +1,197 @@
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::{self, Certificate, OwnedTrustAnchor, RootCertStore, ServerName};
+use tokio_rustls::TlsConnector;
+
+use crate::challenge::to_hex;
+use crate::config::{UpstreamConfig, UpstreamProtocol};
+use crate::error::Error;
+
+// upstream "https://host:porta": o tráfego decifrado volta a ser cifrado até o
+// backend, validado pela CA (bundle próprio ou as raízes públicas) e, com
+// tls_pins, também pelo hash do certificado
+pub struct UpstreamTls {
+    connector: TlsConnector,
+    server_name: ServerName,
+    // sha256 do certificado (DER) do backend; vazio = só a cadeia vale
+    pins: Vec<Vec<u8>>,
+}
+
+impl fmt::Debug for UpstreamTls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UpstreamTls")
+            .field("server_name", &self.server_name)
+            .field("pins", &self.pins.len())
+            .finish()
+    }
+}
+
+// "AB:CD:..." ou "abcd..." -> 32 bytes
+fn parse_pin(pin: &str) -> Option<Vec<u8>> {
+    let hex: String = pin.chars().filter(|c| *c != ':').collect();
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    (0..64)
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn roots(ca: Option<&str>) -> Result<RootCertStore, String> {
+    let mut roots = RootCertStore::empty();
+    let Some(path) = ca else {
+        roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                ta.subject,
+                ta.spki,
+                ta.name_constraints,
+            )
+        }));
+        return Ok(roots);
+    };
+    let file = File::open(path).map_err(|e| format!("upstream.tls_ca: {}: {}", path, e))?;
+    for der in rustls_pemfile::certs(&mut BufReader::new(file))
+        .map_err(|e| format!("upstream.tls_ca: {}: {}", path, e))?
+    {
+        roots
+            .add(&Certificate(der))
+            .map_err(|e| format!("upstream.tls_ca: {}: {}", path, e))?;
+    }
+    if roots.is_empty() {
+        return Err(format!("upstream.tls_ca: no certificate in {}", path));
+    }
+    Ok(roots)
+}
+
+impl UpstreamTls {
+    // None = upstream em texto puro
+    pub fn new(config: &UpstreamConfig) -> Result<Option<Arc<Self>>, String> {
+        if !config.tls() {
+            return Ok(None);
+        }
+        let mut client = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots(config.tls_ca.as_deref())?)
+            .with_no_client_auth();
+        // h2c sobre TLS é HTTP/2 normal, negociado por ALPN
+        client.alpn_protocols = match config.protocol {
+            UpstreamProtocol::Http1 => vec![b"http/1.1".to_vec()],
+            UpstreamProtocol::H2c => vec![b"h2".to_vec()],
+        };
+        let name = match &config.tls_server_name {
+            Some(name) => name.as_str(),
+            None => config.host(),
+        };
+        let server_name = ServerName::try_from(name)
+            .map_err(|_| format!("upstream.tls_server_name: '{}' is not a valid name", name))?;
+        let pins = config
+            .tls_pins
+            .iter()
+            .map(|pin| {
+                parse_pin(pin).ok_or_else(|| {
+                    format!("upstream.tls_pins: '{}' is not a sha256 fingerprint", pin)
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Some(Arc::new(UpstreamTls {
+            connector: TlsConnector::from(Arc::new(client)),
+            server_name,
+            pins,
+        })))
+    }
+
+    pub async fn handshake(&self, tcp: TcpStream, addr: &str) -> Result<UpstreamStream, Error> {
+        let stream = self
+            .connector
+            .connect(self.server_name.clone(), tcp)
+            .await
+            .map_err(|e| Error::Upstream(format!("{}: TLS: {}", addr, e)))?;
+        if !self.pins.is_empty() {
+            let digest = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .map(|leaf| Sha256::digest(&leaf.0).to_vec());
+            if !digest.as_ref().is_some_and(|d| self.pins.contains(d)) {
+                return Err(Error::Upstream(format!(
+                    "{}: certificate {} not in tls_pins",
+                    addr,
+                    digest.map_or("-".to_string(), |d| to_hex(&d))
+                )));
+            }
+        }
+        Ok(UpstreamStream::Tls(Box::new(stream)))
+    }
+}
+
+pub enum UpstreamStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl UpstreamStream {
+    pub fn tcp(&self) -> &TcpStream {
+        match self {
+            UpstreamStream::Plain(tcp) => tcp,
+            UpstreamStream::Tls(tls) => tls.get_ref().0,
+        }
+    }
+}
+
+impl AsyncRead for UpstreamStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            UpstreamStream::Plain(tcp) => Pin::new(tcp).poll_read(cx, buf),
+            // Backend que fecha sem close_notify é comum; quem diz se a
+            // resposta veio inteira é o framing HTTP, como no TCP puro
+            UpstreamStream::Tls(tls) => match Pin::new(tls.as_mut()).poll_read(cx, buf) {
+                Poll::Ready(Err(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    Poll::Ready(Ok(()))
+                }
+                poll => poll,
+            },
+        }
+    }
+}
+
+impl AsyncWrite for UpstreamStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            UpstreamStream::Plain(tcp) => Pin::new(tcp).poll_write(cx, buf),
+            UpstreamStream::Tls(tls) => Pin::new(tls.as_mut()).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            UpstreamStream::Plain(tcp) => Pin::new(tcp).poll_flush(cx),
+            UpstreamStream::Tls(tls) => Pin::new(tls.as_mut()).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            UpstreamStream::Plain(tcp) => Pin::new(tcp).poll_shutdown(cx),
+            UpstreamStream::Tls(tls) => Pin::new(tls.as_mut()).poll_shutdown(cx),
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use crate::challenge::{to_hex, unix_now};
+use crate::config::{Config, WatermarkConfig};
+use crate::error::Error;
+use crate::metrics::Metrics;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const MIN_SECRET: usize = 32;
+const QUEUE_CAPACITY: usize = 10_000;
+
+// Onde a marca vai na resposta da rota
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WatermarkMode {
+    Header,
+    // Comentário antes de </head> (só HTML 200, como o [inject])
+    Html,
+    Both,
+}
+
+impl WatermarkMode {
+    pub fn header(&self) -> bool {
+        matches!(self, WatermarkMode::Header | WatermarkMode::Both)
+    }
+
+    pub fn html(&self) -> bool {
+        matches!(self, WatermarkMode::Html | WatermarkMode::Both)
+    }
+}
+
+// Uma linha JSON por resposta marcada: é por ela que o vazamento chega no cliente
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    ts: u64,
+    token: &'a str,
+    ip: IpAddr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session: Option<&'a str>,
+    path: &'a str,
+}
+
+struct Entry {
+    line: String,
+    file: String,
+}
+
+pub struct Watermarks {
+    config: Arc<ArcSwap<Config>>,
+    secret: Vec<u8>,
+    tx: mpsc::Sender<Entry>,
+    metrics: Arc<Metrics>,
+}
+
+impl Watermarks {
+    pub fn new(config: Arc<ArcSwap<Config>>, metrics: Arc<Metrics>) -> Result<Arc<Self>, Error> {
+        let secret = load_secret(&config.load().watermark)?;
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(write_loop(rx));
+        Ok(Arc::new(Watermarks {
+            config,
+            secret,
+            tx,
+            metrics,
+        }))
+    }
+
+    // Token = época (8 hex) + hmac(época|ip|sessão|path) truncado: parece um
+    // request id, não dá para forjar o de outro cliente e, com o segredo fixo,
+    // é verificável mesmo sem a trilha
+    pub fn mark(&self, ip: IpAddr, session: Option<&str>, path: &str) -> String {
+        let ts = unix_now();
+        let mut mac = HmacSha256::new_from_slice(&self.secret).expect("HMAC aceita qualquer chave");
+        for part in [
+            ts.to_string().as_str(),
+            &ip.to_string(),
+            session.unwrap_or(""),
+            path,
+        ] {
+            mac.update(part.as_bytes());
+            mac.update(b"|");
+        }
+        let token = format!(
+            "{:08x}{}",
+            ts as u32,
+            to_hex(&mac.finalize().into_bytes()[..12])
+        );
+
+        let record = AuditRecord {
+            ts,
+            token: &token,
+            ip,
+            session,
+            path,
+        };
+        let line = serde_json::to_string(&record).unwrap_or_default() + "\n";
+        let file = self.config.load().watermark.audit_log.clone();
+        if self.tx.try_send(Entry { line, file }).is_err() {
+            self.metrics
+                .inc("oblivion_watermark_audit_dropped_total", &[]);
+        }
+        self.metrics.inc("oblivion_watermarks_total", &[]);
+        token
+    }
+}
+
+pub fn load_secret(config: &WatermarkConfig) -> Result<Vec<u8>, Error> {
+    let Some(path) = &config.secret_file else {
+        let mut secret = vec![0u8; MIN_SECRET];
+        getrandom::getrandom(&mut secret).expect("❌ Erro: sem fonte de entropia para o watermark");
+        return Ok(secret);
+    };
+    let raw = std::fs::read(path)
+        .map_err(|e| Error::Config(format!("watermark.secret_file '{}' ilegível: {}", path, e)))?;
+    let secret = raw.trim_ascii().to_vec();
+    if secret.len() < MIN_SECRET {
+        return Err(Error::Config(format!(
+            "watermark.secret_file '{}': segredo curto demais (mínimo {} bytes)",
+            path, MIN_SECRET
+        )));
+    }
+    Ok(secret)
+}
+
+async fn write_loop(mut rx: mpsc::Receiver<Entry>) {
+    let mut file: Option<(String, File)> = None;
+    while let Some(entry) = rx.recv().await {
+        if file.as_ref().is_none_or(|(open, _)| *open != entry.file) {
+            file = match OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&entry.file)
+                .await
+            {
+                Ok(f) => Some((entry.file.clone(), f)),
+                Err(e) => {
+                    warn!(file = %entry.file, error = %e, "Failed to open watermark audit log");
+                    None
+                }
+            };
+        }
+        if let Some((_, f)) = file.as_mut()
+            && let Err(e) = f.write_all(entry.line.as_bytes()).await
+        {
+            warn!(file = %entry.file, error = %e, "Failed to write watermark audit log");
+            file = None;
+        }
+    }
+}
@@ -0,0 +1,486 @@
+use std::fmt;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::http::Request;
+use crate::metrics::Metrics;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+const CLOSE_PROTOCOL: u16 = 1002;
+const CLOSE_INVALID_DATA: u16 = 1007;
+const CLOSE_POLICY: u16 = 1008;
+const CLOSE_TOO_BIG: u16 = 1009;
+const CLOSE_INTERNAL: u16 = 1011;
+
+// Quem escreveu os frames lidos: cliente mascara, servidor nunca (RFC 6455 5.1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Client,
+    Server,
+}
+
+impl Side {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Side::Client => "client",
+            Side::Server => "server",
+        }
+    }
+}
+
+// Por que o WAF derrubou o WebSocket; vai dentro do io::Error do pump
+#[derive(Debug)]
+pub struct Violation {
+    // Código do close mandado ao cliente
+    pub code: u16,
+    pub label: &'static str,
+    pub reason: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "websocket {}: {}", self.label, self.reason)
+    }
+}
+
+impl std::error::Error for Violation {}
+
+impl Violation {
+    pub fn of(e: &std::io::Error) -> Option<&Violation> {
+        e.get_ref().and_then(|e| e.downcast_ref())
+    }
+}
+
+// Frame errado do upstream não é culpa do cliente: ele recebe 1011
+fn violation(
+    side: Side,
+    code: u16,
+    label: &'static str,
+    reason: impl Into<String>,
+) -> std::io::Error {
+    std::io::Error::other(Violation {
+        code: if side == Side::Server {
+            CLOSE_INTERNAL
+        } else {
+            code
+        },
+        label,
+        reason: reason.into(),
+    })
+}
+
+pub fn requested(req: &Request) -> bool {
+    req.method.eq_ignore_ascii_case("GET")
+        && req.header("Upgrade").is_some_and(|upgrade| {
+            upgrade
+                .split(',')
+                .any(|p| p.trim().eq_ignore_ascii_case("websocket"))
+        })
+}
+
+// Mensagem de texto vista pelas regras como o corpo do request do upgrade
+pub fn message(upgrade: &Request, text: &str) -> Request {
+    Request {
+        method: upgrade.method.clone(),
+        path: upgrade.path.clone(),
+        version: upgrade.version.clone(),
+        headers: upgrade.headers.clone(),
+        header_order: upgrade.header_order.clone(),
+        body: text.to_string(),
+        ja3: upgrade.ja3.clone(),
+        ja4: upgrade.ja4.clone(),
+        body_as_form: Some(false),
+    }
+}
+
+// Close do lado do servidor (sem máscara); o motivo cabe em 123 bytes
+pub fn close_frame(code: u16, reason: &str) -> Vec<u8> {
+    let mut end = reason.len().min(123);
+    while !reason.is_char_boundary(end) {
+        end -= 1;
+    }
+    let mut frame = vec![0x80 | OP_CLOSE, 2 + end as u8];
+    frame.extend_from_slice(&code.to_be_bytes());
+    frame.extend_from_slice(&reason.as_bytes()[..end]);
+    frame
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    // Bytes como chegaram (repassados sem mudança) e o payload desmascarado
+    raw: Vec<u8>,
+    payload: Vec<u8>,
+}
+
+// extensions: o 101 negociou uma extensão (permessage-deflate usa RSV1)
+async fn read_frame<R>(
+    reader: &mut R,
+    side: Side,
+    max_size: usize,
+    extensions: bool,
+) -> std::io::Result<Option<Frame>>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut head = [0u8; 2];
+    // EOF entre frames é fim normal; no meio de um, erro
+    if reader.read(&mut head[..1]).await? == 0 {
+        return Ok(None);
+    }
+    reader.read_exact(&mut head[1..]).await?;
+    let mut raw = head.to_vec();
+    let fin = head[0] & 0x80 != 0;
+    let opcode = head[0] & 0x0f;
+    let masked = head[1] & 0x80 != 0;
+    if head[0] & 0x70 != 0 && !extensions {
+        return Err(violation(
+            side,
+            CLOSE_PROTOCOL,
+            "protocol",
+            "reserved bits set",
+        ));
+    }
+    if !matches!(
+        opcode,
+        OP_CONTINUATION | OP_TEXT | OP_BINARY | OP_CLOSE | OP_PING | OP_PONG
+    ) {
+        return Err(violation(
+            side,
+            CLOSE_PROTOCOL,
+            "protocol",
+            format!("unknown opcode {:#x}", opcode),
+        ));
+    }
+    if masked != (side == Side::Client) {
+        return Err(violation(side, CLOSE_PROTOCOL, "protocol", "wrong masking"));
+    }
+    let length = match head[1] & 0x7f {
+        126 => {
+            let mut bytes = [0u8; 2];
+            reader.read_exact(&mut bytes).await?;
+            raw.extend_from_slice(&bytes);
+            u64::from(u16::from_be_bytes(bytes))
+        }
+        127 => {
+            let mut bytes = [0u8; 8];
+            reader.read_exact(&mut bytes).await?;
+            raw.extend_from_slice(&bytes);
+            u64::from_be_bytes(bytes)
+        }
+        n => u64::from(n),
+    };
+    if opcode & 0x8 != 0 && (!fin || length > 125) {
+        return Err(violation(
+            side,
+            CLOSE_PROTOCOL,
+            "protocol",
+            "invalid control frame",
+        ));
+    }
+    if length > max_size as u64 {
+        return Err(violation(
+            side,
+            CLOSE_TOO_BIG,
+            "too_large",
+            format!("frame of {} bytes", length),
+        ));
+    }
+    let mut mask = [0u8; 4];
+    if masked {
+        reader.read_exact(&mut mask).await?;
+        raw.extend_from_slice(&mask);
+    }
+    let start = raw.len();
+    raw.resize(start + length as usize, 0);
+    reader.read_exact(&mut raw[start..]).await?;
+    let payload = raw[start..]
+        .iter()
+        .enumerate()
+        .map(|(i, b)| if masked { b ^ mask[i % 4] } else { *b })
+        .collect();
+    Ok(Some(Frame {
+        fin,
+        opcode,
+        raw,
+        payload,
+    }))
+}
+
+pub type Inspect<'a> = &'a (dyn Fn(&str) -> Option<String> + Sync);
+
+// Frames de um sentido até o EOF. Com inspect, cada mensagem de texto fica
+// segurada até o último fragmento e só segue se as regras deixarem; controle
+// (ping, pong, close) passa na hora, mesmo no meio de uma mensagem
+pub async fn pump<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    side: Side,
+    max_message: usize,
+    extensions: bool,
+    inspect: Option<Inspect<'_>>,
+    metrics: &Metrics,
+) -> std::io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut message: Option<u8> = None;
+    let mut size = 0;
+    let mut held = Vec::new();
+    let mut text = Vec::new();
+    while let Some(frame) = read_frame(reader, side, max_message, extensions).await? {
+        if frame.opcode & 0x8 != 0 {
+            writer.write_all(&frame.raw).await?;
+            continue;
+        }
+        let opcode = match (frame.opcode, message) {
+            (OP_CONTINUATION, Some(opcode)) => opcode,
+            (OP_CONTINUATION, None) => {
+                return Err(violation(
+                    side,
+                    CLOSE_PROTOCOL,
+                    "protocol",
+                    "continuation without message",
+                ));
+            }
+            (opcode, None) => {
+                size = 0;
+                opcode
+            }
+            (_, Some(_)) => {
+                return Err(violation(
+                    side,
+                    CLOSE_PROTOCOL,
+                    "protocol",
+                    "message interleaved with another",
+                ));
+            }
+        };
+        size += frame.payload.len();
+        if size > max_message {
+            return Err(violation(
+                side,
+                CLOSE_TOO_BIG,
+                "too_large",
+                format!("message over {} bytes", max_message),
+            ));
+        }
+        let inspected = opcode == OP_TEXT && inspect.is_some();
+        if inspected {
+            held.extend_from_slice(&frame.raw);
+            text.extend_from_slice(&frame.payload);
+        } else {
+            writer.write_all(&frame.raw).await?;
+        }
+        message = (!frame.fin).then_some(opcode);
+        if !frame.fin {
+            continue;
+        }
+        metrics.inc(
+            "oblivion_websocket_messages_total",
+            &[("direction", side.label())],
+        );
+        if let Some(inspect) = inspect.filter(|_| inspected) {
+            let Ok(text) = String::from_utf8(std::mem::take(&mut text)) else {
+                return Err(violation(
+                    side,
+                    CLOSE_INVALID_DATA,
+                    "invalid_utf8",
+                    "text message is not UTF-8",
+                ));
+            };
+            if let Some(reason) = inspect(&text) {
+                return Err(violation(side, CLOSE_POLICY, "blocked", reason));
+            }
+            writer.write_all(&std::mem::take(&mut held)).await?;
+        }
+    }
+    writer.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::duplex;
+
+    use super::*;
+
+    const MASK: [u8; 4] = [0x37, 0xfa, 0x21, 0x3d];
+
+    // Frame do cliente (mascarado) ou do servidor (mask None)
+    fn frame(fin: bool, opcode: u8, payload: &[u8], mask: Option<[u8; 4]>) -> Vec<u8> {
+        let mut out = vec![if fin { 0x80 } else { 0 } | opcode];
+        let bit = if mask.is_some() { 0x80 } else { 0 };
+        match payload.len() {
+            n if n < 126 => out.push(bit | n as u8),
+            n if n <= 0xffff => {
+                out.push(bit | 126);
+                out.extend_from_slice(&(n as u16).to_be_bytes());
+            }
+            n => {
+                out.push(bit | 127);
+                out.extend_from_slice(&(n as u64).to_be_bytes());
+            }
+        }
+        match mask {
+            Some(mask) => {
+                out.extend_from_slice(&mask);
+                out.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+            }
+            None => out.extend_from_slice(payload),
+        }
+        out
+    }
+
+    fn client(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        frame(fin, opcode, payload, Some(MASK))
+    }
+
+    // Roda o pump do lado do cliente sobre duplex: (resultado, bytes repassados)
+    async fn run(
+        input: &[u8],
+        max_message: usize,
+        inspect: Option<Inspect<'_>>,
+    ) -> (std::io::Result<()>, Vec<u8>) {
+        let (mut feed, mut reader) = duplex(1 << 20);
+        let (mut writer, mut out) = duplex(1 << 20);
+        feed.write_all(input).await.unwrap();
+        drop(feed);
+        let metrics = Metrics::new();
+        let result = pump(
+            &mut reader,
+            &mut writer,
+            Side::Client,
+            max_message,
+            false,
+            inspect,
+            &metrics,
+        )
+        .await;
+        drop(writer);
+        let mut forwarded = Vec::new();
+        out.read_to_end(&mut forwarded).await.unwrap();
+        (result, forwarded)
+    }
+
+    fn violated(result: std::io::Result<()>) -> (u16, &'static str) {
+        let e = result.unwrap_err();
+        let v = Violation::of(&e).expect("violation");
+        (v.code, v.label)
+    }
+
+    fn block_union(text: &str) -> Option<String> {
+        text.contains("union select").then(|| "sqli".to_string())
+    }
+
+    #[tokio::test]
+    async fn read_frame_unmasks_and_checks_masking() {
+        let (mut feed, mut reader) = duplex(1024);
+        feed.write_all(&client(true, OP_TEXT, b"hello"))
+            .await
+            .unwrap();
+        feed.write_all(&frame(true, OP_TEXT, b"hello", None))
+            .await
+            .unwrap();
+        drop(feed);
+
+        let first = read_frame(&mut reader, Side::Client, 1024, false)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(first.fin);
+        assert_eq!(first.opcode, OP_TEXT);
+        assert_eq!(first.payload, b"hello");
+        assert_eq!(first.raw, client(true, OP_TEXT, b"hello"));
+
+        // Cliente sem máscara: 1002; o mesmo erro vindo do upstream vira 1011
+        let unmasked = read_frame(&mut reader, Side::Client, 1024, false).await;
+        assert_eq!(violated(unmasked.map(|_| ())), (CLOSE_PROTOCOL, "protocol"));
+        let (mut feed, mut reader) = duplex(1024);
+        feed.write_all(&client(true, OP_TEXT, b"x")).await.unwrap();
+        drop(feed);
+        let masked_upstream = read_frame(&mut reader, Side::Server, 1024, false).await;
+        assert_eq!(
+            violated(masked_upstream.map(|_| ())),
+            (CLOSE_INTERNAL, "protocol")
+        );
+    }
+
+    #[tokio::test]
+    async fn read_frame_refuses_oversize_before_reading_payload() {
+        let (mut feed, mut reader) = duplex(1024);
+        // Só o cabeçalho: anuncia 70000 bytes que nunca chegam
+        let head = client(true, OP_BINARY, &vec![0; 70_000]);
+        feed.write_all(&head[..10]).await.unwrap();
+        let result = read_frame(&mut reader, Side::Client, 65_536, false).await;
+        assert_eq!(violated(result.map(|_| ())), (CLOSE_TOO_BIG, "too_large"));
+    }
+
+    #[tokio::test]
+    async fn fragmented_text_is_held_until_inspected() {
+        let ping = client(true, OP_PING, b"p");
+        let parts = [
+            client(false, OP_TEXT, b"1 uni"),
+            client(true, OP_CONTINUATION, b"on select 2"),
+        ];
+        let input = [parts[0].clone(), ping.clone(), parts[1].clone()].concat();
+        let (result, forwarded) = run(&input, 1024, Some(&block_union)).await;
+        assert_eq!(violated(result), (CLOSE_POLICY, "blocked"));
+        // Nada da mensagem passou; o ping do meio sim
+        assert_eq!(forwarded, ping);
+
+        let clean = [
+            client(false, OP_TEXT, b"hello "),
+            ping.clone(),
+            client(true, OP_CONTINUATION, b"world"),
+        ];
+        let (result, forwarded) = run(&clean.concat(), 1024, Some(&block_union)).await;
+        result.unwrap();
+        assert_eq!(forwarded, [&clean[1][..], &clean[0], &clean[2]].concat());
+    }
+
+    #[tokio::test]
+    async fn interleaved_messages_are_refused() {
+        let input = [client(false, OP_TEXT, b"a"), client(true, OP_BINARY, b"b")].concat();
+        let (result, _) = run(&input, 1024, None).await;
+        assert_eq!(violated(result), (CLOSE_PROTOCOL, "protocol"));
+
+        let orphan = client(true, OP_CONTINUATION, b"a");
+        let (result, _) = run(&orphan, 1024, None).await;
+        assert_eq!(violated(result), (CLOSE_PROTOCOL, "protocol"));
+    }
+
+    #[tokio::test]
+    async fn message_size_counts_every_fragment() {
+        let input = [
+            client(false, OP_TEXT, &[b'a'; 600]),
+            client(true, OP_CONTINUATION, &[b'a'; 600]),
+        ]
+        .concat();
+        let (result, forwarded) = run(&input, 1000, Some(&block_union)).await;
+        assert_eq!(violated(result), (CLOSE_TOO_BIG, "too_large"));
+        assert!(forwarded.is_empty());
+
+        let (result, forwarded) = run(&client(true, OP_TEXT, &[b'a'; 1001]), 1000, None).await;
+        assert_eq!(violated(result), (CLOSE_TOO_BIG, "too_large"));
+        assert!(forwarded.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unmasked_client_frame_closes_the_pump() {
+        let input = [
+            client(true, OP_TEXT, b"ok"),
+            frame(true, OP_TEXT, b"bare", None),
+        ]
+        .concat();
+        let (result, forwarded) = run(&input, 1024, None).await;
+        assert_eq!(violated(result), (CLOSE_PROTOCOL, "protocol"));
+        assert_eq!(forwarded, client(true, OP_TEXT, b"ok"));
+    }
+}